@@ -0,0 +1,173 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The sites on which a user has disabled content blocking.
+//!
+//! The allowlist is stored as JSON of the form
+//!
+//! ```json
+//! { "domains": ["example.org"], "url-filters": ["^https?://intranet/"] }
+//! ```
+
+use parse::Error;
+use repr::UrlFilter;
+use request_url::RequestUrl;
+use serde_json::{self, Map, Value};
+use std::fmt;
+
+/// A function notified of changes to an allowlist.
+type ChangeListener = Box<dyn Fn(&Allowlist) + Send + Sync>;
+
+/// Pages on which no rules apply, identified by their domain or by a URL filter.
+#[derive(Default)]
+pub struct Allowlist {
+    domains: Vec<String>,
+    patterns: Vec<UrlFilter>,
+    on_change: Option<ChangeListener>,
+}
+
+impl fmt::Debug for Allowlist {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Allowlist")
+         .field("domains", &self.domains)
+         .field("url_filters", &self.url_filters().collect::<Vec<_>>())
+         .finish()
+    }
+}
+
+impl Allowlist {
+    /// Create an empty allowlist.
+    pub fn new() -> Allowlist {
+        Allowlist::default()
+    }
+
+    /// Whether no pages are allowlisted.
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty() && self.patterns.is_empty()
+    }
+
+    /// The allowlisted domains, which include their subdomains.
+    pub fn domains(&self) -> impl Iterator<Item=&str> {
+        self.domains.iter().map(|domain| &**domain)
+    }
+
+    /// The URL filters of the allowlisted pages.
+    pub fn url_filters(&self) -> impl Iterator<Item=&str> {
+        self.patterns.iter().map(|pattern| pattern.as_str())
+    }
+
+    /// Allowlist a domain and its subdomains. Returns whether it was not already present.
+    pub fn add_domain(&mut self, domain: &str) -> bool {
+        let domain = normalize(domain);
+        if domain.is_empty() || self.domains.contains(&domain) {
+            return false;
+        }
+        self.domains.push(domain);
+        self.changed();
+        true
+    }
+
+    /// Remove a domain. Returns whether it was present.
+    pub fn remove_domain(&mut self, domain: &str) -> bool {
+        let domain = normalize(domain);
+        let len = self.domains.len();
+        self.domains.retain(|d| *d != domain);
+        let removed = self.domains.len() != len;
+        if removed {
+            self.changed();
+        }
+        removed
+    }
+
+    /// Allowlist the pages whose URL matches a regular expression, in the
+    /// syntax of `url-filter`. Returns whether it was not already present.
+    pub fn add_url_filter(&mut self, pattern: &str) -> Result<bool, Error> {
+        if self.url_filters().any(|p| p == pattern) {
+            return Ok(false);
+        }
+        let filter = UrlFilter::new(pattern).ok_or_else(|| {
+            Error::InvalidRegex { index: self.patterns.len(), pattern: pattern.to_owned() }
+        })?;
+        self.patterns.push(filter);
+        self.changed();
+        Ok(true)
+    }
+
+    /// Remove a URL filter. Returns whether it was present.
+    pub fn remove_url_filter(&mut self, pattern: &str) -> bool {
+        let len = self.patterns.len();
+        self.patterns.retain(|p| p.as_str() != pattern);
+        let removed = self.patterns.len() != len;
+        if removed {
+            self.changed();
+        }
+        removed
+    }
+
+    /// Register a function called after every change, eg. to persist the
+    /// allowlist with `to_json`. It replaces any previously registered function.
+    pub fn on_change<F: Fn(&Allowlist) + Send + Sync + 'static>(&mut self, f: F) {
+        self.on_change = Some(Box::new(f));
+    }
+
+    fn changed(&self) {
+        if let Some(ref on_change) = self.on_change {
+            on_change(self);
+        }
+    }
+
+    /// Whether content blocking is disabled on the page at `page`.
+    pub fn matches(&self, page: &dyn RequestUrl) -> bool {
+        let host_matches = page.host().is_some_and(|host| {
+            let host = host.trim_end_matches('.');
+            // Hosts may contain non-ASCII characters, so they are compared as bytes.
+            let host = host.as_bytes();
+            self.domains.iter().any(|domain| {
+                let domain = domain.as_bytes();
+                host.eq_ignore_ascii_case(domain) ||
+                    host.len() > domain.len() &&
+                    host[host.len() - domain.len()..].eq_ignore_ascii_case(domain) &&
+                    host[host.len() - domain.len() - 1] == b'.'
+            })
+        });
+        host_matches || self.patterns.iter().any(|pattern| pattern.is_match(page.as_str()))
+    }
+
+    /// Serialize the entries of the allowlist to JSON.
+    pub fn to_json(&self) -> String {
+        let mut object = Map::new();
+        object.insert("domains".to_owned(),
+                      Value::Array(self.domains().map(Value::from).collect()));
+        object.insert("url-filters".to_owned(),
+                      Value::Array(self.url_filters().map(Value::from).collect()));
+        Value::Object(object).to_string()
+    }
+
+    /// Restore an allowlist serialized by `to_json`.
+    pub fn from_json(body: &str) -> Result<Allowlist, Error> {
+        let json: Value = serde_json::from_str(body).map_err(|_| Error::JSON)?;
+        let strings = |field| -> Result<Vec<&str>, Error> {
+            match json.get(field) {
+                Some(Value::Array(values)) => {
+                    values.iter().map(|v| v.as_str().ok_or(Error::MissingField { index: 0, field }))
+                          .collect()
+                }
+                None => Ok(vec![]),
+                Some(_) => Err(Error::MissingField { index: 0, field }),
+            }
+        };
+        let mut allowlist = Allowlist::new();
+        for domain in strings("domains")? {
+            allowlist.add_domain(domain);
+        }
+        for pattern in strings("url-filters")? {
+            allowlist.add_url_filter(pattern)?;
+        }
+        Ok(allowlist)
+    }
+}
+
+fn normalize(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_ascii_lowercase()
+}
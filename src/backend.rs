@@ -0,0 +1,406 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The strategies for finding the rules of a list that may match a request.
+//! Every candidate is then evaluated in full, so a backend only decides how
+//! much work is spent ruling out the others, and how much memory that takes.
+
+#[cfg(feature = "simd")]
+use aho_corasick::AhoCorasick;
+use index::{DomainIndex, candidates_into};
+#[cfg(feature = "simd")]
+use lint::plain_literal;
+use prefilter::LiteralFilter;
+use regex::RegexSet;
+#[cfg(feature = "automata")]
+use regex_automata::{Input, MatchKind, PatternSet};
+#[cfg(feature = "automata")]
+use regex_automata::meta::Regex;
+use repr::{Request, Rule};
+#[cfg(feature = "simd")]
+use std::collections::HashMap;
+use std::mem;
+
+/// Finds the rules of a list that may match a request, ahead of evaluating
+/// them in full.
+pub trait MatchBackend: Send + Sync {
+    /// Store in `candidates` the positions of the rules that may match
+    /// `request`, in increasing order, replacing its contents. A rule left out
+    /// must not match the request. Reusing the memory of `candidates` lets
+    /// matching avoid allocating; see `MatchArena`.
+    fn candidates_into(&self, request: &Request, candidates: &mut Vec<usize>);
+
+    /// The positions of the rules that may match `request`, in increasing order.
+    fn candidates(&self, request: &Request) -> Vec<usize> {
+        let mut candidates = vec![];
+        self.candidates_into(request, &mut candidates);
+        candidates
+    }
+
+    /// An estimate of the heap memory used by this backend, in bytes.
+    fn heap_size(&self) -> usize;
+
+    /// The number of rules whose URL filters this backend matches without
+    /// evaluating a regular expression, other than to confirm a candidate.
+    fn fast_path_rules(&self) -> usize {
+        0
+    }
+}
+
+/// Memory reused between requests for the work of matching them, so that
+/// matching with `process_rules_for_request_in` reaches a steady state where it
+/// does not allocate. That holds for the `Linear`, `Indexed` and `Literal`
+/// backends, for requests with at most two reactions and a redirect chain of
+/// at most one URL; the other backends allocate while searching.
+#[derive(Clone, Debug, Default)]
+pub struct MatchArena {
+    pub(crate) candidates: Vec<usize>,
+}
+
+impl MatchArena {
+    /// An empty arena, which grows as needed.
+    pub fn new() -> MatchArena {
+        MatchArena::default()
+    }
+
+    /// An arena large enough for matching requests against lists of up to
+    /// `rules` rules, so that not even the first request allocates.
+    pub fn with_capacity(rules: usize) -> MatchArena {
+        // The domain index needs room for twice the rules specific to a host.
+        MatchArena { candidates: Vec::with_capacity(2 * rules) }
+    }
+}
+
+/// The built-in backends, chosen with `RuleList::set_match_backend`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MatchBackendKind {
+    /// Evaluate every rule. Needs no memory, and suits short lists.
+    Linear,
+    /// Skip the rules restricted to other hosts, and those whose URL filters
+    /// require substrings missing from the URL.
+    #[default]
+    Indexed,
+    /// Match the URL filters of every rule at once with regex sets, compiled
+    /// when the backend is chosen. Uses the most memory, and takes the longest
+    /// to build, but does the least work for each request.
+    RegexSet,
+    /// Match the URL filters of every rule at once with a single automaton,
+    /// determinized lazily as requests are matched. Takes longer to build and
+    /// more memory than `RegexSet`, but the work for each request barely grows
+    /// with the size of the list, which suits the largest lists. Requires the
+    /// `automata` feature.
+    #[cfg(feature = "automata")]
+    Automaton,
+    /// Search for the URL filters that are plain strings all at once, with
+    /// SIMD instructions where the CPU supports them, and find candidates among
+    /// the other rules like `Indexed`. Most rules of typical lists are plain
+    /// strings. Requires the `simd` feature.
+    #[cfg(feature = "simd")]
+    Literal,
+}
+
+impl MatchBackendKind {
+    /// Build the backend of this kind for `rules`.
+    pub(crate) fn build(self, rules: &[Rule]) -> Box<dyn MatchBackend> {
+        match self {
+            MatchBackendKind::Linear => Box::new(LinearBackend { len: rules.len() }),
+            MatchBackendKind::Indexed => Box::new(IndexedBackend::new(rules)),
+            MatchBackendKind::RegexSet => Box::new(RegexSetBackend::new(rules)),
+            #[cfg(feature = "automata")]
+            MatchBackendKind::Automaton => Box::new(AutomatonBackend::new(rules)),
+            #[cfg(feature = "simd")]
+            MatchBackendKind::Literal => Box::new(LiteralBackend::new(rules)),
+        }
+    }
+}
+
+struct LinearBackend {
+    len: usize,
+}
+
+impl MatchBackend for LinearBackend {
+    fn candidates_into(&self, _: &Request, candidates: &mut Vec<usize>) {
+        candidates.clear();
+        candidates.extend(0..self.len);
+    }
+
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+struct IndexedBackend {
+    len: usize,
+    index: DomainIndex,
+    filter: LiteralFilter,
+}
+
+impl IndexedBackend {
+    fn new(rules: &[Rule]) -> IndexedBackend {
+        IndexedBackend {
+            len: rules.len(),
+            index: DomainIndex::new(rules),
+            filter: LiteralFilter::new(rules),
+        }
+    }
+}
+
+impl MatchBackend for IndexedBackend {
+    fn candidates_into(&self, request: &Request, candidates: &mut Vec<usize>) {
+        candidates_into(self.len, &self.index, &self.filter, request, candidates)
+    }
+
+    fn heap_size(&self) -> usize {
+        self.index.heap_size() + self.filter.heap_size()
+    }
+}
+
+/// The number of URL filters combined in each regex set, which keeps every set
+/// well below the size limit of the regex crate.
+const SET_SIZE: usize = 256;
+
+struct RegexSetBackend {
+    /// Each set, with the position of the rule of each of its patterns.
+    sets: Vec<(RegexSet, Vec<usize>)>,
+    /// The rules whose URL filters also match the redirect chain.
+    redirect_chain: Vec<bool>,
+    /// The rules whose URL filters did not fit in a set, which are always candidates.
+    unmatched: Vec<usize>,
+    source_size: usize,
+}
+
+impl RegexSetBackend {
+    fn new(rules: &[Rule]) -> RegexSetBackend {
+        let mut backend = RegexSetBackend {
+            sets: vec![],
+            redirect_chain: rules.iter().map(|rule| rule.trigger.match_redirect_chain).collect(),
+            unmatched: vec![],
+            source_size: rules.iter().map(|rule| rule.trigger.url_filter.as_str().len()).sum(),
+        };
+        let positions: Vec<usize> = (0..rules.len()).collect();
+        for chunk in positions.chunks(SET_SIZE) {
+            let patterns = chunk.iter().map(|&position| rules[position].trigger.url_filter.as_str());
+            match RegexSet::new(patterns) {
+                Ok(set) => backend.sets.push((set, chunk.to_vec())),
+                Err(_) => {
+                    // Some pattern is too large or not supported; compile each
+                    // of the others on its own.
+                    for &position in chunk {
+                        match RegexSet::new([rules[position].trigger.url_filter.as_str()]) {
+                            Ok(set) => backend.sets.push((set, vec![position])),
+                            Err(_) => backend.unmatched.push(position),
+                        }
+                    }
+                }
+            }
+        }
+        backend
+    }
+}
+
+impl MatchBackend for RegexSetBackend {
+    fn candidates_into(&self, request: &Request, candidates: &mut Vec<usize>) {
+        candidates.clear();
+        candidates.extend_from_slice(&self.unmatched);
+        for (set, positions) in &self.sets {
+            candidates.extend(set.matches(request.url.as_str()).into_iter().map(|i| positions[i]));
+            for url in request.redirect_chain {
+                candidates.extend(set.matches(url.as_str()).into_iter()
+                                     .map(|i| positions[i])
+                                     .filter(|&position| self.redirect_chain[position]));
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+    }
+
+    /// The compiled programs of the sets are not included, since the regex
+    /// crate does not report their size.
+    fn heap_size(&self) -> usize {
+        self.source_size + self.redirect_chain.capacity() + self.unmatched.capacity() * mem::size_of::<usize>() +
+            self.sets.iter().map(|(_, positions)| {
+                mem::size_of::<(RegexSet, Vec<usize>)>() + positions.capacity() * mem::size_of::<usize>()
+            }).sum::<usize>()
+    }
+}
+
+#[cfg(feature = "automata")]
+struct AutomatonBackend {
+    /// The automaton, if any URL filter could be compiled into it.
+    automaton: Option<Regex>,
+    /// The position of the rule of each pattern of the automaton.
+    positions: Vec<usize>,
+    /// The rules whose URL filters also match the redirect chain.
+    redirect_chain: Vec<bool>,
+    /// The rules whose URL filters the automaton does not support, which are
+    /// always candidates.
+    unmatched: Vec<usize>,
+}
+
+#[cfg(feature = "automata")]
+impl AutomatonBackend {
+    fn new(rules: &[Rule]) -> AutomatonBackend {
+        let mut positions: Vec<usize> = (0..rules.len()).collect();
+        let mut unmatched = vec![];
+        // Every pattern matching a URL is needed, not just the leftmost one.
+        // The list decides how large the automaton gets, rather than a limit.
+        let config = Regex::config().match_kind(MatchKind::All).nfa_size_limit(None);
+        let automaton = loop {
+            if positions.is_empty() {
+                break None;
+            }
+            let patterns: Vec<&str> =
+                positions.iter().map(|&position| rules[position].trigger.url_filter.as_str()).collect();
+            match Regex::builder().configure(config.clone()).build_many(&patterns) {
+                Ok(automaton) => break Some(automaton),
+                // The syntax of a pattern is not supported: leave it out and
+                // build the automaton again.
+                Err(error) => match error.pattern() {
+                    Some(pattern) => unmatched.push(positions.remove(pattern.as_usize())),
+                    None => {
+                        unmatched.append(&mut positions);
+                        break None;
+                    }
+                },
+            }
+        };
+        unmatched.sort_unstable();
+        AutomatonBackend {
+            automaton,
+            positions,
+            redirect_chain: rules.iter().map(|rule| rule.trigger.match_redirect_chain).collect(),
+            unmatched,
+        }
+    }
+}
+
+#[cfg(feature = "automata")]
+impl MatchBackend for AutomatonBackend {
+    fn candidates_into(&self, request: &Request, candidates: &mut Vec<usize>) {
+        candidates.clear();
+        candidates.extend_from_slice(&self.unmatched);
+        if let Some(ref automaton) = self.automaton {
+            let mut matches = PatternSet::new(automaton.pattern_len());
+            automaton.which_overlapping_matches(&Input::new(request.url.as_str()), &mut matches);
+            candidates.extend(matches.iter().map(|pattern| self.positions[pattern.as_usize()]));
+            for url in request.redirect_chain {
+                matches.clear();
+                automaton.which_overlapping_matches(&Input::new(url.as_str()), &mut matches);
+                candidates.extend(matches.iter()
+                                         .map(|pattern| self.positions[pattern.as_usize()])
+                                         .filter(|&position| self.redirect_chain[position]));
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+    }
+
+    fn heap_size(&self) -> usize {
+        self.automaton.as_ref().map_or(0, Regex::memory_usage) + self.redirect_chain.capacity() +
+            (self.positions.capacity() + self.unmatched.capacity()) * mem::size_of::<usize>()
+    }
+}
+
+/// Searches for plain strings, with the positions of the rules whose URL
+/// filters match each of them.
+#[cfg(feature = "simd")]
+struct LiteralSearcher {
+    searcher: AhoCorasick,
+    positions: Vec<Vec<usize>>,
+}
+
+#[cfg(feature = "simd")]
+impl LiteralSearcher {
+    fn new(literals: HashMap<String, Vec<usize>>, caseless: bool) -> Option<LiteralSearcher> {
+        if literals.is_empty() {
+            return None;
+        }
+        let (literals, positions): (Vec<String>, Vec<Vec<usize>>) = literals.into_iter().unzip();
+        let searcher = AhoCorasick::builder().ascii_case_insensitive(caseless).build(&literals).ok()?;
+        Some(LiteralSearcher { searcher, positions })
+    }
+
+    /// The positions of the rules whose strings appear in `url`.
+    fn find<'a>(&'a self, url: &'a str) -> impl Iterator<Item=usize> + 'a {
+        self.searcher.find_overlapping_iter(url)
+            .flat_map(move |found| self.positions[found.pattern().as_usize()].iter().cloned())
+    }
+
+    fn heap_size(&self) -> usize {
+        self.searcher.memory_usage() +
+            self.positions.iter().map(|positions| {
+                mem::size_of::<Vec<usize>>() + positions.capacity() * mem::size_of::<usize>()
+            }).sum::<usize>()
+    }
+}
+
+#[cfg(feature = "simd")]
+struct LiteralBackend {
+    /// The searchers for case-sensitive and caseless strings.
+    searchers: Vec<LiteralSearcher>,
+    /// The rules whose URL filters are plain strings found by the searchers.
+    literal: Vec<bool>,
+    /// The rules whose URL filters also match the redirect chain.
+    redirect_chain: Vec<bool>,
+    /// Finds the candidates among the other rules.
+    others: IndexedBackend,
+}
+
+#[cfg(feature = "simd")]
+impl LiteralBackend {
+    fn new(rules: &[Rule]) -> LiteralBackend {
+        let mut sensitive = HashMap::new();
+        let mut caseless = HashMap::new();
+        for (position, rule) in rules.iter().enumerate() {
+            match plain_literal(rule.trigger.url_filter.as_str()) {
+                Some((literal, false)) => sensitive.entry(literal).or_insert_with(Vec::new).push(position),
+                // The searcher only folds the case of ASCII letters.
+                Some((literal, true)) if literal.is_ascii() => {
+                    caseless.entry(literal).or_insert_with(Vec::new).push(position)
+                }
+                _ => {}
+            }
+        }
+        let searchers: Vec<LiteralSearcher> =
+            LiteralSearcher::new(sensitive, false).into_iter().chain(LiteralSearcher::new(caseless, true)).collect();
+        let mut literal = vec![false; rules.len()];
+        for searcher in &searchers {
+            for &position in searcher.positions.iter().flatten() {
+                literal[position] = true;
+            }
+        }
+        LiteralBackend {
+            searchers,
+            literal,
+            redirect_chain: rules.iter().map(|rule| rule.trigger.match_redirect_chain).collect(),
+            others: IndexedBackend::new(rules),
+        }
+    }
+}
+
+#[cfg(feature = "simd")]
+impl MatchBackend for LiteralBackend {
+    fn candidates_into(&self, request: &Request, candidates: &mut Vec<usize>) {
+        self.others.candidates_into(request, candidates);
+        candidates.retain(|&position| !self.literal[position]);
+        for searcher in &self.searchers {
+            candidates.extend(searcher.find(request.url.as_str()));
+            for url in request.redirect_chain {
+                candidates.extend(searcher.find(url.as_str()).filter(|&position| self.redirect_chain[position]));
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+    }
+
+    fn heap_size(&self) -> usize {
+        self.searchers.iter().map(LiteralSearcher::heap_size).sum::<usize>() + self.literal.capacity() +
+            self.redirect_chain.capacity() + self.others.heap_size()
+    }
+
+    fn fast_path_rules(&self) -> usize {
+        self.literal.iter().filter(|&&literal| literal).count()
+    }
+}
@@ -0,0 +1,148 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Replays a corpus of requests against a content blocker list and reports
+//! how quickly the list can be evaluated.
+//!
+//! Usage: `content-blocker-bench <list.json> <corpus.txt> [iterations]`
+//!
+//! Each non-empty line of the corpus that does not start with `#` contains a
+//! URL, a resource type and a load type separated by whitespace, using the
+//! same keywords as the JSON list format (eg. `https://ads.example/a.js script
+//! third-party`).
+
+extern crate content_blocker;
+extern crate url;
+
+use content_blocker::{OwnedRequest, Profile};
+use content_blocker::{parse_list, process_rules_for_request, process_rules_for_request_profiled};
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+use std::time::{Duration, Instant};
+use url::Url;
+
+fn read_file(path: &str) -> String {
+    let mut body = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut body)) {
+        fail(&format!("couldn't read {}: {}", path, e));
+    }
+    body
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("content-blocker-bench: {}", message);
+    process::exit(1);
+}
+
+fn parse_corpus(body: &str) -> Vec<OwnedRequest> {
+    let mut samples = vec![];
+    for (number, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 {
+            fail(&format!("corpus line {}: expected `url resource-type load-type`", number + 1));
+        }
+        let url = match Url::parse(fields[0]) {
+            Ok(url) => url,
+            Err(e) => fail(&format!("corpus line {}: invalid url: {}", number + 1, e)),
+        };
+        let resource_type = match fields[1].parse() {
+            Ok(resource_type) => resource_type,
+            Err(()) => fail(&format!("corpus line {}: unknown resource type `{}`",
+                                     number + 1, fields[1])),
+        };
+        let load_type = match fields[2].parse() {
+            Ok(load_type) => load_type,
+            Err(()) => fail(&format!("corpus line {}: unknown load type `{}`",
+                                     number + 1, fields[2])),
+        };
+        samples.push(OwnedRequest {
+            url: url.into_string(),
+            resource_type,
+            load_type,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: vec![],
+            headers: None,
+        });
+    }
+    samples
+}
+
+fn as_micros(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1e6 + duration.subsec_nanos() as f64 / 1e3
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 || args.len() > 4 {
+        fail("usage: content-blocker-bench <list.json> <corpus.txt> [iterations]");
+    }
+    let iterations = match args.get(3).map(|n| n.parse::<usize>()) {
+        None => 1,
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => fail("iterations must be a positive integer"),
+    };
+
+    let list_body = read_file(&args[1]);
+    let start = Instant::now();
+    let rules = match parse_list(&list_body) {
+        Ok(rules) => rules,
+        Err(e) => fail(&format!("couldn't parse list: {}", e)),
+    };
+    let parse_time = start.elapsed();
+
+    let samples = parse_corpus(&read_file(&args[2]));
+    if samples.is_empty() {
+        fail("corpus contains no requests");
+    }
+
+    let mut latencies = Vec::with_capacity(samples.len() * iterations);
+    let mut matched = 0;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        for sample in &samples {
+            let request_start = Instant::now();
+            let reactions = process_rules_for_request(&rules, &sample.as_request());
+            latencies.push(request_start.elapsed());
+            if !reactions.is_empty() {
+                matched += 1;
+            }
+        }
+    }
+    let total = start.elapsed();
+    latencies.sort();
+    let p50 = latencies[latencies.len() / 2];
+    let p99 = latencies[(latencies.len() * 99 / 100).min(latencies.len() - 1)];
+
+    println!("list parsed in {:.1}ms", as_micros(parse_time) / 1e3);
+    println!("{} requests ({} samples x {} iterations) in {:.1}ms",
+             latencies.len(), samples.len(), iterations, as_micros(total) / 1e3);
+    println!("throughput: {:.0} requests/s", latencies.len() as f64 / (as_micros(total) / 1e6));
+    println!("latency: p50 {:.2}us, p99 {:.2}us, max {:.2}us",
+             as_micros(p50), as_micros(p99), as_micros(latencies[latencies.len() - 1]));
+    println!("requests with reactions: {}", matched / iterations);
+    println!("rules never matched: {} of {}", rules.coverage(&samples).never_matched().count(), rules.len());
+
+    let mut profile = Profile::new();
+    for _ in 0..iterations {
+        for sample in &samples {
+            process_rules_for_request_profiled(&rules, &sample.as_request(), &mut profile);
+        }
+    }
+    let rule_total = as_micros(profile.total_time());
+
+    println!("per-rule time share (rule index: share of total, url-filter):");
+    for timing in profile.slowest(&rules, 20) {
+        let share = if rule_total > 0. { as_micros(timing.regex_time) / rule_total * 100. } else { 0. };
+        println!("  {:>6}: {:>5.1}% ({:.1}us) {}",
+                 timing.index, share, as_micros(timing.regex_time), timing.url_filter);
+    }
+}
@@ -0,0 +1,63 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Checks the matching backends of this crate against files of conformance
+//! test vectors, such as `conformance/webkit.json`, and reports every request
+//! whose reactions differ from those expected.
+//!
+//! Usage: `content-blocker-conformance <vectors.json>...`
+
+extern crate content_blocker;
+
+use content_blocker::{Backend, CompiledBackend, RuleListBackend, run_conformance_vectors};
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+
+fn read_file(path: &str) -> String {
+    let mut body = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut body)) {
+        fail(&format!("couldn't read {}: {}", path, e));
+    }
+    body
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("content-blocker-conformance: {}", message);
+    process::exit(1);
+}
+
+fn check<B: Backend>(name: &str, backend: &B, path: &str, body: &str) -> bool {
+    let report = match run_conformance_vectors(backend, body) {
+        Ok(report) => report,
+        Err(e) => fail(&format!("{}: invalid test vectors: {}", path, e)),
+    };
+    for failure in &report.failures {
+        println!("FAIL {} {}: {} ({})", name, path, failure.vector, failure.url);
+        println!("  expected: {:?}", failure.expected);
+        match failure.actual {
+            Ok(ref actual) => println!("  actual:   {:?}", actual),
+            Err(ref e) => println!("  error:    {}", e),
+        }
+    }
+    println!("{} {}: {} passed, {} failed", name, path, report.passed, report.failures.len());
+    report.is_success()
+}
+
+fn main() {
+    let paths: Vec<String> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        fail("usage: content-blocker-conformance <vectors.json>...");
+    }
+    let mut success = true;
+    for path in &paths {
+        let body = read_file(path);
+        success &= check("rule-list", &RuleListBackend, path, &body);
+        success &= check("compiled", &CompiledBackend, path, &body);
+    }
+    if !success {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,135 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A directory of lists compiled at runtime, so that lists downloaded by the
+//! embedder are only parsed the first time they are loaded:
+//!
+//! ```ignore
+//! let cache = content_blocker::ListCache::new(profile_dir.join("lists"));
+//! let list = cache.load(&body, &ParseOptions::default())?;
+//! let reactions = process_rules_for_request_compiled(&list.compiled(), &request);
+//! ```
+//!
+//! Each entry is named after a key combining the list body, the version of
+//! this library and the parse options, so a list is compiled again whenever
+//! any of them changes, and entries that no longer match are never read.
+
+use compiled::{CompiledRuleList, VERSION, compile_impl};
+use md5;
+use parse::{Error, ParseOptions, parse_list_with_options_impl};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts the temporary files written by this process, to name them apart.
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// The extension of cache entries.
+const EXTENSION: &str = "rules";
+
+/// The name of the entry for `body` parsed with `options`: the MD5 digest of
+/// everything the compiled bytes depend on.
+fn cache_key(body: &str, options: &ParseOptions) -> String {
+    let mut context = md5::Context::new();
+    context.consume(env!("CARGO_PKG_VERSION").as_bytes());
+    context.consume(VERSION.to_le_bytes());
+    context.consume(format!("{:?}", options).as_bytes());
+    context.consume(body.as_bytes());
+    let mut key = String::with_capacity(32);
+    for byte in context.compute().iter() {
+        let _ = write!(key, "{:02x}", byte);
+    }
+    key
+}
+
+/// Compiled lists stored in a directory, keyed by their contents.
+#[derive(Clone, Debug)]
+pub struct ListCache {
+    dir: PathBuf,
+}
+
+/// A list loaded through a `ListCache`, in the compiled format.
+#[derive(Clone, Debug)]
+pub struct CachedList {
+    bytes: Vec<u8>,
+    from_cache: bool,
+}
+
+impl CachedList {
+    /// The list, evaluated in place.
+    pub fn compiled(&self) -> CompiledRuleList<'_> {
+        CompiledRuleList::from_bytes(&self.bytes).expect("cache entries are checked when they are loaded")
+    }
+
+    /// The list in the format of `RuleList::to_bytes`.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Whether the list was read from the cache, rather than parsed.
+    pub fn from_cache(&self) -> bool {
+        self.from_cache
+    }
+}
+
+impl ListCache {
+    /// Use the directory `dir` for the cache, creating it when the first entry
+    /// is stored.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> ListCache {
+        ListCache { dir: dir.into() }
+    }
+
+    /// The directory of the cache.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn entry(&self, key: &str) -> PathBuf {
+        self.dir.join(key).with_extension(EXTENSION)
+    }
+
+    /// The list of `body` parsed with `options`, read from the cache if it was
+    /// compiled before. Otherwise, or if the entry is damaged, the list is
+    /// parsed, compiled and stored for the next time. Failing to store it does
+    /// not fail the load, since the list is usable either way.
+    pub fn load(&self, body: &str, options: &ParseOptions) -> Result<CachedList, Error> {
+        let entry = self.entry(&cache_key(body, options));
+        if let Ok(bytes) = fs::read(&entry) {
+            if CompiledRuleList::from_bytes(&bytes).is_ok() {
+                return Ok(CachedList { bytes, from_cache: true });
+            }
+        }
+        let bytes = compile_impl(&parse_list_with_options_impl(body, options)?.rules);
+        // Write to a temporary file first, so that a concurrent load never reads
+        // a partial entry. Each writer, in this process or another, has its own
+        // temporary file, so that concurrent writers do not interleave.
+        let count = TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed);
+        let temporary = entry.with_extension(format!("{}.{}.tmp", process::id(), count));
+        let stored = fs::create_dir_all(&self.dir)
+            .and_then(|()| fs::write(&temporary, &bytes))
+            .and_then(|()| fs::rename(&temporary, &entry));
+        if stored.is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+        Ok(CachedList { bytes, from_cache: false })
+    }
+
+    /// Remove every entry of the cache, eg. to reclaim the space of lists that
+    /// are no longer used.
+    pub fn clear(&self) -> Result<(), Error> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(()),
+        };
+        for entry in entries {
+            let path = entry.map_err(|e| Error::Io(e.kind()))?.path();
+            if path.extension().is_some_and(|extension| extension == EXTENSION) {
+                fs::remove_file(&path).map_err(|e| Error::Io(e.kind()))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,655 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A binary format for parsed lists that can be evaluated in place.
+//!
+//! The format contains no pointers, only offsets relative to the start of its
+//! sections, and every integer is a little-endian `u32` read without any
+//! alignment requirement. A compiled list can therefore be written to disk once
+//! and memory-mapped read-only by any number of processes, each of which
+//! evaluates requests directly against the shared bytes.
+//!
+//! ```text
+//! header:   magic "CBRL", version, rule count, domain count, string table length
+//! rules:    RECORD_WORDS words per rule (see `Field`)
+//! domains:  (offset, length) of each domain, context, header name, header
+//!           value pattern and path or query filter in the string table, with
+//!           the domains of each list sorted
+//! strings:  UTF-8 bytes of every URL filter, selector, domain, context, header
+//!           condition and path or query filter
+//! ```
+//!
+//! Only the layout is checked when a compiled list is loaded; URL filters and
+//! selectors are decoded and compiled the first time a rule needs them.
+
+use html::HtmlFilter;
+use parse::Error;
+use procedural::ProceduralSelector;
+use psl::wildcard_tld;
+use regex::Regex;
+use repr::{Action, DomainConstraint, DomainMatcher, DomainTarget, LoadType, Reaction, ReactionRef, Reactions, Request};
+use repr::{CookieScope, ResourceType, ResourceTypeList, Rule, UrlFilterAnchor, host_anchor_matches};
+use request_url::{RequestHeaders, RequestUrl};
+use resources::Resource;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::str;
+use std::sync::OnceLock;
+
+const MAGIC: &[u8; 4] = b"CBRL";
+pub const VERSION: u32 = 9;
+const HEADER_LEN: usize = 20;
+
+/// The words of each rule record, in order.
+#[derive(Copy, Clone)]
+enum Field {
+    UrlFilterOffset,
+    UrlFilterLen,
+    /// A bitmask of `ResourceType::ALL`, or `ALL_TYPES` for no restriction.
+    ResourceTypes,
+    /// A bitmask of `LoadType::ALL`, or 0 for any load type.
+    LoadType,
+    /// 0 for no constraint, 1 for `if-domain` and 2 for `unless-domain`, or 3 and
+    /// 4 when they are evaluated against the top-level document.
+    DomainConstraint,
+    ExactStart,
+    ExactCount,
+    SubdomainStart,
+    SubdomainCount,
+    /// 0 to 4 for block, block-cookies, css-display-none, ignore-previous-rules
+    /// and css-display-none-exception, 5 for css-display-none with a procedural
+    /// selector, which is parsed again whenever it is needed, 6 for css-inject and
+    /// 7 for redirect, whose resource name is stored as the selector. Cookie
+    /// blocking uses 1 for requests, 8 for responses and 9 for both, or 10 when
+    /// limited to some cookie names, which are stored as the selector separated
+    /// by newlines. 11 is for html-filter, parsed again like procedural selectors,
+    /// and 12 for block-inline-scripts.
+    Action,
+    SelectorOffset,
+    SelectorLen,
+    StyleOffset,
+    StyleLen,
+    /// The contexts of the rule, stored in the domain table.
+    ContextStart,
+    /// 0 if the rule applies in every context, otherwise one more than the
+    /// number of contexts it is limited to.
+    ContextCount,
+    /// 0 for any request, 1 for popups opened by a user action and 2 for other popups.
+    UserInitiated,
+    /// 0 for no constraint, 1 if the opener must match the following domains.
+    OpenerDomain,
+    OpenerExactStart,
+    OpenerExactCount,
+    OpenerSubdomainStart,
+    OpenerSubdomainCount,
+    /// Bit 1 if the domain lists contain wildcard-TLD entries such as `example.*`,
+    /// and bit 2 if the opener lists do.
+    WildcardTld,
+    /// 1 if the URL filter also matches the URLs of the redirect chain.
+    MatchRedirectChain,
+    /// The header conditions of the rule, stored in the domain table as the
+    /// name of each header followed by the pattern for its value, which is
+    /// empty for any value. The `if-header` conditions come first.
+    HeaderStart,
+    IfHeaderCount,
+    UnlessHeaderCount,
+    /// The position of the rule's first header condition among those of every
+    /// rule, which locates the compiled value patterns of its conditions.
+    HeaderIndex,
+    /// 1 if the URL filter is a host name compared with the host of the URL.
+    UrlFilterAnchor,
+    /// The path and query filters of the rule, stored in the domain table in
+    /// that order.
+    ComponentStart,
+    /// Bit 1 if the rule has a path filter, and bit 2 if it has a query filter.
+    ComponentFilters,
+    /// The position of the rule's first path or query filter among those of
+    /// every rule, which locates their compiled patterns.
+    ComponentIndex,
+}
+
+const RECORD_WORDS: usize = 32;
+
+/// The fields locating the exact and subdomain lists of a domain matcher, and
+/// its bit of `Field::WildcardTld`.
+type MatcherFields = ([Field; 4], u32);
+const DOMAIN_FIELDS: MatcherFields = ([Field::ExactStart, Field::ExactCount,
+                                       Field::SubdomainStart, Field::SubdomainCount], 1);
+const OPENER_FIELDS: MatcherFields = ([Field::OpenerExactStart, Field::OpenerExactCount,
+                                       Field::OpenerSubdomainStart, Field::OpenerSubdomainCount], 2);
+const RECORD_LEN: usize = RECORD_WORDS * 4;
+const ALL_TYPES: u32 = u32::MAX;
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let b = &bytes[offset..offset + 4];
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// Accumulates the string table, sharing repeated strings such as common domains.
+#[derive(Default)]
+struct StringTable<'a> {
+    bytes: Vec<u8>,
+    offsets: HashMap<&'a str, u32>,
+}
+
+impl<'a> StringTable<'a> {
+    fn add(&mut self, s: &'a str) -> [u32; 2] {
+        let bytes = &mut self.bytes;
+        let offset = *self.offsets.entry(s).or_insert_with(|| {
+            let offset = bytes.len() as u32;
+            bytes.extend_from_slice(s.as_bytes());
+            offset
+        });
+        [offset, s.len() as u32]
+    }
+}
+
+/// Append the domains of `matcher` to the domain table, recording their location in `fields`.
+fn add_matcher<'a>(record: &mut [u32; RECORD_WORDS], fields: MatcherFields, matcher: &'a DomainMatcher,
+                   domains: &mut Vec<[u32; 2]>, strings: &mut StringTable<'a>) {
+    let ([exact_start, exact_count, subdomain_start, subdomain_count], wildcard_bit) = fields;
+    if matcher.wildcard_tld {
+        record[Field::WildcardTld as usize] |= wildcard_bit;
+    }
+    record[exact_start as usize] = domains.len() as u32;
+    record[exact_count as usize] = matcher.exact.len() as u32;
+    domains.extend(matcher.exact.iter().map(|d| strings.add(d)));
+    record[subdomain_start as usize] = domains.len() as u32;
+    record[subdomain_count as usize] = matcher.subdomain.len() as u32;
+    domains.extend(matcher.subdomain.iter().map(|d| strings.add(d)));
+}
+
+/// Serialize `rules` into the compiled format.
+pub fn compile_impl(rules: &[Rule]) -> Vec<u8> {
+    let mut strings = StringTable::default();
+    let mut domains: Vec<[u32; 2]> = vec![];
+    let mut records: Vec<[u32; RECORD_WORDS]> = Vec::with_capacity(rules.len());
+    let mut header_conditions = 0;
+    let mut component_filters = 0;
+
+    // The string table borrows its strings, so joined cookie names must outlive it.
+    let joined_names: Vec<Option<String>> = rules.iter().map(|rule| match rule.action {
+        Action::BlockCookiesMatching(ref names) => Some(names.join("\n")),
+        _ => None,
+    }).collect();
+    for (rule, cookie_names) in rules.iter().zip(&joined_names) {
+        let mut record = [0; RECORD_WORDS];
+        let [offset, len] = strings.add(rule.trigger.url_filter.as_str());
+        record[Field::UrlFilterOffset as usize] = offset;
+        record[Field::UrlFilterLen as usize] = len;
+        record[Field::ResourceTypes as usize] = match rule.trigger.resource_type {
+            ResourceTypeList::All => ALL_TYPES,
+            ResourceTypeList::List(ref types) => types.iter().fold(0, |mask, t| mask | type_bit(*t)),
+        };
+        record[Field::LoadType as usize] = rule.trigger.load_type.mask();
+        let matcher = match rule.trigger.domain_constraint {
+            None => None,
+            Some(DomainConstraint::If(ref matcher)) => {
+                record[Field::DomainConstraint as usize] = 1;
+                Some(matcher)
+            }
+            Some(DomainConstraint::Unless(ref matcher)) => {
+                record[Field::DomainConstraint as usize] = 2;
+                Some(matcher)
+            }
+        };
+        if let Some(matcher) = matcher {
+            add_matcher(&mut record, DOMAIN_FIELDS, matcher, &mut domains, &mut strings);
+            if rule.trigger.domain_target == DomainTarget::TopDocument {
+                record[Field::DomainConstraint as usize] += 2;
+            }
+        }
+        record[Field::UserInitiated as usize] = match rule.trigger.user_initiated {
+            None => 0,
+            Some(true) => 1,
+            Some(false) => 2,
+        };
+        record[Field::MatchRedirectChain as usize] = rule.trigger.match_redirect_chain as u32;
+        record[Field::UrlFilterAnchor as usize] = (rule.trigger.url_filter_anchor == UrlFilterAnchor::Host) as u32;
+        if let Some(ref matcher) = rule.trigger.opener_domain {
+            record[Field::OpenerDomain as usize] = 1;
+            add_matcher(&mut record, OPENER_FIELDS, matcher, &mut domains, &mut strings);
+        }
+        let (if_header, unless_header) = (&rule.trigger.if_header, &rule.trigger.unless_header);
+        record[Field::HeaderStart as usize] = domains.len() as u32;
+        record[Field::IfHeaderCount as usize] = if_header.len() as u32;
+        record[Field::UnlessHeaderCount as usize] = unless_header.len() as u32;
+        record[Field::HeaderIndex as usize] = header_conditions;
+        header_conditions += (if_header.len() + unless_header.len()) as u32;
+        for condition in if_header.iter().chain(unless_header.iter()) {
+            domains.push(strings.add(&condition.name));
+            domains.push(strings.add(condition.value.as_ref().map_or("", |value| value.as_str())));
+        }
+        record[Field::ComponentStart as usize] = domains.len() as u32;
+        record[Field::ComponentIndex as usize] = component_filters;
+        for (bit, filter) in [(1, &rule.trigger.path_filter), (2, &rule.trigger.query_filter)] {
+            if let Some(ref filter) = *filter {
+                record[Field::ComponentFilters as usize] |= bit;
+                domains.push(strings.add(filter.as_str()));
+                component_filters += 1;
+            }
+        }
+        if let Some(ref contexts) = rule.trigger.contexts {
+            record[Field::ContextStart as usize] = domains.len() as u32;
+            record[Field::ContextCount as usize] = contexts.len() as u32 + 1;
+            domains.extend(contexts.iter().map(|c| strings.add(c)));
+        }
+        let (action, selector, style): (u32, Option<&str>, Option<&str>) = match rule.action {
+            Action::Block => (0, None, None),
+            Action::BlockCookies(CookieScope::Request) => (1, None, None),
+            Action::BlockCookies(CookieScope::Response) => (8, None, None),
+            Action::BlockCookies(CookieScope::Both) => (9, None, None),
+            Action::BlockCookiesMatching(_) => (10, cookie_names.as_deref(), None),
+            Action::CssDisplayNone(ref selector) => (2, Some(selector), None),
+            Action::IgnorePreviousRules => (3, None, None),
+            Action::CssDisplayNoneException(ref selector) => (4, Some(selector), None),
+            Action::CssDisplayNoneProcedural(ref selector) => (5, Some(&selector.source), None),
+            Action::CssInject { ref selector, ref style } => (6, Some(selector), Some(style)),
+            Action::Redirect(resource) => (7, Some(resource.name), None),
+            Action::HtmlFilter(ref filter) => (11, Some(&filter.source), None),
+            Action::BlockInlineScripts => (12, None, None),
+        };
+        record[Field::Action as usize] = action;
+        if let Some(selector) = selector {
+            let [offset, len] = strings.add(selector);
+            record[Field::SelectorOffset as usize] = offset;
+            record[Field::SelectorLen as usize] = len;
+        }
+        if let Some(style) = style {
+            let [offset, len] = strings.add(style);
+            record[Field::StyleOffset as usize] = offset;
+            record[Field::StyleLen as usize] = len;
+        }
+        records.push(record);
+    }
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + records.len() * RECORD_LEN +
+                                       domains.len() * 8 + strings.bytes.len());
+    bytes.extend_from_slice(MAGIC);
+    for word in &[VERSION, records.len() as u32, domains.len() as u32, strings.bytes.len() as u32] {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    for word in records.iter().flat_map(|r| r.iter()).chain(domains.iter().flat_map(|d| d.iter())) {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    bytes.extend_from_slice(&strings.bytes);
+    bytes
+}
+
+fn type_bit(resource_type: ResourceType) -> u32 {
+    1 << ResourceType::ALL.iter().position(|t| *t == resource_type).unwrap()
+}
+
+/// A list of rules in the compiled binary format, evaluated directly against
+/// borrowed bytes such as a shared memory mapping. See `RuleList::to_bytes`.
+pub struct CompiledRuleList<'a> {
+    records: &'a [u8],
+    domains: &'a [u8],
+    strings: &'a [u8],
+    /// The URL filters compiled so far; `None` if the filter is not valid.
+    filters: Box<[OnceLock<Option<Regex>>]>,
+    /// The value patterns of the header conditions compiled so far, indexed
+    /// like `Field::HeaderIndex`.
+    header_filters: Box<[OnceLock<Option<Regex>>]>,
+    /// The path and query filters compiled so far, indexed like
+    /// `Field::ComponentIndex`.
+    component_filters: Box<[OnceLock<Option<Regex>>]>,
+}
+
+impl<'a> CompiledRuleList<'a> {
+    /// Use `bytes` produced by `RuleList::to_bytes` as a list of rules, without
+    /// copying them. Fails with `Error::InvalidCompiledList` if the bytes were
+    /// not produced by a compatible version of this library or are truncated.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<CompiledRuleList<'a>, Error> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC || read_u32(bytes, 4) != VERSION {
+            return Err(Error::InvalidCompiledList);
+        }
+        let rule_count = read_u32(bytes, 8) as usize;
+        let domain_count = read_u32(bytes, 12) as usize;
+        let strings_len = read_u32(bytes, 16) as usize;
+        let records_end = rule_count.checked_mul(RECORD_LEN).and_then(|n| n.checked_add(HEADER_LEN));
+        let domains_end = domain_count.checked_mul(8).and_then(|n| records_end?.checked_add(n));
+        match domains_end.and_then(|n| n.checked_add(strings_len)) {
+            Some(len) if len == bytes.len() => {}
+            _ => return Err(Error::InvalidCompiledList),
+        }
+        let (records_end, domains_end) = (records_end.unwrap(), domains_end.unwrap());
+        let mut list = CompiledRuleList {
+            records: &bytes[HEADER_LEN..records_end],
+            domains: &bytes[records_end..domains_end],
+            strings: &bytes[domains_end..],
+            filters: (0..rule_count).map(|_| OnceLock::new()).collect(),
+            header_filters: Box::default(),
+            component_filters: Box::default(),
+        };
+
+        // Check that every offset stays within its section, so that evaluating
+        // the list never reads out of bounds.
+        let string_in_bounds = |offset: u32, len: u32| {
+            (offset as usize).checked_add(len as usize).is_some_and(|end| end <= strings_len)
+        };
+        let mut header_conditions = 0usize;
+        let mut component_filters = 0usize;
+        for rule in 0..rule_count {
+            let field = |f| list.field(rule, f);
+            let domains_in_bounds = |start: u32, count: u32| {
+                (start as usize).checked_add(count as usize).is_some_and(|end| end <= domain_count)
+            };
+            let header_count =
+                (field(Field::IfHeaderCount) as usize).checked_add(field(Field::UnlessHeaderCount) as usize);
+            let headers_in_bounds =
+                header_count.and_then(|count| count.checked_mul(2))
+                            .and_then(|entries| (field(Field::HeaderStart) as usize).checked_add(entries))
+                            .is_some_and(|end| end <= domain_count);
+            if !string_in_bounds(field(Field::UrlFilterOffset), field(Field::UrlFilterLen)) ||
+               !string_in_bounds(field(Field::SelectorOffset), field(Field::SelectorLen)) ||
+               !string_in_bounds(field(Field::StyleOffset), field(Field::StyleLen)) ||
+               !domains_in_bounds(field(Field::ExactStart), field(Field::ExactCount)) ||
+               !domains_in_bounds(field(Field::SubdomainStart), field(Field::SubdomainCount)) ||
+               !domains_in_bounds(field(Field::OpenerExactStart), field(Field::OpenerExactCount)) ||
+               !domains_in_bounds(field(Field::OpenerSubdomainStart), field(Field::OpenerSubdomainCount)) ||
+               field(Field::UserInitiated) > 2 || field(Field::OpenerDomain) > 1 || field(Field::WildcardTld) > 3 ||
+               field(Field::MatchRedirectChain) > 1 || field(Field::UrlFilterAnchor) > 1 ||
+               field(Field::ComponentFilters) > 3 ||
+               !domains_in_bounds(field(Field::ComponentStart), field(Field::ComponentFilters).count_ones()) ||
+               field(Field::ComponentIndex) as usize != component_filters ||
+               !headers_in_bounds ||
+               field(Field::HeaderIndex) as usize != header_conditions ||
+               !domains_in_bounds(field(Field::ContextStart), field(Field::ContextCount).saturating_sub(1)) ||
+               field(Field::LoadType) >> LoadType::ALL.len() != 0 || field(Field::DomainConstraint) > 4 ||
+               field(Field::Action) > 12 {
+                return Err(Error::InvalidCompiledList);
+            }
+            // The entries of the conditions are in bounds, so their count is small.
+            header_conditions += header_count.unwrap_or(0);
+            component_filters += field(Field::ComponentFilters).count_ones() as usize;
+        }
+        list.header_filters = (0..header_conditions).map(|_| OnceLock::new()).collect();
+        list.component_filters = (0..component_filters).map(|_| OnceLock::new()).collect();
+        for domain in 0..domain_count {
+            if !string_in_bounds(read_u32(list.domains, domain * 8), read_u32(list.domains, domain * 8 + 4)) {
+                return Err(Error::InvalidCompiledList);
+            }
+        }
+        Ok(list)
+    }
+
+    /// The number of rules in this list.
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// Whether this list contains no rules.
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    fn field(&self, rule: usize, field: Field) -> u32 {
+        read_u32(self.records, rule * RECORD_LEN + field as usize * 4)
+    }
+
+    fn string(&self, offset: u32, len: u32) -> &'a [u8] {
+        &self.strings[offset as usize..offset as usize + len as usize]
+    }
+
+    fn domain(&self, index: usize) -> &'a [u8] {
+        self.string(read_u32(self.domains, index * 8), read_u32(self.domains, index * 8 + 4))
+    }
+
+    /// Binary search the domain list of `rule` beginning at `start` for `domain`.
+    fn contains_domain(&self, rule: usize, start: Field, count: Field, domain: &[u8]) -> bool {
+        let start = self.field(rule, start) as usize;
+        let (mut low, mut high) = (start, start + self.field(rule, count) as usize);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.domain(middle).cmp(domain) {
+                Ordering::Equal => return true,
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+            }
+        }
+        false
+    }
+
+    /// The compiled equivalent of `DomainMatcher::matches_domain`.
+    fn matches_domain(&self, rule: usize, fields: MatcherFields, domain: &str) -> bool {
+        self.contains_matching_domain(rule, fields, domain) ||
+            self.field(rule, Field::WildcardTld) & fields.1 != 0 &&
+            wildcard_tld(domain).is_some_and(|domain| self.contains_matching_domain(rule, fields, &domain))
+    }
+
+    fn contains_matching_domain(&self, rule: usize, fields: MatcherFields, domain: &str) -> bool {
+        let ([exact_start, exact_count, subdomain_start, subdomain_count], _) = fields;
+        if self.contains_domain(rule, exact_start, exact_count, domain.as_bytes()) {
+            return true;
+        }
+        let mut suffix = domain;
+        loop {
+            if self.contains_domain(rule, subdomain_start, subdomain_count, suffix.as_bytes()) {
+                return true;
+            }
+            match suffix.find('.') {
+                Some(dot) => suffix = &suffix[dot + 1..],
+                None => return false,
+            }
+        }
+    }
+
+    fn url_filter_source(&self, rule: usize) -> Option<&str> {
+        let source = self.string(self.field(rule, Field::UrlFilterOffset), self.field(rule, Field::UrlFilterLen));
+        str::from_utf8(source).ok()
+    }
+
+    fn url_filter(&self, rule: usize) -> Option<&Regex> {
+        self.filters[rule].get_or_init(|| {
+            self.url_filter_source(rule).and_then(|source| Regex::new(source).ok())
+        }).as_ref()
+    }
+
+    /// Whether `headers` satisfy the header condition at position `condition`
+    /// among those of `rule`.
+    fn matches_header(&self, rule: usize, condition: usize, headers: &dyn RequestHeaders) -> bool {
+        let entry = self.field(rule, Field::HeaderStart) as usize + 2 * condition;
+        let value = match str::from_utf8(self.domain(entry)).ok().and_then(|name| headers.get(name)) {
+            Some(value) => value,
+            None => return false,
+        };
+        if self.domain(entry + 1).is_empty() {
+            return true;
+        }
+        self.header_filter(rule, condition).is_some_and(|regex| regex.is_match(value))
+    }
+
+    /// The value pattern of the header condition at position `condition` among
+    /// those of `rule`, which must have one.
+    fn header_filter(&self, rule: usize, condition: usize) -> Option<&Regex> {
+        let pattern = self.domain(self.field(rule, Field::HeaderStart) as usize + 2 * condition + 1);
+        let index = self.field(rule, Field::HeaderIndex) as usize + condition;
+        self.header_filters[index].get_or_init(|| {
+            str::from_utf8(pattern).ok().and_then(|pattern| Regex::new(pattern).ok())
+        }).as_ref()
+    }
+
+    /// Whether the path and query filters of `rule`, if any, match `url`.
+    fn matches_components(&self, rule: usize, url: &dyn RequestUrl) -> bool {
+        let filters = self.field(rule, Field::ComponentFilters);
+        if filters == 0 {
+            return true;
+        }
+        let (path, query) = url.path_and_query();
+        let components = [(1, Some(path)), (2, query)];
+        components.iter().filter(|&&(bit, _)| filters & bit != 0).enumerate().all(|(filter, &(_, text))| {
+            text.is_some_and(|text| self.component_filter(rule, filter).is_some_and(|regex| regex.is_match(text)))
+        })
+    }
+
+    /// The compiled pattern of the path or query filter at position `filter`
+    /// among those of `rule`.
+    fn component_filter(&self, rule: usize, filter: usize) -> Option<&Regex> {
+        let pattern = self.domain(self.field(rule, Field::ComponentStart) as usize + filter);
+        let index = self.field(rule, Field::ComponentIndex) as usize + filter;
+        self.component_filters[index].get_or_init(|| {
+            str::from_utf8(pattern).ok().and_then(|pattern| Regex::new(pattern).ok())
+        }).as_ref()
+    }
+
+    /// Compile the regexes of every rule, which are otherwise compiled the first
+    /// time a request reaches them.
+    pub fn warm_up(&self) {
+        for rule in 0..self.len() {
+            if self.field(rule, Field::UrlFilterAnchor) == 0 {
+                self.url_filter(rule);
+            }
+            let conditions = self.field(rule, Field::IfHeaderCount) + self.field(rule, Field::UnlessHeaderCount);
+            for condition in 0..conditions as usize {
+                let entry = self.field(rule, Field::HeaderStart) as usize + 2 * condition;
+                if !self.domain(entry + 1).is_empty() {
+                    self.header_filter(rule, condition);
+                }
+            }
+            for filter in 0..self.field(rule, Field::ComponentFilters).count_ones() as usize {
+                self.component_filter(rule, filter);
+            }
+        }
+    }
+
+    fn matches(&self, rule: usize, request: &Request) -> bool {
+        let types = self.field(rule, Field::ResourceTypes);
+        if types != ALL_TYPES && types & type_bit(request.resource_type) == 0 {
+            return false;
+        }
+
+        let load_types = self.field(rule, Field::LoadType);
+        if load_types != 0 && !LoadType::mask_matches(load_types, LoadType::of_request(request)) {
+            return false;
+        }
+
+        let user_initiated = self.field(rule, Field::UserInitiated);
+        let opener_domain = self.field(rule, Field::OpenerDomain) == 1;
+        if user_initiated != 0 || opener_domain {
+            let matches = request.opener.is_some_and(|opener| {
+                (user_initiated == 0 || opener.user_initiated == (user_initiated == 1)) &&
+                    (!opener_domain || opener.url.domain().is_some_and(|domain| {
+                        self.matches_domain(rule, OPENER_FIELDS, domain)
+                    }))
+            });
+            if !matches {
+                return false;
+            }
+        }
+
+        let contexts = self.field(rule, Field::ContextCount);
+        if contexts > 0 {
+            let start = self.field(rule, Field::ContextStart) as usize;
+            let matches = request.context.is_some_and(|context| {
+                (start..start + contexts as usize - 1).any(|index| self.domain(index) == context.as_bytes())
+            });
+            if !matches {
+                return false;
+            }
+        }
+
+        let if_header = self.field(rule, Field::IfHeaderCount) as usize;
+        let unless_header = self.field(rule, Field::UnlessHeaderCount) as usize;
+        if if_header + unless_header > 0 {
+            let matches = request.headers.is_some_and(|headers| {
+                let matches = |condition| self.matches_header(rule, condition, headers);
+                (0..if_header).all(matches) && !(if_header..if_header + unless_header).any(matches)
+            });
+            if !matches {
+                return false;
+            }
+        }
+
+        let match_redirect_chain = self.field(rule, Field::MatchRedirectChain) == 1;
+        let matches_url = |url: &dyn RequestUrl| {
+            let matches = if self.field(rule, Field::UrlFilterAnchor) == 1 {
+                self.url_filter_source(rule).is_some_and(|pattern| {
+                    url.host().is_some_and(|host| host_anchor_matches(pattern, host))
+                })
+            } else {
+                self.url_filter(rule).is_some_and(|regex| regex.is_match(url.as_str()))
+            };
+            matches && self.matches_components(rule, url)
+        };
+        let url_matches = matches_url(request.url) ||
+            match_redirect_chain && request.redirect_chain.iter().any(|url| matches_url(url));
+        if !url_matches {
+            return false;
+        }
+
+        let constraint = self.field(rule, Field::DomainConstraint);
+        if constraint == 0 {
+            return true;
+        }
+        let target = match constraint {
+            3 | 4 => request.document.unwrap_or(request.url),
+            _ => request.url,
+        };
+        let matches = target.domain().is_some_and(|domain| self.matches_domain(rule, DOMAIN_FIELDS, domain));
+        matches == (constraint % 2 == 1)
+    }
+}
+
+/// The parts of a matched rule that are decoded again for each request, and
+/// must outlive the reactions borrowing them.
+enum Decoded {
+    Nothing,
+    Procedural(ProceduralSelector),
+    CookieNames(Vec<String>),
+    HtmlFilter(HtmlFilter),
+}
+
+/// The compiled equivalent of `process_rules_for_request_impl`, evaluating every
+/// rule in turn. Cosmetic rules whose selectors are not valid UTF-8 and redirect rules
+/// naming a resource that is not bundled are ignored.
+pub fn process_rules_for_request_compiled_impl(rules: &CompiledRuleList, request: &Request) -> Vec<Reaction> {
+    let matched: Vec<usize> = (0..rules.len()).filter(|&rule| rules.matches(rule, request)).collect();
+    let selector = |rule| str::from_utf8(rules.string(rules.field(rule, Field::SelectorOffset),
+                                                      rules.field(rule, Field::SelectorLen))).ok();
+    let style = |rule| str::from_utf8(rules.string(rules.field(rule, Field::StyleOffset),
+                                                   rules.field(rule, Field::StyleLen))).ok();
+    let decoded: Vec<Decoded> = matched.iter().map(|&rule| {
+        match (rules.field(rule, Field::Action), selector(rule)) {
+            (5, Some(selector)) => ProceduralSelector::parse(selector).map_or(Decoded::Nothing, Decoded::Procedural),
+            (10, Some(names)) => Decoded::CookieNames(names.split('\n').map(str::to_owned).collect()),
+            (11, Some(selector)) => HtmlFilter::parse(selector).map_or(Decoded::Nothing, Decoded::HtmlFilter),
+            _ => Decoded::Nothing,
+        }
+    }).collect();
+
+    let mut reactions = Reactions::default();
+    for (&rule, decoded) in matched.iter().zip(&decoded) {
+        match rules.field(rule, Field::Action) {
+            0 => reactions.push(ReactionRef::Block, rule),
+            1 => reactions.push(ReactionRef::BlockCookies(CookieScope::Request), rule),
+            8 => reactions.push(ReactionRef::BlockCookies(CookieScope::Response), rule),
+            9 => reactions.push(ReactionRef::BlockCookies(CookieScope::Both), rule),
+            12 => reactions.push(ReactionRef::BlockInlineScripts, rule),
+            7 => if let Some(resource) = selector(rule).and_then(Resource::named) {
+                reactions.push(ReactionRef::Redirect(resource), rule);
+            },
+            2 => if let Some(selector) = selector(rule) {
+                reactions.push(ReactionRef::HideMatchingElements(selector), rule);
+            },
+            4 => if let Some(selector) = selector(rule) {
+                reactions.except_hiding(selector);
+            },
+            5 => if let Decoded::Procedural(ref selector) = *decoded {
+                reactions.push(ReactionRef::HideProceduralElements(selector), rule);
+            },
+            10 => if let Decoded::CookieNames(ref names) = *decoded {
+                reactions.push(ReactionRef::BlockCookiesMatching(names), rule);
+            },
+            6 => if let (Some(selector), Some(declarations)) = (selector(rule), style(rule)) {
+                reactions.push(ReactionRef::InjectStyle { selector, declarations }, rule);
+            },
+            11 => if let Decoded::HtmlFilter(ref filter) = *decoded {
+                reactions.push(ReactionRef::FilterHtml(filter), rule);
+            },
+            _ => reactions.clear(),
+        }
+    }
+    reactions.to_vec()
+}
@@ -0,0 +1,309 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Test vectors describing the reactions expected from a list, which any
+//! implementation of the matching semantics can be checked against:
+//!
+//! ```json
+//! [{ "name": "block by substring",
+//!    "list": [{ "trigger": { "url-filter": "webkit.org" }, "action": { "type": "block" } }],
+//!    "requests": [{ "url": "http://webkit.org/", "resource-type": "document",
+//!                   "load-type": "first-party", "reactions": [{ "type": "block" }] }] }]
+//! ```
+//!
+//! Reactions are written like the actions producing them, and must be reported
+//! in the same order. Requests may also give their `context`, `document`,
+//! `redirect-chain`, an array of URLs, and `headers`, an object mapping header
+//! names to values.
+//!
+//! `record_golden_vectors` writes vectors in this format from the reactions a
+//! backend currently reports, so that later versions of the engine can be
+//! checked against them.
+
+use compiled::{CompiledRuleList, process_rules_for_request_compiled_impl};
+use html::HtmlFilter;
+use parse::Error;
+use procedural::{ProceduralSelector, is_procedural};
+use repr::{CookieScope, OwnedRequest, Reaction, Request};
+use resources::Resource;
+use serde_json::{self, Map, Value};
+use {RuleList, parse_list, process_rules_for_request};
+use url::Url;
+
+/// An implementation of the matching semantics whose conformance is tested.
+pub trait Backend {
+    /// A list loaded by this backend.
+    type List;
+
+    /// Load the JSON representation of a list.
+    fn load(&self, body: &str) -> Result<Self::List, Error>;
+
+    /// Evaluate a request against a loaded list.
+    fn process(&self, list: &Self::List, request: &Request) -> Vec<Reaction>;
+}
+
+/// The backend evaluating a `RuleList`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RuleListBackend;
+
+impl Backend for RuleListBackend {
+    type List = RuleList;
+
+    fn load(&self, body: &str) -> Result<RuleList, Error> {
+        parse_list(body)
+    }
+
+    fn process(&self, list: &RuleList, request: &Request) -> Vec<Reaction> {
+        process_rules_for_request(list, request)
+    }
+}
+
+/// The backend evaluating a list in the compiled binary format.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CompiledBackend;
+
+impl Backend for CompiledBackend {
+    type List = Vec<u8>;
+
+    fn load(&self, body: &str) -> Result<Vec<u8>, Error> {
+        let bytes = parse_list(body)?.to_bytes();
+        CompiledRuleList::from_bytes(&bytes)?;
+        Ok(bytes)
+    }
+
+    fn process(&self, list: &Vec<u8>, request: &Request) -> Vec<Reaction> {
+        CompiledRuleList::from_bytes(list)
+            .map(|rules| process_rules_for_request_compiled_impl(&rules, request))
+            .unwrap_or_default()
+    }
+}
+
+/// A request of a test vector whose reactions differed from those expected.
+#[derive(Debug, PartialEq)]
+pub struct ConformanceFailure {
+    /// The name of the test vector.
+    pub vector: String,
+    /// The URL of the request.
+    pub url: String,
+    /// The reactions the request should have.
+    pub expected: Vec<Reaction>,
+    /// The reactions reported by the backend, or the error loading the list.
+    pub actual: Result<Vec<Reaction>, Error>,
+}
+
+/// The outcome of checking a backend against a set of test vectors.
+#[derive(Debug, Default, PartialEq)]
+pub struct ConformanceReport {
+    /// The number of requests with the expected reactions.
+    pub passed: usize,
+    /// The requests with unexpected reactions.
+    pub failures: Vec<ConformanceFailure>,
+}
+
+impl ConformanceReport {
+    /// Whether every request had the expected reactions.
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+struct Case {
+    request: OwnedRequest,
+    reactions: Vec<Reaction>,
+}
+
+struct Vector {
+    name: String,
+    list: String,
+    cases: Vec<Case>,
+}
+
+fn parse_vectors(body: &str) -> Result<Vec<Vector>, Error> {
+    let json: Value = serde_json::from_str(body).map_err(|_| Error::JSON)?;
+    let vectors = json.as_array().ok_or(Error::NotAList)?;
+    vectors.iter().enumerate().map(|(index, vector)| {
+        let missing = |field| Error::MissingField { index, field };
+        let name = vector.get("name").and_then(|n| n.as_str()).ok_or(missing("name"))?;
+        let list = vector.get("list").filter(|l| l.is_array()).ok_or(missing("list"))?;
+        let requests = vector.get("requests").and_then(|r| r.as_array()).ok_or(missing("requests"))?;
+        let cases = requests.iter().map(|request| {
+            let string = |field: &'static str| {
+                request.get(&field["requests.".len()..]).and_then(|v| v.as_str()).ok_or(missing(field))
+            };
+            let reactions = request.get("reactions").and_then(|r| r.as_array())
+                                   .ok_or(missing("requests.reactions"))?;
+            let redirect_chain = match request.get("redirect-chain") {
+                Some(chain) => {
+                    chain.as_array()
+                         .and_then(|chain| chain.iter().map(|url| url.as_str().and_then(|url| Url::parse(url).ok()))
+                                                .collect::<Option<_>>())
+                         .ok_or(missing("requests.redirect-chain"))?
+                }
+                None => vec![],
+            };
+            let headers = match request.get("headers") {
+                Some(headers) => {
+                    let headers = headers.as_object().ok_or(missing("requests.headers"))?;
+                    Some(headers.iter().map(|(name, value)| {
+                        value.as_str().map(|value| (name.clone(), value.to_owned()))
+                    }).collect::<Option<_>>().ok_or(missing("requests.headers"))?)
+                }
+                None => None,
+            };
+            Ok(Case {
+                request: OwnedRequest {
+                    url: string("requests.url")?.to_owned(),
+                    resource_type: string("requests.resource-type")?.parse()
+                        .map_err(|()| missing("requests.resource-type"))?,
+                    load_type: string("requests.load-type")?.parse()
+                        .map_err(|()| missing("requests.load-type"))?,
+                    context: string("requests.context").ok().map(str::to_owned),
+                    opener: None,
+                    document: string("requests.document").ok().map(str::to_owned),
+                    redirect_chain,
+                    headers,
+                },
+                reactions: reactions.iter().map(parse_reaction)
+                                    .collect::<Option<_>>()
+                                    .ok_or(missing("requests.reactions"))?,
+            })
+        }).collect::<Result<_, Error>>()?;
+        Ok(Vector {
+            name: name.to_owned(),
+            list: list.to_string(),
+            cases,
+        })
+    }).collect()
+}
+
+fn parse_reaction(reaction: &Value) -> Option<Reaction> {
+    let string = |field| reaction.get(field).and_then(|v| v.as_str());
+    Some(match string("type")? {
+        "block" => Reaction::Block,
+        "block-cookies" => match reaction.get("names") {
+            Some(names) => Reaction::BlockCookiesMatching(
+                names.as_array()?.iter().map(|n| n.as_str().map(str::to_owned)).collect::<Option<_>>()?),
+            None => Reaction::BlockCookies(string("scope").map_or(Some(CookieScope::Request), |s| s.parse().ok())?),
+        },
+        "redirect" => Reaction::Redirect(Resource::named(string("resource")?)?),
+        "css-display-none" => {
+            let selector = string("selector")?;
+            if is_procedural(selector) {
+                Reaction::HideProceduralElements(ProceduralSelector::parse(selector)?)
+            } else {
+                Reaction::HideMatchingElements(selector.to_owned())
+            }
+        }
+        "css-inject" => Reaction::InjectStyle {
+            selector: string("selector")?.to_owned(),
+            declarations: string("style")?.to_owned(),
+        },
+        "html-filter" => Reaction::FilterHtml(HtmlFilter::parse(string("selector")?)?),
+        "block-inline-scripts" => Reaction::BlockInlineScripts,
+        _ => return None,
+    })
+}
+
+pub fn run_conformance_vectors_impl<B: Backend + ?Sized>(backend: &B, body: &str) -> Result<ConformanceReport, Error> {
+    let mut report = ConformanceReport::default();
+    for vector in parse_vectors(body)? {
+        let list = backend.load(&vector.list);
+        for case in vector.cases {
+            let actual = match list {
+                Ok(ref list) => Ok(backend.process(list, &case.request.as_request())),
+                Err(ref error) => Err(error.clone()),
+            };
+            if actual.as_ref() == Ok(&case.reactions) {
+                report.passed += 1;
+            } else {
+                report.failures.push(ConformanceFailure {
+                    vector: vector.name.clone(),
+                    url: case.request.url,
+                    expected: case.reactions,
+                    actual,
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn reaction_to_json(reaction: &Reaction) -> Value {
+    let mut object = Map::new();
+    let mut insert = |key: &str, value: Value| object.insert(key.to_owned(), value);
+    match *reaction {
+        Reaction::Block => {
+            insert("type", Value::from("block"));
+        }
+        Reaction::BlockCookies(scope) => {
+            insert("type", Value::from("block-cookies"));
+            insert("scope", Value::from(scope.name()));
+        }
+        Reaction::BlockCookiesMatching(ref names) => {
+            insert("type", Value::from("block-cookies"));
+            insert("names", Value::Array(names.iter().map(|name| Value::from(&**name)).collect()));
+        }
+        Reaction::Redirect(resource) => {
+            insert("type", Value::from("redirect"));
+            insert("resource", Value::from(resource.name));
+        }
+        Reaction::HideMatchingElements(ref selector) => {
+            insert("type", Value::from("css-display-none"));
+            insert("selector", Value::from(&**selector));
+        }
+        Reaction::HideProceduralElements(ref selector) => {
+            insert("type", Value::from("css-display-none"));
+            insert("selector", Value::from(&*selector.source));
+        }
+        Reaction::InjectStyle { ref selector, ref declarations } => {
+            insert("type", Value::from("css-inject"));
+            insert("selector", Value::from(&**selector));
+            insert("style", Value::from(&**declarations));
+        }
+        Reaction::FilterHtml(ref filter) => {
+            insert("type", Value::from("html-filter"));
+            insert("selector", Value::from(&*filter.source));
+        }
+        Reaction::BlockInlineScripts => {
+            insert("type", Value::from("block-inline-scripts"));
+        }
+    }
+    Value::Object(object)
+}
+
+pub fn record_golden_vectors_impl<'a, B, I>(backend: &B, name: &str, list: &str, corpus: I) -> Result<String, Error>
+    where B: Backend + ?Sized, I: IntoIterator<Item=&'a OwnedRequest>
+{
+    let rules: Value = serde_json::from_str(list).map_err(|_| Error::JSON)?;
+    let loaded = backend.load(list)?;
+    let requests = corpus.into_iter().map(|request| {
+        let mut case = Map::new();
+        case.insert("url".to_owned(), Value::from(&*request.url));
+        case.insert("resource-type".to_owned(), Value::from(request.resource_type.name()));
+        case.insert("load-type".to_owned(), Value::from(request.load_type.name()));
+        if let Some(ref context) = request.context {
+            case.insert("context".to_owned(), Value::from(&**context));
+        }
+        if let Some(ref document) = request.document {
+            case.insert("document".to_owned(), Value::from(&**document));
+        }
+        if !request.redirect_chain.is_empty() {
+            let chain = request.redirect_chain.iter().map(|url| Value::from(url.as_str())).collect();
+            case.insert("redirect-chain".to_owned(), chain);
+        }
+        if let Some(ref headers) = request.headers {
+            let headers = headers.iter().map(|(name, value)| (name.clone(), Value::from(&**value))).collect();
+            case.insert("headers".to_owned(), Value::Object(headers));
+        }
+        let reactions = backend.process(&loaded, &request.as_request());
+        case.insert("reactions".to_owned(), Value::Array(reactions.iter().map(reaction_to_json).collect()));
+        Value::Object(case)
+    }).collect();
+
+    let mut vector = Map::new();
+    vector.insert("name".to_owned(), Value::from(name));
+    vector.insert("list".to_owned(), rules);
+    vector.insert("requests".to_owned(), Value::Array(requests));
+    Ok(Value::Array(vec![Value::Object(vector)]).to_string())
+}
@@ -0,0 +1,250 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Element hiding rules split by the pages they apply on, so that an embedder
+//! can inject one stylesheet into every page and only look up the selectors
+//! specific to the host of each page.
+
+use lint::matches_everything;
+use repr::{Action, DomainConstraint, LoadTypeList, ReactionRef, Reactions, ResourceType, ResourceTypeList, Rule};
+use repr::Trigger;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::Arc;
+
+/// Whether `trigger` matches every document request of a page depending on
+/// nothing but its host, so that it can be evaluated once per page.
+fn host_only(trigger: &Trigger) -> bool {
+    let documents = match trigger.resource_type {
+        ResourceTypeList::All => true,
+        ResourceTypeList::List(ref types) => types.contains(&ResourceType::Document),
+    };
+    documents && matches_everything(trigger.url_filter.as_str()) && trigger.load_type == LoadTypeList::All &&
+        trigger.contexts.is_none() && trigger.user_initiated.is_none() && trigger.opener_domain.is_none() &&
+        !trigger.match_redirect_chain && trigger.if_header.is_empty() && trigger.unless_header.is_empty() &&
+        trigger.path_filter.is_none() && trigger.query_filter.is_none()
+}
+
+/// Whether the domain constraint of `rule` selects pages of `host`.
+fn applies_on(rule: &Rule, host: &str) -> bool {
+    match rule.trigger.domain_constraint {
+        None => true,
+        Some(DomainConstraint::If(ref matcher)) => matcher.matches_domain(host),
+        Some(DomainConstraint::Unless(ref matcher)) => !matcher.matches_domain(host),
+    }
+}
+
+/// The rules hiding the same selector on some domains that no exception can
+/// cancel, so that the selector applies on a host as soon as one of them does.
+#[derive(Clone, Debug)]
+struct SelectorBucket {
+    selector: Arc<str>,
+    /// The positions of the rules, to check against disabled groups.
+    rules: Vec<usize>,
+}
+
+/// The `css-display-none` rules of a list, split into those hiding elements on
+/// every page and those whose result depends on the host of the page. Rules
+/// that depend on more than the host, or that an exception depending on more
+/// than the host could cancel, are in neither set and are only reported when
+/// the page is matched as a request.
+#[derive(Clone, Debug, Default)]
+pub struct CosmeticIndex {
+    /// The rules applying everywhere, with no exception that could cancel them.
+    generic: Vec<usize>,
+    /// The site-specific rules that no exception can cancel, one bucket per
+    /// selector, so that lists hiding the same selector on thousands of sites
+    /// store it once.
+    buckets: Vec<SelectorBucket>,
+    /// The buckets applying on each exact domain.
+    bucket_exact: HashMap<Arc<str>, Vec<u32>>,
+    /// The buckets applying on each domain and its subdomains.
+    bucket_subdomain: HashMap<Arc<str>, Vec<u32>>,
+    /// The other rules and exceptions limited to some domains, by each of the
+    /// domains named in their `if-domain` constraint, evaluated in list order.
+    by_domain: HashMap<Arc<str>, Vec<usize>>,
+    /// The other rules and exceptions, evaluated for every host.
+    everywhere: Vec<usize>,
+}
+
+impl CosmeticIndex {
+    /// Split the element hiding rules of `rules`.
+    pub fn new(rules: &[Rule]) -> CosmeticIndex {
+        let mut exceptions: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut last_exception = None;
+        let mut last_complex_exception = None;
+        for (position, rule) in rules.iter().enumerate() {
+            match rule.action {
+                Action::CssDisplayNoneException(ref selector) => {
+                    exceptions.entry(&**selector).or_default().push(position);
+                }
+                Action::IgnorePreviousRules => {
+                    last_exception = Some(position);
+                    if !host_only(&rule.trigger) {
+                        last_complex_exception = Some(position);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut index = CosmeticIndex::default();
+        let mut bucket_of: HashMap<&str, u32> = HashMap::new();
+        for (position, rule) in rules.iter().enumerate() {
+            let indexed = match rule.action {
+                Action::CssDisplayNone(ref selector) => {
+                    if !host_only(&rule.trigger) || last_complex_exception.is_some_and(|last| last > position) {
+                        continue;
+                    }
+                    let selector_exceptions = exceptions.get(&**selector).map_or(&[][..], |positions| positions);
+                    if rule.trigger.domain_constraint.is_none() && selector_exceptions.is_empty() &&
+                       last_exception.is_none_or(|last| last < position) {
+                        index.generic.push(position);
+                        continue;
+                    }
+                    if let Some(DomainConstraint::If(ref matcher)) = rule.trigger.domain_constraint {
+                        if !matcher.wildcard_tld && selector_exceptions.is_empty() &&
+                           last_exception.is_none_or(|last| last < position) {
+                            let next = index.buckets.len() as u32;
+                            let bucket = *bucket_of.entry(&**selector).or_insert(next);
+                            if bucket == next {
+                                index.buckets.push(SelectorBucket { selector: selector.clone(), rules: vec![] });
+                            }
+                            index.buckets[bucket as usize].rules.push(position);
+                            for domain in matcher.exact.iter() {
+                                add_bucket(&mut index.bucket_exact, domain, bucket);
+                            }
+                            for domain in matcher.subdomain.iter() {
+                                add_bucket(&mut index.bucket_subdomain, domain, bucket);
+                            }
+                            continue;
+                        }
+                    }
+                    // An exception is only evaluated with the host if nothing else
+                    // could clear it.
+                    selector_exceptions.iter().all(|&exception| {
+                        host_only(&rules[exception].trigger) &&
+                            last_complex_exception.is_none_or(|last| last < exception)
+                    })
+                }
+                Action::CssDisplayNoneException(_) | Action::IgnorePreviousRules => host_only(&rule.trigger),
+                _ => false,
+            };
+            if !indexed {
+                continue;
+            }
+            match rule.trigger.domain_constraint {
+                Some(DomainConstraint::If(ref matcher)) if !matcher.wildcard_tld => {
+                    for domain in matcher.exact.iter().chain(&*matcher.subdomain) {
+                        index.by_domain.entry(domain.clone()).or_default().push(position);
+                    }
+                }
+                _ => index.everywhere.push(position),
+            }
+        }
+        index
+    }
+
+    /// Whether the rule at `position` is one of the generic rules.
+    pub fn is_generic(&self, position: usize) -> bool {
+        self.generic.binary_search(&position).is_ok()
+    }
+
+    /// An estimate of the heap memory used by this index, in bytes.
+    pub fn heap_size(&self) -> usize {
+        let positions = mem::size_of::<usize>();
+        let map_size = |map: &HashMap<Arc<str>, Vec<u32>>| {
+            map.capacity() * (mem::size_of::<Arc<str>>() + mem::size_of::<Vec<u32>>() + 1) +
+                map.values().map(|buckets| buckets.capacity() * mem::size_of::<u32>()).sum::<usize>()
+        };
+        self.generic.capacity() * positions + self.everywhere.capacity() * positions +
+            self.buckets.capacity() * mem::size_of::<SelectorBucket>() +
+            self.buckets.iter().map(|bucket| bucket.rules.capacity() * positions).sum::<usize>() +
+            map_size(&self.bucket_exact) + map_size(&self.bucket_subdomain) +
+            self.by_domain.capacity() * (mem::size_of::<Arc<str>>() + mem::size_of::<Vec<usize>>() + 1) +
+            self.by_domain.values().map(|rules| rules.capacity() * positions).sum::<usize>()
+    }
+
+    /// A stylesheet hiding the elements matched by the generic rules, with one
+    /// style rule per distinct selector so that a selector the browser rejects
+    /// does not disable the others.
+    pub fn generic_stylesheet(&self, rules: &[Rule], disabled: Option<&[bool]>) -> String {
+        let mut stylesheet = String::new();
+        let mut written = HashSet::new();
+        for &position in &self.generic {
+            if disabled.is_some_and(|disabled| disabled[position]) {
+                continue;
+            }
+            if let Action::CssDisplayNone(ref selector) = rules[position].action {
+                if !written.insert(&**selector) {
+                    continue;
+                }
+                stylesheet.push_str(selector);
+                stylesheet.push_str(" { display: none !important; }\n");
+            }
+        }
+        stylesheet
+    }
+
+    /// The selectors hiding elements on pages of `host` beside the generic ones,
+    /// after applying the exceptions for `host`, each reported once. The
+    /// selectors of rules evaluated in order come first, followed by those of
+    /// the buckets.
+    pub fn specific_selectors_for<'a>(&'a self, rules: &'a [Rule], disabled: Option<&[bool]>, host: &str)
+                                      -> Vec<&'a str> {
+        let mut positions = self.everywhere.clone();
+        let mut buckets = self.bucket_exact.get(host).cloned().unwrap_or_default();
+        let mut suffix = host;
+        loop {
+            if let Some(matching) = self.by_domain.get(suffix) {
+                positions.extend_from_slice(matching);
+            }
+            if let Some(matching) = self.bucket_subdomain.get(suffix) {
+                buckets.extend_from_slice(matching);
+            }
+            match suffix.find('.') {
+                Some(dot) => suffix = &suffix[dot + 1..],
+                None => break,
+            }
+        }
+        positions.sort_unstable();
+        positions.dedup();
+
+        let mut reactions = Reactions::default();
+        for position in positions {
+            let rule = &rules[position];
+            if disabled.is_some_and(|disabled| disabled[position]) || !applies_on(rule, host) {
+                continue;
+            }
+            rule.action.process(position, &mut reactions);
+        }
+        let ordered = reactions.iter().filter_map(|reaction| match *reaction {
+            ReactionRef::HideMatchingElements(selector) => Some(selector),
+            _ => None,
+        });
+
+        buckets.sort_unstable();
+        buckets.dedup();
+        // Every rule of a bucket applies on `host` unless its group is disabled,
+        // in which case the others are checked one by one.
+        let bucketed = buckets.into_iter().map(|bucket| &self.buckets[bucket as usize]).filter(|bucket| {
+            match disabled {
+                Some(disabled) => bucket.rules.iter().any(|&rule| !disabled[rule] && applies_on(&rules[rule], host)),
+                None => true,
+            }
+        }).map(|bucket| &*bucket.selector);
+
+        let mut seen = HashSet::new();
+        ordered.chain(bucketed).filter(|selector| seen.insert(*selector)).collect()
+    }
+}
+
+fn add_bucket(map: &mut HashMap<Arc<str>, Vec<u32>>, domain: &Arc<str>, bucket: u32) {
+    let buckets = map.entry(domain.clone()).or_default();
+    // Consecutive rules of a bucket often name the same domains. Other repeats
+    // are removed when looking up a host.
+    if buckets.last() != Some(&bucket) {
+        buckets.push(bucket);
+    }
+}
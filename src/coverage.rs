@@ -0,0 +1,79 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use backend::MatchBackend;
+use repr::{OwnedRequest, Reactions, Rule};
+use std::cmp::Reverse;
+
+/// How often a single rule applied to the requests of a corpus.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuleCoverage {
+    /// The number of requests matching the rule's trigger.
+    pub matches: u64,
+    /// The number of requests for which the rule's reaction was reported, ie.
+    /// was not discarded by a later exception.
+    pub reactions: u64,
+}
+
+/// How often each rule of a list applied to a corpus of requests, so that list
+/// maintainers can find rules that no longer match anything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// The number of requests in the corpus.
+    pub requests: u64,
+    /// The number of requests with at least one reaction.
+    pub requests_with_reactions: u64,
+    /// Counts for each rule, in list order.
+    pub rules: Vec<RuleCoverage>,
+}
+
+impl Coverage {
+    /// The positions of the rules that matched no request of the corpus.
+    pub fn never_matched(&self) -> impl Iterator<Item=usize> + '_ {
+        self.rules.iter().enumerate().filter(|&(_, rule)| rule.matches == 0).map(|(index, _)| index)
+    }
+
+    /// The positions of the `n` rules that matched the most requests, along with
+    /// their counts, most frequent first.
+    pub fn most_matched(&self, n: usize) -> Vec<(usize, &RuleCoverage)> {
+        let mut rules: Vec<(usize, &RuleCoverage)> =
+            self.rules.iter().enumerate().filter(|&(_, rule)| rule.matches > 0).collect();
+        rules.sort_by_key(|&(index, rule)| (Reverse(rule.matches), index));
+        rules.truncate(n);
+        rules
+    }
+}
+
+/// Evaluate every request of `corpus` against `rules`, counting the requests
+/// each rule matched and those it reacted to, using `backend` to find the
+/// candidate rules of each request. Rules flagged in `disabled` are skipped.
+pub fn coverage_impl<'a, I>(rules: &[Rule], backend: &dyn MatchBackend, disabled: Option<&[bool]>, corpus: I) -> Coverage
+    where I: IntoIterator<Item=&'a OwnedRequest>
+{
+    let mut coverage = Coverage {
+        rules: vec![RuleCoverage::default(); rules.len()],
+        .. Coverage::default()
+    };
+    for request in corpus {
+        let request = request.as_request();
+        let mut reactions = Reactions::default();
+        for position in backend.candidates(&request) {
+            if disabled.is_some_and(|disabled| disabled[position]) {
+                continue;
+            }
+            if rules[position].trigger.matches(&request) {
+                coverage.rules[position].matches += 1;
+                rules[position].action.process(position, &mut reactions);
+            }
+        }
+        coverage.requests += 1;
+        if !reactions.is_empty() {
+            coverage.requests_with_reactions += 1;
+        }
+        for &position in reactions.rules() {
+            coverage.rules[position].reactions += 1;
+        }
+    }
+    coverage
+}
@@ -0,0 +1,125 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A compact format describing how to turn one version of a list into the next.
+//!
+//! A delta is a JSON object recording the number of rules in the list it applies
+//! to, and a sequence of operations that build the new list:
+//!
+//! ```json
+//! {
+//!     "base-length": 3,
+//!     "operations": [
+//!         { "copy": 0, "count": 2 },
+//!         { "insert": [{ "trigger": { "url-filter": "ads" }, "action": { "type": "block" } }] }
+//!     ]
+//! }
+//! ```
+//!
+//! `copy` operations reuse a run of already-parsed rules from the old list, and
+//! `insert` operations carry new rules in the usual JSON representation.
+
+use intern::Interner;
+use parse::{Error, ParseOptions, parse_rule_impl};
+use repr::Rule;
+use serde_json::{self, Map, Value};
+use std::collections::HashMap;
+
+/// Build a delta that transforms `old` into the rules described by the JSON list
+/// `new_body`, whose rules are parsed with `options` to be compared with `old`.
+pub fn create_delta_impl(old: &[Rule], new_body: &str, options: &ParseOptions) -> Result<String, Error> {
+    let json_body: Value = serde_json::from_str(new_body).map_err(|_| Error::JSON)?;
+    let list = json_body.as_array().ok_or(Error::NotAList)?;
+
+    let mut first_index: HashMap<&Rule, usize> = HashMap::new();
+    for (index, rule) in old.iter().enumerate().rev() {
+        first_index.insert(rule, index);
+    }
+
+    let mut operations = vec![];
+    let mut copy: Option<(usize, usize)> = None;
+    let mut inserted = vec![];
+    let mut interner = Interner::new();
+    for source in list {
+        let rule = match parse_rule_impl(0, source, &mut interner, options) {
+            Ok(rule) => rule,
+            Err(_) => continue,
+        };
+
+        if let Some((start, ref mut count)) = copy {
+            if start + *count < old.len() && old[start + *count] == rule {
+                *count += 1;
+                continue;
+            }
+        }
+
+        match first_index.get(&rule) {
+            Some(&start) => {
+                flush_insert(&mut operations, &mut inserted);
+                flush_copy(&mut operations, &mut copy);
+                copy = Some((start, 1));
+            }
+            None => {
+                flush_copy(&mut operations, &mut copy);
+                inserted.push(source.clone());
+            }
+        }
+    }
+    flush_insert(&mut operations, &mut inserted);
+    flush_copy(&mut operations, &mut copy);
+
+    let mut delta = Map::new();
+    delta.insert("base-length".to_owned(), Value::from(old.len() as u64));
+    delta.insert("operations".to_owned(), Value::Array(operations));
+    Ok(Value::Object(delta).to_string())
+}
+
+fn flush_copy(operations: &mut Vec<Value>, copy: &mut Option<(usize, usize)>) {
+    if let Some((start, count)) = copy.take() {
+        let mut operation = Map::new();
+        operation.insert("copy".to_owned(), Value::from(start as u64));
+        operation.insert("count".to_owned(), Value::from(count as u64));
+        operations.push(Value::Object(operation));
+    }
+}
+
+fn flush_insert(operations: &mut Vec<Value>, inserted: &mut Vec<Value>) {
+    if !inserted.is_empty() {
+        let mut operation = Map::new();
+        operation.insert("insert".to_owned(), Value::Array(inserted.split_off(0)));
+        operations.push(Value::Object(operation));
+    }
+}
+
+/// Build a new list of rules by applying the JSON delta `delta` to `old`, parsing
+/// the inserted rules with `options`.
+pub fn apply_delta_impl(old: &[Rule], delta: &str, options: &ParseOptions) -> Result<Vec<Rule>, Error> {
+    let json_body: Value = serde_json::from_str(delta).map_err(|_| Error::JSON)?;
+    let delta = json_body.as_object().ok_or(Error::InvalidDelta)?;
+    let base_length = delta.get("base-length").and_then(|l| l.as_u64()).ok_or(Error::InvalidDelta)?;
+    if base_length != old.len() as u64 {
+        return Err(Error::InvalidDelta);
+    }
+    let operations = delta.get("operations").and_then(|o| o.as_array()).ok_or(Error::InvalidDelta)?;
+
+    let mut rules = vec![];
+    let mut interner = Interner::new();
+    for operation in operations {
+        let operation = operation.as_object().ok_or(Error::InvalidDelta)?;
+        if let Some(inserted) = operation.get("insert") {
+            let inserted = inserted.as_array().ok_or(Error::InvalidDelta)?;
+            rules.extend(inserted.iter().filter_map(|rule| parse_rule_impl(0, rule, &mut interner, options).ok()));
+            continue;
+        }
+
+        let start = operation.get("copy").and_then(|c| c.as_u64()).ok_or(Error::InvalidDelta)?;
+        let count = operation.get("count").and_then(|c| c.as_u64()).ok_or(Error::InvalidDelta)?;
+        let end = start.checked_add(count).ok_or(Error::InvalidDelta)?;
+        if end > old.len() as u64 {
+            return Err(Error::InvalidDelta);
+        }
+        rules.extend_from_slice(&old[start as usize..end as usize]);
+    }
+    Ok(rules)
+}
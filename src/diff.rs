@@ -0,0 +1,70 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use repr::Rule;
+use std::collections::{HashMap, VecDeque};
+
+/// The differences between two versions of a rule list. Rules are identified by
+/// their position in the list they belong to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RuleListDelta {
+    /// Rules of the new list with no counterpart in the old list.
+    pub added: Vec<usize>,
+    /// Rules of the old list with no counterpart in the new list.
+    pub removed: Vec<usize>,
+    /// Pairs of old and new rules that share a trigger but perform different
+    /// actions or belong to different groups.
+    pub changed: Vec<(usize, usize)>,
+}
+
+impl RuleListDelta {
+    /// Whether both lists contain the same rules.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare two lists of rules. Each rule of the new list is paired with an
+/// identical rule of the old list if one remains unpaired; the leftover rules
+/// are reported as changed when they share a trigger, and as added or removed
+/// otherwise.
+pub fn diff_impl(old: &[Rule], new: &[Rule]) -> RuleListDelta {
+    let mut unpaired = HashMap::new();
+    for (index, rule) in old.iter().enumerate() {
+        unpaired.entry(rule)
+                .or_insert_with(VecDeque::new)
+                .push_back(index);
+    }
+
+    let mut paired = vec![false; old.len()];
+    let mut added = vec![];
+    for (index, rule) in new.iter().enumerate() {
+        match unpaired.get_mut(rule).and_then(|i| i.pop_front()) {
+            Some(old_index) => paired[old_index] = true,
+            None => added.push(index),
+        }
+    }
+
+    let mut removed_by_trigger = HashMap::new();
+    for (index, rule) in old.iter().enumerate() {
+        if !paired[index] {
+            removed_by_trigger.entry(&rule.trigger)
+                              .or_insert_with(VecDeque::new)
+                              .push_back(index);
+        }
+    }
+
+    let mut delta = RuleListDelta::default();
+    for index in added {
+        match removed_by_trigger.get_mut(&&new[index].trigger).and_then(|i| i.pop_front()) {
+            Some(old_index) => {
+                paired[old_index] = true;
+                delta.changed.push((old_index, index));
+            }
+            None => delta.added.push(index),
+        }
+    }
+    delta.removed = (0..old.len()).filter(|&index| !paired[index]).collect();
+    delta
+}
@@ -0,0 +1,150 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use index::{host_anchor, trigger_host_anchor};
+use reachability::may_overlap;
+use repr::{Action, LoadTypeList, ResourceTypeList, Rule, Trigger, UrlFilterAnchor};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// The configuration formats of DNS resolvers that blocklists can be exported to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DnsFormat {
+    /// `address=/example.com/#` lines for dnsmasq, which always block subdomains too.
+    Dnsmasq,
+    /// A `server:` clause of `local-zone` and `local-data` entries for unbound.
+    Unbound,
+    /// An adlist for Pi-hole, with plain domains for single hosts and
+    /// `||example.com^` entries for hosts along with their subdomains.
+    PiHole,
+}
+
+/// Why a rule was left out of a DNS blocklist.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DnsSkipReason {
+    /// The URL filter examines more of the URL than its host and scheme.
+    UrlFilter,
+    /// The rule only applies to some resource types, load types, pages or contexts.
+    Conditions,
+    /// The format cannot block a host without also blocking its subdomains.
+    ExactHost,
+    /// The `ignore-previous-rules` rule may unblock requests to exported hosts,
+    /// which a resolver blocks regardless.
+    Exception,
+}
+
+/// A rule affecting whether requests are blocked that a DNS blocklist cannot represent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DnsSkip {
+    /// The position of the rule in its list.
+    pub rule: usize,
+    /// Why the rule was left out.
+    pub reason: DnsSkipReason,
+}
+
+/// A list exported as resolver configuration by `RuleList::to_dns_blocklist`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsBlocklist {
+    /// The configuration, one entry per line.
+    pub config: String,
+    /// The number of hosts blocked by the configuration.
+    pub hosts: usize,
+    /// The blocking rules and exceptions that were left out, in list order.
+    pub skipped: Vec<DnsSkip>,
+}
+
+const WEB_SCHEMES: &[&str] = &["https?", "[^:]+", "[^:]*", "[a-z]+", "[a-z]*"];
+pub const HOST_TERMINATORS: &[&str] = &["/", "[:/]", "[/:]", "(:|/)"];
+
+/// The host of a URL filter anchored to the HTTP(S) URLs of that host, along
+/// with whether it also matches its subdomains, and the rest of the filter.
+pub fn anchored_host(pattern: &str) -> Option<(String, bool, &str)> {
+    let (host, subdomains) = host_anchor(pattern)?;
+    let rest = pattern.strip_prefix("(?i)").unwrap_or(pattern).strip_prefix('^')?;
+    let (scheme, rest) = rest.split_once("://")?;
+    if !WEB_SCHEMES.contains(&scheme) {
+        return None;
+    }
+    let escaped = host.replace('.', "\\.");
+    let end = rest.to_ascii_lowercase().rfind(&escaped)? + escaped.len();
+    Some((host, subdomains, &rest[end..]))
+}
+
+/// The host blocked by a trigger whose URL filter matches every HTTP(S) URL of
+/// that host, along with whether it also matches its subdomains.
+fn blocked_host(trigger: &Trigger) -> Option<(String, bool)> {
+    if trigger.url_filter_anchor == UrlFilterAnchor::Host {
+        return trigger_host_anchor(trigger);
+    }
+    let (host, subdomains, rest) = anchored_host(trigger.url_filter.as_str())?;
+    if HOST_TERMINATORS.contains(&rest) { Some((host, subdomains)) } else { None }
+}
+
+/// Whether a host, along with its subdomains if `subdomains` is set, includes `other`.
+fn covers_host(host: &str, subdomains: bool, other: &str) -> bool {
+    other == host || subdomains && other.strip_suffix(host).is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// Whether an `ignore-previous-rules` trigger may cancel a rule blocking `host`.
+pub fn may_unblock(blocking: &Trigger, host: &str, subdomains: bool, exception: &Trigger) -> bool {
+    may_overlap(blocking, exception) &&
+        trigger_host_anchor(exception).is_none_or(|(other, other_subdomains)| {
+            covers_host(host, subdomains, &other) || covers_host(&other, other_subdomains, host)
+        })
+}
+
+/// Whether a trigger applies to every request whatever its context.
+pub fn unconditional(trigger: &Trigger) -> bool {
+    trigger.resource_type == ResourceTypeList::All && trigger.load_type == LoadTypeList::All &&
+        trigger.domain_constraint.is_none() && trigger.contexts.is_none() &&
+        trigger.user_initiated.is_none() && trigger.opener_domain.is_none() &&
+        trigger.if_header.is_empty() && trigger.unless_header.is_empty() &&
+        trigger.path_filter.is_none() && trigger.query_filter.is_none()
+}
+
+pub fn to_dns_blocklist_impl(rules: &[Rule], format: DnsFormat) -> DnsBlocklist {
+    let mut config = String::new();
+    if format == DnsFormat::Unbound {
+        config.push_str("server:\n");
+    }
+    let mut seen = HashSet::new();
+    let mut exported = vec![];
+    let mut skipped = vec![];
+    for (position, rule) in rules.iter().enumerate() {
+        let reason = match rule.action {
+            Action::Block => match blocked_host(&rule.trigger) {
+                None => DnsSkipReason::UrlFilter,
+                Some(_) if !unconditional(&rule.trigger) => DnsSkipReason::Conditions,
+                Some((_, false)) if format == DnsFormat::Dnsmasq => DnsSkipReason::ExactHost,
+                Some((host, subdomains)) => {
+                    exported.push((position, host.clone(), subdomains));
+                    if seen.insert((host.clone(), subdomains)) {
+                        let _ = match (format, subdomains) {
+                            (DnsFormat::Dnsmasq, _) => writeln!(config, "address=/{}/#", host),
+                            (DnsFormat::Unbound, true) => {
+                                writeln!(config, "    local-zone: \"{}.\" always_nxdomain", host)
+                            }
+                            (DnsFormat::Unbound, false) => writeln!(config, "    local-data: \"{0}. A 0.0.0.0\"\n    \
+                                                                             local-data: \"{0}. AAAA ::\"", host),
+                            (DnsFormat::PiHole, true) => writeln!(config, "||{}^", host),
+                            (DnsFormat::PiHole, false) => writeln!(config, "{}", host),
+                        };
+                    }
+                    continue;
+                }
+            },
+            Action::IgnorePreviousRules if exported.iter().any(|&(earlier, ref host, subdomains)| {
+                may_unblock(&rules[earlier].trigger, host, subdomains, &rule.trigger)
+            }) => DnsSkipReason::Exception,
+            _ => continue,
+        };
+        skipped.push(DnsSkip { rule: position, reason });
+    }
+    DnsBlocklist {
+        config,
+        hosts: seen.len(),
+        skipped,
+    }
+}
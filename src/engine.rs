@@ -0,0 +1,303 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use allowlist::Allowlist;
+use intern::Interner;
+use memory::{MemoryReport, shared_memory_usage_impl};
+use parse::{Error, parse_list_strict_impl};
+use repr::{Action, DomainConstraint, PreparedRequest, Provenance, Reaction, ReactionRef, Request, RuleId};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use url::Url;
+use {RuleList, process_rules_for_request_borrowed};
+
+/// Resolves the canonical names of hosts, so that trackers served from a
+/// first-party subdomain aliasing their own domain through a CNAME record are
+/// matched like requests to that domain.
+pub trait CnameResolver {
+    /// The canonical name of `host`, or `None` if it is not an alias or its
+    /// records have not been resolved yet. Called while matching requests, so
+    /// it should answer from a cache rather than wait for the network.
+    fn canonical_host(&self, host: &str) -> Option<String>;
+}
+
+impl<F: Fn(&str) -> Option<String>> CnameResolver for F {
+    fn canonical_host(&self, host: &str) -> Option<String> {
+        self(host)
+    }
+}
+
+/// Share the domains of the rules of `list` with the other lists of an engine,
+/// so that subscriptions naming the same sites only store each domain once.
+fn share_domains(list: &mut RuleList, domains: &mut Interner) {
+    for rule in &mut list.rules {
+        match rule.trigger.domain_constraint {
+            Some(DomainConstraint::If(ref mut matcher)) |
+            Some(DomainConstraint::Unless(ref mut matcher)) => matcher.share_domains(domains),
+            None => {}
+        }
+        if let Some(ref mut matcher) = rule.trigger.opener_domain {
+            matcher.share_domains(domains);
+        }
+    }
+}
+
+/// Several rule lists evaluated together, along with the user's allowlist and
+/// the rules the user wrote themselves. The domains of the lists' rules are
+/// stored once for the whole engine, and each request's URLs are only split
+/// into host, domain and registrable domain once for all the lists.
+#[derive(Default)]
+pub struct Engine {
+    lists: Vec<RuleList>,
+    allowlist: Allowlist,
+    disabled_groups: Vec<String>,
+    user_rules: RuleList,
+    cname_resolver: Option<Box<dyn CnameResolver + Send + Sync>>,
+    domains: Interner,
+}
+
+impl Engine {
+    /// Create an engine with no lists and an empty allowlist.
+    pub fn new() -> Engine {
+        Engine::default()
+    }
+
+    /// Add a list, evaluated after the lists already added. The groups disabled
+    /// in this engine are disabled in the list too.
+    pub fn add_list(&mut self, mut list: RuleList) {
+        for group in &self.disabled_groups {
+            list.set_group_enabled(group, false);
+        }
+        share_domains(&mut list, &mut self.domains);
+        self.lists.push(list);
+    }
+
+    /// The lists of this engine, in order.
+    pub fn lists(&self) -> &[RuleList] {
+        &self.lists
+    }
+
+    /// The rules written by the user, which are evaluated after every list and
+    /// take precedence over them: a user exception cancels the reactions of all
+    /// lists, and the reactions of other user rules apply whatever the lists'
+    /// exceptions say.
+    pub fn user_rules(&self) -> &RuleList {
+        &self.user_rules
+    }
+
+    /// Replace the user rules with those of `body`, as produced by
+    /// `user_rules_to_json`. Fails without changing the rules if any is invalid.
+    pub fn set_user_rules(&mut self, body: &str) -> Result<(), Error> {
+        let mut rules = RuleList::new(parse_list_strict_impl(body)?);
+        rules.name = Some("user rules".to_owned());
+        for group in &self.disabled_groups {
+            rules.set_group_enabled(group, false);
+        }
+        share_domains(&mut rules, &mut self.domains);
+        self.user_rules = rules;
+        Ok(())
+    }
+
+    /// Add the user rule described by the JSON rule object `rule`, evaluated
+    /// after the user rules already added.
+    pub fn add_user_rule(&mut self, rule: &str) -> Result<RuleId, Error> {
+        let rule = parse_list_strict_impl(&format!("[{}]", rule))?.pop().ok_or(Error::NotAList)?;
+        let id = rule.id();
+        let mut rules = self.user_rules.rules.clone();
+        rules.push(rule);
+        self.user_rules.replace_rules(rules);
+        self.user_rules.name = Some("user rules".to_owned());
+        share_domains(&mut self.user_rules, &mut self.domains);
+        Ok(id)
+    }
+
+    /// Remove the user rules identified by `id`. Returns whether there were any.
+    pub fn remove_user_rule(&mut self, id: RuleId) -> bool {
+        let before = self.user_rules.len();
+        let rules = self.user_rules.rules.iter().filter(|rule| rule.id() != id).cloned().collect();
+        self.user_rules.replace_rules(rules);
+        before != self.user_rules.len()
+    }
+
+    /// Serialize the user rules as a JSON list, to be restored with `set_user_rules`.
+    pub fn user_rules_to_json(&self) -> String {
+        self.user_rules.to_json_string()
+    }
+
+    /// Build the lazily built parts of every list and of the user rules, as
+    /// described by `RuleList::warm_up`. Requests can be processed meanwhile
+    /// from other threads; those reaching a rule first compile it themselves.
+    pub fn warm_up(&self) {
+        for list in self.lists.iter().chain(Some(&self.user_rules)) {
+            list.warm_up();
+        }
+    }
+
+    /// Call `warm_up` on a new thread, so that startup does not wait for it.
+    pub fn warm_up_in_background(engine: &Arc<Engine>) -> JoinHandle<()> {
+        let engine = engine.clone();
+        thread::spawn(move || engine.warm_up())
+    }
+
+    /// Estimate the memory used by the lists and user rules of this engine,
+    /// counting the domains and selectors they share once.
+    pub fn memory_usage(&self) -> MemoryReport {
+        shared_memory_usage_impl(self.lists.iter().chain(Some(&self.user_rules)))
+    }
+
+    /// The pages on which no rules apply.
+    pub fn allowlist(&self) -> &Allowlist {
+        &self.allowlist
+    }
+
+    /// Modify the allowlist, which takes effect for the following requests.
+    pub fn allowlist_mut(&mut self) -> &mut Allowlist {
+        &mut self.allowlist
+    }
+
+    /// Look up the canonical names of requested hosts with `resolver`. A request
+    /// to an alias is evaluated a second time with the host of its URL replaced
+    /// by the canonical name, so that both the URL filters and the domain
+    /// constraints naming the tracker apply, and the reactions the first
+    /// evaluation did not report are added to it.
+    pub fn set_cname_resolver<R>(&mut self, resolver: R) where R: CnameResolver + Send + Sync + 'static {
+        self.cname_resolver = Some(Box::new(resolver));
+    }
+
+    /// Stop looking up the canonical names of requested hosts.
+    pub fn clear_cname_resolver(&mut self) {
+        self.cname_resolver = None;
+    }
+
+    /// The URL of `request` with its host replaced by its canonical name, if
+    /// the resolver knows it is an alias.
+    fn uncloaked_url(&self, request: &Request) -> Option<Url> {
+        let host = request.url.domain()?;
+        let canonical = self.cname_resolver.as_ref()?.canonical_host(host)?;
+        let canonical = canonical.trim_end_matches('.');
+        if canonical.eq_ignore_ascii_case(host) {
+            return None;
+        }
+        let mut url = Url::parse(request.url.as_str()).ok()?;
+        url.set_host(Some(canonical)).ok()?;
+        Some(url)
+    }
+
+    /// The groups that the rules of every list belong to, in sorted order, eg.
+    /// to offer a toggle for each of them.
+    pub fn groups(&self) -> Vec<&str> {
+        let mut groups: Vec<&str> = self.lists.iter().flat_map(|list| list.groups()).collect();
+        groups.sort_unstable();
+        groups.dedup();
+        groups
+    }
+
+    /// Whether the rules of `group` apply. Every group is enabled initially.
+    pub fn is_group_enabled(&self, group: &str) -> bool {
+        !self.disabled_groups.iter().any(|disabled| disabled == group)
+    }
+
+    /// Enable or disable the rules of `group` in every list, including lists
+    /// added later, for the following requests.
+    pub fn set_group_enabled(&mut self, group: &str, enabled: bool) {
+        if enabled {
+            self.disabled_groups.retain(|disabled| disabled != group);
+        } else if self.is_group_enabled(group) {
+            self.disabled_groups.push(group.to_owned());
+        }
+        for list in &mut self.lists {
+            list.set_group_enabled(group, enabled);
+        }
+        self.user_rules.set_group_enabled(group, enabled);
+    }
+
+    /// The selectors that the user rules matching `request` exempt from hiding,
+    /// or `None` if an `ignore-previous-rules` user rule cancels every reaction
+    /// of the lists.
+    fn hiding_exceptions(&self, request: &Request) -> Option<Vec<&str>> {
+        let user = &self.user_rules;
+        let mut excepted = vec![];
+        for (position, rule) in user.rules.iter().enumerate() {
+            if user.disabled.as_ref().is_some_and(|disabled| disabled[position]) || !rule.trigger.matches(request) {
+                continue;
+            }
+            match rule.action {
+                Action::IgnorePreviousRules => return None,
+                Action::CssDisplayNoneException(ref selector) => excepted.push(&**selector),
+                _ => {}
+            }
+        }
+        Some(excepted)
+    }
+
+    /// The reactions to `request`, and to its uncloaked form if its host is an
+    /// alias, along with the list and the position of the rule producing each.
+    fn evaluate(&self, request: &Request) -> Vec<(ReactionRef<'_>, &RuleList, usize)> {
+        let mut reactions = self.evaluate_as(request);
+        if let Some(url) = self.uncloaked_url(request) {
+            let uncloaked = Request {
+                url: &url,
+                resource_type: request.resource_type,
+                load_type: request.load_type,
+                context: request.context,
+                opener: request.opener,
+                document: request.document,
+                redirect_chain: request.redirect_chain,
+                headers: request.headers,
+            };
+            for reaction in self.evaluate_as(&uncloaked) {
+                if !reactions.iter().any(|&(existing, _, _)| existing == reaction.0) {
+                    reactions.push(reaction);
+                }
+            }
+        }
+        reactions
+    }
+
+    /// The reactions to `request`, along with the list and the position of the
+    /// rule producing each of them.
+    fn evaluate_as(&self, request: &Request) -> Vec<(ReactionRef<'_>, &RuleList, usize)> {
+        let prepared = PreparedRequest::new(request);
+        let request = &prepared.as_request();
+        let mut reactions = vec![];
+        if let Some(excepted) = self.hiding_exceptions(request) {
+            for list in &self.lists {
+                let list_reactions = process_rules_for_request_borrowed(list, request);
+                for (&reaction, &rule) in list_reactions.iter().zip(list_reactions.rules()) {
+                    if !reaction.hiding_selector().is_some_and(|selector| excepted.contains(&selector)) {
+                        reactions.push((reaction, list, rule));
+                    }
+                }
+            }
+        }
+        let user_reactions = process_rules_for_request_borrowed(&self.user_rules, request);
+        reactions.extend(user_reactions.iter().zip(user_reactions.rules()).map(|(&reaction, &rule)| {
+            (reaction, &self.user_rules, rule)
+        }));
+        reactions
+    }
+
+    /// Evaluate a request made by the page at `request.document`, or a top-level
+    /// navigation to `request.url` if there is no document. Requests of
+    /// allowlisted pages have no reactions at all; otherwise the reactions of
+    /// each list are reported in order, followed by those of the user rules. An
+    /// `ignore-previous-rules` rule of a list only affects the rules of its list.
+    pub fn process(&self, request: &Request) -> Vec<Reaction> {
+        if self.allowlist.matches(request.document.unwrap_or(request.url)) {
+            return vec![];
+        }
+        self.evaluate(request).into_iter().map(|(reaction, _, _)| reaction.into_owned()).collect()
+    }
+
+    /// Like `process`, but pairs each reaction with the list and rule that
+    /// produced it. Name lists with `RuleList::set_name` to tell them apart.
+    pub fn process_with_provenance(&self, request: &Request) -> Vec<(Reaction, Provenance<'_>)> {
+        if self.allowlist.matches(request.document.unwrap_or(request.url)) {
+            return vec![];
+        }
+        self.evaluate(request).into_iter().map(|(reaction, list, rule)| {
+            (reaction.into_owned(), Provenance { list: list.name(), rule, id: list.ids()[rule] })
+        }).collect()
+    }
+}
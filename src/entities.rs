@@ -0,0 +1,96 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Categorization of tracker domains by the organization operating them, in
+//! the format of Disconnect's `services.json`:
+//!
+//! ```json
+//! { "categories": { "Advertising": [
+//!     { "DoubleClick": { "http://www.doubleclick.net/": ["doubleclick.net", "2mdn.net"] } }
+//! ] } }
+//! ```
+
+use parse::Error;
+use repr::Reaction;
+use serde_json::{self, Value};
+use std::collections::HashMap;
+
+/// The organization operating a tracker domain, and what it tracks for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tracker {
+    /// The name of the organization, eg. `DoubleClick`.
+    pub entity: String,
+    /// The categories the domain is listed under, eg. `Advertising`.
+    pub categories: Vec<String>,
+}
+
+/// A mapping from domains to the trackers operating them.
+#[derive(Clone, Debug, Default)]
+pub struct EntityList {
+    domains: HashMap<String, Tracker>,
+}
+
+impl EntityList {
+    /// The number of domains with a known tracker.
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    /// Whether no domains are categorized.
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// The tracker operating `host`, which may be a subdomain of a listed domain.
+    pub fn lookup(&self, host: &str) -> Option<&Tracker> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let mut suffix = &*host;
+        loop {
+            if let Some(tracker) = self.domains.get(suffix) {
+                return Some(tracker);
+            }
+            suffix = &suffix[suffix.find('.')? + 1..];
+        }
+    }
+}
+
+/// The reactions to a request, along with the tracker it was made to.
+#[derive(Debug, PartialEq)]
+pub struct CategorizedReactions<'a> {
+    /// The reactions to the request.
+    pub reactions: Vec<Reaction>,
+    /// The tracker operating the requested host, if any reactions apply.
+    pub tracker: Option<&'a Tracker>,
+}
+
+pub fn parse_entity_list_impl(body: &str) -> Result<EntityList, Error> {
+    let json: Value = serde_json::from_str(body).map_err(|_| Error::JSON)?;
+    let invalid = Error::MissingField { index: 0, field: "categories" };
+    let categories = json.get("categories").and_then(|c| c.as_object()).ok_or(invalid)?;
+    let mut list = EntityList::default();
+    for (category, entries) in categories {
+        let entries = match entries.as_array() {
+            Some(entries) => entries,
+            None => continue,
+        };
+        // Each entry maps an entity to its homepages, which map to its domains.
+        // Other keys, such as `"performance": "true"`, carry flags that are ignored.
+        for (entity, sites) in entries.iter().filter_map(|e| e.as_object()).flat_map(|e| e.iter()) {
+            let domains = sites.as_object().into_iter()
+                               .flat_map(|sites| sites.values())
+                               .filter_map(|domains| domains.as_array())
+                               .flat_map(|domains| domains.iter().filter_map(|d| d.as_str()));
+            for domain in domains {
+                let tracker = list.domains.entry(domain.to_ascii_lowercase()).or_insert_with(|| Tracker {
+                    entity: entity.clone(),
+                    categories: vec![],
+                });
+                if !tracker.categories.contains(category) {
+                    tracker.categories.push(category.clone());
+                }
+            }
+        }
+    }
+    Ok(list)
+}
@@ -0,0 +1,81 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Trimmed snapshots of EasyList and EasyPrivacy converted to the JSON list
+//! format, and a corpus of requests made while loading popular sites, so that
+//! integration tests and benchmarks run against realistic rules rather than
+//! lists written for the occasion:
+//!
+//! ```ignore
+//! let list = Fixture::EasyList.load();
+//! for request in fixtures::requests() {
+//!     process_rules_for_request(&list, &request.as_request());
+//! }
+//! ```
+//!
+//! The snapshots keep a sample of each section of the lists, with the mix of
+//! URL filters, third-party rules, exceptions and element hiding rules of the
+//! full lists. EasyList and EasyPrivacy are written by The EasyList authors
+//! and distributed under the terms of the GPLv3 and CC BY-SA 3.0 licenses.
+
+use parse_list;
+use repr::OwnedRequest;
+use RuleList;
+
+/// A list embedded with the `fixtures` feature.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Fixture {
+    /// EasyList, blocking advertisements and hiding their placeholders.
+    EasyList,
+    /// EasyPrivacy, blocking analytics and tracking requests.
+    EasyPrivacy,
+}
+
+impl Fixture {
+    /// Every embedded list.
+    pub const ALL: [Fixture; 2] = [Fixture::EasyList, Fixture::EasyPrivacy];
+
+    /// The name of the list.
+    pub fn name(self) -> &'static str {
+        match self {
+            Fixture::EasyList => "EasyList",
+            Fixture::EasyPrivacy => "EasyPrivacy",
+        }
+    }
+
+    /// The JSON body of the list.
+    pub fn body(self) -> &'static str {
+        match self {
+            Fixture::EasyList => include_str!("../fixtures/easylist.json"),
+            Fixture::EasyPrivacy => include_str!("../fixtures/easyprivacy.json"),
+        }
+    }
+
+    /// Parse the list, named after the fixture.
+    pub fn load(self) -> RuleList {
+        let mut list = parse_list(self.body()).expect("the embedded lists are valid");
+        list.set_name(self.name());
+        list
+    }
+}
+
+/// The requests of the embedded corpus, in the format read by
+/// `content-blocker-bench`: each line holds a URL, a resource type and a load
+/// type. The requests have no document, context or headers.
+pub fn requests() -> Vec<OwnedRequest> {
+    include_str!("../fixtures/requests.txt").lines().filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            OwnedRequest {
+                url: fields[0].to_owned(),
+                resource_type: fields[1].parse().expect("the embedded corpus is valid"),
+                load_type: fields[2].parse().expect("the embedded corpus is valid"),
+                context: None,
+                opener: None,
+                document: None,
+                redirect_chain: vec![],
+                headers: None,
+            }
+        }).collect()
+}
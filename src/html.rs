@@ -0,0 +1,86 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use procedural::{ProceduralSelector, ProceduralStep, TextMatcher};
+use selector::is_valid_selector;
+
+/// Elements to remove from an HTML response before the document parses it,
+/// typically inline scripts that detect content blockers. The selector of an
+/// `html-filter` rule is written either as in uBlock Origin, eg.
+/// `script:has-text(adblock)`, or as in AdGuard, eg.
+/// `script[tag-content="adblock"][max-length="5000"]`, and is parsed into the
+/// predicate an HTML parser applies to each element.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HtmlFilter {
+    /// The selector as written in the rule.
+    pub source: String,
+    /// The CSS selector the elements must match, eg. `script`.
+    pub selector: String,
+    /// A condition on the text content of the elements.
+    pub text: Option<TextMatcher>,
+    /// The most characters the content of the elements may have, so that large
+    /// scripts mentioning the text in passing are kept.
+    pub max_length: Option<usize>,
+}
+
+/// Remove the AdGuard attribute `[name="value"]` from `css` and return its
+/// value, in which a quote is written twice. Fails if the value is not closed.
+fn take_attribute(css: &mut String, name: &str) -> Option<Option<String>> {
+    let opening = format!("[{}=\"", name);
+    let start = match css.find(&opening) {
+        Some(start) => start,
+        None => return Some(None),
+    };
+    let mut value = String::new();
+    let mut i = start + opening.len();
+    loop {
+        let quote = i + css[i..].find('"')?;
+        value.push_str(&css[i..quote]);
+        match css.as_bytes().get(quote + 1) {
+            Some(b'"') => {
+                value.push('"');
+                i = quote + 2;
+            }
+            Some(b']') => {
+                css.replace_range(start..quote + 2, "");
+                return Some(Some(value));
+            }
+            _ => return None,
+        }
+    }
+}
+
+impl HtmlFilter {
+    /// Parse the selector of an HTML filtering rule. Returns `None` if it uses
+    /// procedural operators other than a final `:has-text()`, or if the rest
+    /// is not a valid CSS selector.
+    pub fn parse(source: &str) -> Option<HtmlFilter> {
+        let procedural = ProceduralSelector::parse(source.trim())?;
+        let (mut css, mut text) = match procedural.steps.as_slice() {
+            [ProceduralStep::Css(css)] => (css.clone(), None),
+            [ProceduralStep::Css(css), ProceduralStep::HasText(text)] => (css.clone(), Some(text.clone())),
+            _ => return None,
+        };
+        if let Some(content) = take_attribute(&mut css, "tag-content")? {
+            if text.is_some() || content.is_empty() {
+                return None;
+            }
+            text = Some(TextMatcher::Contains(content));
+        }
+        let max_length = match take_attribute(&mut css, "max-length")? {
+            Some(length) => Some(length.parse().ok()?),
+            None => None,
+        };
+        let selector = css.trim();
+        if !is_valid_selector(selector) {
+            return None;
+        }
+        Some(HtmlFilter {
+            source: source.to_owned(),
+            selector: selector.to_owned(),
+            text,
+            max_length,
+        })
+    }
+}
@@ -0,0 +1,266 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use backend::MatchBackend;
+use prefilter::{GramSet, LiteralFilter};
+use repr::{DomainConstraint, DomainTarget, Reactions, Request, Rule, Trigger, UrlFilterAnchor};
+use smallvec::SmallVec;
+use std::collections::HashMap;
+use std::mem;
+use std::time::Instant;
+
+/// Buckets the rules of a list by the domains they are restricted to, so that
+/// matching a request only needs to consider the rules that could apply to its host.
+#[derive(Clone, Debug, Default)]
+pub struct DomainIndex {
+    /// Rules that can only match hosts equal to the key.
+    exact: HashMap<String, Vec<usize>>,
+    /// Rules that can only match hosts equal to, or a subdomain of, the key.
+    subdomain: HashMap<String, Vec<usize>>,
+    /// Rules that can match any host.
+    generic: Vec<usize>,
+}
+
+impl DomainIndex {
+    /// Build an index for `rules`.
+    pub fn new(rules: &[Rule]) -> DomainIndex {
+        let mut index = DomainIndex::default();
+        for (position, rule) in rules.iter().enumerate() {
+            let domains = anchor_domains(rule);
+            if domains.is_empty() {
+                index.generic.push(position);
+            }
+            for (domain, subdomains) in domains {
+                let buckets = if subdomains { &mut index.subdomain } else { &mut index.exact };
+                let bucket = buckets.entry(domain).or_default();
+                if bucket.last() != Some(&position) {
+                    bucket.push(position);
+                }
+            }
+        }
+        index
+    }
+
+    /// An estimate of the heap memory used by this index, in bytes.
+    pub fn heap_size(&self) -> usize {
+        let buckets = |map: &HashMap<String, Vec<usize>>| {
+            map.capacity() * (mem::size_of::<String>() + mem::size_of::<Vec<usize>>() + 1) +
+                map.iter().map(|(k, v)| k.capacity() + v.capacity() * mem::size_of::<usize>()).sum::<usize>()
+        };
+        buckets(&self.exact) + buckets(&self.subdomain) + self.generic.capacity() * mem::size_of::<usize>()
+    }
+
+    /// The positions of the rules that could match a request to `host`, in list order.
+    #[cfg(test)]
+    pub fn candidates(&self, host: Option<&str>) -> Vec<usize> {
+        let mut candidates = vec![];
+        self.candidates_into(host, &mut candidates);
+        candidates
+    }
+
+    /// Like `candidates`, but stores the positions in `candidates`, reusing its
+    /// memory rather than allocating.
+    pub fn candidates_into(&self, host: Option<&str>, candidates: &mut Vec<usize>) {
+        candidates.clear();
+        candidates.extend_from_slice(&self.generic);
+        let generic = candidates.len();
+        let mut suffix = host.unwrap_or("");
+        if let Some(bucket) = self.exact.get(suffix) {
+            candidates.extend_from_slice(bucket);
+        }
+        while !suffix.is_empty() {
+            if let Some(bucket) = self.subdomain.get(suffix) {
+                candidates.extend_from_slice(bucket);
+            }
+            suffix = match suffix.find('.') {
+                Some(dot) => &suffix[dot + 1..],
+                None => "",
+            };
+        }
+        if candidates.len() == generic {
+            return;
+        }
+        candidates[generic..].sort_unstable();
+        let mut specific = 0;
+        for i in generic..candidates.len() {
+            if specific == 0 || candidates[generic + specific - 1] != candidates[i] {
+                candidates[generic + specific] = candidates[i];
+                specific += 1;
+            }
+        }
+
+        // Move the specific rules out of the way, and merge them with the
+        // generic ones from the back, so that no unread position is overwritten.
+        let end = generic + specific;
+        candidates.resize(end + specific, 0);
+        candidates.copy_within(generic..end, end);
+        let (mut g, mut s) = (generic, specific);
+        while s > 0 {
+            if g > 0 && candidates[g - 1] > candidates[end + s - 1] {
+                candidates[g + s - 1] = candidates[g - 1];
+                g -= 1;
+            } else {
+                candidates[g + s - 1] = candidates[end + s - 1];
+                s -= 1;
+            }
+        }
+        candidates.truncate(end);
+    }
+}
+
+/// The domains that a request's host must match for `rule` to match, along with
+/// whether subdomains of each domain match too. An empty list means that the
+/// rule can match any host.
+fn anchor_domains(rule: &Rule) -> Vec<(String, bool)> {
+    if let Some(DomainConstraint::If(ref matcher)) = rule.trigger.domain_constraint {
+        // Constraints on the top-level document say nothing about the requested
+        // host, and wildcard-TLD domains cannot be looked up by host.
+        if rule.trigger.domain_target == DomainTarget::Request && !matcher.wildcard_tld {
+            return matcher.exact.iter().map(|d| (d.to_string(), false))
+                          .chain(matcher.subdomain.iter().map(|d| (d.to_string(), true)))
+                          .collect();
+        }
+    }
+    trigger_host_anchor(&rule.trigger).into_iter().collect()
+}
+
+/// The host that `trigger` can only match URLs of, along with whether its
+/// subdomains match too, like `host_anchor` but for either kind of URL filter.
+pub fn trigger_host_anchor(trigger: &Trigger) -> Option<(String, bool)> {
+    let pattern = trigger.url_filter.as_str();
+    match trigger.url_filter_anchor {
+        UrlFilterAnchor::Url => host_anchor(pattern),
+        // A name ending with a dot is the first labels of hosts under any domain.
+        UrlFilterAnchor::Host if pattern.ends_with('.') => None,
+        UrlFilterAnchor::Host => Some((pattern.to_owned(), true)),
+    }
+}
+
+/// Recognize URL filters of the form `^scheme://(sub\.)?example\.com/...`, which
+/// can only match hosts equal to `example.com` (or below it, if the optional
+/// subdomain group is present). Anything that does not fit this shape exactly is
+/// treated as unanchored.
+pub fn host_anchor(pattern: &str) -> Option<(String, bool)> {
+    // Alternations anywhere other than a port-or-path terminator could escape the anchor.
+    if pattern.replacen("(:|/)", "", 1).contains('|') {
+        return None;
+    }
+    let pattern = pattern.strip_prefix("(?i)").unwrap_or(pattern);
+    let rest = pattern.strip_prefix('^')?;
+    let separator = rest.find("://")?;
+    let scheme = &rest[..separator];
+    if !scheme.chars().all(|c| c.is_ascii_alphanumeric() || "?+-[]^:".contains(c)) {
+        return None;
+    }
+    let mut rest = &rest[separator + 3..];
+    rest = rest.strip_prefix('+').unwrap_or(rest);
+
+    const SUBDOMAIN_GROUPS: &[&str] = &[
+        "(www\\.)?", "([^/]*\\.)?", "([^/]+\\.)?", "([^:/]*\\.)?", "([^:/]+\\.)?",
+        "([^/:]*\\.)?", "([^/:]+\\.)?",
+    ];
+    let mut subdomains = false;
+    for group in SUBDOMAIN_GROUPS {
+        if let Some(after) = rest.strip_prefix(group) {
+            rest = after;
+            subdomains = true;
+            break;
+        }
+    }
+
+    let mut host = String::new();
+    let mut chars = rest.char_indices();
+    let terminator = loop {
+        match chars.next() {
+            Some((_, '\\')) => match chars.next() {
+                Some((_, '.')) => host.push('.'),
+                _ => return None,
+            },
+            Some((_, c)) if c.is_ascii_alphanumeric() || c == '-' => host.push(c.to_ascii_lowercase()),
+            Some((position, _)) => break &rest[position..],
+            None => break "",
+        }
+    };
+
+    // The host must be followed by something that ends it.
+    let terminated = terminator.starts_with('/') || terminator.starts_with(':') ||
+                     terminator.starts_with('$') || terminator.starts_with("(:|/)") ||
+                     terminator.starts_with('[') && !terminator.starts_with("[^") &&
+                     terminator[1..].find(']').is_some_and(|end| {
+                         end > 0 && terminator[1..end + 1].chars().all(|c| ":/?#".contains(c))
+                     });
+    if !terminated || host.is_empty() || host.starts_with('.') || host.ends_with('.') {
+        return None;
+    }
+    Some((host, subdomains))
+}
+
+/// Conditions under which matching stops before every candidate rule is evaluated.
+#[derive(Clone, Debug, Default)]
+pub struct MatchLimits<'a> {
+    /// Stop at the first matching rule marked as a block that cannot be overridden.
+    pub final_blocks: Option<&'a [bool]>,
+    /// Give up once this time has passed.
+    pub deadline: Option<Instant>,
+    /// Skip the rules marked as disabled.
+    pub disabled: Option<&'a [bool]>,
+}
+
+/// Store in `candidates` the positions of the rules that may match a request,
+/// in order: those that could apply to the request's host and whose required
+/// substrings appear in the request's URL.
+pub fn candidates_into(rule_count: usize,
+                       index: &DomainIndex,
+                       filter: &LiteralFilter,
+                       request: &Request,
+                       candidates: &mut Vec<usize>) {
+    candidates.clear();
+    // A rule matching the redirect chain may match a URL of any host, and needs
+    // its substrings in any one of the URLs.
+    let grams: SmallVec<[GramSet; 2]> =
+        Some(request.url.as_str()).into_iter()
+                                  .chain(request.redirect_chain.iter().map(|url| url.as_str()))
+                                  .map(GramSet::from_text)
+                                  .collect();
+    if grams.iter().all(|grams| filter.rejects_all(grams)) {
+        return;
+    }
+
+    // Credentials can make a URL look like it has a different host to a pattern.
+    if request.url.has_credentials() || !request.redirect_chain.is_empty() {
+        candidates.extend(0..rule_count);
+    } else {
+        index.candidates_into(request.url.host(), candidates);
+    }
+    candidates.retain(|&position| grams.iter().any(|grams| filter.may_match(position, grams)));
+}
+
+/// Match a request against the rules, only evaluating the candidate rules that
+/// `backend` finds for the request, which are stored in `candidates`. Returns
+/// the reactions, and `false` if the deadline of `limits` passed before
+/// matching finished.
+pub fn process_rules_for_request_indexed_impl<'a>(rules: &'a [Rule],
+                                                  backend: &dyn MatchBackend,
+                                                  limits: &MatchLimits,
+                                                  request: &Request,
+                                                  candidates: &mut Vec<usize>) -> (Reactions<'a>, bool) {
+    let mut reactions = Reactions::default();
+    backend.candidates_into(request, candidates);
+    for &position in candidates.iter() {
+        let rule = &rules[position];
+        if limits.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return (reactions, false);
+        }
+        if limits.disabled.is_some_and(|disabled| disabled[position]) {
+            continue;
+        }
+        if rule.trigger.matches(request) {
+            rule.action.process(position, &mut reactions);
+            if limits.final_blocks.is_some_and(|final_blocks| final_blocks[position]) {
+                break;
+            }
+        }
+    }
+    (reactions, true)
+}
@@ -0,0 +1,198 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use repr::ResourceType;
+use std::str::FromStr;
+
+/// The destination of a request as defined by the Fetch standard, which
+/// spec-compliant fetch stacks record for every request. Converts into the
+/// `ResourceType` that rules match it as, so that `Request::resource_type` can
+/// be given as `destination.into()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Destination {
+    /// The empty destination of `fetch()`, XMLHttpRequest, beacons and pings.
+    Empty,
+    /// An `<audio>` element.
+    Audio,
+    /// An audio worklet module.
+    AudioWorklet,
+    /// A top-level document.
+    Document,
+    /// An `<embed>` element.
+    Embed,
+    /// A font.
+    Font,
+    /// A `<frame>` element.
+    Frame,
+    /// An `<iframe>` element.
+    IFrame,
+    /// An image.
+    Image,
+    /// A JSON module.
+    Json,
+    /// A web application manifest.
+    Manifest,
+    /// An `<object>` element.
+    Object,
+    /// A paint worklet module.
+    PaintWorklet,
+    /// A report, such as a CSP violation report.
+    Report,
+    /// A script.
+    Script,
+    /// A service worker script.
+    ServiceWorker,
+    /// A shared worker script.
+    SharedWorker,
+    /// A stylesheet.
+    Style,
+    /// A `<track>` element.
+    Track,
+    /// A `<video>` element.
+    Video,
+    /// A federated identity request.
+    WebIdentity,
+    /// A dedicated worker script.
+    Worker,
+    /// An XSLT stylesheet.
+    Xslt,
+    /// A WebSocket connection. Fetch gives these the empty destination, but
+    /// `Sec-Fetch-Dest` reports them as `websocket`.
+    WebSocket,
+}
+
+impl FromStr for Destination {
+    type Err = ();
+
+    /// Parse a destination as serialized by Fetch and `Sec-Fetch-Dest`.
+    fn from_str(s: &str) -> Result<Destination, ()> {
+        Ok(match s {
+            "" | "empty" => Destination::Empty,
+            "audio" => Destination::Audio,
+            "audioworklet" => Destination::AudioWorklet,
+            "document" => Destination::Document,
+            "embed" => Destination::Embed,
+            "font" => Destination::Font,
+            "frame" => Destination::Frame,
+            "iframe" => Destination::IFrame,
+            "image" => Destination::Image,
+            "json" => Destination::Json,
+            "manifest" => Destination::Manifest,
+            "object" => Destination::Object,
+            "paintworklet" => Destination::PaintWorklet,
+            "report" => Destination::Report,
+            "script" => Destination::Script,
+            "serviceworker" => Destination::ServiceWorker,
+            "sharedworker" => Destination::SharedWorker,
+            "style" => Destination::Style,
+            "track" => Destination::Track,
+            "video" => Destination::Video,
+            "webidentity" => Destination::WebIdentity,
+            "worker" => Destination::Worker,
+            "xslt" => Destination::Xslt,
+            "websocket" => Destination::WebSocket,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl From<Destination> for ResourceType {
+    fn from(destination: Destination) -> ResourceType {
+        match destination {
+            Destination::Document | Destination::Frame | Destination::IFrame => ResourceType::Document,
+            Destination::Image => ResourceType::Image,
+            Destination::Style | Destination::Xslt => ResourceType::StyleSheet,
+            Destination::Script | Destination::AudioWorklet | Destination::PaintWorklet => ResourceType::Script,
+            Destination::Worker => ResourceType::Worker,
+            Destination::SharedWorker => ResourceType::SharedWorker,
+            Destination::ServiceWorker => ResourceType::ServiceWorker,
+            Destination::Font => ResourceType::Font,
+            Destination::Audio | Destination::Video | Destination::Track => ResourceType::Media,
+            Destination::WebSocket => ResourceType::WebSocket,
+            Destination::Report => ResourceType::CspReport,
+            Destination::Empty | Destination::Embed | Destination::Object | Destination::Manifest |
+            Destination::Json | Destination::WebIdentity => ResourceType::Raw,
+        }
+    }
+}
+
+impl Destination {
+    /// The resource type of a request to this destination, given whether the
+    /// request has the `keepalive` flag set. Fetch has no destination for
+    /// beacons, but `navigator.sendBeacon` sends keepalive requests with the
+    /// empty destination, which are `Beacon`s rather than `Raw`.
+    pub fn resource_type(self, keepalive: bool) -> ResourceType {
+        match self {
+            Destination::Empty if keepalive => ResourceType::Beacon,
+            destination => destination.into(),
+        }
+    }
+}
+
+impl ResourceType {
+    /// Guess the resource type of a request from whatever context is available:
+    /// the scheme of a WebSocket URL, the Fetch `destination` of the request,
+    /// its `Accept` header, and finally the file extension of the URL. The first
+    /// of these that is conclusive is used, and requests that cannot be
+    /// classified are `Raw`.
+    pub fn infer(url: &str, accept: Option<&str>, destination: Option<&str>) -> ResourceType {
+        from_scheme(url).or_else(|| destination.and_then(from_destination))
+                   .or_else(|| accept.and_then(from_accept))
+                   .or_else(|| from_extension(url))
+                   .unwrap_or(ResourceType::Raw)
+    }
+}
+
+fn from_scheme(url: &str) -> Option<ResourceType> {
+    let scheme = &url[..url.find(':')?];
+    if scheme.eq_ignore_ascii_case("ws") || scheme.eq_ignore_ascii_case("wss") {
+        Some(ResourceType::WebSocket)
+    } else {
+        None
+    }
+}
+
+fn from_destination(destination: &str) -> Option<ResourceType> {
+    // The empty destination covers too many kinds of requests to be conclusive.
+    match destination.parse() {
+        Ok(Destination::Empty) | Err(()) => None,
+        Ok(destination) => Some(destination.into()),
+    }
+}
+
+fn from_accept(accept: &str) -> Option<ResourceType> {
+    // Browsers list the most specific type first, eg. `image/avif,image/webp,*/*`.
+    let first = accept.split(',').next()?.split(';').next()?.trim().to_ascii_lowercase();
+    Some(match &*first {
+        "text/html" | "application/xhtml+xml" => ResourceType::Document,
+        "image/svg+xml" => ResourceType::SVGDocument,
+        "text/css" => ResourceType::StyleSheet,
+        "application/javascript" | "text/javascript" | "application/ecmascript" => ResourceType::Script,
+        t if t.starts_with("image/") => ResourceType::Image,
+        t if t.starts_with("font/") || t.starts_with("application/font-") => ResourceType::Font,
+        t if t.starts_with("audio/") || t.starts_with("video/") => ResourceType::Media,
+        _ => return None,
+    })
+}
+
+fn from_extension(url: &str) -> Option<ResourceType> {
+    let path = url.split(['?', '#']).next()?;
+    let path = match path.find("://") {
+        Some(separator) => &path[separator + 3..],
+        None => path,
+    };
+    let file = &path[path.find('/')?..];
+    let extension = file.rsplit('/').next()?.rsplit_once('.')?.1.to_ascii_lowercase();
+    Some(match &*extension {
+        "html" | "htm" | "xhtml" => ResourceType::Document,
+        "png" | "jpg" | "jpeg" | "gif" | "webp" | "avif" | "bmp" | "ico" => ResourceType::Image,
+        "svg" => ResourceType::SVGDocument,
+        "css" => ResourceType::StyleSheet,
+        "js" | "mjs" => ResourceType::Script,
+        "woff" | "woff2" | "ttf" | "otf" | "eot" => ResourceType::Font,
+        "mp3" | "mp4" | "m4a" | "ogg" | "oga" | "ogv" | "wav" | "webm" | "m3u8" | "vtt" => ResourceType::Media,
+        _ => return None,
+    })
+}
@@ -0,0 +1,41 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Shares a single allocation between equal strings, such as the domains and
+/// selectors that large lists repeat across thousands of rules.
+#[derive(Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    /// Create an empty interner.
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    /// Return the shared copy of `s`, adding it if it has not been seen before.
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(shared) = self.strings.get(s) {
+            return shared.clone();
+        }
+        let shared: Arc<str> = Arc::from(s);
+        self.strings.insert(shared.clone());
+        shared
+    }
+
+    /// Replace `s` with the shared copy of its contents, or make it the shared
+    /// copy if there is none yet, so that its allocation is reused.
+    pub fn share(&mut self, s: &mut Arc<str>) {
+        match self.strings.get(&**s) {
+            Some(shared) => *s = shared.clone(),
+            None => {
+                self.strings.insert(s.clone());
+            }
+        }
+    }
+}
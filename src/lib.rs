@@ -4,37 +4,865 @@
 
 //! A library for parsing [Safari-style content blocking lists](https://developer.apple.com/library/ios/documentation/Extensions/Conceptual/ContentBlockingRules/CreatingRules/CreatingRules.html)
 //! and evaluating them against network requests.
+//!
+//! The enums describing resource types, load types, actions, reactions and
+//! errors are marked `#[non_exhaustive]`, since list formats gain new values
+//! over time. Code matching on them needs a catch-all arm, which should treat
+//! an unrecognized reaction as something the embedder does not support and
+//! skip it, and an unrecognized resource type like `ResourceType::Raw`.
 
 #![deny(missing_docs)]
 
+#[cfg(feature = "simd")]
+extern crate aho_corasick;
+extern crate base64;
+#[cfg(feature = "selector-validation")]
+extern crate cssparser;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "proxy")]
+extern crate http;
+extern crate md5;
+#[cfg(feature = "quickcheck")]
+extern crate quickcheck;
 extern crate regex;
+#[cfg(feature = "automata")]
+extern crate regex_automata;
+extern crate regex_syntax;
+#[cfg(feature = "selector-validation")]
+extern crate selectors;
 extern crate serde_json;
+extern crate smallvec;
+#[cfg(feature = "proxy")]
+extern crate tower_layer;
+#[cfg(feature = "proxy")]
+extern crate tower_service;
 extern crate url;
 
+mod allowlist;
+mod backend;
+mod cache;
+mod compiled;
+mod conformance;
+mod cosmetic;
+mod coverage;
+mod delta;
+mod diff;
+mod dns;
+mod engine;
+mod entities;
+#[cfg(feature = "fixtures")]
+pub mod fixtures;
+mod html;
+mod index;
+mod infer;
+mod intern;
+mod lint;
+mod memory;
+mod merge;
+mod metadata;
+mod optimize;
+mod pac;
 mod parse;
+mod precompile;
+mod prefilter;
+mod procedural;
+mod psl;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+mod reachability;
+mod profile;
+#[cfg(feature = "quickcheck")]
+pub mod properties;
 mod repr;
+mod request_url;
+mod resources;
+mod safari;
+mod selector;
+mod stats;
+mod stream;
+#[cfg(feature = "servo")]
+pub mod servo;
+#[cfg(feature = "subscriptions")]
+pub mod subscriptions;
+mod verify;
+mod view;
 
-pub use parse::Error;
-use parse::parse_list_impl;
-pub use repr::{ResourceType, LoadType, Request, Reaction};
-use repr::{Rule, process_rules_for_request_impl};
+pub use parse::{Error, ParseOptions, RuleLimitPolicy, TrustLevel, UNTRUSTED_PATTERN_LIMIT, ValidationOptions};
+pub use allowlist::Allowlist;
+pub use backend::{MatchArena, MatchBackend, MatchBackendKind};
+pub use cache::{CachedList, ListCache};
+use compiled::{compile_impl, process_rules_for_request_compiled_impl};
+pub use compiled::CompiledRuleList;
+use conformance::{record_golden_vectors_impl, run_conformance_vectors_impl};
+pub use conformance::{Backend, CompiledBackend, ConformanceFailure, ConformanceReport, RuleListBackend};
+use cosmetic::CosmeticIndex;
+use coverage::coverage_impl;
+pub use coverage::{Coverage, RuleCoverage};
+use delta::{apply_delta_impl, create_delta_impl};
+use diff::diff_impl;
+use index::{MatchLimits, process_rules_for_request_indexed_impl};
+pub use diff::RuleListDelta;
+use dns::to_dns_blocklist_impl;
+pub use dns::{DnsBlocklist, DnsFormat, DnsSkip, DnsSkipReason};
+pub use engine::{CnameResolver, Engine};
+use entities::parse_entity_list_impl;
+pub use entities::{CategorizedReactions, EntityList, Tracker};
+pub use html::HtmlFilter;
+pub use infer::Destination;
+use lint::{lint_impl, lint_list_impl};
+pub use lint::{LintFinding, LintKind, RegexCost, Severity};
+use memory::memory_usage_impl;
+pub use memory::MemoryReport;
+use merge::merge_impl;
+pub use merge::MergePolicy;
+use metadata::parse_list_with_metadata_impl;
+pub use metadata::ListMetadata;
+use optimize::optimize_impl;
+pub use optimize::OptimizationReport;
+use pac::to_pac_impl;
+pub use pac::{PacFile, PacSkip, PacSkipReason};
+use parse::{ParsedList, parse_list_strict_impl, parse_list_with_options_impl};
+use precompile::{precompile_list_for_build_impl, precompile_list_impl};
+pub use procedural::{ProceduralSelector, ProceduralStep, TextMatcher, Upward};
+use reachability::Reachability;
+pub use profile::{Profile, RuleProfile, RuleTiming};
+pub use psl::{PublicSuffixList, public_suffix_list, registrable_domain, set_public_suffix_list};
+pub use repr::{ResourceType, LoadType, MatchPolicy, Request, Reaction, ReactionRef, Reactions};
+pub use repr::{BoundedReactions, CookieScope, DomainTarget, HidingHints, Opener, OwnedOpener, OwnedRequest, Provenance};
+pub use repr::{PreparedRequest, Rule, RuleId, ShadowEvaluation, SourceLocation, UrlFilterAnchor};
+pub use request_url::{PreparedUrl, RequestHeaders, RequestUrl};
+pub use resources::Resource;
+use safari::to_safari_impl;
+pub use safari::{SAFARI_RULE_LIMIT, SafariExport, SafariSkip, SafariSkipReason};
+use repr::{process_rules_for_request_profiled_impl, warm_up_impl};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+pub use stats::CompileStats;
+use stats::compile_stats_impl;
+use stream::parse_list_from_reader_impl;
+pub use verify::{SignatureVerifier, checksum};
+use verify::{verify_checksum_impl, verify_signature_impl};
+pub use view::{ActionType, RuleView, Rules};
+use view::{count_by_resource_type_impl, rules_iter};
 
 #[cfg(test)]
 mod tests;
 
 /// An encapsulation of a list of parsed rules.
-pub struct RuleList(Vec<Rule>);
+#[derive(Clone)]
+pub struct RuleList {
+    name: Option<String>,
+    rules: Vec<Rule>,
+    backend: Arc<dyn MatchBackend>,
+    backend_kind: MatchBackendKind,
+    reachability: Reachability,
+    /// The element hiding rules split into generic and site-specific ones.
+    cosmetics: CosmeticIndex,
+    disabled_groups: Vec<String>,
+    /// Whether each rule belongs to a disabled group, if any group is disabled.
+    disabled: Option<Box<[bool]>>,
+    stats: CompileStats,
+    /// The identifiers of the rules, computed when first needed.
+    ids: OnceLock<Box<[RuleId]>>,
+}
+
+impl fmt::Debug for RuleList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RuleList")
+         .field("name", &self.name)
+         .field("rules", &self.rules)
+         .finish()
+    }
+}
+
+/// An empty list.
+impl Default for RuleList {
+    fn default() -> RuleList {
+        RuleList::new(vec![])
+    }
+}
+
+impl RuleList {
+    fn new(rules: Vec<Rule>) -> RuleList {
+        RuleList::with_backend(rules, MatchBackendKind::default())
+    }
+
+    fn with_backend(rules: Vec<Rule>, backend_kind: MatchBackendKind) -> RuleList {
+        let start = Instant::now();
+        let backend: Arc<dyn MatchBackend> = backend_kind.build(&rules).into();
+        let reachability = Reachability::new(&rules);
+        let cosmetics = CosmeticIndex::new(&rules);
+        let stats = compile_stats_impl(&rules, &*backend, start.elapsed());
+        RuleList {
+            name: None,
+            rules,
+            backend,
+            backend_kind,
+            reachability,
+            cosmetics,
+            disabled_groups: vec![],
+            disabled: None,
+            stats,
+            ids: OnceLock::new(),
+        }
+    }
+
+    /// Build a list of parsed rules, noting the rules left out while parsing them.
+    fn parsed(parsed: ParsedList, options: &ParseOptions) -> RuleList {
+        let mut list = RuleList::with_backend(parsed.rules, options.match_backend);
+        list.stats.rules_skipped = parsed.skipped;
+        list.stats.dropped_rules = parsed.dropped;
+        list
+    }
+
+    /// The rules of this list that are in no disabled group, along with their
+    /// positions in the list, for the exports, which have no notion of groups.
+    fn enabled_rules(&self) -> (Cow<'_, [Rule]>, Vec<usize>) {
+        match self.disabled {
+            None => (Cow::Borrowed(&self.rules), (0..self.rules.len()).collect()),
+            Some(ref disabled) => {
+                let positions: Vec<usize> = (0..self.rules.len()).filter(|&position| !disabled[position]).collect();
+                (positions.iter().map(|&position| self.rules[position].clone()).collect(), positions)
+            }
+        }
+    }
+
+    /// The options that the rules of a delta for this list are parsed with, so
+    /// that they take the domain target of its rules.
+    fn delta_options(&self) -> ParseOptions {
+        let domain_target = self.rules.first().map_or(DomainTarget::default(), |rule| rule.trigger.domain_target);
+        ParseOptions { domain_target, ..ParseOptions::default() }
+    }
+
+    /// Replace the rules of this list, keeping its name, disabled groups, match
+    /// backend and the rules left out while parsing it.
+    fn replace_rules(&mut self, rules: Vec<Rule>) {
+        let name = self.name.take();
+        let disabled_groups = mem::take(&mut self.disabled_groups);
+        let stats = mem::take(&mut self.stats);
+        *self = RuleList::with_backend(rules, self.backend_kind);
+        self.stats.carry_over(stats);
+        self.name = name;
+        self.disabled_groups = disabled_groups;
+        self.update_disabled();
+    }
+
+    fn update_disabled(&mut self) {
+        self.disabled = if self.disabled_groups.is_empty() {
+            None
+        } else {
+            Some(self.rules.iter().map(|rule| {
+                rule.groups.iter().any(|group| self.disabled_groups.iter().any(|disabled| **group == **disabled))
+            }).collect())
+        };
+    }
+
+    /// Combine `lists` into a single list, resolving conflicts between their
+    /// exceptions as described by `policy` and dropping duplicate rules. The
+    /// groups disabled in any of the lists are disabled in the merged list, which
+    /// uses the match backend of the first list.
+    pub fn merge<I: IntoIterator<Item=RuleList>>(lists: I, policy: MergePolicy) -> RuleList {
+        let mut disabled_groups = vec![];
+        let mut backend_kind = None;
+        let mut skipped = 0;
+        let mut dropped = vec![];
+        let lists = lists.into_iter().map(|list| {
+            backend_kind.get_or_insert(list.backend_kind);
+            skipped += list.stats.rules_skipped;
+            dropped.extend(list.stats.dropped_rules);
+            disabled_groups.extend(list.disabled_groups);
+            list.rules
+        }).collect();
+        let mut merged = RuleList::with_backend(merge_impl(lists, policy), backend_kind.unwrap_or_default());
+        merged.stats.rules_skipped = skipped;
+        merged.stats.dropped_rules = dropped;
+        disabled_groups.sort_unstable();
+        disabled_groups.dedup();
+        merged.disabled_groups = disabled_groups;
+        merged.update_disabled();
+        merged
+    }
+
+    /// The groups that the rules of this list belong to, in sorted order.
+    pub fn groups(&self) -> Vec<&str> {
+        let mut groups: Vec<&str> = self.rules.iter().flat_map(|rule| rule.groups.iter().map(|g| &**g)).collect();
+        groups.sort_unstable();
+        groups.dedup();
+        groups
+    }
+
+    /// Whether the rules of `group` apply. Every group is enabled initially.
+    pub fn is_group_enabled(&self, group: &str) -> bool {
+        !self.disabled_groups.iter().any(|disabled| disabled == group)
+    }
+
+    /// Enable or disable every rule of `group` for the following requests. A rule
+    /// belonging to several groups only applies while all of them are enabled.
+    /// Disabled rules are still included by `to_bytes` and the exports.
+    pub fn set_group_enabled(&mut self, group: &str, enabled: bool) {
+        if enabled == self.is_group_enabled(group) {
+            return;
+        }
+        if enabled {
+            self.disabled_groups.retain(|disabled| disabled != group);
+        } else {
+            self.disabled_groups.push(group.to_owned());
+        }
+        self.update_disabled();
+    }
+
+    /// The backend finding the rules that may match each request.
+    pub fn match_backend(&self) -> MatchBackendKind {
+        self.backend_kind
+    }
+
+    /// Find the rules that may match each request with the backend of `kind`,
+    /// building it for the rules of this list. Every backend finds the same
+    /// reactions, but they differ in speed and memory use.
+    pub fn set_match_backend(&mut self, kind: MatchBackendKind) {
+        if kind != self.backend_kind {
+            let start = Instant::now();
+            self.backend = kind.build(&self.rules).into();
+            self.backend_kind = kind;
+            // Only the match backend is rebuilt.
+            let stats = compile_stats_impl(&self.rules, &*self.backend, start.elapsed());
+            let previous = mem::replace(&mut self.stats, stats);
+            self.stats.carry_over(previous);
+        }
+    }
+
+    /// How this list was parsed and prepared for matching.
+    pub fn compile_stats(&self) -> &CompileStats {
+        &self.stats
+    }
+
+    /// Build the parts of this list that are otherwise built the first time they
+    /// are needed: the regexes of the URL filters and header conditions, and the
+    /// rule identifiers. This takes a while for large lists, so it is meant to
+    /// run on a background thread after startup, so that the first requests
+    /// matched do not wait for the regexes they reach to compile.
+    pub fn warm_up(&self) {
+        warm_up_impl(&self.rules);
+        self.ids();
+    }
+
+    /// The name of this list, as reported in the provenance of its reactions.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Name this list, eg. after the subscription it was downloaded from.
+    pub fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_owned());
+    }
+
+    /// The number of rules in this list.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Whether this list contains no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Iterate over read-only views of the rules in this list, in order.
+    pub fn iter(&self) -> Rules<'_> {
+        rules_iter(&self.rules)
+    }
+
+    /// The identifiers of the rules in this list, in order.
+    pub fn ids(&self) -> &[RuleId] {
+        self.ids.get_or_init(|| self.rules.iter().map(Rule::id).collect())
+    }
+
+    /// The position of the first rule identified by `id`, eg. to find a rule
+    /// again after the list was updated.
+    pub fn position(&self, id: RuleId) -> Option<usize> {
+        self.ids().iter().position(|&other| other == id)
+    }
+
+    /// Look up the rule at position `index`.
+    pub fn get(&self, index: usize) -> Option<RuleView<'_>> {
+        self.iter().nth(index)
+    }
+
+    /// Iterate over the rules performing the given kind of action.
+    pub fn with_action(&self, action: ActionType) -> impl Iterator<Item=RuleView<'_>> {
+        self.iter().filter(move |rule| rule.action_type() == action)
+    }
+
+    /// Iterate over the rules whose `if-domain` or `unless-domain` constraints
+    /// cover `domain`, ie. the rules that behave differently for that site.
+    pub fn referencing_domain<'a>(&'a self, domain: &'a str) -> impl Iterator<Item=RuleView<'a>> {
+        self.iter().filter(move |rule| rule.references_domain(domain))
+    }
+
+    /// A stylesheet hiding the elements of the `css-display-none` rules that
+    /// apply on every page, to be injected into each page as is. Rules with a
+    /// domain constraint, or that an exception could cancel, are left to
+    /// `specific_selectors_for`.
+    pub fn generic_stylesheet(&self) -> String {
+        self.cosmetics.generic_stylesheet(&self.rules, self.disabled.as_deref())
+    }
+
+    /// The selectors hiding elements on the pages of `host` in addition to the
+    /// generic stylesheet, after applying the exceptions for that host. Rules
+    /// whose triggers depend on more than the host of the page, such as its
+    /// URL or resource type, are in neither set and are still reported when
+    /// the page is matched as a request.
+    pub fn specific_selectors_for(&self, host: &str) -> Vec<&str> {
+        self.cosmetics.specific_selectors_for(&self.rules, self.disabled.as_deref(), host)
+    }
+
+    /// Iterate over the `ignore-previous-rules` rules that can never take effect,
+    /// because no earlier rule with a visible effect can match the same requests.
+    pub fn unreachable_exceptions(&self) -> impl Iterator<Item=RuleView<'_>> {
+        self.reachability.unreachable_exceptions.iter().filter_map(move |&index| self.get(index))
+    }
+
+    /// Report rules that can never take effect: blocking rules whose requests an
+    /// earlier rule always blocks already, rules restricted to no resource types,
+    /// and exceptions preceding every rule they could cancel. Shadowing is only
+    /// detected between URL filters that are identical, match everything, or are
+    /// plain strings contained in one another, so not every redundant rule is found.
+    /// Suspicious rules, such as blocking rules matching every request, URL
+    /// filters without literal text or with constructs that are costly for the
+    /// regex engine, are reported as warnings.
+    pub fn lint(&self) -> Vec<LintFinding> {
+        lint_impl(&self.rules, &self.reachability)
+    }
+
+    /// Evaluate every request of `corpus` against this list, counting how often
+    /// each rule matched and how often its reaction was reported.
+    pub fn coverage<'a, I: IntoIterator<Item=&'a OwnedRequest>>(&self, corpus: I) -> Coverage {
+        coverage_impl(&self.rules, &*self.backend, self.disabled.as_deref(), corpus)
+    }
+
+    /// Count the rules that can apply to each resource type. Rules without a
+    /// resource type restriction are counted for every type.
+    pub fn count_by_resource_type(&self) -> HashMap<ResourceType, usize> {
+        count_by_resource_type_impl(&self.rules)
+    }
+
+    /// Estimate the memory used by this list. Only URL filters that have been
+    /// compiled by matching requests contribute to `regex_programs`, and measuring
+    /// them is slow, so this is intended for diagnostics rather than routine use.
+    pub fn memory_usage(&self) -> MemoryReport {
+        memory_usage_impl(self)
+    }
+
+    /// Remove redundant rules from this list: rules followed by an identical rule
+    /// are dropped, and element hiding rules with identical triggers are combined
+    /// into a single selector list. The optimized list produces equivalent reactions
+    /// for every request, although duplicate reactions are collapsed and hidden
+    /// selectors may be reported together.
+    pub fn optimize(&mut self) -> OptimizationReport {
+        let rules = mem::take(&mut self.rules);
+        let (rules, report) = optimize_impl(rules);
+        self.replace_rules(rules);
+        report
+    }
+
+    /// Serialize this list into a binary format that can be evaluated in place
+    /// by `CompiledRuleList`, eg. after writing it to a file that many processes
+    /// memory-map.
+    /// The compiled format has no groups, so rules in disabled groups are left out.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        compile_impl(&self.enabled_rules().0)
+    }
+
+    /// Serialize this list as a JSON list in the format read by `parse_list`,
+    /// which parses back into an equivalent list.
+    pub fn to_json_string(&self) -> String {
+        serde_json::Value::Array(self.rules.iter().map(Rule::to_json).collect()).to_string()
+    }
+
+    /// Export this list as Safari content blockers of at most `max_rules` rules
+    /// each, eg. `SAFARI_RULE_LIMIT`. Element hiding rules with identical triggers
+    /// are combined to save rules, and rules Safari cannot represent are reported
+    /// as skipped. Safari compares domain constraints with the top-level
+    /// document, so they are only exported once the domain target of the list
+    /// is set to `DomainTarget::TopDocument`. Fails if the list has too many
+    /// `ignore-previous-rules` rules to be split under the limit. Rules in
+    /// disabled groups are left out.
+    pub fn to_safari(&self, max_rules: usize) -> Result<SafariExport, Error> {
+        let (rules, positions) = self.enabled_rules();
+        let mut export = to_safari_impl(&rules, max_rules)?;
+        for skip in &mut export.skipped {
+            skip.rule = positions[skip.rule];
+        }
+        Ok(export)
+    }
+
+    /// Export the rules blocking whole hosts as configuration for a DNS resolver,
+    /// so that the same list can also block requests at the network level. Other
+    /// blocking rules, and exceptions that could unblock exported hosts, are
+    /// reported as skipped. Rules in disabled groups are left out.
+    pub fn to_dns_blocklist(&self, format: DnsFormat) -> DnsBlocklist {
+        let (rules, positions) = self.enabled_rules();
+        let mut blocklist = to_dns_blocklist_impl(&rules, format);
+        for skip in &mut blocklist.skipped {
+            skip.rule = positions[skip.rule];
+        }
+        blocklist
+    }
+
+    /// Convert the rules blocking whole hosts, or path prefixes on a host, into a
+    /// Proxy Auto-Config script sending those requests to the proxy at `blackhole`
+    /// (eg. `127.0.0.1:9`). Browsers only pass the path of `http` URLs to PAC
+    /// scripts, so path prefixes have no effect on `https` requests. Rules in
+    /// disabled groups are left out.
+    pub fn to_pac(&self, blackhole: &str) -> PacFile {
+        let (rules, positions) = self.enabled_rules();
+        let mut pac = to_pac_impl(&rules, blackhole);
+        for skip in &mut pac.skipped {
+            skip.rule = positions[skip.rule];
+        }
+        pac
+    }
+
+    /// Limit every rule of this list to the given browsing contexts, eg. so that
+    /// a stricter list only applies to private browsing. Rules that already name
+    /// their contexts keep those that are among `contexts`.
+    pub fn limit_to_contexts(&mut self, contexts: &[&str]) {
+        let contexts: Vec<Arc<str>> = contexts.iter().map(|&context| context.into()).collect();
+        let mut rules = mem::take(&mut self.rules);
+        for rule in &mut rules {
+            rule.trigger.limit_to_contexts(&contexts);
+        }
+        self.replace_rules(rules);
+    }
+
+    /// Choose what the `if-domain` and `unless-domain` constraints of every rule
+    /// of this list are evaluated against. Lists written for Safari expect them
+    /// to name the sites the rules apply on, ie. `DomainTarget::TopDocument`.
+    pub fn set_domain_target(&mut self, target: DomainTarget) {
+        let mut rules = mem::take(&mut self.rules);
+        for rule in &mut rules {
+            rule.trigger.domain_target = target;
+        }
+        self.replace_rules(rules);
+    }
+
+    /// Replace the rules of this list with the result of applying a delta created
+    /// by `create_delta`. The list is left unmodified if the delta is malformed or
+    /// refers to rules that this list does not contain. Inserted rules take the
+    /// domain target of the rules of this list.
+    pub fn apply_delta(&mut self, delta: &str) -> Result<(), Error> {
+        let rules = apply_delta_impl(&self.rules, delta, &self.delta_options())?;
+        self.replace_rules(rules);
+        Ok(())
+    }
+}
 
 /// Attempt to match the given request against the provided rules. Returns a list
 /// of actions to take in response; an empty list means that the request should
 /// continue unmodified.
 pub fn process_rules_for_request(rules: &RuleList, request: &Request) -> Vec<Reaction> {
-    process_rules_for_request_impl(&rules.0, request)
+    process_rules_for_request_borrowed(rules, request).to_vec()
+}
+
+/// Like `process_rules_for_request`, but avoids allocating for the common case
+/// of few reactions, and borrows selectors from `rules` instead of copying them.
+pub fn process_rules_for_request_borrowed<'a>(rules: &'a RuleList, request: &Request) -> Reactions<'a> {
+    process_rules_for_request_with_policy(rules, request, MatchPolicy::All)
+}
+
+/// Like `process_rules_for_request_borrowed`, but allows matching to stop early
+/// according to `policy`.
+pub fn process_rules_for_request_with_policy<'a>(rules: &'a RuleList,
+                                                 request: &Request,
+                                                 policy: MatchPolicy) -> Reactions<'a> {
+    let final_blocks = match policy {
+        MatchPolicy::All => None,
+        MatchPolicy::FirstBlock => Some(&rules.reachability.final_blocks[..]),
+    };
+    let limits = MatchLimits {
+        final_blocks,
+        deadline: None,
+        disabled: rules.disabled.as_deref(),
+    };
+    process_rules_for_request_indexed_impl(&rules.rules, &*rules.backend, &limits, request, &mut vec![]).0
+}
+
+/// Like `process_rules_for_request_borrowed`, but does its work in the memory
+/// of `arena`, which is reused from one request to the next. Embedders with
+/// soft real-time constraints can keep an arena per thread, so that matching
+/// does not allocate once the arena has grown to fit.
+pub fn process_rules_for_request_in<'a>(rules: &'a RuleList,
+                                        request: &Request,
+                                        arena: &mut MatchArena) -> Reactions<'a> {
+    let limits = MatchLimits {
+        final_blocks: None,
+        deadline: None,
+        disabled: rules.disabled.as_deref(),
+    };
+    process_rules_for_request_indexed_impl(&rules.rules, &*rules.backend, &limits, request, &mut arena.candidates).0
+}
+
+/// Like `process_rules_for_request`, but pairs each reaction with the list and
+/// rule that produced it.
+pub fn process_rules_for_request_with_provenance<'a>(rules: &'a RuleList,
+                                                     request: &Request) -> Vec<(Reaction, Provenance<'a>)> {
+    let reactions = process_rules_for_request_borrowed(rules, request);
+    reactions.iter().zip(reactions.rules()).map(|(reaction, &rule)| {
+        (reaction.into_owned(), Provenance { list: rules.name(), rule, id: rules.ids()[rule] })
+    }).collect()
+}
+
+/// Like `process_rules_for_request`, but pairs each `HideMatchingElements`
+/// reaction with hints about the rule that produced it. Generic selectors are
+/// those of `RuleList::generic_stylesheet`.
+pub fn process_rules_for_request_with_hiding_hints(rules: &RuleList,
+                                                   request: &Request) -> Vec<(Reaction, Option<HidingHints>)> {
+    let reactions = process_rules_for_request_borrowed(rules, request);
+    reactions.iter().zip(reactions.rules()).map(|(reaction, &rule)| {
+        let hints = match *reaction {
+            ReactionRef::HideMatchingElements(_) => {
+                Some(HidingHints { generic: rules.cosmetics.is_generic(rule), rule: rules.ids()[rule] })
+            }
+            _ => None,
+        };
+        (reaction.into_owned(), hints)
+    }).collect()
+}
+
+/// Like `process_rules_for_request`, but also reports the tracker operating the
+/// requested host according to `entities`, so that user interfaces can explain
+/// what was blocked. No tracker is reported for requests without reactions.
+pub fn process_rules_for_request_categorized<'a>(rules: &RuleList,
+                                                 entities: &'a EntityList,
+                                                 request: &Request) -> CategorizedReactions<'a> {
+    let reactions = process_rules_for_request(rules, request);
+    let tracker = if reactions.is_empty() {
+        None
+    } else {
+        request.url.host().and_then(|host| entities.lookup(host))
+    };
+    CategorizedReactions {
+        reactions,
+        tracker,
+    }
+}
+
+/// The element hiding and styling reactions for the document of a frame at
+/// `frame_url` inside the page at `top_url`, or for the page itself if both
+/// are the same. The frame is matched as a document request of the page, so
+/// domain constraints are compared with the frame, or with the page for lists
+/// whose domain target is `DomainTarget::TopDocument`. Frames without a host of
+/// their own, such as `about:blank` frames, get the reactions of the page.
+pub fn cosmetic_rules_for_frame(rules: &RuleList,
+                                frame_url: &dyn RequestUrl,
+                                top_url: &dyn RequestUrl) -> Vec<Reaction> {
+    let url = if frame_url.host().is_some() { frame_url } else { top_url };
+    let top_level = url.as_str() == top_url.as_str();
+    let request = Request {
+        url,
+        resource_type: ResourceType::Document,
+        load_type: if top_level { LoadType::FirstParty } else { LoadType::by_site(url, top_url) },
+        context: None,
+        opener: None,
+        document: if top_level { None } else { Some(top_url) },
+        redirect_chain: &[],
+        headers: None,
+    };
+    process_rules_for_request_borrowed(rules, &request).into_iter().filter(|reaction| {
+        matches!(*reaction, ReactionRef::HideMatchingElements(_) | ReactionRef::HideProceduralElements(_) |
+                            ReactionRef::InjectStyle { .. })
+    }).map(ReactionRef::into_owned).collect()
+}
+
+/// Like `process_rules_for_request`, but gives up once `budget` has been spent,
+/// so that a pathological list cannot stall the caller indefinitely. When the
+/// result is incomplete, allowing the request unmodified is the conservative choice.
+pub fn process_rules_for_request_with_deadline(rules: &RuleList,
+                                               request: &Request,
+                                               budget: Duration) -> BoundedReactions {
+    let limits = MatchLimits {
+        final_blocks: None,
+        deadline: Some(Instant::now() + budget),
+        disabled: rules.disabled.as_deref(),
+    };
+    let (reactions, complete) =
+        process_rules_for_request_indexed_impl(&rules.rules, &*rules.backend, &limits, request, &mut vec![]);
+    BoundedReactions {
+        reactions: reactions.to_vec(),
+        complete,
+    }
+}
+
+/// Evaluate a request against `primary`, and against `candidate` for
+/// comparison, so that a new version of a list can be tried on real traffic
+/// before it is deployed. Only the reactions of `primary` are meant to apply;
+/// evaluating `candidate` gives up once `budget` has been spent, so that a
+/// pathological candidate cannot stall the request.
+pub fn process_rules_for_request_shadowed(primary: &RuleList,
+                                          candidate: &RuleList,
+                                          request: &Request,
+                                          budget: Duration) -> ShadowEvaluation {
+    let start = Instant::now();
+    let reactions = process_rules_for_request(primary, request);
+    let primary_time = start.elapsed();
+    let start = Instant::now();
+    let candidate = process_rules_for_request_with_deadline(candidate, request, budget);
+    ShadowEvaluation {
+        reactions,
+        candidate,
+        primary_time,
+        candidate_time: start.elapsed(),
+    }
+}
+
+/// Like `process_rules_for_request`, but evaluates a list in the compiled
+/// binary format without first loading it into a `RuleList`.
+pub fn process_rules_for_request_compiled(rules: &CompiledRuleList, request: &Request) -> Vec<Reaction> {
+    process_rules_for_request_compiled_impl(rules, request)
+}
+
+/// Compare two versions of a rule list, reporting the rules that were added,
+/// removed or changed. Moving a rule within the list is not considered a change.
+pub fn diff(old: &RuleList, new: &RuleList) -> RuleListDelta {
+    diff_impl(&old.rules, &new.rules)
+}
+
+/// Create a compact delta that turns `old` into the list described by the JSON
+/// string `new_body`. Rules already present in `old` are referenced rather than
+/// repeated, so the delta only carries the rules that changed. See `RuleList::apply_delta`.
+pub fn create_delta(old: &RuleList, new_body: &str) -> Result<String, Error> {
+    create_delta_impl(&old.rules, new_body, &old.delta_options())
+}
+
+/// Like `process_rules_for_request`, but additionally accumulates the time spent
+/// evaluating each rule's URL filter in `profile`. The same profile can be reused
+/// across many requests against the same list; see `Profile::slowest`.
+pub fn process_rules_for_request_profiled(rules: &RuleList,
+                                          request: &Request,
+                                          profile: &mut Profile) -> Vec<Reaction> {
+    process_rules_for_request_profiled_impl(&rules.rules, rules.disabled.as_deref(), request, profile)
 }
 
 /// Parse a string containing a JSON representation of a content blocker list.
 /// Returns a vector of parsed rules, or an error representing the nature of
 /// the invalid input. Any rules missing required fields will be silently ignored.
 pub fn parse_list(body: &str) -> Result<RuleList, Error> {
-    parse_list_impl(body).map(|r| RuleList(r))
+    parse_list_with_options(body, &ParseOptions::default())
+}
+
+/// Like `parse_list`, but rejects the whole list with an error describing the
+/// first invalid rule instead of silently ignoring it.
+pub fn parse_list_strict(body: &str) -> Result<RuleList, Error> {
+    parse_list_strict_impl(body).map(RuleList::new)
+}
+
+/// Lint the list `body` as `RuleList::lint` does, also reporting every rule
+/// that `parse_list` would drop as an error, so that linting tools can choose
+/// their strictness by severity. Findings refer to rules by their position in
+/// the body. Fails only if the body is not a JSON list.
+pub fn lint_list(body: &str) -> Result<Vec<LintFinding>, Error> {
+    lint_list_impl(body)
+}
+
+/// Like `parse_list`, but reads the list from `reader`, parsing one rule at a
+/// time instead of holding the whole body in memory, eg. while it is downloaded.
+/// Fails if reading fails, or if the body is not a JSON list. With the `gzip`
+/// feature, gzip and zlib compressed bodies are decompressed while they are read.
+pub fn parse_list_from_reader<R: Read>(reader: R) -> Result<RuleList, Error> {
+    parse_list_from_reader_with_options(reader, &ParseOptions::default())
+}
+
+/// Like `parse_list_from_reader`, with `options` as in `parse_list_with_options`.
+pub fn parse_list_from_reader_with_options<R: Read>(reader: R, options: &ParseOptions) -> Result<RuleList, Error> {
+    parse_list_from_reader_impl(reader, options).map(|parsed| RuleList::parsed(parsed, options))
+}
+
+/// Like `parse_list`, but takes the body as bytes, eg. as downloaded, rather
+/// than as a string. Compressed bodies are accepted as by `parse_list_from_reader`.
+pub fn parse_list_from_slice(body: &[u8]) -> Result<RuleList, Error> {
+    parse_list_from_reader(body)
+}
+
+/// Like `parse_list_from_slice`, with `options` as in `parse_list_with_options`.
+pub fn parse_list_from_slice_with_options(body: &[u8], options: &ParseOptions) -> Result<RuleList, Error> {
+    parse_list_from_reader_with_options(body, options)
+}
+
+/// Parse the list in the file at `input`, rejecting it if any rule is invalid,
+/// and write it to `output` in the format of `RuleList::to_bytes`, to be loaded
+/// later with `CompiledRuleList::from_bytes`.
+pub fn precompile_list<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> Result<RuleList, Error> {
+    precompile_list_impl(input.as_ref(), output.as_ref())
+}
+
+/// Like `precompile_list`, for use in a build script: writes the list to
+/// `OUT_DIR`, where `include_rules!` embeds it in the crate being built, and
+/// has Cargo rerun the script when `input` changes. Returns the path written.
+pub fn precompile_list_for_build<P: AsRef<Path>>(input: P) -> Result<PathBuf, Error> {
+    precompile_list_for_build_impl(input.as_ref())
+}
+
+/// Parse a content blocker list, with `options` controlling which rules are
+/// accepted and the defaults of the fields they omit.
+pub fn parse_list_with_options(body: &str, options: &ParseOptions) -> Result<RuleList, Error> {
+    parse_list_with_options_impl(body, options).map(|parsed| RuleList::parsed(parsed, options))
+}
+
+/// Parse a content blocker list that may describe itself with metadata, either
+/// through a header of `! Key: value` comment lines preceding the JSON list, or
+/// by wrapping the list in an object of the form `{ "metadata": {...}, "rules": [...] }`.
+/// Recognized keys are `title`, `version`, `homepage`, `expires` and `license`.
+pub fn parse_list_with_metadata(body: &str) -> Result<(RuleList, ListMetadata), Error> {
+    parse_list_with_metadata_and_options(body, &ParseOptions::default())
+}
+
+/// Like `parse_list_with_metadata`, with `options` controlling which rules are
+/// accepted and the defaults of the fields they omit, as in `parse_list_with_options`.
+pub fn parse_list_with_metadata_and_options(body: &str, options: &ParseOptions)
+                                            -> Result<(RuleList, ListMetadata), Error> {
+    parse_list_with_metadata_impl(body, options)
+        .map(|(parsed, metadata)| (RuleList::parsed(parsed, options), metadata))
+}
+
+/// Parse a list categorizing tracker domains, in the format of Disconnect's
+/// `services.json`.
+pub fn parse_entity_list(body: &str) -> Result<EntityList, Error> {
+    parse_entity_list_impl(body)
+}
+
+/// Check that `backend` reports the expected reactions for every request of a
+/// set of JSON test vectors, such as those in the `conformance` directory of
+/// this crate. Returns an error if the vectors themselves are malformed.
+pub fn run_conformance_vectors<B: Backend + ?Sized>(backend: &B, body: &str) -> Result<ConformanceReport, Error> {
+    run_conformance_vectors_impl(backend, body)
+}
+
+/// Record the reactions that `backend` reports for every request of `corpus`
+/// under the JSON list `list`, as a test vector named `name`. Passing the result
+/// to `run_conformance_vectors` later, eg. with a new version of this crate or
+/// another backend, reports every request whose reactions changed. Openers are
+/// not recorded, so popup rules should be covered by hand-written vectors.
+pub fn record_golden_vectors<'a, B, I>(backend: &B, name: &str, list: &str, corpus: I) -> Result<String, Error>
+    where B: Backend + ?Sized, I: IntoIterator<Item=&'a OwnedRequest>
+{
+    record_golden_vectors_impl(backend, name, list, corpus)
+}
+
+/// Verify the ABP-style `! Checksum:` comment of a list body before parsing it,
+/// so that truncated or tampered downloads can be rejected.
+pub fn verify_checksum(body: &str) -> Result<(), Error> {
+    verify_checksum_impl(body)
+}
+
+/// Verify a detached signature over a list body before parsing it.
+pub fn verify_signature<V: SignatureVerifier + ?Sized>(body: &str,
+                                                       signature: &[u8],
+                                                       verifier: &V) -> Result<(), Error> {
+    verify_signature_impl(body, signature, verifier)
 }
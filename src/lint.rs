@@ -0,0 +1,342 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use intern::Interner;
+use parse::{Error, ParseOptions, parse_rule_impl};
+use prefilter::required_literal;
+use reachability::{Reachability, may_overlap};
+use regex_syntax;
+use regex_syntax::hir::{Hir, HirKind, RepetitionKind, RepetitionRange};
+use repr::{Action, LoadType, ResourceTypeList, Rule, Trigger, UrlFilterAnchor};
+use serde_json::{self, Value};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A problem with a rule of a list, found by `RuleList::lint`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LintFinding {
+    /// The position of the rule in its list.
+    pub rule: usize,
+    /// What is wrong with the rule.
+    pub kind: LintKind,
+}
+
+/// How serious a finding is, so that linting tools can choose which findings
+/// to report. Severities are ordered from the least serious.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The rule has no effect, which costs memory and time but is harmless.
+    Info,
+    /// The rule is accepted, but is likely a mistake or slows matching down.
+    Warning,
+    /// The rule is invalid, and is dropped when the list is parsed.
+    Error,
+}
+
+/// The kinds of problems reported by `RuleList::lint`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LintKind {
+    /// The rule blocks only requests that an earlier blocking rule with a broader
+    /// trigger always blocks already.
+    ShadowedBlock {
+        /// The position of the broader rule.
+        by: usize,
+    },
+    /// The rule's `resource-type` list is empty or names no known type, so it
+    /// never matches.
+    EmptyResourceTypes,
+    /// The `ignore-previous-rules` rule precedes every rule it could cancel.
+    UnreachableException,
+    /// The rule acts on network requests, and its URL filter matches every URL
+    /// on every site, eg. `url-filter: ""`.
+    MatchesEverything,
+    /// The URL filter has no literal text that every match contains, so it is
+    /// evaluated for every request rather than skipped by the prefilter.
+    NoLiteral,
+    /// The URL filter is likely to be slow to compile or match, or to take
+    /// much memory, although matching takes linear time.
+    CostlyRegex(RegexCost),
+    /// The rule is invalid, and is dropped when the list is parsed. Only
+    /// reported by `lint_list`.
+    Invalid(Error),
+}
+
+/// Why a URL filter is costly.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RegexCost {
+    /// An unbounded repetition is repeated without bound, eg. `(a+)*`, which
+    /// multiplies the states the regex engine tracks at once.
+    NestedRepetition,
+    /// A leading `.*` is followed by an alternation, eg. `.*(ads|track)`, so
+    /// no literal prefix can be extracted and every branch is attempted at
+    /// every position of the URL.
+    WildcardAlternation,
+}
+
+impl LintKind {
+    /// How serious the problem is.
+    pub fn severity(&self) -> Severity {
+        match *self {
+            LintKind::ShadowedBlock { .. } | LintKind::UnreachableException => Severity::Info,
+            LintKind::EmptyResourceTypes | LintKind::MatchesEverything | LintKind::NoLiteral |
+            LintKind::CostlyRegex(_) => Severity::Warning,
+            LintKind::Invalid(_) => Severity::Error,
+        }
+    }
+}
+
+impl LintFinding {
+    /// How serious the problem is.
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            LintKind::ShadowedBlock { by } =>
+                write!(f, "rule {}: always blocked already by rule {}", self.rule, by),
+            LintKind::EmptyResourceTypes =>
+                write!(f, "rule {}: no resource types, so it never matches", self.rule),
+            LintKind::UnreachableException =>
+                write!(f, "rule {}: no earlier rule can match the requests it would ignore", self.rule),
+            LintKind::MatchesEverything =>
+                write!(f, "rule {}: matches every request on every site", self.rule),
+            LintKind::NoLiteral =>
+                write!(f, "rule {}: url-filter has no literal text, so it is evaluated for every request", self.rule),
+            LintKind::CostlyRegex(RegexCost::NestedRepetition) =>
+                write!(f, "rule {}: url-filter nests unbounded repetitions", self.rule),
+            LintKind::CostlyRegex(RegexCost::WildcardAlternation) =>
+                write!(f, "rule {}: url-filter starts with a wildcard followed by an alternation", self.rule),
+            LintKind::Invalid(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Whether every request matched by the conditions of `narrow` other than its
+/// URL filter is also matched by those of `broad`.
+fn covers_conditions(broad: &Trigger, narrow: &Trigger) -> bool {
+    let resource_types = match (&broad.resource_type, &narrow.resource_type) {
+        (ResourceTypeList::All, _) => true,
+        (ResourceTypeList::List(broad), ResourceTypeList::List(narrow)) => narrow.iter().all(|t| broad.contains(t)),
+        (ResourceTypeList::List(_), ResourceTypeList::All) => false,
+    };
+    let (broad_loads, narrow_loads) = (broad.load_type.mask(), narrow.load_type.mask());
+    let load_types = LoadType::ASPECTS.iter().all(|&aspect| {
+        broad_loads & aspect == 0 || narrow_loads & aspect != 0 && narrow_loads & aspect & !broad_loads == 0
+    });
+    let contexts = match (&broad.contexts, &narrow.contexts) {
+        (None, _) => true,
+        (Some(broad), Some(narrow)) => narrow.iter().all(|c| broad.contains(c)),
+        (Some(_), None) => false,
+    };
+    // A host name matches where it appears in the URL whenever it matches the
+    // host, but not the other way around.
+    let url_filters = broad.url_filter_anchor == UrlFilterAnchor::Url ||
+                      narrow.url_filter_anchor == UrlFilterAnchor::Host && broad.url_filter == narrow.url_filter;
+    let components = (broad.path_filter.is_none() || broad.path_filter == narrow.path_filter) &&
+                     (broad.query_filter.is_none() || broad.query_filter == narrow.query_filter);
+    resource_types && load_types && contexts && url_filters && components &&
+        (broad.domain_constraint.is_none() ||
+         broad.domain_constraint == narrow.domain_constraint && broad.domain_target == narrow.domain_target) &&
+        (broad.user_initiated.is_none() || broad.user_initiated == narrow.user_initiated) &&
+        (broad.opener_domain.is_none() || broad.opener_domain == narrow.opener_domain) &&
+        (broad.match_redirect_chain || !narrow.match_redirect_chain) &&
+        broad.if_header.iter().all(|condition| narrow.if_header.contains(condition)) &&
+        broad.unless_header.is_empty()
+}
+
+/// Whether a URL filter matches every URL.
+pub fn matches_everything(pattern: &str) -> bool {
+    matches!(pattern.strip_prefix("(?i)").unwrap_or(pattern), "" | ".*" | "^.*" | ".*$" | "^.*$")
+}
+
+fn is_unbounded(kind: &RepetitionKind) -> bool {
+    matches!(*kind, RepetitionKind::ZeroOrMore | RepetitionKind::OneOrMore |
+                    RepetitionKind::Range(RepetitionRange::AtLeast(_)))
+}
+
+/// Whether some part of `hir` satisfies `found`.
+fn contains(hir: &Hir, found: &dyn Fn(&Hir) -> bool) -> bool {
+    found(hir) || match *hir.kind() {
+        HirKind::Repetition(ref repetition) => contains(&repetition.hir, found),
+        HirKind::Group(ref group) => contains(&group.hir, found),
+        HirKind::Concat(ref hirs) | HirKind::Alternation(ref hirs) => hirs.iter().any(|hir| contains(hir, found)),
+        _ => false,
+    }
+}
+
+fn unbounded_repetition(hir: &Hir) -> Option<&Hir> {
+    match *hir.kind() {
+        HirKind::Repetition(ref repetition) if is_unbounded(&repetition.kind) => Some(&repetition.hir),
+        _ => None,
+    }
+}
+
+/// Why the URL filter `pattern` is likely to be costly, if it is.
+pub fn regex_cost(pattern: &str) -> Option<RegexCost> {
+    let hir = regex_syntax::Parser::new().parse(pattern).ok()?;
+    let nested = contains(&hir, &|hir| {
+        unbounded_repetition(hir).is_some_and(|inner| contains(inner, &|hir| unbounded_repetition(hir).is_some()))
+    });
+    if nested {
+        return Some(RegexCost::NestedRepetition);
+    }
+    let unanchored = pattern.strip_prefix("(?i)").unwrap_or(pattern);
+    let unanchored = unanchored.strip_prefix('^').unwrap_or(unanchored);
+    if (unanchored.starts_with(".*") || unanchored.starts_with(".+")) &&
+       contains(&hir, &|hir| matches!(*hir.kind(), HirKind::Alternation(_))) {
+        return Some(RegexCost::WildcardAlternation);
+    }
+    None
+}
+
+/// The text matched by a URL filter that is a plain string, along with whether
+/// it is matched regardless of case.
+pub fn plain_literal(pattern: &str) -> Option<(String, bool)> {
+    let (pattern, caseless) = match pattern.strip_prefix("(?i)") {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let mut literal = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c) if c.is_ascii_punctuation() => literal.push(c),
+                _ => return None,
+            },
+            '.' | '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$' => return None,
+            c => literal.push(c),
+        }
+    }
+    if literal.is_empty() {
+        return None;
+    }
+    if caseless {
+        literal = literal.to_lowercase();
+    }
+    Some((literal, caseless))
+}
+
+pub fn lint_impl(rules: &[Rule], reachability: &Reachability) -> Vec<LintFinding> {
+    let mut findings = vec![];
+    let exceptions: Vec<usize> = rules.iter().enumerate()
+                                      .filter(|&(_, rule)| rule.action == Action::IgnorePreviousRules)
+                                      .map(|(position, _)| position)
+                                      .collect();
+
+    // Earlier blocking rules, indexed by what their URL filters match.
+    let mut match_all: Vec<usize> = vec![];
+    let mut by_source: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut by_caseless_literal: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut by_literal: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut longest_literal = 0;
+
+    for (position, rule) in rules.iter().enumerate() {
+        if matches!(rule.trigger.resource_type, ResourceTypeList::List(ref types) if types.is_empty()) {
+            findings.push(LintFinding { rule: position, kind: LintKind::EmptyResourceTypes });
+            continue;
+        }
+        let source = rule.trigger.url_filter.as_str();
+        if matches_everything(source) {
+            let network = matches!(rule.action, Action::Block | Action::BlockCookies(_) |
+                                                Action::BlockCookiesMatching(_) | Action::Redirect(_));
+            if network && rule.trigger.domain_constraint.is_none() &&
+               rule.trigger.resource_type == ResourceTypeList::All &&
+               rule.trigger.path_filter.is_none() && rule.trigger.query_filter.is_none() {
+                findings.push(LintFinding { rule: position, kind: LintKind::MatchesEverything });
+            }
+        } else if required_literal(source).is_none() {
+            findings.push(LintFinding { rule: position, kind: LintKind::NoLiteral });
+        }
+        if let Some(cost) = regex_cost(source) {
+            findings.push(LintFinding { rule: position, kind: LintKind::CostlyRegex(cost) });
+        }
+        if rule.action != Action::Block {
+            continue;
+        }
+        let literal = plain_literal(source);
+
+        let mut candidates = match_all.clone();
+        candidates.extend(by_source.get(source).into_iter().flatten());
+        if let Some((ref text, caseless)) = literal {
+            // A caseless literal covers every filter containing it in any case,
+            // but a case-sensitive one only covers case-sensitive filters.
+            let lowered = text.to_ascii_lowercase();
+            for start in (0..text.len()).filter(|&i| text.is_char_boundary(i)) {
+                let ends = (start + 1..text.len() + 1).take_while(|&end| end - start <= longest_literal);
+                for end in ends.filter(|&i| text.is_char_boundary(i)) {
+                    candidates.extend(by_caseless_literal.get(&lowered[start..end]).into_iter().flatten());
+                    if !caseless {
+                        candidates.extend(by_literal.get(&text[start..end]).into_iter().flatten());
+                    }
+                }
+            }
+        }
+        candidates.sort_unstable();
+        let shadowed_by = candidates.into_iter().find(|&earlier| {
+            covers_conditions(&rules[earlier].trigger, &rule.trigger) &&
+                // Otherwise disabling a group of the earlier rule would unshadow this one.
+                rules[earlier].groups.iter().all(|group| rule.groups.contains(group)) &&
+                !exceptions.iter().any(|&exception| {
+                    earlier < exception && exception < position &&
+                        may_overlap(&rules[exception].trigger, &rule.trigger)
+                })
+        });
+        if let Some(by) = shadowed_by {
+            findings.push(LintFinding { rule: position, kind: LintKind::ShadowedBlock { by } });
+        }
+
+        if matches_everything(source) {
+            match_all.push(position);
+        }
+        by_source.entry(source).or_default().push(position);
+        if let Some((text, caseless)) = literal {
+            longest_literal = longest_literal.max(text.len());
+            let index = if caseless { &mut by_caseless_literal } else { &mut by_literal };
+            index.entry(text).or_default().push(position);
+        }
+    }
+
+    findings.extend(reachability.unreachable_exceptions.iter().map(|&rule| LintFinding {
+        rule,
+        kind: LintKind::UnreachableException,
+    }));
+    findings.sort_by_key(|finding| finding.rule);
+    findings
+}
+
+/// Lint the list `body` as `lint_impl` does, also reporting the rules that
+/// parsing drops. Findings refer to rules by their position in the body.
+pub fn lint_list_impl(body: &str) -> Result<Vec<LintFinding>, Error> {
+    let json: Value = serde_json::from_str(body).map_err(|_| Error::JSON)?;
+    let values = json.as_array().ok_or(Error::NotAList)?;
+    let mut interner = Interner::new();
+    let mut findings = vec![];
+    let mut rules = vec![];
+    // The position in the body of each parsed rule.
+    let mut positions = vec![];
+    for (index, value) in values.iter().enumerate() {
+        match parse_rule_impl(index, value, &mut interner, &ParseOptions::default()) {
+            Ok(rule) => {
+                rules.push(rule);
+                positions.push(index);
+            }
+            Err(error) => findings.push(LintFinding { rule: index, kind: LintKind::Invalid(error) }),
+        }
+    }
+    for mut finding in lint_impl(&rules, &Reachability::new(&rules)) {
+        finding.rule = positions[finding.rule];
+        if let LintKind::ShadowedBlock { ref mut by } = finding.kind {
+            *by = positions[*by];
+        }
+        findings.push(finding);
+    }
+    findings.sort_by_key(|finding| finding.rule);
+    Ok(findings)
+}
@@ -0,0 +1,121 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use html::HtmlFilter;
+use procedural::{ProceduralSelector, ProceduralStep, TextMatcher, Upward};
+use repr::{Action, DomainConstraint, DomainMatcher, LoadType, LoadTypeList, ResourceType, ResourceTypeList};
+use std::collections::HashSet;
+use std::mem;
+use std::sync::Arc;
+use RuleList;
+
+/// An estimate of the memory used by a parsed list, in bytes, broken down by
+/// what it is used for. Allocator overhead is not included, and strings shared
+/// between rules are only counted once.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryReport {
+    /// The rules themselves, including the source of each URL filter.
+    pub rules: usize,
+    /// The programs of the URL filters that have been compiled so far.
+    pub regex_programs: usize,
+    /// The `if-domain` and `unless-domain` lists.
+    pub domain_tables: usize,
+    /// The selectors of element hiding rules.
+    pub selectors: usize,
+    /// The indexes used to skip rules that cannot match a request.
+    pub indexes: usize,
+}
+
+impl MemoryReport {
+    /// The total of every category.
+    pub fn total(&self) -> usize {
+        self.rules + self.regex_programs + self.domain_tables + self.selectors + self.indexes
+    }
+}
+
+/// The size of a reference to a shared string, plus the size of the string
+/// itself if it has not been counted yet.
+fn shared_size(s: &Arc<str>, seen: &mut HashSet<*const u8>) -> usize {
+    let mut size = mem::size_of::<Arc<str>>();
+    if seen.insert(s.as_ptr()) {
+        // The string shares its allocation with the reference counts.
+        size += s.len() + 2 * mem::size_of::<usize>();
+    }
+    size
+}
+
+fn procedural_size(selector: &ProceduralSelector) -> usize {
+    mem::size_of::<ProceduralSelector>() + selector.source.len() +
+        selector.steps.iter().map(|step| mem::size_of::<ProceduralStep>() + match *step {
+            ProceduralStep::Css(ref css) => css.len(),
+            ProceduralStep::Has(ref selector) => procedural_size(selector),
+            ProceduralStep::HasText(TextMatcher::Contains(ref text)) => text.len(),
+            ProceduralStep::HasText(TextMatcher::Regex { ref pattern, ref flags }) => pattern.len() + flags.len(),
+            ProceduralStep::Upward(Upward::Closest(ref css)) => css.len(),
+            ProceduralStep::Upward(Upward::Levels(_)) => 0,
+        }).sum::<usize>()
+}
+
+fn domain_table_size(matcher: &DomainMatcher, seen: &mut HashSet<*const u8>) -> usize {
+    matcher.exact.iter().chain(matcher.subdomain.iter())
+           .map(|domain| shared_size(domain, seen))
+           .sum()
+}
+
+/// Estimate the memory used by the parts of a `RuleList`.
+pub fn memory_usage_impl(list: &RuleList) -> MemoryReport {
+    let mut report = MemoryReport::default();
+    add_memory_usage(&mut report, list, &mut HashSet::new());
+    report
+}
+
+/// Estimate the memory used by several lists together, counting the strings
+/// they share with each other once.
+pub fn shared_memory_usage_impl<'a, I>(lists: I) -> MemoryReport where I: IntoIterator<Item=&'a RuleList> {
+    let mut report = MemoryReport::default();
+    let mut seen = HashSet::new();
+    for list in lists {
+        add_memory_usage(&mut report, list, &mut seen);
+    }
+    report
+}
+
+fn add_memory_usage(report: &mut MemoryReport, list: &RuleList, seen: &mut HashSet<*const u8>) {
+    report.rules += mem::size_of_val(&*list.rules);
+    report.indexes += list.backend.heap_size() + list.cosmetics.heap_size();
+    for rule in &list.rules {
+        report.rules += rule.trigger.url_filter.as_str().len();
+        if let ResourceTypeList::List(ref types) = rule.trigger.resource_type {
+            report.rules += types.capacity() * mem::size_of::<ResourceType>();
+        }
+        if let LoadTypeList::List(ref types) = rule.trigger.load_type {
+            report.rules += types.capacity() * mem::size_of::<LoadType>();
+        }
+        report.regex_programs += rule.trigger.url_filter.program_size().unwrap_or(0);
+        for filter in rule.trigger.path_filter.iter().chain(&rule.trigger.query_filter) {
+            report.rules += filter.as_str().len();
+            report.regex_programs += filter.program_size().unwrap_or(0);
+        }
+        match rule.trigger.domain_constraint {
+            Some(DomainConstraint::If(ref matcher)) |
+            Some(DomainConstraint::Unless(ref matcher)) => report.domain_tables += domain_table_size(matcher, seen),
+            None => {}
+        }
+        if let Some(ref matcher) = rule.trigger.opener_domain {
+            report.domain_tables += domain_table_size(matcher, seen);
+        }
+        match rule.action {
+            Action::CssDisplayNone(ref selector) |
+            Action::CssDisplayNoneException(ref selector) => report.selectors += shared_size(selector, seen),
+            Action::CssDisplayNoneProcedural(ref selector) => report.selectors += procedural_size(selector),
+            Action::HtmlFilter(ref filter) => {
+                report.selectors += mem::size_of::<HtmlFilter>() + filter.source.len() + filter.selector.len();
+            }
+            Action::CssInject { ref selector, ref style } => {
+                report.selectors += shared_size(selector, seen) + shared_size(style, seen);
+            }
+            _ => {}
+        }
+    }
+}
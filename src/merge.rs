@@ -0,0 +1,51 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use repr::{Action, Rule};
+use std::collections::HashSet;
+
+/// How `RuleList::merge` resolves conflicts between the `ignore-previous-rules`
+/// rules of one list and the rules of the others. Within a single list, rules
+/// keep their order either way. To keep every list's exceptions to itself,
+/// evaluate the lists separately with an `Engine` instead of merging them.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MergePolicy {
+    /// Append the lists in order, as if their bodies were concatenated. An
+    /// exception cancels the rules of the lists before its own, but not those
+    /// of the lists after it.
+    #[default]
+    Concatenate,
+    /// Exceptions take precedence over the rules of every list, whichever came
+    /// first: the exceptions of all lists are moved after every other rule. An
+    /// exception applying to a request then also cancels the rules that followed
+    /// it in its own list.
+    ExceptionsWin,
+}
+
+/// Combine `lists` as described by `policy`. A rule followed by an identical
+/// rule is dropped, since the later rule reproduces its reaction whenever it
+/// would have applied.
+pub fn merge_impl(lists: Vec<Vec<Rule>>, policy: MergePolicy) -> Vec<Rule> {
+    let mut rules: Vec<Rule> = match policy {
+        MergePolicy::Concatenate => lists.into_iter().flatten().collect(),
+        MergePolicy::ExceptionsWin => {
+            let (exceptions, mut rules): (Vec<Rule>, Vec<Rule>) =
+                lists.into_iter().flatten().partition(|rule| rule.action == Action::IgnorePreviousRules);
+            rules.extend(exceptions);
+            rules
+        }
+    };
+
+    let mut keep = vec![true; rules.len()];
+    {
+        let mut seen = HashSet::new();
+        for (index, rule) in rules.iter().enumerate().rev() {
+            keep[index] = seen.insert(rule);
+        }
+    }
+    let mut keep = keep.into_iter();
+    rules.retain(|_| keep.next().unwrap_or(true));
+    rules
+}
@@ -0,0 +1,155 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use parse::{Error, ParseOptions, ParsedList};
+use serde_json::{self, Value};
+use std::ops::Range;
+use std::time::Duration;
+use stream::parse_array_at;
+
+/// Information describing a list as a whole, as opposed to its individual rules.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ListMetadata {
+    /// The human-readable name of the list.
+    pub title: Option<String>,
+    /// The version of the list, in whatever format its authors use.
+    pub version: Option<String>,
+    /// A URL where more information about the list can be found.
+    pub homepage: Option<String>,
+    /// How long the list remains valid before it should be fetched again.
+    pub expires: Option<Duration>,
+    /// The license under which the list is distributed.
+    pub license: Option<String>,
+}
+
+impl ListMetadata {
+    fn set(&mut self, key: &str, value: &str) {
+        let value = value.trim();
+        match &*key.trim().to_lowercase() {
+            "title" => self.title = Some(value.to_owned()),
+            "version" => self.version = Some(value.to_owned()),
+            "homepage" => self.homepage = Some(value.to_owned()),
+            "expires" => self.expires = parse_expires(value),
+            "license" => self.license = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+}
+
+/// Parse an ABP-style expiry such as `4 days (update frequency)` or `12 hours`.
+/// A bare number is interpreted as a number of days.
+fn parse_expires(value: &str) -> Option<Duration> {
+    let mut words = value.split_whitespace();
+    let amount: u64 = words.next()?.parse().ok()?;
+    let unit = match words.next() {
+        Some(unit) if unit.starts_with("hour") => 60 * 60,
+        Some(unit) if unit.starts_with("day") => 24 * 60 * 60,
+        Some(unit) if !unit.starts_with('(') => return None,
+        _ => 24 * 60 * 60,
+    };
+    amount.checked_mul(unit).map(Duration::from_secs)
+}
+
+/// Parse a list that may carry metadata, either as a header of ABP-style
+/// `! Key: value` comment lines preceding the JSON list, or as a JSON object
+/// wrapping the list:
+///
+/// ```json
+/// { "metadata": { "title": "Example", "expires": "4 days" }, "rules": [] }
+/// ```
+pub fn parse_list_with_metadata_impl(body: &str, options: &ParseOptions) -> Result<(ParsedList, ListMetadata), Error> {
+    let mut metadata = ListMetadata::default();
+
+    let mut json_start = 0;
+    for line in body.split('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with('!') {
+            if let Some(colon) = trimmed.find(':') {
+                metadata.set(&trimmed[1..colon], &trimmed[colon + 1..]);
+            }
+        } else if !trimmed.is_empty() && !trimmed.starts_with("[Adblock") {
+            break;
+        }
+        json_start += line.len() + 1;
+    }
+    let json = body.get(json_start..).unwrap_or("");
+
+    let json_body: Value = serde_json::from_str(json).map_err(|_| Error::JSON)?;
+    let rules = match json_body {
+        Value::Array(_) => json_start..body.len(),
+        Value::Object(ref wrapper) => {
+            if let Some(fields) = wrapper.get("metadata").and_then(|m| m.as_object()) {
+                for (key, value) in fields {
+                    match *value {
+                        Value::String(ref value) => metadata.set(key, value),
+                        Value::Number(ref value) => metadata.set(key, &value.to_string()),
+                        _ => {}
+                    }
+                }
+            }
+            if !wrapper.get("rules").is_some_and(Value::is_array) {
+                return Err(Error::NotAList);
+            }
+            let rules = rules_array(json).ok_or(Error::NotAList)?;
+            json_start + rules.start..json_start + rules.end
+        }
+        _ => return Err(Error::NotAList),
+    };
+
+    // The rules are parsed from the body itself, so that they are located in it.
+    let line = body[..rules.start].matches('\n').count() + 1;
+    let parsed = parse_array_at(body[rules.clone()].as_bytes(), rules.start, line, options)?;
+    Ok((parsed, metadata))
+}
+
+/// The byte range of the array that is the value of the `rules` key of the
+/// JSON object `json`, which must be valid.
+fn rules_array(json: &str) -> Option<Range<usize>> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut string_start = 0;
+    // The last string directly inside the object, which is the key of the
+    // value that follows it.
+    let mut key = "";
+    let mut start = None;
+    let mut range = None;
+    for (i, byte) in json.bytes().enumerate() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if byte == b'\\' {
+                escaped = true;
+            } else if byte == b'"' {
+                in_string = false;
+                if depth == 1 {
+                    key = &json[string_start + 1..i];
+                }
+            }
+            continue;
+        }
+        match byte {
+            b'"' => {
+                in_string = true;
+                string_start = i;
+            }
+            b'[' | b'{' => {
+                if depth == 1 && byte == b'[' && key == "rules" {
+                    start = Some(i);
+                }
+                depth += 1;
+            }
+            b']' | b'}' => {
+                depth -= 1;
+                if depth == 1 {
+                    if let Some(start) = start.take() {
+                        range = Some(start..i + 1);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    range
+}
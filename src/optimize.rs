@@ -0,0 +1,99 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use repr::{Action, Rule};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// A summary of the changes made by `RuleList::optimize`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OptimizationReport {
+    /// The number of rules before optimizing.
+    pub rules_before: usize,
+    /// The number of rules after optimizing.
+    pub rules_after: usize,
+    /// The number of rules removed because a later rule had an identical trigger and action.
+    pub duplicates_removed: usize,
+    /// The number of `css-display-none` rules folded into the selector list of an
+    /// earlier rule with an identical trigger.
+    pub selectors_merged: usize,
+}
+
+/// Remove redundant rules from a list while preserving the set of reactions
+/// produced for any request.
+///
+/// A rule is dropped if a later rule has an identical trigger and action, since
+/// the later rule reproduces its reaction whenever it would have fired. Rules
+/// hiding elements with identical triggers are combined into a single rule with
+/// a selector list, as long as no `ignore-previous-rules` rule lies between them.
+/// Exceptions for element hiding name exact selectors, so selectors are never
+/// combined in lists that contain any.
+pub fn optimize_impl(rules: Vec<Rule>) -> (Vec<Rule>, OptimizationReport) {
+    let mut report = OptimizationReport {
+        rules_before: rules.len(),
+        .. OptimizationReport::default()
+    };
+
+    let mut keep = vec![true; rules.len()];
+    let mut extra_selectors: HashMap<usize, Vec<Arc<str>>> = HashMap::new();
+    {
+        let mut seen = HashSet::new();
+        for (index, rule) in rules.iter().enumerate().rev() {
+            if !seen.insert((&rule.trigger, &rule.action, &rule.groups)) {
+                keep[index] = false;
+                report.duplicates_removed += 1;
+            }
+        }
+
+        let has_hiding_exceptions = rules.iter().any(|rule| {
+            matches!(rule.action, Action::CssDisplayNoneException(_))
+        });
+        let mut hiding_rules = HashMap::new();
+        for (index, rule) in rules.iter().enumerate().filter(|_| !has_hiding_exceptions) {
+            if !keep[index] {
+                continue;
+            }
+            match rule.action {
+                Action::IgnorePreviousRules => hiding_rules.clear(),
+                Action::CssDisplayNone(ref selector) => {
+                    match hiding_rules.get(&(&rule.trigger, &rule.groups)) {
+                        Some(&target) => {
+                            extra_selectors.entry(target).or_default().push(selector.clone());
+                            keep[index] = false;
+                            report.selectors_merged += 1;
+                        }
+                        None => {
+                            hiding_rules.insert((&rule.trigger, &rule.groups), index);
+                        }
+                    }
+                }
+                Action::Block | Action::BlockCookies(_) | Action::BlockCookiesMatching(_) |
+                Action::Redirect(_) | Action::CssDisplayNoneProcedural(_) |
+                Action::CssDisplayNoneException(_) | Action::CssInject { .. } | Action::HtmlFilter(_) |
+                Action::BlockInlineScripts => {}
+            }
+        }
+    }
+
+    let mut optimized = Vec::with_capacity(keep.iter().filter(|k| **k).count());
+    for (index, (mut rule, keep)) in rules.into_iter().zip(keep).enumerate() {
+        if !keep {
+            continue;
+        }
+        if let Some(selectors) = extra_selectors.remove(&index) {
+            if let Action::CssDisplayNone(ref mut combined) = rule.action {
+                let mut joined = combined.to_string();
+                for selector in selectors {
+                    joined.push_str(", ");
+                    joined.push_str(&selector);
+                }
+                *combined = joined.into();
+            }
+        }
+        optimized.push(rule);
+    }
+
+    report.rules_after = optimized.len();
+    (optimized, report)
+}
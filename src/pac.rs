@@ -0,0 +1,161 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use dns::{HOST_TERMINATORS, anchored_host, may_unblock, unconditional};
+use index::trigger_host_anchor;
+use lint::plain_literal;
+use repr::{Action, Rule, Trigger, UrlFilterAnchor};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// Why a rule was left out of a PAC file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PacSkipReason {
+    /// The URL filter is not limited to a host, or to a path prefix on a host.
+    UrlFilter,
+    /// The rule only applies to some resource types, load types, pages or contexts.
+    Conditions,
+    /// The `ignore-previous-rules` rule may unblock requests that the PAC file
+    /// sends to the blackhole proxy regardless.
+    Exception,
+}
+
+/// A rule affecting whether requests are blocked that a PAC file cannot represent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PacSkip {
+    /// The position of the rule in its list.
+    pub rule: usize,
+    /// Why the rule was left out.
+    pub reason: PacSkipReason,
+}
+
+/// A list converted to a Proxy Auto-Config script by `RuleList::to_pac`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PacFile {
+    /// The JavaScript source of the PAC file.
+    pub script: String,
+    /// The number of hosts blocked entirely.
+    pub hosts: usize,
+    /// The number of path prefixes blocked on specific hosts.
+    pub prefixes: usize,
+    /// The blocking rules and exceptions that were left out, in list order.
+    pub skipped: Vec<PacSkip>,
+}
+
+/// What a blocking rule blocks: a host, and optionally a path prefix on it
+/// along with whether the prefix is matched regardless of case.
+type Blocked = (String, bool, Option<(String, bool)>);
+
+fn blocked(trigger: &Trigger) -> Option<Blocked> {
+    if trigger.url_filter_anchor == UrlFilterAnchor::Host {
+        return trigger_host_anchor(trigger).map(|(host, subdomains)| (host, subdomains, None));
+    }
+    let pattern = trigger.url_filter.as_str();
+    let (host, subdomains, rest) = anchored_host(pattern)?;
+    if HOST_TERMINATORS.contains(&rest) {
+        return Some((host, subdomains, None));
+    }
+    let (prefix, _) = plain_literal(rest)?;
+    if !prefix.starts_with('/') {
+        return None;
+    }
+    let caseless = pattern.starts_with("(?i)");
+    let prefix = if caseless { prefix.to_ascii_lowercase() } else { prefix };
+    Some((host, subdomains, Some((prefix, caseless))))
+}
+
+fn quote(text: &str) -> String {
+    Value::String(text.to_owned()).to_string()
+}
+
+const FIND_PROXY: &str = "\
+function FindProxyForURL(url, host) {
+    host = host.toLowerCase();
+    for (var domain = host; ; domain = domain.substring(domain.indexOf(\".\") + 1)) {
+        if (HOSTS.hasOwnProperty(domain) && (domain === host || HOSTS[domain])) {
+            return BLACKHOLE;
+        }
+        if (domain.indexOf(\".\") < 0) {
+            break;
+        }
+    }
+    var start = url.indexOf(\"/\", url.indexOf(\"://\") + 3);
+    var path = start < 0 ? \"/\" : url.substring(start);
+    for (var i = 0; i < PREFIXES.length; i++) {
+        var entry = PREFIXES[i];
+        var suffix = host.substring(host.length - entry[0].length - 1);
+        if ((host === entry[0] || entry[1] && suffix === \".\" + entry[0]) &&
+            (entry[3] ? path.toLowerCase() : path).indexOf(entry[2]) === 0) {
+            return BLACKHOLE;
+        }
+    }
+    return \"DIRECT\";
+}
+";
+
+pub fn to_pac_impl(rules: &[Rule], blackhole: &str) -> PacFile {
+    let mut hosts: Vec<(String, bool)> = vec![];
+    // The position of each host in `hosts`, whose entry also covers subdomains
+    // if any rule blocking the host does, since a script object keeps only the
+    // last of several entries with the same name.
+    let mut host_positions: HashMap<String, usize> = HashMap::new();
+    let mut prefixes = vec![];
+    let mut seen = HashSet::new();
+    let mut exported = vec![];
+    let mut skipped = vec![];
+    for (position, rule) in rules.iter().enumerate() {
+        let reason = match rule.action {
+            Action::Block => match blocked(&rule.trigger) {
+                None => PacSkipReason::UrlFilter,
+                Some(_) if !unconditional(&rule.trigger) => PacSkipReason::Conditions,
+                Some(entry) => {
+                    exported.push((position, entry.0.clone(), entry.1));
+                    if seen.insert(entry.clone()) {
+                        match entry {
+                            (host, subdomains, None) => match host_positions.get(&host) {
+                                Some(&i) => hosts[i].1 |= subdomains,
+                                None => {
+                                    host_positions.insert(host.clone(), hosts.len());
+                                    hosts.push((host, subdomains));
+                                }
+                            },
+                            (host, subdomains, Some((prefix, caseless))) =>
+                                prefixes.push((host, subdomains, prefix, caseless)),
+                        }
+                    }
+                    continue;
+                }
+            },
+            Action::IgnorePreviousRules if exported.iter().any(|&(earlier, ref host, subdomains)| {
+                may_unblock(&rules[earlier].trigger, host, subdomains, &rule.trigger)
+            }) => PacSkipReason::Exception,
+            _ => continue,
+        };
+        skipped.push(PacSkip { rule: position, reason });
+    }
+
+    let mut script = String::new();
+    let _ = writeln!(script, "var BLACKHOLE = {};", quote(&format!("PROXY {}", blackhole)));
+    script.push_str("var HOSTS = {");
+    for (i, &(ref host, subdomains)) in hosts.iter().enumerate() {
+        let separator = if i == 0 { "\n" } else { ",\n" };
+        let _ = write!(script, "{}    {}: {}", separator, quote(host), subdomains as u8);
+    }
+    script.push_str("\n};\nvar PREFIXES = [");
+    for (i, &(ref host, subdomains, ref prefix, caseless)) in prefixes.iter().enumerate() {
+        let separator = if i == 0 { "\n" } else { ",\n" };
+        let _ = write!(script, "{}    [{}, {}, {}, {}]",
+                       separator, quote(host), subdomains as u8, quote(prefix), caseless as u8);
+    }
+    script.push_str("\n];\n\n");
+    script.push_str(FIND_PROXY);
+    PacFile {
+        script,
+        hosts: hosts.len(),
+        prefixes: prefixes.len(),
+        skipped,
+    }
+}
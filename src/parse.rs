@@ -2,23 +2,370 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use regex::Regex;
-use repr::{Action, DomainConstraint, DomainMatcher, LoadType, ResourceType};
-use repr::{ResourceTypeList, Rule, Trigger};
-use serde_json::{self, Value};
+use backend::MatchBackendKind;
+use html::HtmlFilter;
+use intern::Interner;
+use md5;
+use procedural::{ProceduralSelector, is_procedural};
+use regex;
+use repr::{Action, CookieScope, DomainConstraint, DomainMatcher, DomainTarget, LoadType, ResourceType};
+use repr::{HeaderCondition, LoadTypeList, ResourceTypeList, Rule, RuleId, SourceLocation, Trigger, UrlFilter};
+use repr::UrlFilterAnchor;
+use resources::Resource;
+use selector::is_valid_selector;
+use serde_json::{Map, Value};
+use std::error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use stream::parse_uncompressed;
 
 /// Errors returned when parsing a JSON representation of a list of rules.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Error {
     /// A JSON parsing error occurred.
     JSON,
     /// The root JSON object was not a list.
     NotAList,
+    /// The rule at the given index is missing a required field, or the field has
+    /// the wrong type.
+    MissingField {
+        /// The position of the rule in the list.
+        index: usize,
+        /// The name of the missing field, eg. `trigger.url-filter`.
+        field: &'static str,
+    },
+    /// The URL filter of the rule at the given index is not a valid regular expression.
+    InvalidRegex {
+        /// The position of the rule in the list.
+        index: usize,
+        /// The pattern that failed to compile.
+        pattern: String,
+    },
+    /// The URL filter of the rule at the given index is anchored to the host, but
+    /// is not a host name.
+    InvalidHostPattern {
+        /// The position of the rule in the list.
+        index: usize,
+        /// The URL filter.
+        pattern: String,
+    },
+    /// The rule at the given index specifies both `if-domain` and `unless-domain`.
+    ConflictingDomainConstraints {
+        /// The position of the rule in the list.
+        index: usize,
+    },
+    /// The rule at the given index has an action type that is not supported.
+    UnsupportedAction {
+        /// The position of the rule in the list.
+        index: usize,
+        /// The unsupported action type.
+        action: String,
+    },
+    /// The rule at the given index exceeds a size limit, such as the maximum size
+    /// of a compiled URL filter.
+    SizeLimitExceeded {
+        /// The position of the rule in the list.
+        index: usize,
+        /// The limit that was exceeded.
+        limit: usize,
+    },
+    /// The selector of the cosmetic rule at the given index is invalid.
+    InvalidSelector {
+        /// The position of the rule in the list.
+        index: usize,
+        /// The invalid selector.
+        selector: String,
+    },
+    /// The style of the CSS injection rule at the given index is invalid.
+    InvalidStyle {
+        /// The position of the rule in the list.
+        index: usize,
+        /// The invalid style.
+        style: String,
+    },
+    /// The redirect rule at the given index names a resource that is not bundled.
+    UnknownResource {
+        /// The position of the rule in the list.
+        index: usize,
+        /// The name of the resource.
+        name: String,
+    },
+    /// A delta was malformed or did not apply to the list it was applied to.
+    InvalidDelta,
+    /// The list body did not contain a checksum.
+    MissingChecksum,
+    /// The checksum of the list body did not match its contents.
+    ChecksumMismatch,
+    /// The signature of the list body could not be verified.
+    InvalidSignature,
+    /// Bytes were not a compiled list produced by this version of the library.
+    InvalidCompiledList,
+    /// The rule at the given index names a resource type that is not supported,
+    /// and `ParseOptions::reject_unknown_resource_types` is set.
+    UnknownResourceType {
+        /// The position of the rule in the list.
+        index: usize,
+        /// The unsupported resource type.
+        name: String,
+    },
+    /// The rule at the given index has an empty `resource-type` list, and
+    /// `ParseOptions::reject_empty_resource_types` is set.
+    EmptyResourceTypes {
+        /// The position of the rule in the list.
+        index: usize,
+    },
+    /// The list has more rules than `ParseOptions::max_rules` allows, and the
+    /// `rule_limit_policy` is `RuleLimitPolicy::Error`, or cannot be exported
+    /// to Safari under the given limit.
+    TooManyRules {
+        /// The maximum number of rules.
+        limit: usize,
+    },
+    /// Reading the list failed.
+    Io(io::ErrorKind),
+    /// A pattern of the rule at the given index is not a glob, and the list is
+    /// parsed with `TrustLevel::Untrusted`.
+    UntrustedPattern {
+        /// The position of the rule in the list.
+        index: usize,
+        /// The field holding the pattern, eg. `trigger.url-filter`.
+        field: &'static str,
+        /// The pattern.
+        pattern: String,
+    },
 }
 
-impl ResourceType {
-    fn from_str(s: &str) -> Option<ResourceType> {
-        Some(match s {
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::JSON => write!(f, "invalid JSON"),
+            Error::NotAList => write!(f, "the root JSON value is not a list"),
+            Error::MissingField { index, field } =>
+                write!(f, "rule {}: missing or invalid field `{}`", index, field),
+            Error::InvalidRegex { index, ref pattern } =>
+                write!(f, "rule {}: invalid url-filter `{}`", index, pattern),
+            Error::ConflictingDomainConstraints { index } =>
+                write!(f, "rule {}: both if-domain and unless-domain are specified", index),
+            Error::UnsupportedAction { index, ref action } =>
+                write!(f, "rule {}: unsupported action type `{}`", index, action),
+            Error::SizeLimitExceeded { index, limit } =>
+                write!(f, "rule {}: exceeds the size limit of {} bytes", index, limit),
+            Error::InvalidSelector { index, ref selector } =>
+                write!(f, "rule {}: invalid selector `{}`", index, selector),
+            Error::InvalidStyle { index, ref style } =>
+                write!(f, "rule {}: invalid style `{}`", index, style),
+            Error::UnknownResource { index, ref name } =>
+                write!(f, "rule {}: unknown redirect resource `{}`", index, name),
+            Error::InvalidDelta => write!(f, "the delta does not apply to this list"),
+            Error::MissingChecksum => write!(f, "the list has no checksum"),
+            Error::ChecksumMismatch => write!(f, "the list checksum does not match its contents"),
+            Error::InvalidSignature => write!(f, "the list signature could not be verified"),
+            Error::InvalidCompiledList => write!(f, "invalid or incompatible compiled list"),
+            Error::UnknownResourceType { index, ref name } =>
+                write!(f, "rule {}: unknown resource type `{}`", index, name),
+            Error::EmptyResourceTypes { index } => write!(f, "rule {}: empty resource-type list", index),
+            Error::TooManyRules { limit } => write!(f, "the list has more than {} rules", limit),
+            Error::Io(kind) => write!(f, "couldn't read the list: {}", kind),
+            Error::InvalidHostPattern { index, ref pattern } =>
+                write!(f, "rule {}: `{}` is anchored to the host but is not a host name", index, pattern),
+            Error::UntrustedPattern { index, field, ref pattern } =>
+                write!(f, "rule {}: `{}` in {} is not allowed in untrusted lists", index, pattern, field),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl Error {
+    /// The field of the rule that the error is about, in the notation of
+    /// `Error::MissingField`, eg. `action.selector`, so that an editor can point
+    /// at it. `None` for errors about the whole list.
+    pub fn field(&self) -> Option<&'static str> {
+        Some(match *self {
+            Error::MissingField { field, .. } | Error::UntrustedPattern { field, .. } => field,
+            Error::InvalidRegex { .. } | Error::InvalidHostPattern { .. } |
+            Error::SizeLimitExceeded { .. } => "trigger.url-filter",
+            Error::ConflictingDomainConstraints { .. } => "trigger.unless-domain",
+            Error::UnsupportedAction { .. } => "action.type",
+            Error::InvalidSelector { .. } => "action.selector",
+            Error::InvalidStyle { .. } => "action.style",
+            Error::UnknownResource { .. } => "action.resource",
+            Error::UnknownResourceType { .. } | Error::EmptyResourceTypes { .. } => "trigger.resource-type",
+            Error::JSON | Error::NotAList | Error::InvalidDelta | Error::MissingChecksum |
+            Error::ChecksumMismatch | Error::InvalidSignature | Error::InvalidCompiledList |
+            Error::TooManyRules { .. } | Error::Io(_) => return None,
+        })
+    }
+}
+
+/// How tolerant parsing is of lists written for other content blockers, or
+/// written carelessly. The default options are those of `parse_list`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Fail with an error describing the first invalid rule instead of ignoring
+    /// it, as `parse_list_strict` does.
+    pub strict: bool,
+    /// Whether the URL filters of triggers that omit `url-filter-is-case-sensitive`
+    /// are case-sensitive, which they are by default. For compatibility with
+    /// existing lists, the key itself is inverted: setting it to `true` makes
+    /// the filters of a trigger case-insensitive.
+    pub case_sensitive: bool,
+    /// Treat rules naming an unsupported resource type as invalid, rather than
+    /// ignoring the unsupported type.
+    pub reject_unknown_resource_types: bool,
+    /// Treat rules with an empty `resource-type` list as invalid, rather than as
+    /// rules that never match.
+    pub reject_empty_resource_types: bool,
+    /// The most rules a list may have. Lists with more are handled as described
+    /// by `rule_limit_policy`.
+    pub max_rules: Option<usize>,
+    /// What to do with lists that have more rules than `max_rules`.
+    pub rule_limit_policy: RuleLimitPolicy,
+    /// What the domain constraints of every rule are compared against.
+    pub domain_target: DomainTarget,
+    /// The backend the parsed list finds the rules that may match a request with.
+    pub match_backend: MatchBackendKind,
+    /// How far the list is trusted, which bounds the patterns of its rules.
+    pub trust: TrustLevel,
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            strict: false,
+            case_sensitive: true,
+            reject_unknown_resource_types: false,
+            reject_empty_resource_types: false,
+            max_rules: None,
+            rule_limit_policy: RuleLimitPolicy::default(),
+            domain_target: DomainTarget::default(),
+            match_backend: MatchBackendKind::default(),
+            trust: TrustLevel::default(),
+        }
+    }
+}
+
+/// The longest pattern accepted in a list parsed with `TrustLevel::Untrusted`,
+/// in bytes.
+pub const UNTRUSTED_PATTERN_LIMIT: usize = 256;
+
+/// How far a list is trusted, eg. to accept any regular expression from the
+/// lists bundled with the embedder, but only simple patterns from lists the
+/// user adds from anywhere.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TrustLevel {
+    /// The URL filters and header patterns may be any regular expression.
+    #[default]
+    Trusted,
+    /// The URL filters and header patterns must be globs of at most
+    /// `UNTRUSTED_PATTERN_LIMIT` bytes: literal text, escaped punctuation, `.`
+    /// and `.*` wildcards, and `^` and `$` anchors at either end. Globs compile
+    /// to small programs, and most of them to plain strings matched without a
+    /// regular expression. Rules with other patterns are invalid.
+    Untrusted,
+}
+
+/// Whether `pattern` is a glob as described by `TrustLevel::Untrusted`.
+fn is_glob(pattern: &str) -> bool {
+    let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+    let pattern = pattern.strip_suffix('$').filter(|p| !p.ends_with('\\')).unwrap_or(pattern);
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c) if c.is_ascii_punctuation() => {}
+                _ => return false,
+            },
+            '.' => {
+                chars.next_if_eq(&'*');
+            }
+            '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$' => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Check that `pattern`, found in `field` of the rule at `index`, is allowed at
+/// the trust level of `options`.
+fn check_trust(index: usize, field: &'static str, pattern: &str, options: &ParseOptions) -> Result<(), Error> {
+    match options.trust {
+        TrustLevel::Trusted => Ok(()),
+        TrustLevel::Untrusted if pattern.len() > UNTRUSTED_PATTERN_LIMIT => {
+            Err(Error::SizeLimitExceeded { index, limit: UNTRUSTED_PATTERN_LIMIT })
+        }
+        TrustLevel::Untrusted if !is_glob(pattern) => {
+            Err(Error::UntrustedPattern { index, field, pattern: pattern.to_owned() })
+        }
+        TrustLevel::Untrusted => Ok(()),
+    }
+}
+
+/// How lists with more rules than `ParseOptions::max_rules` are handled, eg.
+/// to impose a limit like the one of Safari on lists from untrusted sources.
+/// The rules dropped to stay within the limit are listed by the compile
+/// statistics of the list.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RuleLimitPolicy {
+    /// Fail with `Error::TooManyRules`. Invalid rules count towards the limit.
+    #[default]
+    Error,
+    /// Keep the first rules, and drop the rest of the list without parsing it.
+    Truncate,
+    /// Keep the rules that matter most, dropping the later rules of the lowest
+    /// priority first. Exceptions come first, since dropping them could break
+    /// sites, then the rules acting on network requests, then element hiding
+    /// rules. The whole list is parsed, but no more rules than the limit are
+    /// held at once.
+    Prioritize,
+}
+
+/// The checks `Rule::validate` makes beyond parsing the rule, which only checks
+/// the syntax of its patterns.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationOptions {
+    /// Compile the URL filter, as matching a request would, to report filters
+    /// too large for the regex engine.
+    pub compile_url_filter: bool,
+    /// The longest URL filter accepted, in bytes.
+    pub max_url_filter_length: Option<usize>,
+    /// Treat an empty `resource-type` list as invalid, rather than as a rule
+    /// that never matches.
+    pub reject_empty_resource_types: bool,
+}
+
+/// The priority of a rule for `RuleLimitPolicy::Prioritize`, higher first.
+fn priority(rule: &Rule) -> usize {
+    match rule.action {
+        Action::IgnorePreviousRules | Action::CssDisplayNoneException(_) => 2,
+        Action::Block | Action::BlockCookies(_) | Action::BlockCookiesMatching(_) | Action::Redirect(_) |
+        Action::BlockInlineScripts => 1,
+        Action::CssDisplayNone(_) | Action::CssDisplayNoneProcedural(_) | Action::CssInject { .. } |
+        Action::HtmlFilter(_) => 0,
+    }
+}
+
+/// The rules of a parsed list, along with those that were left out.
+#[derive(Debug, Default)]
+pub struct ParsedList {
+    /// The rules that were kept, in list order.
+    pub rules: Vec<Rule>,
+    /// The number of invalid rules that were ignored.
+    pub skipped: usize,
+    /// The positions of the rules dropped to stay within `ParseOptions::max_rules`.
+    pub dropped: Vec<usize>,
+}
+
+impl FromStr for ResourceType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ResourceType, ()> {
+        Ok(match s {
             "document" => ResourceType::Document,
             "image" => ResourceType::Image,
             "style-sheet" => ResourceType::StyleSheet,
@@ -28,163 +375,702 @@ impl ResourceType {
             "svg-document" => ResourceType::SVGDocument,
             "media" => ResourceType::Media,
             "popup" => ResourceType::Popup,
-            _ => return None,
+            "websocket" => ResourceType::WebSocket,
+            "worker" => ResourceType::Worker,
+            "shared-worker" => ResourceType::SharedWorker,
+            "service-worker" => ResourceType::ServiceWorker,
+            "csp-report" => ResourceType::CspReport,
+            "beacon" => ResourceType::Beacon,
+            "object-subrequest" => ResourceType::ObjectSubrequest,
+            _ => return Err(()),
         })
     }
 }
 
+impl ResourceType {
+    /// The keyword naming this resource type in the JSON list format.
+    pub fn name(self) -> &'static str {
+        match self {
+            ResourceType::Document => "document",
+            ResourceType::Image => "image",
+            ResourceType::StyleSheet => "style-sheet",
+            ResourceType::Script => "script",
+            ResourceType::Font => "font",
+            ResourceType::Raw => "raw",
+            ResourceType::SVGDocument => "svg-document",
+            ResourceType::Media => "media",
+            ResourceType::Popup => "popup",
+            ResourceType::WebSocket => "websocket",
+            ResourceType::Worker => "worker",
+            ResourceType::SharedWorker => "shared-worker",
+            ResourceType::ServiceWorker => "service-worker",
+            ResourceType::CspReport => "csp-report",
+            ResourceType::Beacon => "beacon",
+            ResourceType::ObjectSubrequest => "object-subrequest",
+        }
+    }
+}
+
+impl FromStr for LoadType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<LoadType, ()> {
+        match s {
+            "first-party" => Ok(LoadType::FirstParty),
+            "third-party" => Ok(LoadType::ThirdParty),
+            "same-site" => Ok(LoadType::SameSite),
+            "cross-site" => Ok(LoadType::CrossSite),
+            "top-frame" => Ok(LoadType::TopFrame),
+            "child-frame" => Ok(LoadType::ChildFrame),
+            _ => Err(()),
+        }
+    }
+}
+
 impl LoadType {
-    fn from_str(s: &str) -> Option<LoadType> {
+    /// The keyword naming this load type in the JSON list format.
+    pub fn name(self) -> &'static str {
+        match self {
+            LoadType::FirstParty => "first-party",
+            LoadType::ThirdParty => "third-party",
+            LoadType::SameSite => "same-site",
+            LoadType::CrossSite => "cross-site",
+            LoadType::TopFrame => "top-frame",
+            LoadType::ChildFrame => "child-frame",
+        }
+    }
+}
+
+/// Whether `name` is a valid cookie name, possibly containing `*` wildcards.
+fn is_cookie_name_pattern(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&b))
+}
+
+impl FromStr for CookieScope {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<CookieScope, ()> {
         match s {
-            "first-party" => Some(LoadType::FirstParty),
-            "third-party" => Some(LoadType::ThirdParty),
-            _ => None,
+            "request" => Ok(CookieScope::Request),
+            "response" => Ok(CookieScope::Response),
+            "both" => Ok(CookieScope::Both),
+            _ => Err(()),
+        }
+    }
+}
+
+impl CookieScope {
+    /// The keyword naming this scope in the JSON list format.
+    pub fn name(self) -> &'static str {
+        match self {
+            CookieScope::Request => "request",
+            CookieScope::Response => "response",
+            CookieScope::Both => "both",
         }
     }
 }
 
 impl DomainMatcher {
-    pub fn new<'a, T, Iter>(iter: Iter) -> DomainMatcher
+    #[cfg(any(test, feature = "quickcheck"))]
+    pub fn new<T, Iter>(iter: Iter) -> DomainMatcher
+        where T: AsRef<str>, Iter: IntoIterator<Item=T>
+    {
+        DomainMatcher::interned(iter, &mut Interner::new())
+    }
+
+    /// Like `new`, but shares the domains with other users of `interner`.
+    pub fn interned<T, Iter>(iter: Iter, interner: &mut Interner) -> DomainMatcher
         where T: AsRef<str>, Iter: IntoIterator<Item=T>
     {
         let mut exact = vec![];
         let mut subdomain = vec![];
         for domain in iter {
             let domain = domain.as_ref();
-            if domain.starts_with("*") {
-                subdomain.push(domain[1..].to_owned());
+            if let Some(suffix) = domain.strip_prefix('*') {
+                subdomain.push(interner.intern(suffix));
             } else {
-                exact.push(domain.to_owned());
+                exact.push(interner.intern(domain));
             }
         }
+        exact.sort_unstable();
+        exact.dedup();
+        subdomain.sort_unstable();
+        subdomain.dedup();
         DomainMatcher {
+            wildcard_tld: exact.iter().chain(&subdomain).any(|domain| domain.ends_with(".*")),
             exact: exact.into_boxed_slice(),
             subdomain: subdomain.into_boxed_slice(),
         }
     }
+
+    /// Share the domains of an existing matcher with other users of `interner`.
+    /// Equal strings sort equally, so the lists stay sorted.
+    pub fn share_domains(&mut self, interner: &mut Interner) {
+        for domain in self.exact.iter_mut().chain(self.subdomain.iter_mut()) {
+            interner.share(domain);
+        }
+    }
 }
 
 impl Action {
-    fn from_json(v: &Value) -> Option<Action> {
-        let v = match v.as_object() {
-            Some(v) => v,
-            None => return None,
-        };
-
-        v.get("type").and_then(|t| t.as_str()).and_then(|t| {
-            Some(match t {
-                "block" => Action::Block,
-                "block-cookies" => Action::BlockCookies,
-                "ignore-previous-rules" => Action::IgnorePreviousRules,
-                "css-display-none" => {
-                    let selector = match v.get("selector").and_then(|s| s.as_str()) {
-                        Some(s) => s,
-                        None => return None,
-                    };
-                    Action::CssDisplayNone(selector.to_owned())
+    fn from_json(index: usize, v: &Value, interner: &mut Interner) -> Result<Action, Error> {
+        let missing = |field| Error::MissingField { index, field };
+        let v = v.as_object().ok_or(missing("action"))?;
+        let t = v.get("type").and_then(|t| t.as_str()).ok_or(missing("action.type"))?;
+        Ok(match t {
+            "block" => Action::Block,
+            "block-cookies" => match v.get("names") {
+                // Named cookies are blocked in both directions, so a scope does not apply.
+                Some(names) => {
+                    let names: Option<Vec<String>> =
+                        names.as_array()
+                             .and_then(|names| names.iter()
+                                                    .map(|name| name.as_str()
+                                                                    .filter(|name| is_cookie_name_pattern(name))
+                                                                    .map(str::to_owned))
+                                                    .collect());
+                    match names {
+                        Some(names) if !names.is_empty() && !v.contains_key("scope") =>
+                            Action::BlockCookiesMatching(names.into()),
+                        _ => return Err(missing("action.names")),
+                    }
+                }
+                None => Action::BlockCookies(match v.get("scope") {
+                    Some(scope) => scope.as_str().and_then(|s| s.parse().ok())
+                                        .ok_or(missing("action.scope"))?,
+                    None => CookieScope::Request,
+                }),
+            },
+            "redirect" => {
+                let name = v.get("resource").and_then(|s| s.as_str())
+                            .ok_or(missing("action.resource"))?;
+                let resource = Resource::named(name).ok_or_else(|| {
+                    Error::UnknownResource { index, name: name.to_owned() }
+                })?;
+                Action::Redirect(resource)
+            }
+            "ignore-previous-rules" => Action::IgnorePreviousRules,
+            "block-inline-scripts" => Action::BlockInlineScripts,
+            "css-display-none" => {
+                let selector = v.get("selector").and_then(|s| s.as_str())
+                                .ok_or(missing("action.selector"))?;
+                if is_procedural(selector) {
+                    let procedural = ProceduralSelector::parse(selector).ok_or_else(|| {
+                        Error::InvalidSelector { index, selector: selector.to_owned() }
+                    })?;
+                    Action::CssDisplayNoneProcedural(Arc::new(procedural))
+                } else if is_valid_selector(selector) {
+                    Action::CssDisplayNone(interner.intern(selector))
+                } else {
+                    return Err(Error::InvalidSelector { index, selector: selector.to_owned() });
                 }
-                _ => return None,
-            })
+            }
+            "html-filter" => {
+                let selector = v.get("selector").and_then(|s| s.as_str())
+                                .ok_or(missing("action.selector"))?;
+                let filter = HtmlFilter::parse(selector).ok_or_else(|| {
+                    Error::InvalidSelector { index, selector: selector.to_owned() }
+                })?;
+                Action::HtmlFilter(Arc::new(filter))
+            }
+            "css-display-none-exception" => {
+                let selector = v.get("selector").and_then(|s| s.as_str())
+                                .ok_or(missing("action.selector"))?;
+                Action::CssDisplayNoneException(interner.intern(selector))
+            }
+            "css-inject" => {
+                let selector = v.get("selector").and_then(|s| s.as_str())
+                                .ok_or(missing("action.selector"))?;
+                let style = v.get("style").and_then(|s| s.as_str())
+                             .ok_or(missing("action.style"))?;
+                if !is_valid_selector(selector) {
+                    return Err(Error::InvalidSelector { index, selector: selector.to_owned() });
+                }
+                // The declarations are placed inside a style rule, which they must not escape.
+                if style.trim().is_empty() || style.contains(['{', '}']) || style.contains("</") {
+                    return Err(Error::InvalidStyle { index, style: style.to_owned() });
+                }
+                Action::CssInject {
+                    selector: interner.intern(selector),
+                    style: interner.intern(style),
+                }
+            }
+            _ => return Err(Error::UnsupportedAction { index, action: t.to_owned() }),
         })
     }
+
+    /// The JSON action object parsed as this action.
+    pub fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        let mut insert = |key: &str, value: Value| object.insert(key.to_owned(), value);
+        match *self {
+            Action::Block => {
+                insert("type", Value::from("block"));
+            }
+            Action::BlockCookies(scope) => {
+                insert("type", Value::from("block-cookies"));
+                insert("scope", Value::from(scope.name()));
+            }
+            Action::BlockCookiesMatching(ref names) => {
+                insert("type", Value::from("block-cookies"));
+                insert("names", Value::Array(names.iter().map(|name| Value::from(&**name)).collect()));
+            }
+            Action::Redirect(resource) => {
+                insert("type", Value::from("redirect"));
+                insert("resource", Value::from(resource.name));
+            }
+            Action::CssDisplayNone(ref selector) => {
+                insert("type", Value::from("css-display-none"));
+                insert("selector", Value::from(&**selector));
+            }
+            Action::CssDisplayNoneProcedural(ref selector) => {
+                insert("type", Value::from("css-display-none"));
+                insert("selector", Value::from(&*selector.source));
+            }
+            Action::CssInject { ref selector, ref style } => {
+                insert("type", Value::from("css-inject"));
+                insert("selector", Value::from(&**selector));
+                insert("style", Value::from(&**style));
+            }
+            Action::HtmlFilter(ref filter) => {
+                insert("type", Value::from("html-filter"));
+                insert("selector", Value::from(&*filter.source));
+            }
+            Action::CssDisplayNoneException(ref selector) => {
+                insert("type", Value::from("css-display-none-exception"));
+                insert("selector", Value::from(&**selector));
+            }
+            Action::IgnorePreviousRules => {
+                insert("type", Value::from("ignore-previous-rules"));
+            }
+            Action::BlockInlineScripts => {
+                insert("type", Value::from("block-inline-scripts"));
+            }
+        }
+        Value::Object(object)
+    }
+}
+
+fn domains_to_json(matcher: &DomainMatcher) -> Value {
+    matcher.exact.iter().map(|d| Value::from(&**d))
+           .chain(matcher.subdomain.iter().map(|d| Value::from(format!("*{}", d))))
+           .collect()
+}
+
+impl Trigger {
+    /// The JSON trigger object parsed as this trigger with the default parse
+    /// options. The domain target is chosen by the options rather than the JSON,
+    /// so it is not included.
+    pub fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        let components = [("path-filter", &self.path_filter), ("query-filter", &self.query_filter)];
+        let flag = self.url_filter.as_str().starts_with("(?i)") || components.iter().any(|&(_, filter)| {
+            filter.as_ref().is_some_and(|filter| filter.as_str().starts_with("(?i)"))
+        });
+        let strip = |filter: &UrlFilter| filter.as_str().strip_prefix("(?i)").unwrap_or(filter.as_str()).to_owned();
+        object.insert("url-filter".to_owned(), Value::from(strip(&self.url_filter)));
+        if flag {
+            object.insert("url-filter-is-case-sensitive".to_owned(), Value::Bool(true));
+        }
+        if self.url_filter_anchor == UrlFilterAnchor::Host {
+            object.insert("url-filter-anchor".to_owned(), Value::from("host"));
+        }
+        for &(key, filter) in &components {
+            if let Some(ref filter) = *filter {
+                object.insert(key.to_owned(), Value::from(strip(filter)));
+            }
+        }
+        if let ResourceTypeList::List(ref types) = self.resource_type {
+            object.insert("resource-type".to_owned(), types.iter().map(|t| Value::from(t.name())).collect());
+        }
+        if let LoadTypeList::List(ref types) = self.load_type {
+            for (key, aspect) in ["load-type", "site-relation", "load-context"].iter().zip(&LoadType::ASPECTS) {
+                let names: Vec<Value> = types.iter().filter(|t| t.bit() & aspect != 0)
+                                             .map(|t| Value::from(t.name())).collect();
+                if !names.is_empty() {
+                    object.insert((*key).to_owned(), Value::Array(names));
+                }
+            }
+        }
+        match self.domain_constraint {
+            Some(DomainConstraint::If(ref matcher)) => {
+                object.insert("if-domain".to_owned(), domains_to_json(matcher));
+            }
+            Some(DomainConstraint::Unless(ref matcher)) => {
+                object.insert("unless-domain".to_owned(), domains_to_json(matcher));
+            }
+            None => {}
+        }
+        if let Some(ref contexts) = self.contexts {
+            object.insert("context".to_owned(), contexts.iter().map(|c| Value::from(&**c)).collect());
+        }
+        if let Some(user_initiated) = self.user_initiated {
+            object.insert("user-initiated".to_owned(), Value::Bool(user_initiated));
+        }
+        if let Some(ref matcher) = self.opener_domain {
+            object.insert("opener-domain".to_owned(), domains_to_json(matcher));
+        }
+        if self.match_redirect_chain {
+            object.insert("match-redirect-chain".to_owned(), Value::Bool(true));
+        }
+        for (key, conditions) in [("if-header", &self.if_header), ("unless-header", &self.unless_header)] {
+            if conditions.is_empty() {
+                continue;
+            }
+            let conditions = conditions.iter().map(|condition| {
+                let mut object = Map::new();
+                object.insert("name".to_owned(), Value::from(&*condition.name));
+                if let Some(ref value) = condition.value {
+                    object.insert("value".to_owned(), Value::from(value.as_str()));
+                }
+                Value::Object(object)
+            }).collect();
+            object.insert(key.to_owned(), conditions);
+        }
+        Value::Object(object)
+    }
+}
+
+impl Rule {
+    /// Parse a single JSON rule object, eg. one a user is typing, as `parse_list`
+    /// would parse it in a list. The error identifies the invalid field with
+    /// `Error::field`, and its index is 0.
+    pub fn parse_json(rule: &str) -> Result<Rule, Error> {
+        let value: Value = serde_json::from_str(rule).map_err(|_| Error::JSON)?;
+        parse_rule_impl(0, &value, &mut Interner::new(), &ParseOptions::default())
+    }
+
+    /// Check this rule further, as described by `options`.
+    pub fn validate(&self, options: &ValidationOptions) -> Result<(), Error> {
+        let url_filter = &self.trigger.url_filter;
+        if let Some(limit) = options.max_url_filter_length.filter(|&limit| url_filter.as_str().len() > limit) {
+            return Err(Error::SizeLimitExceeded { index: 0, limit });
+        }
+        if options.reject_empty_resource_types {
+            if let ResourceTypeList::List(ref types) = self.trigger.resource_type {
+                if types.is_empty() {
+                    return Err(Error::EmptyResourceTypes { index: 0 });
+                }
+            }
+        }
+        if options.compile_url_filter {
+            match url_filter.compile() {
+                Ok(_) => {}
+                Err(&regex::Error::CompiledTooBig(limit)) => return Err(Error::SizeLimitExceeded { index: 0, limit }),
+                Err(_) => return Err(Error::InvalidRegex { index: 0, pattern: url_filter.as_str().to_owned() }),
+            }
+        }
+        Ok(())
+    }
+
+    /// The identifier of this rule: a hash of its JSON form, which lists keys in
+    /// sorted order, along with the domain target the JSON form leaves out.
+    pub fn id(&self) -> RuleId {
+        let mut content = self.to_json().to_string();
+        if self.trigger.domain_target == DomainTarget::TopDocument {
+            content.push_str("top-document");
+        }
+        let digest = md5::compute(content.as_bytes());
+        let mut id = 0;
+        for &byte in &digest[..8] {
+            id = id << 8 | u64::from(byte);
+        }
+        RuleId(id)
+    }
+
+    /// The JSON rule object parsed as this rule, as by `Trigger::to_json`.
+    pub(crate) fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        object.insert("trigger".to_owned(), self.trigger.to_json());
+        object.insert("action".to_owned(), self.action.to_json());
+        if !self.groups.is_empty() {
+            object.insert("tags".to_owned(), self.groups.iter().map(|g| Value::from(&**g)).collect());
+        }
+        Value::Object(object)
+    }
+}
+
+/// Parse the JSON rule object at position `index` of a list, reporting why the
+/// rule is invalid if it cannot be parsed. Domains and selectors are shared
+/// with the other rules parsed with `interner`.
+pub fn parse_rule_impl(index: usize,
+                       rule: &Value,
+                       interner: &mut Interner,
+                       options: &ParseOptions) -> Result<Rule, Error> {
+    let missing = |field| Error::MissingField { index, field };
+    let obj = rule.as_object().ok_or(missing("rule"))?;
+    let trigger_source = obj.get("trigger").and_then(|t| t.as_object()).ok_or(missing("trigger"))?;
+
+    let url_filter_is_case_sensitive = trigger_source.get("url-filter-is-case-sensitive")
+                                                     .and_then(|u| u.as_bool())
+                                                     .unwrap_or(!options.case_sensitive);
+
+    let filter = trigger_source.get("url-filter").and_then(|u| u.as_str())
+                               .ok_or(missing("trigger.url-filter"))?;
+    check_trust(index, "trigger.url-filter", filter, options)?;
+    let url_filter_anchor = match trigger_source.get("url-filter-anchor") {
+        None => UrlFilterAnchor::Url,
+        Some(anchor) if anchor == "url" => UrlFilterAnchor::Url,
+        Some(anchor) if anchor == "host" => UrlFilterAnchor::Host,
+        Some(_) => return Err(missing("trigger.url-filter-anchor")),
+    };
+    let flag = if url_filter_is_case_sensitive {
+        "(?i)"
+    } else {
+        ""
+    };
+    let url_filter = match url_filter_anchor {
+        UrlFilterAnchor::Url => {
+            match UrlFilter::new(&format!("{}{}", flag, filter)) {
+                Some(filter) => filter,
+                None => return Err(Error::InvalidRegex { index, pattern: filter.to_owned() }),
+            }
+        }
+        // Hosts are compared in lowercase, as `url::Url` serializes them. A host
+        // name is also a regular expression matching wherever the name appears,
+        // so the filter can still find candidates in the URL like any other.
+        UrlFilterAnchor::Host => {
+            let valid = !filter.is_empty() && !filter.starts_with('.') && !filter.contains("..") &&
+                        filter.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+            match UrlFilter::new(&filter.to_ascii_lowercase()) {
+                Some(filter) if valid => filter,
+                _ => return Err(Error::InvalidHostPattern { index, pattern: filter.to_owned() }),
+            }
+        }
+    };
+
+    // The path and query filters follow the case sensitivity of the URL filter.
+    let mut component_filters = [None, None];
+    let component_keys = [("path-filter", "trigger.path-filter"), ("query-filter", "trigger.query-filter")];
+    for (&(key, field), filter) in component_keys.iter().zip(&mut component_filters) {
+        if let Some(pattern) = trigger_source.get(key).and_then(|p| p.as_str()) {
+            check_trust(index, field, pattern, options)?;
+            *filter = Some(UrlFilter::new(&format!("{}{}", flag, pattern)).ok_or_else(|| {
+                Error::InvalidRegex { index, pattern: pattern.to_owned() }
+            })?);
+        }
+    }
+    let [path_filter, query_filter] = component_filters;
+
+    let resource_type = match trigger_source.get("resource-type").and_then(|r| r.as_array()) {
+        Some(list) if list.is_empty() && options.reject_empty_resource_types => {
+            return Err(Error::EmptyResourceTypes { index });
+        }
+        Some(list) => {
+            let mut types = vec![];
+            for name in list.iter().filter_map(|r| r.as_str()) {
+                match name.parse() {
+                    Ok(resource_type) => types.push(resource_type),
+                    Err(()) if options.reject_unknown_resource_types => {
+                        return Err(Error::UnknownResourceType { index, name: name.to_owned() });
+                    }
+                    Err(()) => {}
+                }
+            }
+            ResourceTypeList::List(types)
+        }
+        None => ResourceTypeList::All,
+    };
+
+    // Each key restricts one aspect of the load, and a list naming no supported
+    // category of its aspect does not restrict it.
+    let mut load_types: Vec<LoadType> = vec![];
+    for (key, aspect) in ["load-type", "site-relation", "load-context"].iter().zip(&LoadType::ASPECTS) {
+        if let Some(list) = trigger_source.get(*key).and_then(|l| l.as_array()) {
+            load_types.extend(list.iter()
+                                  .filter_map(|l| l.as_str().and_then(|s| s.parse::<LoadType>().ok()))
+                                  .filter(|t| t.bit() & aspect != 0));
+        }
+    }
+    let load_type = if load_types.is_empty() {
+        LoadTypeList::All
+    } else {
+        LoadTypeList::List(load_types)
+    };
+
+    let if_domain =
+        trigger_source.get("if-domain")
+                      .and_then(|i| i.as_array())
+                      .map(|i| i.iter().filter_map(|d| d.as_str()))
+                      .map(|i| DomainMatcher::interned(i, interner));
+
+    let unless_domain =
+        trigger_source.get("unless-domain")
+                      .and_then(|u| u.as_array())
+                      .map(|i| i.iter().filter_map(|d| d.as_str()))
+                      .map(|i| DomainMatcher::interned(i, interner));
+
+    if if_domain.is_some() && unless_domain.is_some() {
+        return Err(Error::ConflictingDomainConstraints { index });
+    }
+
+    let domain_constraint = match if_domain {
+        Some(list) => Some(DomainConstraint::If(list)),
+        None => unless_domain.map(DomainConstraint::Unless),
+    };
+
+    let contexts =
+        trigger_source.get("context")
+                      .and_then(|c| c.as_array())
+                      .map(|c| c.iter().filter_map(|c| c.as_str()).map(|c| interner.intern(c)).collect());
+
+    let user_initiated = trigger_source.get("user-initiated").and_then(|u| u.as_bool());
+
+    let match_redirect_chain =
+        trigger_source.get("match-redirect-chain").and_then(|m| m.as_bool()).unwrap_or(false);
+
+    let mut header_conditions = [vec![], vec![]];
+    let header_keys = [("if-header", "trigger.if-header"), ("unless-header", "trigger.unless-header")];
+    for (&(key, field), conditions) in header_keys.iter().zip(&mut header_conditions) {
+        for condition in trigger_source.get(key).and_then(|h| h.as_array()).into_iter().flatten() {
+            let name = condition.get("name").and_then(|n| n.as_str()).ok_or(missing(field))?;
+            let value = match condition.get("value").and_then(|v| v.as_str()) {
+                Some(pattern) => {
+                    check_trust(index, field, pattern, options)?;
+                    let filter = UrlFilter::new(pattern).ok_or_else(|| {
+                        Error::InvalidRegex { index, pattern: pattern.to_owned() }
+                    })?;
+                    Some(filter)
+                }
+                None => None,
+            };
+            conditions.push(HeaderCondition { name: interner.intern(&name.to_ascii_lowercase()), value });
+        }
+    }
+    let [if_header, unless_header] = header_conditions;
+
+    let opener_domain =
+        trigger_source.get("opener-domain")
+                      .and_then(|o| o.as_array())
+                      .map(|o| o.iter().filter_map(|d| d.as_str()))
+                      .map(|o| DomainMatcher::interned(o, interner));
+
+    let action = Action::from_json(index, obj.get("action").ok_or(missing("action"))?, interner)?;
+
+    let mut groups: Vec<Arc<str>> =
+        obj.get("tags")
+           .and_then(|t| t.as_array())
+           .map(|t| t.iter().filter_map(|g| g.as_str()).map(|g| interner.intern(g)).collect())
+           .unwrap_or_default();
+    groups.extend(obj.get("group").and_then(|g| g.as_str()).map(|g| interner.intern(g)));
+    groups.sort_unstable();
+    groups.dedup();
+
+    Ok(Rule {
+        trigger: Trigger {
+            url_filter,
+            url_filter_anchor,
+            path_filter,
+            query_filter,
+            resource_type,
+            load_type,
+            domain_constraint,
+            contexts,
+            user_initiated,
+            opener_domain,
+            domain_target: options.domain_target,
+            match_redirect_chain,
+            if_header: if_header.into_boxed_slice(),
+            unless_header: unless_header.into_boxed_slice(),
+        },
+        action,
+        groups: groups.into_boxed_slice(),
+        source: None,
+    })
 }
 
 /// Parse a string containing a JSON representation of a content blocker list.
 /// Returns a vector of parsed rules, or an error representing the nature of
 /// the invalid input. Any rules missing required fields will be silently ignored.
+#[cfg(test)]
 pub fn parse_list_impl(body: &str) -> Result<Vec<Rule>, Error> {
-    let json_body: Value = try!(serde_json::from_str(body).map_err(|_| Error::JSON));
-    let list = try!(json_body.as_array().ok_or(Error::NotAList));
-    let mut rules = vec![];
-    for rule in list {
-        let obj = match rule.as_object() {
-            Some(obj) => obj,
-            None => continue,
-        };
+    parse_list_with_options_impl(body, &ParseOptions::default()).map(|parsed| parsed.rules)
+}
 
-        let trigger_source = match obj.get("trigger").and_then(|t| t.as_object()) {
-            Some(trigger) => trigger,
-            None => continue,
-        };
+/// Like `parse_list_impl`, but fails with a description of the first invalid
+/// rule instead of ignoring it. Every URL filter is compiled immediately, so
+/// that patterns exceeding the size limits are reported too.
+pub fn parse_list_strict_impl(body: &str) -> Result<Vec<Rule>, Error> {
+    let options = ParseOptions { strict: true, ..ParseOptions::default() };
+    parse_list_with_options_impl(body, &options).map(|parsed| parsed.rules)
+}
 
-        let url_filter_is_case_sensitive = trigger_source.get("url-filter-is-case-sensitive")
-                                                         .and_then(|u| u.as_bool())
-                                                         .unwrap_or(false);
+/// Parse a list as described by `options`. The body is split into rules the
+/// way a stream is, which locates each rule in it.
+pub fn parse_list_with_options_impl(body: &str, options: &ParseOptions) -> Result<ParsedList, Error> {
+    parse_uncompressed(body.as_bytes(), options)
+}
 
-        let url_filter = match trigger_source.get("url-filter").and_then(|u| u.as_str()) {
-            Some(filter) => {
-                let flag = if url_filter_is_case_sensitive {
-                    "(?i)"
-                } else {
-                    ""
-                };
-                match Regex::new(&format!("{}{}", flag, filter)) {
-                    Ok(filter) => filter,
-                    Err(_) => continue,
+/// Parse the rule objects of a list as they are produced by `values`, along
+/// with where they were found, which stops at the first error.
+pub fn parse_rules<I>(values: I, options: &ParseOptions) -> Result<ParsedList, Error>
+    where I: IntoIterator<Item=Result<(Value, SourceLocation), Error>>
+{
+    let mut interner = Interner::new();
+    let mut parsed = ParsedList::default();
+    // The rules kept so far, by priority if they are prioritized.
+    let mut kept: [Vec<Rule>; 3] = Default::default();
+    let mut count = 0;
+    for value in values {
+        let (value, source) = value?;
+        let index = source.index;
+        let full = options.max_rules.is_some_and(|limit| count >= limit);
+        match options.rule_limit_policy {
+            RuleLimitPolicy::Error => {
+                if let Some(limit) = options.max_rules.filter(|&limit| index >= limit) {
+                    return Err(Error::TooManyRules { limit });
                 }
             }
-            None => continue,
+            RuleLimitPolicy::Truncate if full => {
+                parsed.dropped.push(index);
+                continue;
+            }
+            RuleLimitPolicy::Truncate | RuleLimitPolicy::Prioritize => {}
+        }
+        let mut rule = match parse_rule_impl(index, &value, &mut interner, options) {
+            Ok(rule) => rule,
+            Err(_) if !options.strict => {
+                parsed.skipped += 1;
+                continue;
+            }
+            Err(error) => return Err(error),
         };
-
-        let resource_type = match trigger_source.get("resource-type").and_then(|r| r.as_array()) {
-            Some(list) => {
-                ResourceTypeList::List(
-                    list.iter()
-                        .filter_map(|r| r.as_str()
-                                         .and_then(|s| ResourceType::from_str(s)))
-                        .collect())
+        if options.strict {
+            match rule.trigger.url_filter.compile() {
+                Ok(_) => {}
+                Err(&regex::Error::CompiledTooBig(limit)) => return Err(Error::SizeLimitExceeded { index, limit }),
+                Err(_) => {
+                    let pattern = rule.trigger.url_filter.as_str().to_owned();
+                    return Err(Error::InvalidRegex { index, pattern });
+                }
             }
-            None => ResourceTypeList::All,
+        }
+        rule.source = Some(source);
+        let rank = match options.rule_limit_policy {
+            RuleLimitPolicy::Prioritize => priority(&rule),
+            _ => 0,
         };
-
-        let load_type =
-            trigger_source.get("load-type")
-                          .and_then(|l| l.as_array())
-                          .and_then(|list|
-                                    list.iter()
-                                        .filter_map(|l| l.as_str()
-                                                         .and_then(|s| LoadType::from_str(s)))
-                                        .next());
-
-        let if_domain =
-            trigger_source.get("if-domain")
-                          .and_then(|i| i.as_array())
-                          .map(|i| i.iter().filter_map(|d| d.as_str()))
-                          .map(DomainMatcher::new);
-
-        let unless_domain =
-            trigger_source.get("unless-domain")
-                          .and_then(|u| u.as_array())
-                          .map(|i| i.iter().filter_map(|d| d.as_str()))
-                          .map(DomainMatcher::new);
-
-        if if_domain.is_some() && unless_domain.is_some() {
-            continue;
-        }
-
-        let domain_constraint = if let Some(list) = if_domain {
-            Some(DomainConstraint::If(list))
-        } else if let Some(list) = unless_domain {
-            Some(DomainConstraint::Unless(list))
+        if full {
+            // Make room by dropping the latest rule of a lower priority.
+            match kept[..rank].iter_mut().find(|rules| !rules.is_empty()).and_then(Vec::pop) {
+                Some(evicted) => parsed.dropped.extend(evicted.source.map(|source| source.index)),
+                None => {
+                    parsed.dropped.push(index);
+                    continue;
+                }
+            }
         } else {
-            None
-        };
-
-        let action = match obj.get("action").and_then(Action::from_json) {
-            Some(action) => action,
-            None => continue,
-        };
-
-        rules.push(Rule {
-            trigger: Trigger {
-                url_filter: url_filter,
-                resource_type: resource_type,
-                load_type: load_type,
-                domain_constraint: domain_constraint,
-            },
-            action: action,
-        });
+            count += 1;
+        }
+        kept[rank].push(rule);
     }
-
-    Ok(rules)
+    let [mut rules, network, exceptions] = kept;
+    if !network.is_empty() || !exceptions.is_empty() {
+        rules.extend(network);
+        rules.extend(exceptions);
+        rules.sort_by_key(|rule| rule.source.map(|source| source.index));
+    }
+    parsed.rules = rules;
+    parsed.dropped.sort_unstable();
+    Ok(parsed)
 }
@@ -0,0 +1,54 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Precompiling lists at build time, so that a default list ships inside the
+//! binary in the format of `RuleList::to_bytes` and costs nothing to parse at
+//! startup. A build script compiles the list into `OUT_DIR`:
+//!
+//! ```ignore
+//! content_blocker::precompile_list_for_build("easylist.json").unwrap();
+//! ```
+//!
+//! and the crate embeds it with `include_rules!("easylist.json")`.
+
+use parse::{Error, ParseOptions};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use stream::parse_list_from_reader_impl;
+use RuleList;
+
+pub fn precompile_list_impl(input: &Path, output: &Path) -> Result<RuleList, Error> {
+    let file = File::open(input).map_err(|e| Error::Io(e.kind()))?;
+    // A rule that would be skipped at runtime is a mistake in the shipped list.
+    let options = ParseOptions { strict: true, ..ParseOptions::default() };
+    let list = RuleList::parsed(parse_list_from_reader_impl(BufReader::new(file), &options)?, &options);
+    fs::write(output, list.to_bytes()).map_err(|e| Error::Io(e.kind()))?;
+    Ok(list)
+}
+
+pub fn precompile_list_for_build_impl(input: &Path) -> Result<PathBuf, Error> {
+    let out_dir = env::var_os("OUT_DIR").ok_or(Error::Io(io::ErrorKind::NotFound))?;
+    let name = input.file_name().ok_or(Error::Io(io::ErrorKind::InvalidInput))?;
+    // `include_rules!` names the output after the input, plus this extension.
+    let output = Path::new(&out_dir).join(format!("{}.rules", name.to_string_lossy()));
+    println!("cargo:rerun-if-changed={}", input.display());
+    precompile_list_impl(input, &output)?;
+    Ok(output)
+}
+
+/// Embed a list precompiled by `precompile_list_for_build` in the build script
+/// of the calling crate, as a `CompiledRuleList<'static>`. The argument is the
+/// file name of the list, eg. `include_rules!("easylist.json")`.
+///
+/// The build dependency on this library must be the same version as the
+/// regular one, or loading the embedded list panics.
+#[macro_export]
+macro_rules! include_rules {
+    ($name:literal) => {
+        $crate::CompiledRuleList::from_bytes(include_bytes!(concat!(env!("OUT_DIR"), "/", $name, ".rules")))
+            .expect("the list was precompiled by a different version of content-blocker")
+    };
+}
@@ -0,0 +1,229 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use repr::Rule;
+use std::mem;
+
+/// The length of the substrings hashed by the filter.
+const GRAM: usize = 3;
+/// The number of bits in each filter; a power of two.
+const BITS: usize = 4096;
+const WORDS: usize = BITS / 64;
+
+/// A set of hashed substrings, which may report false positives but never false negatives.
+#[derive(Clone)]
+pub struct GramSet([u64; WORDS]);
+
+impl GramSet {
+    fn new() -> GramSet {
+        GramSet([0; WORDS])
+    }
+
+    /// Record every substring of length `GRAM` of the ASCII-lowercased `text`.
+    pub fn from_text(text: &str) -> GramSet {
+        let mut set = GramSet::new();
+        let bytes = text.as_bytes();
+        if bytes.len() >= GRAM {
+            for window in bytes.windows(GRAM) {
+                set.insert(hash(window));
+            }
+        }
+        set
+    }
+
+    fn insert(&mut self, hash: u32) {
+        let bit = hash as usize % BITS;
+        self.0[bit / 64] |= 1 << (bit % 64);
+    }
+
+    fn contains(&self, hash: u32) -> bool {
+        let bit = hash as usize % BITS;
+        self.0[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    fn intersects(&self, other: &GramSet) -> bool {
+        self.0.iter().zip(other.0.iter()).any(|(a, b)| a & b != 0)
+    }
+}
+
+/// Hash a window of bytes, ignoring ASCII case.
+fn hash(window: &[u8]) -> u32 {
+    // FNV-1a.
+    window.iter().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ byte.to_ascii_lowercase() as u32).wrapping_mul(0x01000193)
+    })
+}
+
+/// A fast-reject stage consulted before any regular expression is evaluated. Each
+/// rule is associated with a short substring that every URL it matches must
+/// contain; a request whose URL contains none of these substrings cannot match
+/// any rule that has one.
+#[derive(Clone)]
+pub struct LiteralFilter {
+    /// The hashed required substring of each rule, if one could be determined.
+    rule_grams: Vec<Option<u32>>,
+    /// The union of the required substrings of every rule with a visible effect.
+    any_rule: GramSet,
+    /// Whether some rule with a visible effect has no required substring.
+    unfiltered: bool,
+}
+
+impl LiteralFilter {
+    /// Build a filter for `rules`.
+    pub fn new(rules: &[Rule]) -> LiteralFilter {
+        let mut filter = LiteralFilter {
+            rule_grams: Vec::with_capacity(rules.len()),
+            any_rule: GramSet::new(),
+            unfiltered: false,
+        };
+        for rule in rules {
+            let gram = required_literal(rule.trigger.url_filter.as_str())
+                .filter(|literal| literal.len() >= GRAM)
+                .map(|literal| hash(&literal.as_bytes()[..GRAM]));
+            // Exceptions never produce a reaction by themselves.
+            if rule.action.produces_reaction() {
+                match gram {
+                    Some(gram) => filter.any_rule.insert(gram),
+                    None => filter.unfiltered = true,
+                }
+            }
+            filter.rule_grams.push(gram);
+        }
+        filter
+    }
+
+    /// An estimate of the memory used by this filter, in bytes.
+    pub fn heap_size(&self) -> usize {
+        mem::size_of::<GramSet>() + self.rule_grams.capacity() * mem::size_of::<Option<u32>>()
+    }
+
+    /// Whether no rule of the list can produce a reaction for a URL containing `grams`.
+    pub fn rejects_all(&self, grams: &GramSet) -> bool {
+        !self.unfiltered && !grams.intersects(&self.any_rule)
+    }
+
+    /// Whether the rule at `index` could match a URL containing `grams`.
+    pub fn may_match(&self, index: usize, grams: &GramSet) -> bool {
+        self.rule_grams[index].is_none_or(|gram| grams.contains(gram))
+    }
+}
+
+/// Find the longest run of literal ASCII characters that must appear in any
+/// string matched by `pattern`. Patterns using alternation, or constructs this
+/// analysis does not understand, have no required literal.
+pub fn required_literal(pattern: &str) -> Option<String> {
+    if pattern.contains('|') {
+        return None;
+    }
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut best = String::new();
+    let mut current = String::new();
+    let mut i = 0;
+
+    fn flush(current: &mut String, best: &mut String) {
+        if current.len() > best.len() {
+            *best = current.clone();
+        }
+        current.clear();
+    }
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                match chars.get(i + 1) {
+                    Some(&c) if c.is_ascii_punctuation() => current.push(c),
+                    Some(&c) if "xuUpP".contains(c) => return None,
+                    Some(_) => flush(&mut current, &mut best),
+                    None => return None,
+                }
+                i += 2;
+                continue;
+            }
+            '[' => {
+                flush(&mut current, &mut best);
+                i = skip_class(&chars, i)?;
+                continue;
+            }
+            '(' => {
+                flush(&mut current, &mut best);
+                i = skip_group(&chars, i)?;
+                continue;
+            }
+            '?' | '*' => {
+                // The previous atom is optional.
+                current.pop();
+                flush(&mut current, &mut best);
+            }
+            '{' => {
+                current.pop();
+                flush(&mut current, &mut best);
+                i += chars[i..].iter().position(|&c| c == '}')?;
+            }
+            '+' | '.' | '^' | '$' => flush(&mut current, &mut best),
+            ')' | ']' => return None,
+            c if c.is_ascii() => current.push(c),
+            _ => flush(&mut current, &mut best),
+        }
+        i += 1;
+    }
+    flush(&mut current, &mut best);
+
+    if best.is_empty() {
+        None
+    } else {
+        Some(best.to_ascii_lowercase())
+    }
+}
+
+/// Return the position following the character class starting at `start`.
+fn skip_class(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    if chars.get(i) == Some(&'^') {
+        i += 1;
+    }
+    if chars.get(i) == Some(&']') {
+        i += 1;
+    }
+    let mut depth = 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Return the position following the group starting at `start`.
+fn skip_group(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    let mut depth = 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '[' => {
+                i = skip_class(chars, i)?;
+                continue;
+            }
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
@@ -0,0 +1,220 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Procedural element hiding selectors, using the extended syntax of uBlock
+//! Origin: `div.ad:has(> a.sponsor):has-text(/promoted/i):upward(2)`.
+
+/// An element hiding selector that cannot be expressed in plain CSS. Elements
+/// are selected by applying each step in turn to the elements selected by the
+/// previous step.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProceduralSelector {
+    /// The selector in extended syntax.
+    pub source: String,
+    /// The operations to apply, in order.
+    pub steps: Vec<ProceduralStep>,
+}
+
+/// A single operation of a procedural selector.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ProceduralStep {
+    /// Select the elements matching a plain CSS selector. The first step selects
+    /// from the whole document; later steps start with a combinator relative to
+    /// the elements selected so far (a leading space meaning descendants).
+    Css(String),
+    /// Keep the elements that contain an element matched by the given selector,
+    /// which is relative to each element.
+    Has(ProceduralSelector),
+    /// Keep the elements whose text content matches.
+    HasText(TextMatcher),
+    /// Replace each element with one of its ancestors.
+    Upward(Upward),
+}
+
+/// A condition on the text content of an element.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TextMatcher {
+    /// The text contains the given string.
+    Contains(String),
+    /// The text matches a JavaScript-style regular expression, given with its flags.
+    Regex {
+        /// The source of the regular expression.
+        pattern: String,
+        /// The flags following the closing `/`, eg. `i`.
+        flags: String,
+    },
+}
+
+/// Which ancestor `:upward()` selects.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Upward {
+    /// The ancestor this many levels up.
+    Levels(u32),
+    /// The nearest ancestor matching a CSS selector.
+    Closest(String),
+}
+
+/// The operators that make a selector procedural. A `:has()` that does not
+/// contain one of these is left to the CSS engine.
+const PROCEDURAL_OPERATORS: &[&str] = &[":has-text(", ":upward("];
+
+/// How deeply `:has()` operators may be nested. Each level is parsed separately,
+/// so without a limit a crafted selector takes quadratic time and memory.
+const MAX_NESTING: usize = 8;
+
+/// Whether `selector` uses extended syntax that needs a procedural implementation.
+pub fn is_procedural(selector: &str) -> bool {
+    PROCEDURAL_OPERATORS.iter().any(|operator| selector.contains(operator))
+}
+
+impl ProceduralSelector {
+    /// Parse a selector in extended syntax. Returns `None` if an operator is
+    /// malformed, eg. has unbalanced parentheses or an invalid argument.
+    pub fn parse(source: &str) -> Option<ProceduralSelector> {
+        ProceduralSelector::parse_nested(source, 0)
+    }
+
+    fn parse_nested(source: &str, depth: usize) -> Option<ProceduralSelector> {
+        if depth > MAX_NESTING {
+            return None;
+        }
+        let mut steps = vec![];
+        let mut css_start = 0;
+        let mut i = 0;
+        let bytes = source.as_bytes();
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => i += 2,
+                b'"' | b'\'' => i = skip_string(source, i)?,
+                b'[' => i += source[i..].find(']')? + 1,
+                b'(' => i = skip_parens(source, i)?,
+                b':' => {
+                    let operator = [":has-text(", ":has(", ":upward("].iter()
+                                       .find(|operator| source[i..].starts_with(**operator));
+                    let operator = match operator {
+                        Some(operator) => operator,
+                        None => {
+                            i += 1;
+                            continue;
+                        }
+                    };
+                    let open = i + operator.len() - 1;
+                    let close = skip_parens(source, open)? - 1;
+                    let argument = source[open + 1..close].trim();
+                    if css_start < i {
+                        steps.push(ProceduralStep::Css(source[css_start..i].to_owned()));
+                    }
+                    steps.push(match *operator {
+                        ":has(" => ProceduralStep::Has(ProceduralSelector::parse_nested(argument, depth + 1)?),
+                        ":has-text(" => ProceduralStep::HasText(TextMatcher::parse(argument)?),
+                        _ => ProceduralStep::Upward(Upward::parse(argument)?),
+                    });
+                    i = close + 1;
+                    css_start = i;
+                }
+                _ => i += 1,
+            }
+        }
+        if css_start < source.len() {
+            steps.push(ProceduralStep::Css(source[css_start..].to_owned()));
+        }
+        if steps.is_empty() {
+            return None;
+        }
+        Some(ProceduralSelector {
+            source: source.to_owned(),
+            steps,
+        })
+    }
+}
+
+impl TextMatcher {
+    fn parse(argument: &str) -> Option<TextMatcher> {
+        if argument.len() > 1 && argument.starts_with('/') {
+            let end = argument.rfind('/')?;
+            if end > 0 {
+                let flags = &argument[end + 1..];
+                if flags.chars().all(|c| "gimsuy".contains(c)) {
+                    return Some(TextMatcher::Regex {
+                        pattern: argument[1..end].to_owned(),
+                        flags: flags.to_owned(),
+                    });
+                }
+            }
+        }
+        let text = unquote(argument);
+        if text.is_empty() {
+            None
+        } else {
+            Some(TextMatcher::Contains(text.to_owned()))
+        }
+    }
+}
+
+impl Upward {
+    fn parse(argument: &str) -> Option<Upward> {
+        if argument.is_empty() {
+            return None;
+        }
+        if argument.bytes().all(|b| b.is_ascii_digit()) {
+            return match argument.parse() {
+                Ok(levels) if (1..=256).contains(&levels) => Some(Upward::Levels(levels)),
+                _ => None,
+            };
+        }
+        Some(Upward::Closest(argument.to_owned()))
+    }
+}
+
+/// Strip one level of matching quotes from `s`.
+fn unquote(s: &str) -> &str {
+    for quote in &["\"", "'"] {
+        if s.len() >= 2 && s.starts_with(quote) && s.ends_with(quote) {
+            return &s[1..s.len() - 1];
+        }
+    }
+    s
+}
+
+/// Return the position following the quoted string starting at `start`.
+fn skip_string(s: &str, start: usize) -> Option<usize> {
+    let quote = s.as_bytes()[start];
+    let mut i = start + 1;
+    while i < s.len() {
+        match s.as_bytes()[i] {
+            b'\\' => i += 1,
+            b if b == quote => return Some(i + 1),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Return the position following the parenthesized group starting at `start`.
+fn skip_parens(s: &str, start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < s.len() {
+        match s.as_bytes()[i] {
+            b'\\' => i += 1,
+            // Quotes in a regular expression need not be balanced.
+            b'"' | b'\'' => {
+                i = skip_string(s, i).unwrap_or(i + 1);
+                continue;
+            }
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
@@ -0,0 +1,82 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use RuleList;
+use repr::RuleId;
+use std::cmp::Reverse;
+use std::time::Duration;
+
+/// Cumulative measurements for a single rule.
+#[derive(Clone, Debug, Default)]
+pub struct RuleProfile {
+    /// The number of times the URL filter was evaluated.
+    pub evaluations: u64,
+    /// The total time spent evaluating the URL filter.
+    pub regex_time: Duration,
+}
+
+/// Cumulative URL filter evaluation times for the rules of a list, gathered
+/// across any number of requests.
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    /// Measurements for each rule, in list order.
+    pub rules: Vec<RuleProfile>,
+}
+
+/// The measurements recorded for a single rule of a profiled list.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuleTiming {
+    /// The position of the rule in the parsed list.
+    pub index: usize,
+    /// The identifier of the rule.
+    pub id: RuleId,
+    /// The source of the rule's URL filter.
+    pub url_filter: String,
+    /// The number of times the URL filter was evaluated.
+    pub evaluations: u64,
+    /// The total time spent evaluating the URL filter.
+    pub regex_time: Duration,
+}
+
+impl Profile {
+    /// Create an empty profile.
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /// Make sure there is an entry for each of the first `len` rules.
+    pub fn reserve(&mut self, len: usize) {
+        if self.rules.len() < len {
+            self.rules.resize(len, RuleProfile::default());
+        }
+    }
+
+    /// The total time spent evaluating URL filters across all rules.
+    pub fn total_time(&self) -> Duration {
+        self.rules.iter().fold(Duration::new(0, 0), |acc, rule| acc + rule.regex_time)
+    }
+
+    /// Discard all measurements recorded so far.
+    pub fn clear(&mut self) {
+        self.rules.clear();
+    }
+
+    /// Report the `n` rules of `rules` which have spent the most time evaluating
+    /// their URL filters, slowest first. `rules` must be the list that was profiled.
+    pub fn slowest(&self, rules: &RuleList, n: usize) -> Vec<RuleTiming> {
+        let profiled = rules.rules.iter().zip(&self.rules).enumerate();
+        let mut timings: Vec<RuleTiming> = profiled.map(|(index, (rule, profile))| {
+            RuleTiming {
+                index,
+                id: rules.ids()[index],
+                url_filter: rule.trigger.url_filter.as_str().to_owned(),
+                evaluations: profile.evaluations,
+                regex_time: profile.regex_time,
+            }
+        }).collect();
+        timings.sort_by_key(|timing| Reverse(timing.regex_time));
+        timings.truncate(n);
+        timings
+    }
+}
@@ -0,0 +1,198 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Support for property-based testing with `quickcheck`.
+//!
+//! `RuleList` and `OwnedRequest` implement `Arbitrary`, drawing URLs, domains
+//! and patterns from a small vocabulary so that generated rules match generated
+//! requests often enough to be interesting. The functions of this module are
+//! invariants that hold for every list and request:
+//!
+//! ```ignore
+//! quickcheck(optimize_preserves_reactions as fn(RuleList, OwnedRequest) -> bool);
+//! ```
+
+use compiled::{CompiledRuleList, process_rules_for_request_compiled_impl};
+use parse::parse_list_strict_impl;
+use quickcheck::{Arbitrary, Gen};
+use repr::{Action, CookieScope, DomainConstraint, DomainMatcher, DomainTarget, LoadType, OwnedRequest, Reaction};
+use repr::{LoadTypeList, ResourceType, ResourceTypeList, Rule, Trigger, UrlFilter, UrlFilterAnchor};
+use resources::Resource;
+use std::collections::BTreeSet;
+use {RuleList, process_rules_for_request};
+
+const HOSTS: &[&str] = &["example.com", "ads.example.com", "tracker.net", "cdn.tracker.net", "news.org"];
+const PATHS: &[&str] = &["/", "/index.html", "/ads/banner.png", "/track.js", "/pixel.gif?id=1"];
+const DOMAINS: &[&str] = &["example.com", "*example.com", "tracker.net", "*tracker.net", "news.org"];
+const URL_FILTERS: &[&str] = &[
+    ".*", "ads", "track", "banner", "[0-9]", "\\.js$", "pixel\\.gif", "^https?://",
+    "^https?://([^/]*\\.)?tracker\\.net/", "^https?://ads\\.example\\.com/", "(?i)EXAMPLE",
+];
+const SELECTORS: &[&str] = &[".ad", "#banner", "div.sponsored", "iframe[src*=\"ads\"]"];
+const CONTEXTS: &[&str] = &["private", "work"];
+
+fn choose<T: Copy>(g: &mut Gen, values: &[T]) -> T {
+    *g.choose(values).expect("vocabularies are not empty")
+}
+
+fn subset<T: Copy>(g: &mut Gen, values: &[T]) -> Vec<T> {
+    values.iter().cloned().filter(|_| bool::arbitrary(g)).collect()
+}
+
+impl Arbitrary for ResourceType {
+    fn arbitrary(g: &mut Gen) -> ResourceType {
+        choose(g, ResourceType::ALL)
+    }
+}
+
+impl Arbitrary for LoadType {
+    fn arbitrary(g: &mut Gen) -> LoadType {
+        choose(g, &[LoadType::FirstParty, LoadType::ThirdParty])
+    }
+}
+
+impl Arbitrary for CookieScope {
+    fn arbitrary(g: &mut Gen) -> CookieScope {
+        choose(g, &[CookieScope::Request, CookieScope::Response, CookieScope::Both])
+    }
+}
+
+impl Arbitrary for Trigger {
+    fn arbitrary(g: &mut Gen) -> Trigger {
+        let mut pattern = choose(g, URL_FILTERS).to_owned();
+        if bool::arbitrary(g) {
+            pattern.push_str(choose(g, URL_FILTERS).trim_start_matches('^'));
+        }
+        let domains = DomainMatcher::new(subset(g, DOMAINS));
+        let domain_constraint = match u8::arbitrary(g) % 3 {
+            _ if domains.exact.is_empty() && domains.subdomain.is_empty() => None,
+            0 => None,
+            1 => Some(DomainConstraint::If(domains)),
+            _ => Some(DomainConstraint::Unless(domains)),
+        };
+        let contexts = subset(g, CONTEXTS);
+        Trigger {
+            url_filter: UrlFilter::new(&pattern).expect("the vocabulary only forms valid patterns"),
+            url_filter_anchor: UrlFilterAnchor::Url,
+            path_filter: None,
+            query_filter: None,
+            resource_type: match subset(g, ResourceType::ALL) {
+                ref types if types.is_empty() => ResourceTypeList::All,
+                types => ResourceTypeList::List(types),
+            },
+            load_type: match subset(g, LoadType::ALL) {
+                ref types if types.is_empty() => LoadTypeList::All,
+                types => LoadTypeList::List(types),
+            },
+            domain_constraint,
+            contexts: if contexts.is_empty() {
+                None
+            } else {
+                Some(contexts.into_iter().map(Into::into).collect())
+            },
+            user_initiated: None,
+            opener_domain: None,
+            domain_target: if bool::arbitrary(g) { DomainTarget::TopDocument } else { DomainTarget::Request },
+            match_redirect_chain: bool::arbitrary(g),
+            if_header: Box::default(),
+            unless_header: Box::default(),
+        }
+    }
+}
+
+impl Arbitrary for Action {
+    fn arbitrary(g: &mut Gen) -> Action {
+        match u8::arbitrary(g) % 8 {
+            0 | 1 => Action::Block,
+            2 => Action::BlockCookies(CookieScope::arbitrary(g)),
+            3 => Action::BlockCookiesMatching(vec!["_ga".to_owned(), "id*".to_owned()].into()),
+            4 => Action::Redirect(g.choose(Resource::all()).expect("resources are bundled")),
+            5 => Action::CssDisplayNone(choose(g, SELECTORS).into()),
+            6 => Action::CssDisplayNoneException(choose(g, SELECTORS).into()),
+            _ => Action::IgnorePreviousRules,
+        }
+    }
+}
+
+impl Arbitrary for Rule {
+    fn arbitrary(g: &mut Gen) -> Rule {
+        Rule {
+            trigger: Trigger::arbitrary(g),
+            action: Action::arbitrary(g),
+            groups: Box::default(),
+            source: None,
+        }
+    }
+}
+
+impl Arbitrary for RuleList {
+    fn arbitrary(g: &mut Gen) -> RuleList {
+        RuleList::new(Vec::arbitrary(g))
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item=RuleList>> {
+        Box::new(self.rules.shrink().map(RuleList::new))
+    }
+}
+
+impl Arbitrary for OwnedRequest {
+    fn arbitrary(g: &mut Gen) -> OwnedRequest {
+        let scheme = choose(g, &["http", "https"]);
+        OwnedRequest {
+            url: format!("{}://{}{}", scheme, choose(g, HOSTS), choose(g, PATHS)),
+            resource_type: ResourceType::arbitrary(g),
+            load_type: LoadType::arbitrary(g),
+            context: if bool::arbitrary(g) { Some(choose(g, CONTEXTS).to_owned()) } else { None },
+            opener: None,
+            document: if bool::arbitrary(g) {
+                Some(format!("https://{}/", choose(g, HOSTS)))
+            } else {
+                None
+            },
+            redirect_chain: vec![],
+            headers: None,
+        }
+    }
+}
+
+/// Whether evaluating `list` after serializing it with `RuleList::to_bytes`
+/// produces the same reactions to `request` as evaluating it directly.
+pub fn serialization_preserves_reactions(list: RuleList, request: OwnedRequest) -> bool {
+    let bytes = list.to_bytes();
+    let compiled = match CompiledRuleList::from_bytes(&bytes) {
+        Ok(compiled) => compiled,
+        Err(_) => return false,
+    };
+    let request = request.as_request();
+    process_rules_for_request(&list, &request) == process_rules_for_request_compiled_impl(&compiled, &request)
+}
+
+/// Whether parsing the output of `RuleList::to_json_string` produces the rules
+/// of `list` again, apart from the domain targets chosen by the parse options.
+pub fn json_preserves_rules(list: RuleList) -> bool {
+    let mut expected = list.rules.clone();
+    for rule in &mut expected {
+        rule.trigger.domain_target = DomainTarget::Request;
+    }
+    parse_list_strict_impl(&list.to_json_string()).is_ok_and(|rules| rules == expected)
+}
+
+/// Whether `RuleList::optimize` leaves the reactions of `list` to `request`
+/// unchanged, up to the duplicates and combined selectors the optimizer allows.
+pub fn optimize_preserves_reactions(list: RuleList, request: OwnedRequest) -> bool {
+    let request = request.as_request();
+    let before = process_rules_for_request(&list, &request);
+    let mut optimized = list;
+    optimized.optimize();
+    normalized(&before) == normalized(&process_rules_for_request(&optimized, &request))
+}
+
+/// The distinct effects of `reactions`, with combined selectors split apart.
+fn normalized(reactions: &[Reaction]) -> BTreeSet<String> {
+    reactions.iter().flat_map(|reaction| match *reaction {
+        Reaction::HideMatchingElements(ref selectors) =>
+            selectors.split(", ").map(|selector| format!("hide {}", selector)).collect(),
+        ref reaction => vec![format!("{:?}", reaction)],
+    }).collect()
+}
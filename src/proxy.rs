@@ -0,0 +1,314 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Middleware applying the reactions of an `Engine` to the requests passing
+//! through an HTTP proxy.
+//!
+//! `ContentBlockerLayer` wraps any Tower service handling `http` requests, such
+//! as the client forwarding requests upstream. Hyper servers accept the wrapped
+//! service through `hyper_util::service::TowerToHyperService`. Requests are
+//! classified from their `Sec-Fetch-Dest`, `Sec-Fetch-Site`, `Accept` and
+//! `Referer` headers, falling back to the extension of the URL, and then:
+//!
+//! * redirected requests are answered with the surrogate resource,
+//! * blocked requests are answered with an empty `403 Forbidden` response,
+//! * blocked cookies are removed from the `Cookie` header of the request and
+//!   the `Set-Cookie` headers of the response.
+//!
+//! `CONNECT` requests are passed through, since the requests they tunnel cannot
+//! be inspected.
+
+use engine::Engine;
+use http::header::{self, HeaderMap, HeaderValue};
+use http::{Method, Request, Response, StatusCode};
+use repr::{self, LoadType, Reaction, ResourceType};
+use request_url::{RequestHeaders, RequestUrl};
+use resources::Resource;
+use std::borrow::Cow;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+use url::Url;
+
+/// A Tower layer wrapping services in a `ContentBlocker`.
+#[derive(Clone)]
+pub struct ContentBlockerLayer {
+    engine: Arc<Engine>,
+}
+
+impl ContentBlockerLayer {
+    /// Create a layer evaluating requests against `engine`.
+    pub fn new(engine: Arc<Engine>) -> ContentBlockerLayer {
+        ContentBlockerLayer {
+            engine,
+        }
+    }
+}
+
+impl<S> Layer<S> for ContentBlockerLayer {
+    type Service = ContentBlocker<S>;
+
+    fn layer(&self, inner: S) -> ContentBlocker<S> {
+        ContentBlocker::new(inner, self.engine.clone())
+    }
+}
+
+/// A service applying the reactions of an engine to requests before passing
+/// them on to the inner service, and to the responses it returns.
+#[derive(Clone)]
+pub struct ContentBlocker<S> {
+    inner: S,
+    engine: Arc<Engine>,
+}
+
+impl<S> ContentBlocker<S> {
+    /// Wrap `inner`, evaluating requests against `engine`.
+    pub fn new(inner: S, engine: Arc<Engine>) -> ContentBlocker<S> {
+        ContentBlocker {
+            inner,
+            engine,
+        }
+    }
+
+    /// The wrapped service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// The engine requests are evaluated against.
+    pub fn engine(&self) -> &Arc<Engine> {
+        &self.engine
+    }
+}
+
+impl RequestHeaders for HeaderMap {
+    fn get(&self, name: &str) -> Option<&str> {
+        HeaderMap::get(self, name).and_then(|value| value.to_str().ok())
+    }
+
+    fn entries(&self) -> Vec<(&str, &str)> {
+        self.iter().filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?))).collect()
+    }
+}
+
+fn header<'a, B>(request: &'a Request<B>, name: &str) -> Option<&'a str> {
+    request.headers().get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Evaluate a proxied request, or return no reactions if it cannot be classified.
+fn process<B>(engine: &Engine, request: &Request<B>) -> Vec<Reaction> {
+    if request.method() == Method::CONNECT {
+        return vec![];
+    }
+    let uri = request.uri();
+    let url = if uri.scheme().is_some() {
+        Url::parse(&uri.to_string())
+    } else {
+        let host = match header(request, header::HOST.as_str()) {
+            Some(host) => host,
+            None => return vec![],
+        };
+        let path = uri.path_and_query().map_or("/", |path| path.as_str());
+        Url::parse(&format!("http://{}{}", host, path))
+    };
+    let mut url = match url {
+        Ok(url) => url,
+        Err(_) => return vec![],
+    };
+    // The handshake of a WebSocket connection is an HTTP request, but rules are
+    // written against the `ws:` URL of the connection.
+    if header(request, header::UPGRADE.as_str()).is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket")) {
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        let _ = url.set_scheme(scheme);
+    }
+
+    let destination = header(request, "sec-fetch-dest");
+    let resource_type = match header(request, header::CONTENT_TYPE.as_str()) {
+        // Violation reports of browsers without `Sec-Fetch-Dest: report`.
+        Some(content_type) if content_type.starts_with("application/csp-report") => ResourceType::CspReport,
+        _ => ResourceType::infer(url.as_str(), header(request, header::ACCEPT.as_str()), destination),
+    };
+    // The referrer of a navigation is the previous page, not the one loading it.
+    let page = match destination {
+        Some("document") => None,
+        _ => header(request, header::REFERER.as_str()).and_then(|referrer| Url::parse(referrer).ok()),
+    };
+    let load_type = match header(request, "sec-fetch-site") {
+        Some("same-origin") | Some("none") => LoadType::FirstParty,
+        Some(_) => LoadType::ThirdParty,
+        None => match page {
+            Some(ref page) if page.origin() != url.origin() => LoadType::ThirdParty,
+            _ => LoadType::FirstParty,
+        },
+    };
+    let request = repr::Request {
+        url: &url,
+        resource_type,
+        load_type,
+        context: None,
+        opener: None,
+        document: page.as_ref().map(|page| page as &dyn RequestUrl),
+        redirect_chain: &[],
+        headers: Some(request.headers()),
+    };
+    engine.process(&request)
+}
+
+/// The name of a cookie given its `name=value` pair, or its `Set-Cookie` header.
+fn cookie_name(cookie: &[u8]) -> Cow<'_, str> {
+    let pair = cookie.split(|&b| b == b';').next().unwrap_or(cookie);
+    String::from_utf8_lossy(pair.split(|&b| b == b'=').next().unwrap_or(pair).trim_ascii())
+}
+
+/// The cookie reactions to a request, applied to its headers and those of its response.
+#[derive(Default)]
+struct CookieFilter {
+    strip_request: bool,
+    strip_response: bool,
+    matching: Vec<Reaction>,
+}
+
+impl CookieFilter {
+    fn new(reactions: Vec<Reaction>) -> CookieFilter {
+        let mut filter = CookieFilter::default();
+        for reaction in reactions {
+            match reaction {
+                Reaction::BlockCookies(scope) => {
+                    filter.strip_request |= scope.applies_to_request();
+                    filter.strip_response |= scope.applies_to_response();
+                }
+                reaction @ Reaction::BlockCookiesMatching(_) => filter.matching.push(reaction),
+                _ => {}
+            }
+        }
+        filter
+    }
+
+    fn blocks(&self, cookie: &[u8]) -> bool {
+        let name = cookie_name(cookie);
+        self.matching.iter().any(|reaction| reaction.blocks_cookie(&name))
+    }
+
+    fn filter_request(&self, headers: &mut HeaderMap) {
+        if self.strip_request {
+            headers.remove(header::COOKIE);
+            return;
+        }
+        if self.matching.is_empty() || !headers.contains_key(header::COOKIE) {
+            return;
+        }
+        let mut kept = vec![];
+        for value in headers.get_all(header::COOKIE) {
+            for pair in value.as_bytes().split(|&b| b == b';').map(<[u8]>::trim_ascii) {
+                if pair.is_empty() || self.blocks(pair) {
+                    continue;
+                }
+                if !kept.is_empty() {
+                    kept.extend_from_slice(b"; ");
+                }
+                kept.extend_from_slice(pair);
+            }
+        }
+        headers.remove(header::COOKIE);
+        if !kept.is_empty() {
+            let value = HeaderValue::from_bytes(&kept).expect("cookies are taken from valid header values");
+            headers.insert(header::COOKIE, value);
+        }
+    }
+
+    fn filter_response(&self, headers: &mut HeaderMap) {
+        if self.strip_response {
+            headers.remove(header::SET_COOKIE);
+            return;
+        }
+        if self.matching.is_empty() || !headers.contains_key(header::SET_COOKIE) {
+            return;
+        }
+        let kept: Vec<HeaderValue> = headers.get_all(header::SET_COOKIE).iter()
+                                            .filter(|value| !self.blocks(value.as_bytes()))
+                                            .cloned()
+                                            .collect();
+        headers.remove(header::SET_COOKIE);
+        for value in kept {
+            headers.append(header::SET_COOKIE, value);
+        }
+    }
+}
+
+fn surrogate<B: From<Vec<u8>>>(resource: &Resource) -> Response<B> {
+    Response::builder().header(header::CONTENT_TYPE, resource.content_type)
+                       .body(B::from(resource.content()))
+                       .expect("bundled content types are valid header values")
+}
+
+fn blocked<B: From<Vec<u8>>>() -> Response<B> {
+    let mut response = Response::new(B::from(vec![]));
+    *response.status_mut() = StatusCode::FORBIDDEN;
+    response
+}
+
+enum State<F, B> {
+    Ready(Option<Response<B>>),
+    Forwarded(Pin<Box<F>>, CookieFilter),
+}
+
+/// The response future of a `ContentBlocker`.
+pub struct ResponseFuture<F, B> {
+    state: State<F, B>,
+}
+
+// The inner future is pinned separately, and nothing else is structurally pinned.
+impl<F, B> Unpin for ResponseFuture<F, B> {}
+
+impl<F, B, E> Future for ResponseFuture<F, B>
+    where F: Future<Output=Result<Response<B>, E>>
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match self.get_mut().state {
+            State::Ready(ref mut response) => Poll::Ready(Ok(response.take().expect("polled after completion"))),
+            State::Forwarded(ref mut future, ref cookies) => future.as_mut().poll(cx).map_ok(|mut response| {
+                cookies.filter_response(response.headers_mut());
+                response
+            }),
+        }
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for ContentBlocker<S>
+    where S: Service<Request<ReqBody>, Response=Response<ResBody>>,
+          ResBody: From<Vec<u8>>
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future, ResBody>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<ReqBody>) -> Self::Future {
+        let reactions = process(&self.engine, &request);
+        // Like a browser, prefer a surrogate to failing the request outright.
+        let redirect = reactions.iter().find_map(|reaction| match *reaction {
+            Reaction::Redirect(resource) => Some(resource),
+            _ => None,
+        });
+        let response = match redirect {
+            Some(resource) => Some(surrogate(resource)),
+            None if reactions.contains(&Reaction::Block) => Some(blocked()),
+            None => None,
+        };
+        if let Some(response) = response {
+            return ResponseFuture { state: State::Ready(Some(response)) };
+        }
+        let cookies = CookieFilter::new(reactions);
+        cookies.filter_request(request.headers_mut());
+        ResponseFuture { state: State::Forwarded(Box::pin(self.inner.call(request)), cookies) }
+    }
+}
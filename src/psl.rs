@@ -0,0 +1,158 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The [public suffix list](https://publicsuffix.org/), which tells which part
+//! of a host is the site it belongs to, eg. `example.co.uk` for
+//! `www.example.co.uk`.
+//!
+//! With the `psl` feature, a snapshot of the list is embedded in the crate and
+//! used until another one is installed with `set_public_suffix_list`. Without
+//! it, every host is treated as if its last label was its public suffix until a
+//! list is installed.
+
+use repr::LoadType;
+use request_url::RequestUrl;
+use std::collections::HashSet;
+use std::sync::{Arc, OnceLock, RwLock};
+use url::idna;
+
+/// A parsed public suffix list.
+#[derive(Clone, Debug, Default)]
+pub struct PublicSuffixList {
+    suffixes: HashSet<String>,
+    /// The parents of wildcard rules, eg. `ck` for `*.ck`.
+    wildcards: HashSet<String>,
+    /// The domains of exception rules, eg. `www.ck` for `!www.ck`.
+    exceptions: HashSet<String>,
+}
+
+impl PublicSuffixList {
+    /// Parse a list in the format of `public_suffix_list.dat`. Internationalized
+    /// rules are converted to their ASCII form, like the hosts they apply to.
+    pub fn parse(body: &str) -> PublicSuffixList {
+        let mut list = PublicSuffixList::default();
+        for line in body.lines() {
+            let rule = match line.split_whitespace().next() {
+                Some(rule) if !rule.starts_with("//") => rule,
+                _ => continue,
+            };
+            let (set, domain) = if let Some(domain) = rule.strip_prefix('!') {
+                (&mut list.exceptions, domain)
+            } else if let Some(domain) = rule.strip_prefix("*.") {
+                (&mut list.wildcards, domain)
+            } else {
+                (&mut list.suffixes, rule)
+            };
+            if let Ok(domain) = idna::domain_to_ascii(domain) {
+                set.insert(domain);
+            }
+        }
+        list
+    }
+
+    /// The snapshot of the list embedded in this crate.
+    #[cfg(feature = "psl")]
+    pub fn builtin() -> PublicSuffixList {
+        PublicSuffixList::parse(include_str!("../psl/public_suffix_list.dat"))
+    }
+
+    /// An empty list, since the snapshot is only embedded with the `psl` feature.
+    #[cfg(not(feature = "psl"))]
+    pub fn builtin() -> PublicSuffixList {
+        PublicSuffixList::default()
+    }
+
+    /// The number of rules in this list.
+    pub fn len(&self) -> usize {
+        self.suffixes.len() + self.wildcards.len() + self.exceptions.len()
+    }
+
+    /// Whether this list has no rules.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The public suffix of a lowercase domain, eg. `co.uk` for `www.example.co.uk`.
+    /// Domains matching no rule have their last label as public suffix.
+    pub fn public_suffix<'a>(&self, domain: &'a str) -> &'a str {
+        let domain = domain.trim_end_matches('.');
+        let mut suffix = domain;
+        loop {
+            let parent = suffix.find('.').map(|dot| &suffix[dot + 1..]);
+            if self.exceptions.contains(suffix) {
+                return parent.unwrap_or(suffix);
+            }
+            if self.suffixes.contains(suffix) || parent.is_some_and(|parent| self.wildcards.contains(parent)) {
+                return suffix;
+            }
+            match parent {
+                Some(parent) => suffix = parent,
+                None => return suffix,
+            }
+        }
+    }
+
+    /// The registrable domain of a lowercase domain, ie. its public suffix and
+    /// the label before it, or `None` if the domain is itself a public suffix.
+    pub fn registrable_domain<'a>(&self, domain: &'a str) -> Option<&'a str> {
+        let domain = domain.trim_end_matches('.');
+        let suffix = self.public_suffix(domain);
+        if suffix.len() == domain.len() {
+            return None;
+        }
+        let labels = &domain[..domain.len() - suffix.len() - 1];
+        let start = labels.rfind('.').map_or(0, |dot| dot + 1);
+        Some(&domain[start..])
+    }
+}
+
+fn current() -> &'static RwLock<Arc<PublicSuffixList>> {
+    static CURRENT: OnceLock<RwLock<Arc<PublicSuffixList>>> = OnceLock::new();
+    CURRENT.get_or_init(|| RwLock::new(Arc::new(PublicSuffixList::builtin())))
+}
+
+/// The public suffix list used for domain matching and by `registrable_domain`.
+pub fn public_suffix_list() -> Arc<PublicSuffixList> {
+    current().read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Replace the public suffix list used from now on, eg. with a more recent
+/// snapshot fetched by the embedder.
+pub fn set_public_suffix_list(list: PublicSuffixList) {
+    *current().write().unwrap_or_else(|e| e.into_inner()) = Arc::new(list);
+}
+
+/// The registrable domain of the host of `url`, eg. `example.co.uk` for
+/// `https://www.example.co.uk/`, or `None` for IP addresses and public suffixes.
+pub fn registrable_domain<U: RequestUrl + ?Sized>(url: &U) -> Option<&str> {
+    url.registrable_domain()
+}
+
+/// A domain with its public suffix replaced by `*`, eg. `www.example.*` for
+/// `www.example.co.uk`, as written in rules applying to every top-level domain
+/// of a site. Public suffixes themselves have no such form.
+pub fn wildcard_tld(domain: &str) -> Option<String> {
+    let domain = domain.trim_end_matches('.');
+    let list = public_suffix_list();
+    list.registrable_domain(domain)?;
+    let suffix = list.public_suffix(domain);
+    Some(format!("{}*", &domain[..domain.len() - suffix.len()]))
+}
+
+/// The registrable domain of a URL, or its host if it has none.
+fn site(url: &dyn RequestUrl) -> Option<&str> {
+    url.registrable_domain().or_else(|| url.host())
+}
+
+impl LoadType {
+    /// Classify a request to `url` made by the page at `document` by comparing
+    /// their registrable domains, as ad blockers do, rather than their origins:
+    /// requests to other hosts of the same site are first-party.
+    pub fn by_site(url: &dyn RequestUrl, document: &dyn RequestUrl) -> LoadType {
+        match (site(url), site(document)) {
+            (Some(a), Some(b)) if a == b => LoadType::FirstParty,
+            _ => LoadType::ThirdParty,
+        }
+    }
+}
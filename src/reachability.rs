@@ -0,0 +1,100 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use repr::{Action, DomainConstraint, DomainMatcher, LoadType, ResourceTypeList, Rule, Trigger};
+
+/// Which rules of a list can be affected by a later `ignore-previous-rules` rule.
+#[derive(Clone, Debug, Default)]
+pub struct Reachability {
+    /// Whether each rule is a blocking rule that no later exception can override.
+    pub final_blocks: Vec<bool>,
+    /// The positions of the exception rules that cannot match any request that an
+    /// earlier rule with a visible effect also matches.
+    pub unreachable_exceptions: Vec<usize>,
+}
+
+impl Reachability {
+    /// Analyze `rules`.
+    pub fn new(rules: &[Rule]) -> Reachability {
+        let exceptions: Vec<usize> = rules.iter().enumerate()
+                                          .filter(|&(_, rule)| rule.action == Action::IgnorePreviousRules)
+                                          .map(|(position, _)| position)
+                                          .collect();
+        let final_blocks = rules.iter().enumerate().map(|(position, rule)| {
+            rule.action == Action::Block &&
+                !exceptions.iter().rev()
+                           .take_while(|&&exception| exception > position)
+                           .any(|&exception| may_overlap(&rule.trigger, &rules[exception].trigger))
+        }).collect();
+        let unreachable_exceptions = exceptions.iter().cloned().filter(|&exception| {
+            !rules[..exception].iter().any(|rule| {
+                rule.action.produces_reaction() &&
+                    may_overlap(&rule.trigger, &rules[exception].trigger)
+            })
+        }).collect();
+        Reachability {
+            final_blocks,
+            unreachable_exceptions,
+        }
+    }
+}
+
+/// Whether some request could match both triggers, judging only by their
+/// resource types, load types, contexts, user-initiated conditions and domain
+/// constraints. URL filters are not compared, so this may report overlaps that
+/// cannot happen.
+pub fn may_overlap(a: &Trigger, b: &Trigger) -> bool {
+    if let (ResourceTypeList::List(a), ResourceTypeList::List(b)) = (&a.resource_type, &b.resource_type) {
+        if !a.iter().any(|t| b.contains(t)) {
+            return false;
+        }
+    }
+    let (a_loads, b_loads) = (a.load_type.mask(), b.load_type.mask());
+    if LoadType::ASPECTS.iter().any(|&aspect| a_loads & aspect != 0 && b_loads & aspect != 0 &&
+                                              a_loads & b_loads & aspect == 0) {
+        return false;
+    }
+    if let (Some(a), Some(b)) = (a.user_initiated, b.user_initiated) {
+        if a != b {
+            return false;
+        }
+    }
+    if let (Some(a), Some(b)) = (&a.contexts, &b.contexts) {
+        if !a.iter().any(|c| b.contains(c)) {
+            return false;
+        }
+    }
+    if a.domain_target != b.domain_target {
+        return true;
+    }
+    match (&a.domain_constraint, &b.domain_constraint) {
+        (Some(DomainConstraint::If(a)), Some(DomainConstraint::If(b))) => domains_overlap(a, b),
+        (Some(DomainConstraint::If(included)), Some(DomainConstraint::Unless(excluded))) |
+        (Some(DomainConstraint::Unless(excluded)), Some(DomainConstraint::If(included))) => {
+            !excludes_all(excluded, included)
+        }
+        _ => true,
+    }
+}
+
+/// Whether `domain` is `parent` or one of its subdomains.
+fn is_within(domain: &str, parent: &str) -> bool {
+    domain == parent || domain.ends_with(parent) && domain[..domain.len() - parent.len()].ends_with('.')
+}
+
+/// Whether some host is matched by both matchers.
+fn domains_overlap(a: &DomainMatcher, b: &DomainMatcher) -> bool {
+    // Wildcard-TLD entries would need the public suffix list to compare.
+    a.wildcard_tld || b.wildcard_tld ||
+        a.exact.iter().any(|domain| b.matches_domain(domain)) ||
+        b.exact.iter().any(|domain| a.matches_domain(domain)) ||
+        a.subdomain.iter().any(|x| b.subdomain.iter().any(|y| is_within(x, y) || is_within(y, x)))
+}
+
+/// Whether every host matched by `included` is also matched by `excluded`.
+fn excludes_all(excluded: &DomainMatcher, included: &DomainMatcher) -> bool {
+    !excluded.wildcard_tld && !included.wildcard_tld &&
+        included.exact.iter().all(|domain| excluded.matches_domain(domain)) &&
+        included.subdomain.iter().all(|domain| excluded.subdomain.iter().any(|parent| is_within(domain, parent)))
+}
@@ -2,22 +2,189 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use regex::Regex;
-use std::cmp::Ordering;
+use regex::{self, Regex, RegexBuilder};
+use regex_syntax;
+use std::sync::{Arc, OnceLock};
+use html::HtmlFilter;
+use procedural::ProceduralSelector;
+use psl::wildcard_tld;
+use profile::{Profile, RuleProfile};
+use request_url::{PreparedUrl, RequestHeaders, RequestUrl};
+use resources::Resource;
+use smallvec::SmallVec;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::time::{Duration, Instant};
 use url::Url;
 
 /// A request that could be filtered.
 pub struct Request<'a> {
-    /// The requested URL.
-    pub url: &'a Url,
+    /// The requested URL, such as a `url::Url` or a `&str`.
+    pub url: &'a dyn RequestUrl,
+    /// The resource type for which this request was initiated.
+    pub resource_type: ResourceType,
+    /// The relationship of this request to the originating document, either
+    /// `FirstParty` or `ThirdParty`. The site and frame categories are derived
+    /// from the URLs of the request and its document.
+    pub load_type: LoadType,
+    /// The browsing context making the request, eg. `private` or the name of a
+    /// container. Rules limited to some contexts never apply to requests without one.
+    pub context: Option<&'a str>,
+    /// The page that opened the window being loaded, for popups.
+    pub opener: Option<Opener<'a>>,
+    /// The URL of the top-level document making the request, or `None` when the
+    /// request is the navigation of that document itself.
+    pub document: Option<&'a dyn RequestUrl>,
+    /// The URLs this request was redirected from, oldest first, and not
+    /// including `url`. Only the URL filters of triggers opting in with
+    /// `match-redirect-chain` are matched against them.
+    pub redirect_chain: &'a [Url],
+    /// The headers of the request, if they are known. Triggers with header
+    /// conditions never match requests without them.
+    pub headers: Option<&'a dyn RequestHeaders>,
+}
+
+/// The page that opened a popup.
+#[derive(Copy, Clone)]
+pub struct Opener<'a> {
+    /// The URL of the opening page.
+    pub url: &'a dyn RequestUrl,
+    /// Whether the popup was opened in response to a user action, such as a click.
+    pub user_initiated: bool,
+}
+
+/// Like `Opener`, but owning the URL.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OwnedOpener {
+    /// The serialized URL of the opening page.
+    pub url: String,
+    /// Whether the popup was opened in response to a user action.
+    pub user_initiated: bool,
+}
+
+/// A request that owns its URL, so that it can be queued, sent to another
+/// thread or stored, unlike `Request`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OwnedRequest {
+    /// The serialized requested URL.
+    pub url: String,
     /// The resource type for which this request was initiated.
     pub resource_type: ResourceType,
     /// The relationship of this request to the originating document.
     pub load_type: LoadType,
+    /// The browsing context making the request.
+    pub context: Option<String>,
+    /// The page that opened the window being loaded, for popups.
+    pub opener: Option<OwnedOpener>,
+    /// The serialized URL of the top-level document making the request.
+    pub document: Option<String>,
+    /// The URLs the request was redirected from, oldest first.
+    pub redirect_chain: Vec<Url>,
+    /// The names and values of the request's headers, if they are known.
+    pub headers: Option<Vec<(String, String)>>,
+}
+
+impl OwnedRequest {
+    /// Borrow this request for matching.
+    pub fn as_request(&self) -> Request<'_> {
+        Request {
+            url: &self.url,
+            resource_type: self.resource_type,
+            load_type: self.load_type,
+            context: self.context.as_deref(),
+            opener: self.opener.as_ref().map(|opener| Opener {
+                url: &opener.url,
+                user_initiated: opener.user_initiated,
+            }),
+            document: self.document.as_ref().map(|document| document as &dyn RequestUrl),
+            redirect_chain: &self.redirect_chain,
+            headers: self.headers.as_ref().map(|headers| headers as &dyn RequestHeaders),
+        }
+    }
+}
+
+/// A request whose URLs have been prepared for matching, so that evaluating it
+/// against several lists only works out the parts of its URLs once.
+///
+/// No lowercased copy of the URLs is kept: the case-insensitive paths fold
+/// ASCII case as they scan (the literal searcher is built case-insensitive and
+/// the prefilter hashes lowercased bytes), so a copy would only cost an extra
+/// allocation per URL.
+pub struct PreparedRequest<'a> {
+    url: PreparedUrl<'a>,
+    document: Option<PreparedUrl<'a>>,
+    opener: Option<(PreparedUrl<'a>, bool)>,
+    resource_type: ResourceType,
+    load_type: LoadType,
+    context: Option<&'a str>,
+    redirect_chain: &'a [Url],
+    headers: Option<&'a dyn RequestHeaders>,
+}
+
+impl<'a> PreparedRequest<'a> {
+    /// Prepare the URLs of `request`.
+    pub fn new(request: &Request<'a>) -> PreparedRequest<'a> {
+        PreparedRequest {
+            url: PreparedUrl::new(request.url),
+            document: request.document.map(PreparedUrl::new),
+            opener: request.opener.map(|opener| (PreparedUrl::new(opener.url), opener.user_initiated)),
+            resource_type: request.resource_type,
+            load_type: request.load_type,
+            context: request.context,
+            redirect_chain: request.redirect_chain,
+            headers: request.headers,
+        }
+    }
+
+    /// The prepared URL of the request.
+    pub fn url(&self) -> &PreparedUrl<'a> {
+        &self.url
+    }
+
+    /// The prepared URL of the top-level document making the request.
+    pub fn document(&self) -> Option<&PreparedUrl<'a>> {
+        self.document.as_ref()
+    }
+
+    /// Borrow this request for matching.
+    pub fn as_request(&self) -> Request<'_> {
+        Request {
+            url: &self.url,
+            resource_type: self.resource_type,
+            load_type: self.load_type,
+            context: self.context,
+            opener: self.opener.as_ref().map(|&(ref url, user_initiated)| Opener { url, user_initiated }),
+            document: self.document.as_ref().map(|document| document as &dyn RequestUrl),
+            redirect_chain: self.redirect_chain,
+            headers: self.headers,
+        }
+    }
+}
+
+impl<'a> From<&Request<'a>> for OwnedRequest {
+    fn from(request: &Request) -> OwnedRequest {
+        OwnedRequest {
+            url: request.url.as_str().to_owned(),
+            resource_type: request.resource_type,
+            load_type: request.load_type,
+            context: request.context.map(str::to_owned),
+            opener: request.opener.map(|opener| OwnedOpener {
+                url: opener.url.as_str().to_owned(),
+                user_initiated: opener.user_initiated,
+            }),
+            document: request.document.map(|document| document.as_str().to_owned()),
+            redirect_chain: request.redirect_chain.to_vec(),
+            headers: request.headers.map(|headers| {
+                headers.entries().into_iter().map(|(name, value)| (name.to_owned(), value.to_owned())).collect()
+            }),
+        }
+    }
 }
 
 /// The type of resource being requested.
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum ResourceType {
     /// A top-level document.
     Document,
@@ -37,10 +204,49 @@ pub enum ResourceType {
     Media,
     /// A popup resource.
     Popup,
+    /// A WebSocket connection, to a `ws:` or `wss:` URL.
+    WebSocket,
+    /// The script of a dedicated worker.
+    Worker,
+    /// The script of a shared worker.
+    SharedWorker,
+    /// The script of a service worker.
+    ServiceWorker,
+    /// A report of a Content Security Policy violation, posted to the
+    /// `report-uri` or `report-to` endpoint of the policy.
+    CspReport,
+    /// A beacon sent with `navigator.sendBeacon`, or another request that
+    /// outlives its page.
+    Beacon,
+    /// A request made by a plugin loaded with `<object>` or `<embed>`, as
+    /// opposed to the load of the plugin content itself.
+    ObjectSubrequest,
+}
+
+impl ResourceType {
+    /// Every resource type, in declaration order.
+    pub const ALL: &'static [ResourceType] = &[
+        ResourceType::Document,
+        ResourceType::Image,
+        ResourceType::StyleSheet,
+        ResourceType::Script,
+        ResourceType::Font,
+        ResourceType::Raw,
+        ResourceType::SVGDocument,
+        ResourceType::Media,
+        ResourceType::Popup,
+        ResourceType::WebSocket,
+        ResourceType::Worker,
+        ResourceType::SharedWorker,
+        ResourceType::ServiceWorker,
+        ResourceType::CspReport,
+        ResourceType::Beacon,
+        ResourceType::ObjectSubrequest,
+    ];
 }
 
 /// A potential list of resource types being requested.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ResourceTypeList {
     /// All possible types.
     All,
@@ -49,50 +255,155 @@ pub enum ResourceTypeList {
 }
 
 /// The type of load that is being initiated.
-#[derive(Copy, Clone, Debug, PartialEq)]
+///
+/// The categories come in pairs describing one aspect of a load each: its
+/// origin, its site, and for navigations, the frame being navigated. A trigger
+/// restricted to several aspects only matches loads satisfying all of them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum LoadType {
     /// Same-origin with respect to the originating page.
     FirstParty,
     /// Cross-origin with respect to the originating page.
     ThirdParty,
+    /// Same registrable domain as the top-level document, even if cross-origin.
+    SameSite,
+    /// Different registrable domain from the top-level document.
+    CrossSite,
+    /// The navigation of a top-level document.
+    TopFrame,
+    /// The navigation of a document embedded in another one.
+    ChildFrame,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl LoadType {
+    /// Every load type, in declaration order.
+    pub const ALL: &'static [LoadType] = &[
+        LoadType::FirstParty,
+        LoadType::ThirdParty,
+        LoadType::SameSite,
+        LoadType::CrossSite,
+        LoadType::TopFrame,
+        LoadType::ChildFrame,
+    ];
+
+    /// The bits of the categories of each aspect in a bitmask of `LoadType::ALL`.
+    pub const ASPECTS: [u32; 3] = [0b11, 0b1100, 0b11_0000];
+
+    /// The bit of this load type in a bitmask of `LoadType::ALL`.
+    pub fn bit(self) -> u32 {
+        1 << LoadType::ALL.iter().position(|t| *t == self).unwrap()
+    }
+
+    /// The load types describing a request, as a bitmask of `LoadType::ALL`.
+    /// Requests without a top-level document are considered same-site unless
+    /// they are third-party, and only document requests are frame navigations.
+    pub fn of_request(request: &Request) -> u32 {
+        let site = match (request.load_type, request.document) {
+            (LoadType::FirstParty, _) | (LoadType::SameSite, _) => LoadType::SameSite,
+            (_, Some(document)) => match LoadType::by_site(request.url, document) {
+                LoadType::FirstParty => LoadType::SameSite,
+                _ => LoadType::CrossSite,
+            },
+            (_, None) => LoadType::CrossSite,
+        };
+        let mut bits = request.load_type.bit() | site.bit();
+        if request.resource_type == ResourceType::Document {
+            let frame = if request.document.is_some() { LoadType::ChildFrame } else { LoadType::TopFrame };
+            bits |= frame.bit();
+        }
+        bits
+    }
+
+    /// Whether a request described by `request_bits` matches a trigger restricted
+    /// to the load types of `mask`: every restricted aspect must match.
+    pub fn mask_matches(mask: u32, request_bits: u32) -> bool {
+        LoadType::ASPECTS.iter().all(|&aspect| mask & aspect == 0 || mask & aspect & request_bits != 0)
+    }
+}
+
+/// A potential list of load types being requested.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LoadTypeList {
+    /// All possible load types.
+    All,
+    /// An explicit list of load types, any of which matches.
+    List(Vec<LoadType>)
+}
+
+impl LoadTypeList {
+    /// This list as a bitmask of `LoadType::ALL`, or 0 for all load types.
+    pub fn mask(&self) -> u32 {
+        match *self {
+            LoadTypeList::All => 0,
+            LoadTypeList::List(ref types) => types.iter().fold(0, |mask, t| mask | t.bit()),
+        }
+    }
+
+    /// Whether `request` matches this list.
+    pub fn matches(&self, request: &Request) -> bool {
+        let mask = self.mask();
+        mask == 0 || LoadType::mask_matches(mask, LoadType::of_request(request))
+    }
+}
+
+/// Sorted, deduplicated lists of domains used to evaluate domain constraints.
+/// The domains are usually shared with other rules of the same list.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct DomainMatcher {
-    pub exact: Box<[String]>,
-    pub subdomain: Box<[String]>,
+    pub exact: Box<[Arc<str>]>,
+    pub subdomain: Box<[Arc<str>]>,
+    /// Whether some domains end with `.*`, matching every public suffix.
+    pub wildcard_tld: bool,
 }
 
 impl DomainMatcher {
-    fn matches(&self, url: &Url) -> bool {
-        let domain = match url.domain() {
-            Some(domain) => domain,
-            None => return false,
-        };
-        for candidate in &*self.exact {
-            if domain == candidate {
+    fn matches(&self, url: &dyn RequestUrl) -> bool {
+        match url.domain() {
+            Some(domain) => self.matches_domain(domain),
+            None => false,
+        }
+    }
+
+    /// Whether `domain` is one of the exact domains, or is covered by one of the
+    /// subdomain patterns. Both lists are sorted, so this takes a binary search
+    /// per label of `domain` rather than a scan of every entry.
+    pub fn matches_domain(&self, domain: &str) -> bool {
+        self.contains(domain) || self.wildcard_tld && wildcard_tld(domain).is_some_and(|domain| self.contains(&domain))
+    }
+
+    fn contains(&self, domain: &str) -> bool {
+        if self.exact.binary_search_by(|d| (**d).cmp(domain)).is_ok() {
+            return true;
+        }
+        let mut suffix = domain;
+        loop {
+            if self.subdomain.binary_search_by(|d| (**d).cmp(suffix)).is_ok() {
                 return true;
             }
-        }
-        for suffix in &*self.subdomain {
-            match domain.len().cmp(&suffix.len()) {
-                Ordering::Equal if domain == suffix => return true,
-                Ordering::Greater => {
-                    if domain.as_bytes()[domain.len() - suffix.len() - 1] == b'.' {
-                        if domain.ends_with(suffix) {
-                            return true;
-                        }
-                    }
-                }
-                _ => {}
+            match suffix.find('.') {
+                Some(dot) => suffix = &suffix[dot + 1..],
+                None => return false,
             }
         }
-        false
+    }
+}
+
+/// The domains in list syntax, separated by commas, with subdomain patterns
+/// prefixed with `*`.
+impl fmt::Display for DomainMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let subdomains: Vec<String> = self.subdomain.iter().map(|d| format!("*{}", d)).collect();
+        write_list(f, &self.exact)?;
+        if !self.exact.is_empty() && !subdomains.is_empty() {
+            f.write_str(",")?;
+        }
+        write_list(f, &subdomains)
     }
 }
 
 /// Conditions which restrict the set of matches for a particular trigger.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum DomainConstraint {
     /// Only trigger if the domain matches one of the included strings.
     If(DomainMatcher),
@@ -100,41 +411,300 @@ pub enum DomainConstraint {
     Unless(DomainMatcher),
 }
 
+/// What the domains of `if-domain` and `unless-domain` constraints are compared
+/// against.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DomainTarget {
+    /// The host of the requested URL.
+    #[default]
+    Request,
+    /// The host of the top-level document making the request, as in WebKit, so
+    /// that a constraint selects the sites a rule applies on rather than the
+    /// servers it applies to. Navigations compare the requested URL.
+    TopDocument,
+}
+
+/// What the URL filter of a trigger is matched against.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum UrlFilterAnchor {
+    /// The whole URL, which the filter is a regular expression for.
+    #[default]
+    Url,
+    /// The start of a label of the host of the URL. The filter is a host name,
+    /// compared as a string rather than a regular expression: `example.com`
+    /// matches `example.com` and its subdomains, and a name ending with a dot,
+    /// such as `ads.`, matches any host with a label of that name followed by
+    /// others. This replaces patterns like `^[^:]+://+([^:/]+\.)?example\.com[:/]`,
+    /// which are slower and easy to get subtly wrong.
+    Host,
+}
+
+/// Whether `host` matches the host name `pattern` of a trigger anchored with
+/// `UrlFilterAnchor::Host`.
+pub fn host_anchor_matches(pattern: &str, host: &str) -> bool {
+    let label_starts = Some(0).into_iter().chain(host.match_indices('.').map(|(dot, _)| dot + 1));
+    label_starts.map(|start| &host[start..]).any(|rest| {
+        rest == pattern || pattern.ends_with('.') && rest.starts_with(pattern)
+    })
+}
+
+/// A regular expression matched against request URLs. Only the syntax of the
+/// pattern is checked up front; the pattern is compiled the first time it is
+/// evaluated, so rules that never get that far cost little time or memory.
+#[derive(Clone)]
+pub struct UrlFilter {
+    source: String,
+    compiled: OnceLock<Result<Regex, regex::Error>>,
+}
+
+/// Filters are compared by their source, whether or not they were compiled.
+impl PartialEq for UrlFilter {
+    fn eq(&self, other: &UrlFilter) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for UrlFilter {}
+
+impl Hash for UrlFilter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.source.hash(state)
+    }
+}
+
+/// Only the source is shown, since the compiled program is not readable.
+impl fmt::Debug for UrlFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("UrlFilter").field(&self.source).finish()
+    }
+}
+
+impl fmt::Display for UrlFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl UrlFilter {
+    /// Create a filter for `source`, or `None` if it is not a valid regular expression.
+    pub fn new(source: &str) -> Option<UrlFilter> {
+        regex_syntax::Parser::new().parse(source).ok()?;
+        Some(UrlFilter {
+            source: source.to_owned(),
+            compiled: OnceLock::new(),
+        })
+    }
+
+    /// The source of the regular expression.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether the regular expression has been compiled yet.
+    #[cfg(test)]
+    pub fn is_compiled(&self) -> bool {
+        self.compiled.get().is_some()
+    }
+
+    /// Compile the regular expression if that has not happened yet.
+    pub fn compile(&self) -> Result<&Regex, &regex::Error> {
+        self.compiled.get_or_init(|| Regex::new(&self.source)).as_ref()
+    }
+
+    /// An estimate of the memory used by the compiled program of this filter, or
+    /// `None` if it has not been compiled. The regex crate does not report this
+    /// directly, so it is found by searching for the smallest size limit that
+    /// the pattern compiles under, which makes this too slow for routine use.
+    pub fn program_size(&self) -> Option<usize> {
+        self.compiled.get()?.as_ref().ok()?;
+        let (mut low, mut high) = (0, 10 * (1 << 20));
+        while low < high {
+            let limit = low + (high - low) / 2;
+            if RegexBuilder::new(&self.source).size_limit(limit).build().is_ok() {
+                high = limit;
+            } else {
+                low = limit + 1;
+            }
+        }
+        Some(low)
+    }
+
+    /// Whether the regular expression matches `text`. A filter which fails to
+    /// compile, eg. by exceeding the size limit, never matches.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.compile().map(|regex| regex.is_match(text)).unwrap_or(false)
+    }
+}
+
 /// A set of filters that determine if a given rule's action is performed.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Trigger {
     /// A simple regex that is matched against the characters in the destination resource's URL.
-    pub url_filter: Regex,
+    pub url_filter: UrlFilter,
+    /// What the URL filter is matched against.
+    pub url_filter_anchor: UrlFilterAnchor,
+    /// A regex that the path of the URL must also match, so that a pattern
+    /// like `^/ads/` cannot match elsewhere in the URL. It has the same case
+    /// sensitivity flag as the URL filter.
+    pub path_filter: Option<UrlFilter>,
+    /// A regex that the query of the URL, without the `?`, must also match.
+    /// URLs without a query never match it.
+    pub query_filter: Option<UrlFilter>,
     /// The classes of resources for which this trigger matches.
     pub resource_type: ResourceTypeList,
-    /// The category of loads for which this trigger matches.
-    pub load_type: Option<LoadType>,
+    /// The categories of loads for which this trigger matches.
+    pub load_type: LoadTypeList,
     /// Domains which modify the behaviour of this trigger, either specifically including or
     /// excluding from the matches based on string comparison.
     pub domain_constraint: Option<DomainConstraint>,
+    /// The browsing contexts in which this trigger matches, or `None` for all of them.
+    pub contexts: Option<Box<[Arc<str>]>>,
+    /// Whether this trigger only matches popups opened by a user action, or only
+    /// those opened without one.
+    pub user_initiated: Option<bool>,
+    /// The domains of the pages whose popups this trigger matches.
+    pub opener_domain: Option<DomainMatcher>,
+    /// What the domain constraint is evaluated against.
+    pub domain_target: DomainTarget,
+    /// Whether the URL filter also matches the URLs the request was redirected
+    /// from, to catch trackers bouncing through other hosts before an innocuous
+    /// final URL.
+    pub match_redirect_chain: bool,
+    /// The headers that must be present in the request, with matching values.
+    pub if_header: Box<[HeaderCondition]>,
+    /// The headers that must not be present in the request with matching values.
+    pub unless_header: Box<[HeaderCondition]>,
+}
+
+/// A request header, and optionally a pattern for its value.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HeaderCondition {
+    /// The name of the header, in lowercase.
+    pub name: Arc<str>,
+    /// A regex that the value of the header must match, or `None` for any value.
+    pub value: Option<UrlFilter>,
+}
+
+/// The header name, followed by `~` and the value pattern if there is one.
+impl fmt::Display for HeaderCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)?;
+        match self.value {
+            Some(ref value) => write!(f, "~{}", value.as_str()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl HeaderCondition {
+    /// Whether `headers` contain this header with a matching value.
+    pub fn matches(&self, headers: &dyn RequestHeaders) -> bool {
+        headers.get(&self.name).is_some_and(|value| self.value.as_ref().is_none_or(|filter| filter.is_match(value)))
+    }
 }
 
 impl Trigger {
-    fn matches(&self, request: &Request) -> bool {
+    /// Restrict this trigger to `contexts`, keeping only those of its own
+    /// contexts that are among them.
+    pub fn limit_to_contexts(&mut self, contexts: &[Arc<str>]) {
+        self.contexts = Some(match self.contexts.take() {
+            Some(own) => own.iter().filter(|c| contexts.contains(c)).cloned().collect(),
+            None => contexts.into(),
+        });
+    }
+
+    /// Whether this trigger applies in the browsing context of `request`.
+    pub fn matches_context(&self, request: &Request) -> bool {
+        match self.contexts {
+            Some(ref contexts) => request.context.is_some_and(|context| {
+                contexts.iter().any(|c| **c == *context)
+            }),
+            None => true,
+        }
+    }
+
+    /// Whether the opener of `request` satisfies this trigger. Triggers with
+    /// opener conditions never match requests without an opener.
+    pub fn matches_opener(&self, request: &Request) -> bool {
+        if self.user_initiated.is_none() && self.opener_domain.is_none() {
+            return true;
+        }
+        request.opener.is_some_and(|opener| {
+            self.user_initiated.is_none_or(|user_initiated| user_initiated == opener.user_initiated) &&
+                self.opener_domain.as_ref().is_none_or(|matcher| matcher.matches(opener.url))
+        })
+    }
+
+    /// Whether the headers of `request` satisfy this trigger. Triggers with
+    /// header conditions never match requests whose headers are not known.
+    pub fn matches_headers(&self, request: &Request) -> bool {
+        if self.if_header.is_empty() && self.unless_header.is_empty() {
+            return true;
+        }
+        request.headers.is_some_and(|headers| {
+            self.if_header.iter().all(|condition| condition.matches(headers)) &&
+                !self.unless_header.iter().any(|condition| condition.matches(headers))
+        })
+    }
+
+    pub fn matches(&self, request: &Request) -> bool {
+        self.matches_timed(request, None)
+    }
+
+    /// Whether the URL filter, and the path and query filters if any, match `url`.
+    fn matches_url(&self, url: &dyn RequestUrl) -> bool {
+        let matches = match self.url_filter_anchor {
+            UrlFilterAnchor::Url => self.url_filter.is_match(url.as_str()),
+            UrlFilterAnchor::Host => url.host().is_some_and(|host| host_anchor_matches(self.url_filter.as_str(), host)),
+        };
+        if !matches || self.path_filter.is_none() && self.query_filter.is_none() {
+            return matches;
+        }
+        let (path, query) = url.path_and_query();
+        self.path_filter.as_ref().is_none_or(|filter| filter.is_match(path)) &&
+            self.query_filter.as_ref().is_none_or(|filter| query.is_some_and(|query| filter.is_match(query)))
+    }
+
+    /// Like `matches`, but records the time spent evaluating the URL filter in
+    /// `profile` if it is provided.
+    fn matches_timed(&self, request: &Request, profile: Option<&mut RuleProfile>) -> bool {
         if let ResourceTypeList::List(ref types) = self.resource_type {
             if types.iter().find(|t| **t == request.resource_type).is_none() {
                 return false;
             }
         }
 
-        if let Some(ref load_type) = self.load_type {
-            if request.load_type != *load_type {
-                return false;
-            }
+        if !self.load_type.matches(request) {
+            return false;
         }
 
-        if self.url_filter.is_match(request.url.as_str()) {
+        if !self.matches_context(request) || !self.matches_opener(request) || !self.matches_headers(request) {
+            return false;
+        }
+
+        let url_matches = match profile {
+            Some(profile) => {
+                let start = Instant::now();
+                let url_matches = self.matches_url(request.url);
+                profile.regex_time += start.elapsed();
+                profile.evaluations += 1;
+                url_matches
+            }
+            None => self.matches_url(request.url),
+        } || self.match_redirect_chain &&
+             request.redirect_chain.iter().any(|url| self.matches_url(url));
+
+        if url_matches {
+            let target = match self.domain_target {
+                DomainTarget::Request => request.url,
+                DomainTarget::TopDocument => request.document.unwrap_or(request.url),
+            };
             match self.domain_constraint {
                 Some(DomainConstraint::If(ref matcher)) => {
-                    return matcher.matches(&request.url);
+                    return matcher.matches(target);
                 }
                 Some(DomainConstraint::Unless(ref matcher)) => {
-                    return !matcher.matches(&request.url);
+                    return !matcher.matches(target);
                 }
                 None => return true,
             }
@@ -144,71 +714,580 @@ impl Trigger {
     }
 }
 
-impl PartialEq for Trigger {
-    fn eq(&self, other: &Trigger) -> bool {
-        self.url_filter.as_str() == other.url_filter.as_str() &&
-            self.resource_type == other.resource_type &&
-            self.load_type == other.load_type &&
-            self.domain_constraint == other.domain_constraint
+/// Which cookies a cookie blocking rule affects.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CookieScope {
+    /// Strip the cookies sent with the request.
+    Request,
+    /// Ignore the `Set-Cookie` headers of the response.
+    Response,
+    /// Both strip the cookies of the request and ignore those set by the response.
+    Both,
+}
+
+impl CookieScope {
+    /// Whether cookies must be stripped from the request.
+    pub fn applies_to_request(self) -> bool {
+        self != CookieScope::Response
+    }
+
+    /// Whether cookies set by the response must be ignored.
+    pub fn applies_to_response(self) -> bool {
+        self != CookieScope::Request
     }
 }
 
 /// The action to take for the provided request.
 #[derive(Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Reaction {
     /// Block the request from starting.
     Block,
-    /// Strip the HTTP cookies from the request.
-    BlockCookies,
+    /// Strip the HTTP cookies from the request, or from the response, or both.
+    BlockCookies(CookieScope),
+    /// Strip the cookies whose names match one of the given patterns from the
+    /// request, and ignore them when set by the response. A `*` in a pattern
+    /// matches any sequence of characters.
+    BlockCookiesMatching(Vec<String>),
+    /// Answer the request with the given surrogate resource instead of fetching it.
+    Redirect(&'static Resource),
+    /// Hide the elements matching the given CSS selector in the originating document.
+    HideMatchingElements(String),
+    /// Hide the elements selected by the given procedural selector in the originating document.
+    HideProceduralElements(ProceduralSelector),
+    /// Apply CSS declarations to the elements matching a selector in the originating document.
+    InjectStyle {
+        /// The CSS selector of the elements to style.
+        selector: String,
+        /// The declarations to apply, eg. `position: static !important`.
+        declarations: String,
+    },
+    /// Remove the elements described by the given filter from the HTML of the
+    /// response before it is parsed.
+    FilterHtml(HtmlFilter),
+    /// Prevent the inline scripts of the requested document from running, as by
+    /// a `script-src` Content Security Policy without `'unsafe-inline'`.
+    BlockInlineScripts,
+}
+
+impl Reaction {
+    /// Whether this reaction strips the cookie with the given name. Reactions
+    /// blocking all cookies match every name, whatever their scope.
+    pub fn blocks_cookie(&self, name: &str) -> bool {
+        match *self {
+            Reaction::BlockCookies(_) => true,
+            Reaction::BlockCookiesMatching(ref patterns) => {
+                patterns.iter().any(|pattern| cookie_name_matches(pattern, name))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether `name` matches `pattern`, in which `*` matches any sequence of characters.
+fn cookie_name_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let last = match parts.pop() {
+        Some(last) => last,
+        None => return rest.is_empty(),
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// How thoroughly a request is evaluated against a list.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MatchPolicy {
+    /// Evaluate every rule that could apply, reporting every reaction.
+    All,
+    /// Stop as soon as the request is blocked by a rule that no later exception
+    /// rule can override, judging by their domains, resource types and load
+    /// types. The other reactions found so far are still reported, but later
+    /// rules are not evaluated, so some reactions may be missing.
+    FirstBlock,
+}
+
+/// The result of matching a request within a time budget.
+#[derive(Debug, PartialEq)]
+pub struct BoundedReactions {
+    /// The reactions of the rules evaluated before the budget ran out. If matching
+    /// is incomplete, a later rule could have added to or discarded these.
+    pub reactions: Vec<Reaction>,
+    /// Whether every rule that could apply was evaluated.
+    pub complete: bool,
+}
+
+/// The result of evaluating a request against a list along with a candidate
+/// to replace it, such as its next version, without letting the candidate
+/// affect the request.
+#[derive(Debug, PartialEq)]
+pub struct ShadowEvaluation {
+    /// The reactions of the primary list, which are the ones to apply.
+    pub reactions: Vec<Reaction>,
+    /// The reactions of the candidate list, evaluated within the budget.
+    pub candidate: BoundedReactions,
+    /// The time spent evaluating the primary list.
+    pub primary_time: Duration,
+    /// The time spent evaluating the candidate list.
+    pub candidate_time: Duration,
+}
+
+impl ShadowEvaluation {
+    /// Whether the candidate finished within its budget, and reacted to the
+    /// request differently than the primary list.
+    pub fn diverges(&self) -> bool {
+        self.candidate.complete && self.candidate.reactions != self.reactions
+    }
+}
+
+/// The rule responsible for a reaction, so that telemetry can report which list
+/// blocked a request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Provenance<'a> {
+    /// The name given to the list containing the rule, if any.
+    pub list: Option<&'a str>,
+    /// The position of the rule in its list.
+    pub rule: usize,
+    /// The identifier of the rule.
+    pub id: RuleId,
+}
+
+/// Hints about the rule of an element hiding reaction, so that the DOM layer
+/// can choose how to inject its selector.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HidingHints {
+    /// Whether the selector hides elements on every page, so that it can go in
+    /// a user-agent stylesheet shared by all documents rather than be injected
+    /// at the author level of each page.
+    pub generic: bool,
+    /// The identifier of the rule.
+    pub rule: RuleId,
+}
+
+/// Like `Reaction`, but borrowing the selector from the rule list instead of
+/// copying it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ReactionRef<'a> {
+    /// Block the request from starting.
+    Block,
+    /// Strip the HTTP cookies from the request, or from the response, or both.
+    BlockCookies(CookieScope),
+    /// Strip the cookies whose names match one of the given patterns.
+    BlockCookiesMatching(&'a [String]),
+    /// Answer the request with the given surrogate resource instead of fetching it.
+    Redirect(&'static Resource),
     /// Hide the elements matching the given CSS selector in the originating document.
-    HideMatchingElements(String)
+    HideMatchingElements(&'a str),
+    /// Hide the elements selected by the given procedural selector in the originating document.
+    HideProceduralElements(&'a ProceduralSelector),
+    /// Apply CSS declarations to the elements matching a selector in the originating document.
+    InjectStyle {
+        /// The CSS selector of the elements to style.
+        selector: &'a str,
+        /// The declarations to apply.
+        declarations: &'a str,
+    },
+    /// Remove the elements described by the given filter from the HTML of the response.
+    FilterHtml(&'a HtmlFilter),
+    /// Prevent the inline scripts of the requested document from running.
+    BlockInlineScripts,
+}
+
+impl<'a> ReactionRef<'a> {
+    /// The source of the selector of an element hiding reaction.
+    pub(crate) fn hiding_selector(&self) -> Option<&'a str> {
+        match *self {
+            ReactionRef::HideMatchingElements(selector) => Some(selector),
+            ReactionRef::HideProceduralElements(selector) => Some(&selector.source),
+            ReactionRef::Block | ReactionRef::BlockCookies(_) | ReactionRef::BlockCookiesMatching(_) |
+            ReactionRef::Redirect(_) | ReactionRef::InjectStyle { .. } | ReactionRef::FilterHtml(_) |
+            ReactionRef::BlockInlineScripts => None,
+        }
+    }
+
+    /// Copy this reaction into an owned `Reaction`.
+    pub fn into_owned(self) -> Reaction {
+        match self {
+            ReactionRef::Block => Reaction::Block,
+            ReactionRef::BlockCookies(scope) => Reaction::BlockCookies(scope),
+            ReactionRef::BlockCookiesMatching(names) => Reaction::BlockCookiesMatching(names.to_vec()),
+            ReactionRef::Redirect(resource) => Reaction::Redirect(resource),
+            ReactionRef::HideMatchingElements(selector) => Reaction::HideMatchingElements(selector.to_owned()),
+            ReactionRef::HideProceduralElements(selector) => Reaction::HideProceduralElements(selector.clone()),
+            ReactionRef::InjectStyle { selector, declarations } => Reaction::InjectStyle {
+                selector: selector.to_owned(),
+                declarations: declarations.to_owned(),
+            },
+            ReactionRef::FilterHtml(filter) => Reaction::FilterHtml(filter.clone()),
+            ReactionRef::BlockInlineScripts => Reaction::BlockInlineScripts,
+        }
+    }
+}
+
+/// The reactions to a request, stored without allocating when there are few
+/// of them, as is the case for almost every request.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Reactions<'a> {
+    reactions: SmallVec<[ReactionRef<'a>; 2]>,
+    /// The position of the rule that produced each reaction.
+    rules: SmallVec<[usize; 2]>,
+    /// Selectors which element hiding exceptions have disabled for this request.
+    hiding_exceptions: SmallVec<[&'a str; 1]>,
+}
+
+impl<'a> Reactions<'a> {
+    /// Copy these reactions into a list of owned `Reaction`s.
+    pub fn to_vec(&self) -> Vec<Reaction> {
+        self.reactions.iter().map(|reaction| reaction.into_owned()).collect()
+    }
+
+    /// The position in its list of the rule that produced each reaction.
+    pub fn rules(&self) -> &[usize] {
+        &self.rules
+    }
+
+    /// Add a reaction produced by the rule at position `rule`, unless it hides
+    /// elements with a selector that has an exception.
+    pub(crate) fn push(&mut self, reaction: ReactionRef<'a>, rule: usize) {
+        if reaction.hiding_selector().is_some_and(|selector| self.hiding_exceptions.contains(&selector)) {
+            return;
+        }
+        self.reactions.push(reaction);
+        self.rules.push(rule);
+    }
+
+    /// Disable hiding elements with `selector`, whether it was requested by an
+    /// earlier or a later rule.
+    pub(crate) fn except_hiding(&mut self, selector: &'a str) {
+        let mut position = 0;
+        while position < self.reactions.len() {
+            if self.reactions[position].hiding_selector() == Some(selector) {
+                self.reactions.remove(position);
+                self.rules.remove(position);
+            } else {
+                position += 1;
+            }
+        }
+        self.hiding_exceptions.push(selector);
+    }
+
+    /// Discard every reaction and exception added so far.
+    pub(crate) fn clear(&mut self) {
+        self.reactions.clear();
+        self.rules.clear();
+        self.hiding_exceptions.clear();
+    }
+}
+
+impl<'a> Deref for Reactions<'a> {
+    type Target = [ReactionRef<'a>];
+
+    fn deref(&self) -> &[ReactionRef<'a>] {
+        &self.reactions
+    }
+}
+
+impl<'a> IntoIterator for Reactions<'a> {
+    type Item = ReactionRef<'a>;
+    type IntoIter = ::smallvec::IntoIter<[ReactionRef<'a>; 2]>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.reactions.into_iter()
+    }
 }
 
 /// An action to take when a rule is triggered.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum Action {
     /// Prevent the network request from starting.
     Block,
-    /// Remove any HTTP cookies from the network request before starting it.
-    BlockCookies,
+    /// Remove any HTTP cookies from the network request before starting it, or
+    /// ignore the cookies set by its response, or both.
+    BlockCookies(CookieScope),
+    /// Remove the cookies whose names match one of the given patterns from the
+    /// network request, and ignore them when set by its response.
+    BlockCookiesMatching(Arc<[String]>),
+    /// Replace the response to the network request with a bundled resource.
+    Redirect(&'static Resource),
     /// Hide elements of the requesting page based on the given CSS selector.
-    CssDisplayNone(String),
+    CssDisplayNone(Arc<str>),
+    /// Hide elements of the requesting page based on a selector in extended syntax.
+    CssDisplayNoneProcedural(Arc<ProceduralSelector>),
+    /// Apply CSS declarations to the elements of the requesting page matching a selector.
+    CssInject {
+        /// The CSS selector of the elements to style.
+        selector: Arc<str>,
+        /// The declarations to apply.
+        style: Arc<str>,
+    },
+    /// Remove elements from the HTML of the response to the network request.
+    HtmlFilter(Arc<HtmlFilter>),
+    /// Prevent the inline scripts of the requested document from running. Only
+    /// meaningful for document requests.
+    BlockInlineScripts,
+    /// Do not hide elements based on the given CSS selector, even if another
+    /// rule asks for it.
+    CssDisplayNoneException(Arc<str>),
     /// Any previously triggered rules do not have their actions performed.
     IgnorePreviousRules,
 }
 
 impl Action {
-    fn process(&self, reactions: &mut Vec<Reaction>) {
+    /// Whether this action can add a reaction by itself, rather than only
+    /// discarding the reactions of other rules.
+    pub fn produces_reaction(&self) -> bool {
+        match *self {
+            Action::Block | Action::BlockCookies(_) | Action::BlockCookiesMatching(_) | Action::Redirect(_) |
+            Action::CssDisplayNone(_) | Action::CssDisplayNoneProcedural(_) | Action::CssInject { .. } |
+            Action::HtmlFilter(_) | Action::BlockInlineScripts => true,
+            Action::CssDisplayNoneException(_) | Action::IgnorePreviousRules => false,
+        }
+    }
+
+    pub fn process<'a>(&'a self, rule: usize, reactions: &mut Reactions<'a>) {
         match *self {
             Action::Block =>
-                reactions.push(Reaction::Block),
-            Action::BlockCookies =>
-                reactions.push(Reaction::BlockCookies),
+                reactions.push(ReactionRef::Block, rule),
+            Action::BlockCookies(scope) =>
+                reactions.push(ReactionRef::BlockCookies(scope), rule),
+            Action::BlockCookiesMatching(ref names) =>
+                reactions.push(ReactionRef::BlockCookiesMatching(names), rule),
+            Action::Redirect(resource) =>
+                reactions.push(ReactionRef::Redirect(resource), rule),
             Action::CssDisplayNone(ref selector) =>
-                reactions.push(Reaction::HideMatchingElements(selector.clone())),
+                reactions.push(ReactionRef::HideMatchingElements(selector), rule),
+            Action::CssDisplayNoneProcedural(ref selector) =>
+                reactions.push(ReactionRef::HideProceduralElements(selector), rule),
+            Action::CssInject { ref selector, ref style } =>
+                reactions.push(ReactionRef::InjectStyle { selector, declarations: style }, rule),
+            Action::HtmlFilter(ref filter) =>
+                reactions.push(ReactionRef::FilterHtml(filter), rule),
+            Action::BlockInlineScripts =>
+                reactions.push(ReactionRef::BlockInlineScripts, rule),
+            Action::CssDisplayNoneException(ref selector) =>
+                reactions.except_hiding(selector),
             Action::IgnorePreviousRules =>
                 reactions.clear(),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 /// A single rule, consisting of a condition to trigger this rule, and an action to take.
 pub struct Rule {
-    pub trigger: Trigger,
-    pub action: Action,
+    pub(crate) trigger: Trigger,
+    pub(crate) action: Action,
+    /// The sorted groups this rule belongs to, eg. `annoyances`. Disabling any
+    /// of them disables the rule.
+    pub(crate) groups: Box<[Arc<str>]>,
+    /// Where the rule was found in the body of its list, if it was parsed from one.
+    pub(crate) source: Option<SourceLocation>,
+}
+
+/// Rules are equal regardless of where they were found, so that moving a rule
+/// within its list does not change it.
+impl PartialEq for Rule {
+    fn eq(&self, other: &Rule) -> bool {
+        self.trigger == other.trigger && self.action == other.action && self.groups == other.groups
+    }
+}
+
+impl Eq for Rule {}
+
+impl Hash for Rule {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.trigger.hash(state);
+        self.action.hash(state);
+        self.groups.hash(state);
+    }
+}
+
+/// Write `values` separated by commas.
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter, values: &[T]) -> fmt::Result {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            f.write_str(",")?;
+        }
+        write!(f, "{}", value)?;
+    }
+    Ok(())
+}
+
+/// A one-line summary of a trigger: its URL filter, followed by the conditions
+/// it imposes as `key=value` pairs named after the keys of the JSON format.
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.url_filter)?;
+        if self.url_filter_anchor == UrlFilterAnchor::Host {
+            f.write_str(" url-filter-anchor=host")?;
+        }
+        if let Some(ref filter) = self.path_filter {
+            write!(f, " path-filter={}", filter)?;
+        }
+        if let Some(ref filter) = self.query_filter {
+            write!(f, " query-filter={}", filter)?;
+        }
+        if let ResourceTypeList::List(ref types) = self.resource_type {
+            f.write_str(" resource-type=")?;
+            write_list(f, &types.iter().map(|t| t.name()).collect::<Vec<_>>())?;
+        }
+        if let LoadTypeList::List(ref types) = self.load_type {
+            f.write_str(" load-type=")?;
+            write_list(f, &types.iter().map(|t| t.name()).collect::<Vec<_>>())?;
+        }
+        match self.domain_constraint {
+            Some(DomainConstraint::If(ref matcher)) => write!(f, " if-domain={}", matcher)?,
+            Some(DomainConstraint::Unless(ref matcher)) => write!(f, " unless-domain={}", matcher)?,
+            None => {}
+        }
+        if self.domain_constraint.is_some() && self.domain_target == DomainTarget::TopDocument {
+            f.write_str(" (of the top document)")?;
+        }
+        if let Some(ref contexts) = self.contexts {
+            f.write_str(" context=")?;
+            write_list(f, contexts)?;
+        }
+        if let Some(user_initiated) = self.user_initiated {
+            write!(f, " user-initiated={}", user_initiated)?;
+        }
+        if let Some(ref matcher) = self.opener_domain {
+            write!(f, " opener-domain={}", matcher)?;
+        }
+        if self.match_redirect_chain {
+            f.write_str(" match-redirect-chain")?;
+        }
+        for (key, conditions) in [("if-header", &self.if_header), ("unless-header", &self.unless_header)] {
+            if !conditions.is_empty() {
+                write!(f, " {}=", key)?;
+                write_list(f, conditions)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The action's type as in the JSON format, followed by its argument.
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Action::Block => f.write_str("block"),
+            Action::BlockCookies(scope) => write!(f, "block-cookies scope={}", scope.name()),
+            Action::BlockCookiesMatching(ref names) => {
+                f.write_str("block-cookies names=")?;
+                write_list(f, names)
+            }
+            Action::Redirect(resource) => write!(f, "redirect {}", resource.name),
+            Action::CssDisplayNone(ref selector) => write!(f, "css-display-none {}", selector),
+            Action::CssDisplayNoneProcedural(ref selector) => write!(f, "css-display-none {}", selector.source),
+            Action::CssInject { ref selector, ref style } => write!(f, "css-inject {} {{ {} }}", selector, style),
+            Action::HtmlFilter(ref filter) => write!(f, "html-filter {}", filter.source),
+            Action::BlockInlineScripts => f.write_str("block-inline-scripts"),
+            Action::CssDisplayNoneException(ref selector) => write!(f, "css-display-none-exception {}", selector),
+            Action::IgnorePreviousRules => f.write_str("ignore-previous-rules"),
+        }
+    }
+}
+
+/// The trigger and action of the rule, eg. `ads resource-type=image -> block`.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {}", self.trigger, self.action)?;
+        if !self.groups.is_empty() {
+            f.write_str(" group=")?;
+            write_list(f, &self.groups)?;
+        }
+        Ok(())
+    }
+}
+
+/// An identifier derived from the content of a rule, which stays the same when
+/// the rule moves within its list or to another version of the list, so that
+/// per-rule state such as hit counters or user overrides can be keyed by it.
+/// Identical rules share an identifier.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RuleId(pub u64);
+
+impl fmt::Display for RuleId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Where a rule was found in the body of the list it was parsed from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    /// The position of the rule in the JSON array, counting the invalid rules
+    /// that were skipped before it.
+    pub index: usize,
+    /// The offset of the first byte of the rule in the body, after decompressing it.
+    pub offset: usize,
+    /// The line the rule starts on, starting from 1.
+    pub line: usize,
 }
 
 
 /// Attempt to match the given request against the provided rules. Returns a list
 /// of actions to take in response; an empty list means that the request should
-/// continue unmodified.
+/// continue unmodified. This evaluates every rule in turn, and serves as the
+/// reference for the indexed matching used by `RuleList`.
+#[cfg(test)]
 pub fn process_rules_for_request_impl(rules: &[Rule], request: &Request) -> Vec<Reaction> {
-    let mut reactions = vec![];
-    for rule in rules {
+    let mut reactions = Reactions::default();
+    for (position, rule) in rules.iter().enumerate() {
         if rule.trigger.matches(request) {
-            rule.action.process(&mut reactions);
+            rule.action.process(position, &mut reactions);
+        }
+    }
+    reactions.to_vec()
+}
+
+/// Like `process_rules_for_request_impl`, but records the time spent evaluating
+/// each rule's URL filter in the provided profile.
+pub fn process_rules_for_request_profiled_impl(rules: &[Rule],
+                                               disabled: Option<&[bool]>,
+                                               request: &Request,
+                                               profile: &mut Profile) -> Vec<Reaction> {
+    profile.reserve(rules.len());
+    let mut reactions = Reactions::default();
+    for (position, (rule, entry)) in rules.iter().zip(&mut profile.rules).enumerate() {
+        if disabled.is_some_and(|disabled| disabled[position]) {
+            continue;
+        }
+        if rule.trigger.matches_timed(request, Some(entry)) {
+            rule.action.process(position, &mut reactions);
+        }
+    }
+    reactions.to_vec()
+}
+
+/// Compile the regular expressions of the URL, path and query filters and header
+/// conditions of `rules` that have not been compiled yet.
+pub fn warm_up_impl(rules: &[Rule]) {
+    for rule in rules {
+        let trigger = &rule.trigger;
+        let _ = trigger.url_filter.compile();
+        for filter in trigger.path_filter.iter().chain(trigger.query_filter.iter()) {
+            let _ = filter.compile();
+        }
+        for condition in trigger.if_header.iter().chain(trigger.unless_header.iter()) {
+            if let Some(ref value) = condition.value {
+                let _ = value.compile();
+            }
         }
     }
-    reactions
 }
@@ -0,0 +1,233 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use psl::public_suffix_list;
+use url::Url;
+
+/// The parts of a requested URL that rules are evaluated against. This is
+/// implemented for `url::Url` and for serialized URLs, and can be implemented
+/// for other URL types so that requests can be matched without converting them.
+pub trait RequestUrl {
+    /// The serialized URL, which URL filters are matched against. Rules are
+    /// written against the serialization produced by `url::Url`, so the scheme
+    /// and host are expected to be lowercase.
+    fn as_str(&self) -> &str;
+
+    /// The host of the URL, without brackets around IPv6 addresses.
+    fn host(&self) -> Option<&str>;
+
+    /// The host of the URL if it is a domain name rather than an IP address.
+    fn domain(&self) -> Option<&str> {
+        self.host().filter(|host| !host.contains(':') &&
+                                  !host.bytes().all(|b| b.is_ascii_digit() || b == b'.'))
+    }
+
+    /// Whether the URL contains a username or password.
+    fn has_credentials(&self) -> bool;
+
+    /// The registrable domain of the host of the URL, eg. `example.co.uk` for
+    /// `https://www.example.co.uk/`, or `None` for IP addresses and public suffixes.
+    fn registrable_domain(&self) -> Option<&str> {
+        self.domain().and_then(|domain| public_suffix_list().registrable_domain(domain))
+    }
+
+    /// The path and query of the URL, without the `?` before the query, which
+    /// is `None` if the URL has none. The path of a URL with an authority is
+    /// `/` if it is empty, as `url::Url` serializes it, while the path of a URL
+    /// without one, such as `data:text/plain,ads`, is everything after its scheme.
+    fn path_and_query(&self) -> (&str, Option<&str>) {
+        let url = self.as_str();
+        let url = &url[..url.find('#').unwrap_or(url.len())];
+        let rest = url.find(':').map_or(url, |colon| &url[colon + 1..]);
+        let (rest, authority) = match rest.strip_prefix("//") {
+            Some(authority) => (&authority[authority.find(['/', '?']).unwrap_or(authority.len())..], true),
+            None => (rest, false),
+        };
+        let (path, query) = match rest.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (rest, None),
+        };
+        (if authority && path.is_empty() { "/" } else { path }, query)
+    }
+}
+
+/// A URL with the parts that rules are evaluated against worked out ahead of
+/// time, so that evaluating many rules, or several lists, against it does not
+/// work them out again for each rule. See `PreparedRequest`.
+#[derive(Clone, Debug)]
+pub struct PreparedUrl<'a> {
+    url: &'a str,
+    host: Option<&'a str>,
+    domain: Option<&'a str>,
+    registrable_domain: Option<&'a str>,
+    credentials: bool,
+    path: &'a str,
+    query: Option<&'a str>,
+}
+
+impl<'a> PreparedUrl<'a> {
+    /// Work out the parts of `url`.
+    pub fn new(url: &'a dyn RequestUrl) -> PreparedUrl<'a> {
+        let (path, query) = url.path_and_query();
+        PreparedUrl {
+            url: url.as_str(),
+            host: url.host(),
+            domain: url.domain(),
+            registrable_domain: url.registrable_domain(),
+            credentials: url.has_credentials(),
+            path,
+            query,
+        }
+    }
+
+    /// The path of the URL, or what follows the scheme of URLs without an
+    /// authority, such as `data:` URLs.
+    pub fn path(&self) -> &'a str {
+        self.path
+    }
+
+    /// The query of the URL, without the leading `?`.
+    pub fn query(&self) -> Option<&'a str> {
+        self.query
+    }
+}
+
+impl<'a> RequestUrl for PreparedUrl<'a> {
+    fn as_str(&self) -> &str {
+        self.url
+    }
+
+    fn path_and_query(&self) -> (&str, Option<&str>) {
+        (self.path, self.query)
+    }
+
+    fn host(&self) -> Option<&str> {
+        self.host
+    }
+
+    fn domain(&self) -> Option<&str> {
+        self.domain
+    }
+
+    fn has_credentials(&self) -> bool {
+        self.credentials
+    }
+
+    fn registrable_domain(&self) -> Option<&str> {
+        self.registrable_domain
+    }
+}
+
+/// The headers of a request, for triggers with header conditions. This is
+/// implemented for lists of name and value pairs, and can be implemented for
+/// the header maps of HTTP libraries.
+pub trait RequestHeaders {
+    /// The value of the header named `name`, given in lowercase, if the request
+    /// has it. For repeated headers, any one of their values.
+    fn get(&self, name: &str) -> Option<&str>;
+
+    /// The names and values of every header of the request.
+    fn entries(&self) -> Vec<(&str, &str)>;
+}
+
+impl<N: AsRef<str>, V: AsRef<str>> RequestHeaders for Vec<(N, V)> {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.iter().find(|(n, _)| n.as_ref().eq_ignore_ascii_case(name)).map(|(_, value)| value.as_ref())
+    }
+
+    fn entries(&self) -> Vec<(&str, &str)> {
+        self.iter().map(|(name, value)| (name.as_ref(), value.as_ref())).collect()
+    }
+}
+
+impl RequestUrl for Url {
+    fn as_str(&self) -> &str {
+        Url::as_str(self)
+    }
+
+    fn host(&self) -> Option<&str> {
+        self.host_str().map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+    }
+
+    fn domain(&self) -> Option<&str> {
+        Url::domain(self)
+    }
+
+    fn has_credentials(&self) -> bool {
+        !self.username().is_empty() || self.password().is_some()
+    }
+
+    fn path_and_query(&self) -> (&str, Option<&str>) {
+        (self.path(), self.query())
+    }
+}
+
+/// Split the authority of a serialized URL into its credentials and host.
+fn authority(url: &str) -> Option<(Option<&str>, &str)> {
+    let rest = &url[url.find("://")? + 3..];
+    let authority = &rest[..rest.find(['/', '?', '#']).unwrap_or(rest.len())];
+    match authority.rfind('@') {
+        Some(at) => Some((Some(&authority[..at]), &authority[at + 1..])),
+        None => Some((None, authority)),
+    }
+}
+
+impl RequestUrl for str {
+    fn as_str(&self) -> &str {
+        self
+    }
+
+    fn host(&self) -> Option<&str> {
+        let (_, host) = authority(self)?;
+        let host = match host.strip_prefix('[') {
+            Some(ipv6) => &ipv6[..ipv6.find(']')?],
+            None => &host[..host.find(':').unwrap_or(host.len())],
+        };
+        if host.is_empty() { None } else { Some(host) }
+    }
+
+    fn has_credentials(&self) -> bool {
+        authority(self).is_some_and(|(credentials, _)| credentials.is_some())
+    }
+}
+
+impl RequestUrl for String {
+    fn as_str(&self) -> &str {
+        self
+    }
+
+    fn host(&self) -> Option<&str> {
+        str::host(self)
+    }
+
+    fn has_credentials(&self) -> bool {
+        str::has_credentials(self)
+    }
+}
+
+impl<T: RequestUrl + ?Sized> RequestUrl for &T {
+    fn as_str(&self) -> &str {
+        (**self).as_str()
+    }
+
+    fn host(&self) -> Option<&str> {
+        (**self).host()
+    }
+
+    fn domain(&self) -> Option<&str> {
+        (**self).domain()
+    }
+
+    fn has_credentials(&self) -> bool {
+        (**self).has_credentials()
+    }
+
+    fn registrable_domain(&self) -> Option<&str> {
+        (**self).registrable_domain()
+    }
+
+    fn path_and_query(&self) -> (&str, Option<&str>) {
+        (**self).path_and_query()
+    }
+}
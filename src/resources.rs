@@ -0,0 +1,91 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Surrogate resources that redirect rules can substitute for blocked
+//! requests, so that pages waiting on them keep working.
+
+use base64;
+
+/// A surrogate resource, embedded as a data URI.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Resource {
+    /// The name rules use to refer to the resource, eg. `noop.js`.
+    pub name: &'static str,
+    /// The MIME type of the resource.
+    pub content_type: &'static str,
+    /// The content of the resource as a `data:` URI.
+    pub data_uri: &'static str,
+}
+
+static RESOURCES: &[Resource] = &[
+    Resource {
+        name: "noop.js",
+        content_type: "application/javascript",
+        data_uri: "data:application/javascript,",
+    },
+    Resource {
+        name: "noop.css",
+        content_type: "text/css",
+        data_uri: "data:text/css,",
+    },
+    Resource {
+        name: "noop.html",
+        content_type: "text/html",
+        data_uri: "data:text/html,",
+    },
+    Resource {
+        name: "noop.txt",
+        content_type: "text/plain",
+        data_uri: "data:text/plain,",
+    },
+    Resource {
+        name: "noop.json",
+        content_type: "application/json",
+        data_uri: "data:application/json,{}",
+    },
+    Resource {
+        name: "1x1.gif",
+        content_type: "image/gif",
+        data_uri: "data:image/gif;base64,R0lGODlhAQABAIAAAAAAAP///yH5BAEAAAAALAAAAAABAAEAAAIBRAA7",
+    },
+    Resource {
+        name: "1x1.png",
+        content_type: "image/png",
+        data_uri: "data:image/png;base64,\
+                   iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAAC0lEQVR42mNgAAIAAAUAAen63NgAAAAASUVORK5CYII=",
+    },
+    // Empty media fails to decode, which players handle like a missing resource.
+    Resource {
+        name: "noop.mp3",
+        content_type: "audio/mpeg",
+        data_uri: "data:audio/mpeg,",
+    },
+    Resource {
+        name: "noop.mp4",
+        content_type: "video/mp4",
+        data_uri: "data:video/mp4,",
+    },
+];
+
+impl Resource {
+    /// Look up a bundled resource by name.
+    pub fn named(name: &str) -> Option<&'static Resource> {
+        RESOURCES.iter().find(|resource| resource.name == name)
+    }
+
+    /// All bundled resources.
+    pub fn all() -> &'static [Resource] {
+        RESOURCES
+    }
+
+    /// The decoded content of the resource, eg. to serve it directly.
+    pub fn content(&self) -> Vec<u8> {
+        let (header, data) = self.data_uri.split_once(',').expect("bundled resources are data URIs");
+        if header.ends_with(";base64") {
+            base64::decode(data).expect("bundled resources are valid base64")
+        } else {
+            data.as_bytes().to_vec()
+        }
+    }
+}
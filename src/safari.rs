@@ -0,0 +1,203 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use dns::may_unblock;
+use index::trigger_host_anchor;
+use optimize::optimize_impl;
+use parse::Error;
+use reachability::may_overlap;
+use repr::{Action, CookieScope, DomainConstraint, DomainTarget, LoadType, LoadTypeList, ResourceType, ResourceTypeList};
+use repr::{Rule, Trigger, UrlFilterAnchor};
+use serde_json::Value;
+
+/// The most rules Safari accepts in the list of a single content blocker.
+pub const SAFARI_RULE_LIMIT: usize = 150_000;
+
+/// Why a rule was left out of a Safari export.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SafariSkipReason {
+    /// Safari has no equivalent of the action, such as a redirect, a procedural
+    /// selector or an exception for a single selector.
+    Action,
+    /// The trigger depends on something Safari does not examine, such as the
+    /// headers, the context or the site of the request, or the path or query of
+    /// its URL on their own, or compares its domain constraint with the
+    /// requested host rather than the top-level document, or names domains
+    /// with any top-level domain, or only resource types Safari does not know.
+    Conditions,
+    /// A later `ignore-previous-rules` rule that was left out may cancel the
+    /// rule, so exporting it could apply it where the list does not.
+    Exception,
+}
+
+/// A rule that a Safari content blocker cannot represent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SafariSkip {
+    /// The position of the rule in its list.
+    pub rule: usize,
+    /// Why the rule was left out.
+    pub reason: SafariSkipReason,
+}
+
+/// A list exported as Safari content blockers by `RuleList::to_safari`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SafariExport {
+    /// The JSON lists of the content blockers, to be loaded together, each with
+    /// no more rules than the limit.
+    pub files: Vec<String>,
+    /// The number of rules in each file.
+    pub rules_per_file: Vec<usize>,
+    /// The number of `css-display-none` rules folded into the selector list of
+    /// another rule with an identical trigger.
+    pub selectors_merged: usize,
+    /// The rules that were left out, in list order.
+    pub skipped: Vec<SafariSkip>,
+}
+
+/// Whether Safari knows the resource type `resource_type`.
+fn safari_supports(resource_type: ResourceType) -> bool {
+    match resource_type {
+        ResourceType::Document | ResourceType::Image | ResourceType::StyleSheet | ResourceType::Script |
+        ResourceType::Font | ResourceType::Raw | ResourceType::SVGDocument | ResourceType::Media |
+        ResourceType::Popup | ResourceType::WebSocket => true,
+        ResourceType::Worker | ResourceType::SharedWorker | ResourceType::ServiceWorker |
+        ResourceType::CspReport | ResourceType::Beacon | ResourceType::ObjectSubrequest => false,
+    }
+}
+
+/// Why `rule` cannot be exported to Safari, if it cannot.
+fn unsupported(rule: &Rule) -> Option<SafariSkipReason> {
+    match rule.action {
+        Action::Block | Action::BlockCookies(CookieScope::Request) |
+        Action::CssDisplayNone(_) | Action::IgnorePreviousRules => {}
+        Action::BlockCookies(_) | Action::BlockCookiesMatching(_) | Action::Redirect(_) |
+        Action::CssDisplayNoneProcedural(_) | Action::CssDisplayNoneException(_) |
+        Action::CssInject { .. } | Action::HtmlFilter(_) | Action::BlockInlineScripts => {
+            return Some(SafariSkipReason::Action)
+        }
+    }
+    let trigger = &rule.trigger;
+    let site_relation = match trigger.load_type {
+        LoadTypeList::All => false,
+        LoadTypeList::List(ref types) => types.iter().any(|t| matches!(*t, LoadType::SameSite | LoadType::CrossSite)),
+    };
+    let unknown_types = match trigger.resource_type {
+        ResourceTypeList::All => false,
+        ResourceTypeList::List(ref types) => !types.iter().any(|&t| safari_supports(t)),
+    };
+    let wildcard_tld = match trigger.domain_constraint {
+        Some(DomainConstraint::If(ref matcher)) | Some(DomainConstraint::Unless(ref matcher)) => matcher.wildcard_tld,
+        None => false,
+    };
+    if site_relation || unknown_types || wildcard_tld ||
+       trigger.contexts.is_some() || trigger.user_initiated.is_some() ||
+       trigger.opener_domain.is_some() || trigger.match_redirect_chain ||
+       !trigger.if_header.is_empty() || !trigger.unless_header.is_empty() ||
+       trigger.path_filter.is_some() || trigger.query_filter.is_some() ||
+       trigger.domain_constraint.is_some() && trigger.domain_target != DomainTarget::TopDocument {
+        return Some(SafariSkipReason::Conditions);
+    }
+    None
+}
+
+/// Whether the `ignore-previous-rules` trigger `exception` may cancel a rule
+/// with the trigger `trigger`.
+fn may_cancel(trigger: &Trigger, exception: &Trigger) -> bool {
+    match trigger_host_anchor(trigger) {
+        Some((host, subdomains)) => may_unblock(trigger, &host, subdomains, exception),
+        None => may_overlap(trigger, exception),
+    }
+}
+
+/// The JSON form of `rule` for Safari, which rejects the keys and resource types
+/// it does not know. Host-anchored URL filters are written as the equivalent
+/// regular expression.
+fn rule_to_json(rule: &Rule) -> Value {
+    let mut json = rule.to_json();
+    if let Value::Object(ref mut object) = json {
+        object.remove("tags");
+        if let Some(&mut Value::Object(ref mut trigger)) = object.get_mut("trigger") {
+            if rule.trigger.url_filter_anchor == UrlFilterAnchor::Host {
+                let host = rule.trigger.url_filter.as_str();
+                let end = if host.ends_with('.') { "" } else { "[:/]" };
+                let filter = format!("^[^:]+://+([^:/]+\\.)?{}{}", host.replace('.', "\\."), end);
+                trigger.remove("url-filter-anchor");
+                trigger.insert("url-filter".to_owned(), Value::from(filter));
+            }
+            if let ResourceTypeList::List(ref types) = rule.trigger.resource_type {
+                let types = types.iter().filter(|&&t| safari_supports(t)).map(|t| Value::from(t.name())).collect();
+                trigger.insert("resource-type".to_owned(), types);
+            }
+        }
+        if let Some(&mut Value::Object(ref mut action)) = object.get_mut("action") {
+            action.remove("scope");
+        }
+    }
+    json
+}
+
+/// Export the rules Safari supports, combining element hiding rules with
+/// identical triggers and splitting the result into files of at most
+/// `max_rules` rules. Safari evaluates each file separately, so every
+/// `ignore-previous-rules` rule is also appended to the files before its own,
+/// where it cancels the rules it followed in the list. The rules that an
+/// exception left out may cancel are left out too. Fails with
+/// `Error::TooManyRules` if those exceptions leave no room for other rules.
+pub fn to_safari_impl(rules: &[Rule], max_rules: usize) -> Result<SafariExport, Error> {
+    let mut skipped = vec![];
+    let mut supported: Vec<(usize, &Rule)> = vec![];
+    for (position, rule) in rules.iter().enumerate() {
+        match unsupported(rule) {
+            Some(reason) => {
+                skipped.push(SafariSkip { rule: position, reason });
+                if rule.action == Action::IgnorePreviousRules {
+                    supported.retain(|&(earlier, earlier_rule)| {
+                        let cancelled = earlier_rule.action != Action::IgnorePreviousRules &&
+                                        may_cancel(&earlier_rule.trigger, &rule.trigger);
+                        if cancelled {
+                            skipped.push(SafariSkip { rule: earlier, reason: SafariSkipReason::Exception });
+                        }
+                        !cancelled
+                    });
+                }
+            }
+            None => supported.push((position, rule)),
+        }
+    }
+    skipped.sort_by_key(|skip| skip.rule);
+    let (rules, report) = optimize_impl(supported.into_iter().map(|(_, rule)| rule.clone()).collect());
+
+    // The number of exceptions from each position to the end of the list.
+    let mut exceptions_after = vec![0; rules.len() + 1];
+    for (position, rule) in rules.iter().enumerate().rev() {
+        exceptions_after[position] = exceptions_after[position + 1] +
+                                     (rule.action == Action::IgnorePreviousRules) as usize;
+    }
+
+    let mut export = SafariExport {
+        files: vec![],
+        rules_per_file: vec![],
+        selectors_merged: report.selectors_merged,
+        skipped,
+    };
+    let mut start = 0;
+    while start < rules.len() || export.files.is_empty() {
+        // Each rule added to a file either takes a new place, or is an exception
+        // that no longer needs to be copied, so the size never shrinks.
+        let mut end = start;
+        while end < rules.len() && end + 1 - start + exceptions_after[end + 1] <= max_rules {
+            end += 1;
+        }
+        if end == start && start < rules.len() {
+            return Err(Error::TooManyRules { limit: max_rules });
+        }
+        let copies = rules[end..].iter().filter(|rule| rule.action == Action::IgnorePreviousRules);
+        let file: Vec<Value> = rules[start..end].iter().chain(copies).map(rule_to_json).collect();
+        export.rules_per_file.push(file.len());
+        export.files.push(Value::Array(file).to_string());
+        start = end;
+    }
+    Ok(export)
+}
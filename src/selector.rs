@@ -0,0 +1,161 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Validation of the selectors of cosmetic rules. Embedders usually combine
+//! these selectors into a single stylesheet, where one invalid selector makes
+//! the browser drop the whole style rule it belongs to.
+
+/// Whether `selector` can be placed in a stylesheet. Without the
+/// `selector-validation` feature only selectors that would escape their
+/// style rule are rejected; with it, the selector must also be accepted by
+/// the selector parser of Servo.
+pub fn is_valid_selector(selector: &str) -> bool {
+    !selector.trim().is_empty() && !selector.contains(['{', '}']) && !selector.contains("</") &&
+        parses(selector)
+}
+
+#[cfg(not(feature = "selector-validation"))]
+fn parses(_selector: &str) -> bool {
+    true
+}
+
+#[cfg(feature = "selector-validation")]
+fn parses(selector: &str) -> bool {
+    use cssparser::{Parser as CssParser, ParserInput};
+    use selectors::parser::{ParseRelative, SelectorList};
+
+    let mut input = ParserInput::new(selector);
+    let mut input = CssParser::new(&mut input);
+    SelectorList::parse(&validation::Validator, &mut input, ParseRelative::No).is_ok()
+}
+
+#[cfg(feature = "selector-validation")]
+mod validation {
+    use cssparser::{CowRcStr, Parser as CssParser, SourceLocation, ToCss, serialize_identifier};
+    use selectors::parser::{self, SelectorImpl, SelectorParseErrorKind};
+    use std::fmt;
+
+    /// Non-tree-structural pseudo-classes that browsers support. Tree-structural
+    /// ones such as `:first-child` and `:not()` are handled by the parser itself.
+    const PSEUDO_CLASSES: &[&str] = &[
+        "active", "any-link", "autofill", "checked", "default", "defined", "disabled", "empty",
+        "enabled", "focus", "focus-visible", "focus-within", "fullscreen", "hover", "in-range",
+        "indeterminate", "invalid", "link", "modal", "optional", "out-of-range", "placeholder-shown",
+        "read-only", "read-write", "required", "target", "valid", "visited",
+    ];
+
+    const FUNCTIONAL_PSEUDO_CLASSES: &[&str] = &["dir", "lang"];
+
+    const PSEUDO_ELEMENTS: &[&str] = &[
+        "after", "backdrop", "before", "file-selector-button", "first-letter", "first-line",
+        "marker", "placeholder", "selection",
+    ];
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Impl;
+
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct Ident(String);
+
+    impl<'a> From<&'a str> for Ident {
+        fn from(s: &'a str) -> Ident {
+            Ident(s.to_owned())
+        }
+    }
+
+    impl ToCss for Ident {
+        fn to_css<W: fmt::Write>(&self, dest: &mut W) -> fmt::Result {
+            serialize_identifier(&self.0, dest)
+        }
+    }
+
+    /// A pseudo-class or pseudo-element, kept with its leading colons.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Pseudo(String);
+
+    impl ToCss for Pseudo {
+        fn to_css<W: fmt::Write>(&self, dest: &mut W) -> fmt::Result {
+            dest.write_str(&self.0)
+        }
+    }
+
+    impl parser::NonTSPseudoClass for Pseudo {
+        type Impl = Impl;
+
+        fn is_active_or_hover(&self) -> bool {
+            self.0 == ":active" || self.0 == ":hover"
+        }
+
+        fn is_user_action_state(&self) -> bool {
+            self.is_active_or_hover() || self.0 == ":focus"
+        }
+    }
+
+    impl parser::PseudoElement for Pseudo {
+        type Impl = Impl;
+    }
+
+    impl SelectorImpl for Impl {
+        type ExtraMatchingData<'a> = ();
+        type AttrValue = Ident;
+        type Identifier = Ident;
+        type LocalName = Ident;
+        type NamespaceUrl = Ident;
+        type NamespacePrefix = Ident;
+        type BorrowedNamespaceUrl = Ident;
+        type BorrowedLocalName = Ident;
+        type NonTSPseudoClass = Pseudo;
+        type PseudoElement = Pseudo;
+    }
+
+    fn known<'i>(names: &[&str], prefix: &str, name: CowRcStr<'i>, location: SourceLocation)
+                 -> Result<Pseudo, cssparser::ParseError<'i, SelectorParseErrorKind<'i>>> {
+        if names.iter().any(|known| known.eq_ignore_ascii_case(&name)) {
+            Ok(Pseudo(format!("{}{}", prefix, name.to_ascii_lowercase())))
+        } else {
+            Err(location.new_custom_error(SelectorParseErrorKind::UnsupportedPseudoClassOrElement(name)))
+        }
+    }
+
+    pub struct Validator;
+
+    impl<'i> parser::Parser<'i> for Validator {
+        type Impl = Impl;
+        type Error = SelectorParseErrorKind<'i>;
+
+        fn parse_nth_child_of(&self) -> bool {
+            true
+        }
+
+        fn parse_is_and_where(&self) -> bool {
+            true
+        }
+
+        fn parse_has(&self) -> bool {
+            true
+        }
+
+        fn allow_forgiving_selectors(&self) -> bool {
+            false
+        }
+
+        fn parse_non_ts_pseudo_class(&self, location: SourceLocation, name: CowRcStr<'i>)
+                                     -> Result<Pseudo, cssparser::ParseError<'i, Self::Error>> {
+            known(PSEUDO_CLASSES, ":", name, location)
+        }
+
+        fn parse_non_ts_functional_pseudo_class<'t>(&self, name: CowRcStr<'i>, arguments: &mut CssParser<'i, 't>)
+                                                    -> Result<Pseudo, cssparser::ParseError<'i, Self::Error>> {
+            let location = arguments.current_source_location();
+            let pseudo = known(FUNCTIONAL_PSEUDO_CLASSES, ":", name, location)?;
+            arguments.expect_ident_or_string()?;
+            Ok(pseudo)
+        }
+
+        fn parse_pseudo_element(&self, location: SourceLocation, name: CowRcStr<'i>)
+                                -> Result<Pseudo, cssparser::ParseError<'i, Self::Error>> {
+            known(PSEUDO_ELEMENTS, "::", name, location)
+        }
+    }
+}
@@ -0,0 +1,151 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! An adapter matching the hook Servo's fetch pipeline calls for every request.
+//!
+//! The `net` crate describes each fetch with its URL, destination and the
+//! document that initiated it, and needs a single answer telling it whether to
+//! proceed, plus what to do with cookies and the originating document. The
+//! `ContentBlockerFilter` trait takes a `FetchMetadata` and returns that answer
+//! as a `FilterDecision`, and is implemented for the matching types of this crate.
+
+use compiled::{CompiledRuleList, process_rules_for_request_compiled_impl};
+use engine::Engine;
+use repr::{LoadType, Reaction, Request, ResourceType};
+use request_url::RequestUrl;
+use resources::Resource;
+use url::Url;
+use {RuleList, process_rules_for_request};
+
+/// The description of a fetch that content blocking decisions are based on.
+#[derive(Copy, Clone, Debug)]
+pub struct FetchMetadata<'a> {
+    /// The URL being fetched.
+    pub url: &'a Url,
+    /// The kind of resource the fetch is for, derived from its destination.
+    pub resource_type: ResourceType,
+    /// The URL of the document initiating the fetch, or `None` for top-level
+    /// navigations.
+    pub document_url: Option<&'a Url>,
+    /// The browsing context making the fetch, eg. `private`.
+    pub context: Option<&'a str>,
+    /// The URLs the fetch was redirected from, oldest first: the fetch's URL
+    /// list without its last entry.
+    pub redirect_chain: &'a [Url],
+}
+
+impl<'a> FetchMetadata<'a> {
+    /// Whether the fetch is same-origin with the initiating document. Top-level
+    /// navigations are first-party.
+    pub fn load_type(&self) -> LoadType {
+        match self.document_url {
+            Some(document) if document.origin() != self.url.origin() => LoadType::ThirdParty,
+            _ => LoadType::FirstParty,
+        }
+    }
+
+    /// The request that rules are evaluated against for this fetch.
+    pub fn to_request(&self) -> Request<'a> {
+        Request {
+            url: self.url,
+            resource_type: self.resource_type,
+            load_type: self.load_type(),
+            context: self.context,
+            opener: None,
+            document: self.document_url.map(|url| url as &dyn RequestUrl),
+            redirect_chain: self.redirect_chain,
+            headers: None,
+        }
+    }
+}
+
+/// What the fetch pipeline must do with the request itself.
+#[derive(Debug, Default, PartialEq)]
+pub enum RequestDecision {
+    /// Fetch the resource as usual.
+    #[default]
+    Proceed,
+    /// Fail the fetch with a network error.
+    Block,
+    /// Answer the fetch with a bundled surrogate resource without touching the network.
+    Redirect(&'static Resource),
+}
+
+/// Everything the fetch pipeline must do for a single fetch.
+#[derive(Debug, Default, PartialEq)]
+pub struct FilterDecision {
+    /// What to do with the request. A redirection takes precedence over blocking,
+    /// since it is how lists keep pages working that expect the resource.
+    pub request: RequestDecision,
+    /// Whether to omit all cookies from the request.
+    pub strip_request_cookies: bool,
+    /// Whether to ignore the cookies set by the response.
+    pub ignore_response_cookies: bool,
+    /// Patterns of names of cookies to omit from the request and ignore in the
+    /// response, in which `*` matches any sequence of characters.
+    pub blocked_cookie_names: Vec<String>,
+    /// Whether to prevent the inline scripts of the fetched document from
+    /// running, eg. by adding a `script-src` Content Security Policy.
+    pub block_inline_scripts: bool,
+    /// The reactions affecting the initiating document, such as hidden elements.
+    pub document: Vec<Reaction>,
+}
+
+impl FilterDecision {
+    /// Sort the reactions to a fetch into a decision.
+    pub fn from_reactions(reactions: Vec<Reaction>) -> FilterDecision {
+        let mut decision = FilterDecision::default();
+        for reaction in reactions {
+            match reaction {
+                Reaction::Block => {
+                    if decision.request == RequestDecision::Proceed {
+                        decision.request = RequestDecision::Block;
+                    }
+                }
+                Reaction::Redirect(resource) => {
+                    if !matches!(decision.request, RequestDecision::Redirect(_)) {
+                        decision.request = RequestDecision::Redirect(resource);
+                    }
+                }
+                Reaction::BlockCookies(scope) => {
+                    decision.strip_request_cookies |= scope.applies_to_request();
+                    decision.ignore_response_cookies |= scope.applies_to_response();
+                }
+                Reaction::BlockCookiesMatching(names) => decision.blocked_cookie_names.extend(names),
+                Reaction::BlockInlineScripts => decision.block_inline_scripts = true,
+                reaction => decision.document.push(reaction),
+            }
+        }
+        decision
+    }
+
+    /// Whether the fetch must not reach the network.
+    pub fn prevents_fetch(&self) -> bool {
+        self.request != RequestDecision::Proceed
+    }
+}
+
+/// The hook invoked by the fetch pipeline before each fetch.
+pub trait ContentBlockerFilter {
+    /// Decide what to do with the fetch described by `metadata`.
+    fn filter(&self, metadata: &FetchMetadata) -> FilterDecision;
+}
+
+impl ContentBlockerFilter for RuleList {
+    fn filter(&self, metadata: &FetchMetadata) -> FilterDecision {
+        FilterDecision::from_reactions(process_rules_for_request(self, &metadata.to_request()))
+    }
+}
+
+impl<'a> ContentBlockerFilter for CompiledRuleList<'a> {
+    fn filter(&self, metadata: &FetchMetadata) -> FilterDecision {
+        FilterDecision::from_reactions(process_rules_for_request_compiled_impl(self, &metadata.to_request()))
+    }
+}
+
+impl ContentBlockerFilter for Engine {
+    fn filter(&self, metadata: &FetchMetadata) -> FilterDecision {
+        FilterDecision::from_reactions(self.process(&metadata.to_request()))
+    }
+}
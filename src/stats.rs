@@ -0,0 +1,57 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use backend::MatchBackend;
+use lint::plain_literal;
+use repr::Rule;
+use std::time::Duration;
+
+/// A summary of how a list was parsed and prepared for matching, eg. to show
+/// the health of each list on an about:blocking page.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompileStats {
+    /// The number of rules in the list.
+    pub rules_accepted: usize,
+    /// The number of rules ignored while parsing the list, because they were
+    /// invalid or unsupported.
+    pub rules_skipped: usize,
+    /// The positions in the list of the rules dropped because it had more than
+    /// `ParseOptions::max_rules`. In a merged list, the positions are those in
+    /// the list each rule was dropped from.
+    pub dropped_rules: Vec<usize>,
+    /// The number of rules whose URL filters are plain strings.
+    pub literal_rules: usize,
+    /// The number of rules whose URL filters need a regular expression.
+    pub regex_rules: usize,
+    /// The number of rules the match backend finds without evaluating a regular
+    /// expression, which depends on the backend.
+    pub fast_path_rules: usize,
+    /// An estimate of the memory used by the match backend, in bytes.
+    pub index_size: usize,
+    /// How long building the match backend and other indexes took.
+    pub duration: Duration,
+}
+
+impl CompileStats {
+    /// Keep what `previous` says about parsing the list, after the list was
+    /// prepared for matching again.
+    pub(crate) fn carry_over(&mut self, previous: CompileStats) {
+        self.rules_skipped = previous.rules_skipped;
+        self.dropped_rules = previous.dropped_rules;
+    }
+}
+
+pub fn compile_stats_impl(rules: &[Rule], backend: &dyn MatchBackend, duration: Duration) -> CompileStats {
+    let literal_rules = rules.iter().filter(|rule| plain_literal(rule.trigger.url_filter.as_str()).is_some()).count();
+    CompileStats {
+        rules_accepted: rules.len(),
+        rules_skipped: 0,
+        dropped_rules: vec![],
+        literal_rules,
+        regex_rules: rules.len() - literal_rules,
+        fast_path_rules: backend.fast_path_rules(),
+        index_size: backend.heap_size(),
+        duration,
+    }
+}
@@ -0,0 +1,187 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Parsing of lists read from a stream, one rule at a time, so that the whole
+//! body of a large list never needs to be held in memory at once. With the
+//! `gzip` feature, bodies compressed with gzip or zlib are recognized by their
+//! first byte, which cannot start a JSON document, and decompressed as they are read.
+
+#[cfg(feature = "gzip")]
+use flate2::bufread::{MultiGzDecoder, ZlibDecoder};
+use parse::{Error, ParseOptions, ParsedList, parse_rules};
+use repr::SourceLocation;
+use serde_json::{self, Value};
+use std::io::{BufRead, BufReader, Read};
+
+/// The elements of a JSON array read from a stream. Each element is delimited
+/// by scanning its bytes, and only then parsed.
+struct Elements<R> {
+    reader: R,
+    /// The bytes of the element being read.
+    element: Vec<u8>,
+    /// Where the element being read starts.
+    location: SourceLocation,
+    /// The number of bytes consumed so far, and the line they end on.
+    offset: usize,
+    line: usize,
+    started: bool,
+    done: bool,
+}
+
+impl<R: BufRead> Elements<R> {
+    fn peek(&mut self) -> Result<Option<u8>, Error> {
+        let buffer = self.reader.fill_buf().map_err(|e| Error::Io(e.kind()))?;
+        Ok(buffer.first().cloned())
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>, Error> {
+        let byte = self.peek()?;
+        if let Some(byte) = byte {
+            self.reader.consume(1);
+            self.offset += 1;
+            if byte == b'\n' {
+                self.line += 1;
+            }
+        }
+        Ok(byte)
+    }
+
+    /// The next byte that is not whitespace.
+    fn next_token(&mut self) -> Result<Option<u8>, Error> {
+        loop {
+            match self.next_byte()? {
+                Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') => {}
+                byte => return Ok(byte),
+            }
+        }
+    }
+
+    /// Consume the opening bracket of the array.
+    fn start(&mut self) -> Result<(), Error> {
+        match self.next_token()? {
+            Some(b'[') => Ok(()),
+            Some(byte) => {
+                // Tell a valid document that is not a list from an invalid one.
+                let mut rest = vec![byte];
+                self.reader.read_to_end(&mut rest).map_err(|e| Error::Io(e.kind()))?;
+                match serde_json::from_slice::<Value>(&rest) {
+                    Ok(_) => Err(Error::NotAList),
+                    Err(_) => Err(Error::JSON),
+                }
+            }
+            None => Err(Error::JSON),
+        }
+    }
+
+    /// Read the bytes of the next element into `self.element`, and consume the
+    /// separator following it. Returns `false` at the end of the array.
+    fn read_element(&mut self) -> Result<bool, Error> {
+        self.element.clear();
+        let first = match self.next_token()? {
+            Some(b']') if !self.started => return self.finish().map(|()| false),
+            Some(byte) => byte,
+            None => return Err(Error::JSON),
+        };
+        self.location = SourceLocation {
+            index: if self.started { self.location.index + 1 } else { 0 },
+            offset: self.offset - 1,
+            line: self.line,
+        };
+        self.started = true;
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut byte = first;
+        loop {
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if byte == b'\\' {
+                    escaped = true;
+                } else if byte == b'"' {
+                    in_string = false;
+                }
+            } else {
+                match byte {
+                    b'"' => in_string = true,
+                    b'[' | b'{' => depth += 1,
+                    b']' | b'}' if depth > 0 => depth -= 1,
+                    b',' if depth == 0 => return Ok(true),
+                    b']' => return self.finish().map(|()| true),
+                    _ => {}
+                }
+            }
+            self.element.push(byte);
+            byte = self.next_byte()?.ok_or(Error::JSON)?;
+        }
+    }
+
+    /// Check that nothing but whitespace follows the array.
+    fn finish(&mut self) -> Result<(), Error> {
+        self.done = true;
+        match self.next_token()? {
+            None => Ok(()),
+            Some(_) => Err(Error::JSON),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Elements<R> {
+    type Item = Result<(Value, SourceLocation), Error>;
+
+    fn next(&mut self) -> Option<Result<(Value, SourceLocation), Error>> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_element().and_then(|more| {
+            if !more {
+                return Ok(None);
+            }
+            let value = serde_json::from_slice(&self.element).map_err(|_| Error::JSON)?;
+            Ok(Some((value, self.location)))
+        });
+        match result {
+            Ok(Some(value)) => Some(Ok(value)),
+            Ok(None) => None,
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+pub fn parse_uncompressed<R: BufRead>(reader: R, options: &ParseOptions) -> Result<ParsedList, Error> {
+    parse_array_at(reader, 0, 1, options)
+}
+
+/// Parse the list read from `reader`, which is the part of a larger body that
+/// starts at `offset`, on line `line`, so that rules are located in that body.
+pub fn parse_array_at<R: BufRead>(reader: R, offset: usize, line: usize, options: &ParseOptions)
+                                  -> Result<ParsedList, Error> {
+    let mut elements = Elements {
+        reader,
+        element: vec![],
+        location: SourceLocation { index: 0, offset, line },
+        offset,
+        line,
+        started: false,
+        done: false,
+    };
+    elements.start()?;
+    parse_rules(elements, options)
+}
+
+/// Parse a list read from `reader`, which may be compressed.
+pub fn parse_list_from_reader_impl<R: Read>(reader: R, options: &ParseOptions) -> Result<ParsedList, Error> {
+    let mut reader = BufReader::new(reader);
+    let first = reader.fill_buf().map_err(|e| Error::Io(e.kind()))?.first().cloned();
+    match first {
+        #[cfg(feature = "gzip")]
+        Some(0x1f) => parse_uncompressed(BufReader::new(MultiGzDecoder::new(reader)), options),
+        #[cfg(feature = "gzip")]
+        Some(b'x') => parse_uncompressed(BufReader::new(ZlibDecoder::new(reader)), options),
+        _ => parse_uncompressed(reader, options),
+    }
+}
@@ -0,0 +1,161 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Bookkeeping for lists that are periodically fetched from remote sources.
+//!
+//! A `SubscriptionManager` remembers where each list came from, when it was last
+//! fetched and how long it remains valid, and reports which lists are due for a
+//! refresh. Fetching is left to the embedder through the `Fetcher` trait.
+
+use {Error, ListMetadata, RuleList, parse_list_with_metadata};
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// A source of list bodies, typically backed by the embedder's network stack.
+pub trait Fetcher {
+    /// The error reported when a list could not be fetched.
+    type Error;
+
+    /// Retrieve the body of the list located at `url`.
+    fn fetch(&mut self, url: &Url) -> Result<String, Self::Error>;
+}
+
+/// The reasons a subscription could not be refreshed.
+#[derive(Debug, PartialEq)]
+pub enum RefreshError<E> {
+    /// The fetcher failed to retrieve the list.
+    Fetch(E),
+    /// The retrieved list could not be parsed.
+    Parse(Error),
+}
+
+/// A single list tracked by a `SubscriptionManager`.
+pub struct Subscription {
+    url: Url,
+    rules: Option<RuleList>,
+    metadata: ListMetadata,
+    last_fetched: Option<SystemTime>,
+}
+
+impl Subscription {
+    /// The location the list is fetched from.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// The most recently fetched rules, if the list has been fetched successfully.
+    pub fn rules(&self) -> Option<&RuleList> {
+        self.rules.as_ref()
+    }
+
+    /// The metadata of the most recently fetched list.
+    pub fn metadata(&self) -> &ListMetadata {
+        &self.metadata
+    }
+
+    /// When the list was last fetched successfully.
+    pub fn last_fetched(&self) -> Option<SystemTime> {
+        self.last_fetched
+    }
+}
+
+/// Tracks a set of subscribed lists and their expiry.
+pub struct SubscriptionManager {
+    subscriptions: Vec<Subscription>,
+    default_expiry: Duration,
+}
+
+impl SubscriptionManager {
+    /// Create a manager with no subscriptions. Lists that do not specify how long
+    /// they remain valid expire after `default_expiry`.
+    pub fn new(default_expiry: Duration) -> SubscriptionManager {
+        SubscriptionManager {
+            subscriptions: vec![],
+            default_expiry,
+        }
+    }
+
+    /// Subscribe to the list at `url`. The new subscription is immediately due for
+    /// a refresh. Returns false if the list was already subscribed to.
+    pub fn add(&mut self, url: Url) -> bool {
+        if self.get(&url).is_some() {
+            return false;
+        }
+        self.subscriptions.push(Subscription {
+            url,
+            rules: None,
+            metadata: ListMetadata::default(),
+            last_fetched: None,
+        });
+        true
+    }
+
+    /// Stop tracking the list at `url`, returning its subscription if there was one.
+    pub fn remove(&mut self, url: &Url) -> Option<Subscription> {
+        let index = self.subscriptions.iter().position(|s| s.url == *url)?;
+        Some(self.subscriptions.remove(index))
+    }
+
+    /// Look up the subscription for the list at `url`.
+    pub fn get(&self, url: &Url) -> Option<&Subscription> {
+        self.subscriptions.iter().find(|s| s.url == *url)
+    }
+
+    /// Iterate over all subscriptions in the order they were added.
+    pub fn iter(&self) -> ::std::slice::Iter<'_, Subscription> {
+        self.subscriptions.iter()
+    }
+
+    /// When the given subscription should next be refreshed.
+    fn refresh_time(&self, subscription: &Subscription) -> Option<SystemTime> {
+        let expiry = subscription.metadata.expires.unwrap_or(self.default_expiry);
+        subscription.last_fetched.map(|fetched| fetched + expiry)
+    }
+
+    /// The lists which have never been fetched or have expired as of `now`.
+    pub fn due(&self, now: SystemTime) -> Vec<&Url> {
+        self.subscriptions.iter().filter(|s| {
+            self.refresh_time(s).is_none_or(|time| time <= now)
+        }).map(|s| &s.url).collect()
+    }
+
+    /// The earliest time at which any list will need to be refreshed, or `None`
+    /// if there are no subscriptions. Lists that have never been fetched are due
+    /// at `UNIX_EPOCH`.
+    pub fn next_refresh(&self) -> Option<SystemTime> {
+        self.subscriptions.iter().map(|s| {
+            self.refresh_time(s).unwrap_or(SystemTime::UNIX_EPOCH)
+        }).min()
+    }
+
+    /// Record a freshly fetched body for the list at `url`. The previous rules are
+    /// kept if the new body cannot be parsed.
+    pub fn update(&mut self, url: &Url, body: &str, now: SystemTime) -> Result<(), Error> {
+        let (rules, metadata) = parse_list_with_metadata(body)?;
+        if let Some(subscription) = self.subscriptions.iter_mut().find(|s| s.url == *url) {
+            subscription.rules = Some(rules);
+            subscription.metadata = metadata;
+            subscription.last_fetched = Some(now);
+        }
+        Ok(())
+    }
+
+    /// Fetch and update every list that is due as of `now`. Lists that fail to
+    /// refresh keep their previous rules and remain due; their errors are returned.
+    pub fn refresh_due<F: Fetcher>(&mut self, fetcher: &mut F, now: SystemTime)
+                                   -> Vec<(Url, RefreshError<F::Error>)> {
+        let due: Vec<Url> = self.due(now).into_iter().cloned().collect();
+        let mut errors = vec![];
+        for url in due {
+            let result = fetcher.fetch(&url)
+                                .map_err(RefreshError::Fetch)
+                                .and_then(|body| self.update(&url, &body, now)
+                                                     .map_err(RefreshError::Parse));
+            if let Err(e) = result {
+                errors.push((url, e));
+            }
+        }
+        errors
+    }
+}
@@ -2,20 +2,68 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use parse::{Error, parse_list_impl};
-use regex::Regex;
-use repr::{Action, DomainConstraint, DomainMatcher, LoadType, Reaction};
-use repr::{Request, ResourceType, ResourceTypeList, Rule};
-use repr::{Trigger, process_rules_for_request_impl};
+use {RuleList, parse_entity_list, parse_list, process_rules_for_request, process_rules_for_request_borrowed};
+use {Tracker, parse_list_from_reader, parse_list_from_slice, precompile_list, process_rules_for_request_categorized};
+use {Allowlist, CompiledBackend, DnsFormat, DnsSkip, DnsSkipReason, Engine, LintFinding, LintKind, MergePolicy};
+use {RegexCost, Severity, lint_list};
+use {Backend, PacSkip, PacSkipReason, RuleCoverage, RuleListBackend, record_golden_vectors, registrable_domain};
+use {process_rules_for_request_shadowed, process_rules_for_request_with_deadline, process_rules_for_request_with_policy};
+use {Destination, MatchBackendKind, SourceLocation, diff, process_rules_for_request_with_provenance, run_conformance_vectors};
+use {CompileStats, ListCache, MatchArena, RuleLimitPolicy, parse_list_strict, parse_list_with_options};
+use {parse_list_from_reader_with_options, parse_list_from_slice_with_options, parse_list_with_metadata_and_options};
+use {HidingHints, HtmlFilter, SAFARI_RULE_LIMIT, SafariSkip, SafariSkipReason, ValidationOptions};
+use {TrustLevel, UNTRUSTED_PATTERN_LIMIT, UrlFilterAnchor};
+use cosmetic_rules_for_frame;
+use process_rules_for_request_with_hiding_hints;
+use process_rules_for_request_in;
+use process_rules_for_request_profiled;
+use create_delta;
+use compiled::{CompiledRuleList, compile_impl, process_rules_for_request_compiled_impl};
+use delta::{apply_delta_impl, create_delta_impl};
+use diff::{RuleListDelta, diff_impl};
+use index::{DomainIndex, MatchLimits, host_anchor, process_rules_for_request_indexed_impl};
+use lint::regex_cost;
+use metadata::{ListMetadata, parse_list_with_metadata_impl};
+use optimize::{OptimizationReport, optimize_impl};
+use parse::{Error, ParseOptions, parse_list_impl, parse_list_strict_impl, parse_list_with_options_impl};
+use prefilter::{GramSet, LiteralFilter, required_literal};
+use procedural::{ProceduralSelector, ProceduralStep, TextMatcher, Upward};
+use psl::PublicSuffixList;
+use reachability::Reachability;
+use profile::Profile;
+use repr::{Action, DomainConstraint, DomainMatcher, DomainTarget, LoadType, LoadTypeList, MatchPolicy, Reaction};
+use repr::{BoundedReactions, CookieScope, Opener, OwnedRequest, Provenance, ReactionRef, Request, ResourceType, ResourceTypeList, Rule};
+use request_url::{PreparedUrl, RequestHeaders, RequestUrl};
+use resources::Resource;
+use repr::{Trigger, UrlFilter, process_rules_for_request_impl, process_rules_for_request_profiled_impl};
+use repr::PreparedRequest;
+use serde_json;
+use std::io;
+use std::slice;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 use url::Url;
+use verify::{SignatureVerifier, checksum, verify_checksum_impl, verify_signature_impl};
+use view::ActionType;
 
 impl Default for Trigger {
     fn default() -> Trigger {
         Trigger {
-            url_filter: Regex::new("").unwrap(),
+            url_filter: UrlFilter::new("").unwrap(),
+            url_filter_anchor: UrlFilterAnchor::Url,
+            path_filter: None,
+            query_filter: None,
             resource_type: ResourceTypeList::All,
-            load_type: None,
+            load_type: LoadTypeList::All,
             domain_constraint: None,
+            contexts: None,
+            user_initiated: None,
+            opener_domain: None,
+            domain_target: DomainTarget::Request,
+            match_redirect_chain: false,
+            if_header: Box::default(),
+            unless_header: Box::default(),
         }
     }
 }
@@ -52,6 +100,8 @@ fn missing_defaults() {
     let rule = Rule {
         trigger: Trigger::default(),
         action: Action::Block,
+        groups: Box::default(),
+        source: None,
     };
     assert_eq!(parse_list_impl("[{ \"trigger\": { \"url-filter\": \"\"}, \
                                 \"action\": { \"type\": \"block\" } }]"), Ok(vec![rule]));
@@ -61,10 +111,12 @@ fn missing_defaults() {
 fn url_filter_is_case_sensitive() {
     let rule = Rule {
         trigger: Trigger {
-            url_filter: Regex::new("(?i)hi").unwrap(),
+            url_filter: UrlFilter::new("(?i)hi").unwrap(),
             .. Trigger::default()
         },
         action: Action::Block,
+        groups: Box::default(),
+        source: None,
     };
     assert_eq!(parse_list_impl("[{ \"trigger\": { \"url-filter\": \"hi\", \
                                 \"url-filter-is-case-sensitive\": true\
@@ -73,18 +125,24 @@ fn url_filter_is_case_sensitive() {
 
 #[test]
 fn load_type() {
-    for &(type_, ref name) in &[(LoadType::FirstParty, "first-party"),
-                                (LoadType::ThirdParty, "third-party")] {
+    for (types, name) in &[(LoadTypeList::List(vec![LoadType::FirstParty]), "\"first-party\""),
+                                    (LoadTypeList::List(vec![LoadType::ThirdParty]), "\"third-party\""),
+                                    (LoadTypeList::List(vec![LoadType::FirstParty, LoadType::ThirdParty]),
+                                     "\"first-party\", \"same-planet\", \"third-party\""),
+                                    (LoadTypeList::All, "\"same-planet\""),
+                                    (LoadTypeList::All, "")] {
         let rule = Rule {
             trigger: Trigger {
-                load_type: Some(type_),
+                load_type: types.clone(),
                 .. Trigger::default()
             },
             action: Action::Block,
+            groups: Box::default(),
+            source: None,
         };
-        println!("checking {:?}", type_);
+        println!("checking {:?}", types);
         assert_eq!(parse_list_impl(&format!("[{{ \"trigger\": {{ \"url-filter\": \"\", \
-                                             \"load-type\": [\"{}\"]\
+                                             \"load-type\": [{}]\
                                              }}, \"action\": {{ \"type\": \"block\" }} }}]", name)),
                    Ok(vec![rule]));
     }
@@ -100,13 +158,22 @@ fn resource_type() {
                                 (ResourceType::Raw, "raw"),
                                 (ResourceType::SVGDocument, "svg-document"),
                                 (ResourceType::Media, "media"),
-                                (ResourceType::Popup, "popup")] {
+                                (ResourceType::Popup, "popup"),
+                                (ResourceType::WebSocket, "websocket"),
+                                (ResourceType::Worker, "worker"),
+                                (ResourceType::SharedWorker, "shared-worker"),
+                                (ResourceType::ServiceWorker, "service-worker"),
+                                (ResourceType::CspReport, "csp-report"),
+                                (ResourceType::Beacon, "beacon"),
+                                (ResourceType::ObjectSubrequest, "object-subrequest")] {
         let rule = Rule {
             trigger: Trigger {
                 resource_type: ResourceTypeList::List(vec![type_, ResourceType::Document]),
                 .. Trigger::default()
             },
             action: Action::Block,
+            groups: Box::default(),
+            source: None,
         };
         println!("checking {:?}", type_);
         assert_eq!(parse_list_impl(&format!("[{{ \"trigger\": {{ \"url-filter\": \"\", \
@@ -122,10 +189,12 @@ fn if_domain() {
         trigger: Trigger {
             domain_constraint: Some(
                 DomainConstraint::If(
-                    DomainMatcher::new(&["domain", "*domain2"]))),
+                    DomainMatcher::new(["domain", "*domain2"]))),
             .. Trigger::default()
         },
         action: Action::Block,
+        groups: Box::default(),
+        source: None,
     };
     assert_eq!(parse_list_impl("[{ \"trigger\": { \"url-filter\": \"\", \
                                 \"if-domain\": [\"domain\", \"*domain2\"]\
@@ -138,10 +207,12 @@ fn unless_domain() {
         trigger: Trigger {
             domain_constraint: Some(
                 DomainConstraint::Unless(
-                    DomainMatcher::new(&["domain", "*domain2"]))),
+                    DomainMatcher::new(["domain", "*domain2"]))),
             .. Trigger::default()
         },
         action: Action::Block,
+        groups: Box::default(),
+        source: None,
     };
     assert_eq!(parse_list_impl("[{ \"trigger\": { \"url-filter\": \"\",\
                                 \"unless-domain\": [\"domain\", \"*domain2\"]\
@@ -157,12 +228,14 @@ fn if_unless_domain() {
 
 #[test]
 fn action() {
-    for &(ref action, ref name) in &[(Action::Block, "block"),
-                                     (Action::BlockCookies, "block-cookies"),
-                                     (Action::IgnorePreviousRules, "ignore-previous-rules")] {
+    for (action, name) in &[(Action::Block, "block"),
+                            (Action::BlockCookies(CookieScope::Request), "block-cookies"),
+                            (Action::IgnorePreviousRules, "ignore-previous-rules")] {
         let rule = Rule {
             trigger: Trigger::default(),
             action: action.clone(),
+            groups: Box::default(),
+            source: None,
         };
         println!("checking {:?}", action);
         assert_eq!(parse_list_impl(&format!("[{{ \"trigger\": {{ \"url-filter\": \"\"\
@@ -172,7 +245,9 @@ fn action() {
 
     let rule = Rule {
         trigger: Trigger::default(),
-        action: Action::CssDisplayNone("selector".to_owned()),
+        action: Action::CssDisplayNone("selector".into()),
+        groups: Box::default(),
+        source: None,
     };
     assert_eq!(parse_list_impl("[{ \"trigger\": { \"url-filter\": \"\"\
                                 }, \"action\": { \"type\": \"css-display-none\",\
@@ -184,10 +259,12 @@ fn action() {
 fn url_filter_matches() {
     let rule = Rule {
         trigger: Trigger {
-            url_filter: Regex::new("http[s]?://domain.org").unwrap(),
+            url_filter: UrlFilter::new("http[s]?://domain.org").unwrap(),
             .. Trigger::default()
         },
         action: Action::Block,
+        groups: Box::default(),
+        source: None,
     };
 
     for &(url, expected) in &[("http://domain.org/test/page1.html", &[Reaction::Block][..]),
@@ -197,9 +274,14 @@ fn url_filter_matches() {
             url: &Url::parse(url).unwrap(),
             resource_type: ResourceType::Document,
             load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
         };
         println!("checking {:?}", url);
-        let reactions = process_rules_for_request_impl(&[rule.clone()], &request);
+        let reactions = process_rules_for_request_impl(slice::from_ref(&rule), &request);
         assert_eq!(reactions, expected);
     }
 }
@@ -208,10 +290,12 @@ fn url_filter_matches() {
 fn caseless_url_filter_matches() {
     let rule = Rule {
         trigger: Trigger {
-            url_filter: Regex::new("(?i)http[s]?://domain.org").unwrap(),
+            url_filter: UrlFilter::new("(?i)http[s]?://domain.org").unwrap(),
             .. Trigger::default()
         },
         action: Action::Block,
+        groups: Box::default(),
+        source: None,
     };
 
     for &(url, expected) in &[("http://DOMAIN.ORG/test/page1.html", &[Reaction::Block][..]),
@@ -221,22 +305,74 @@ fn caseless_url_filter_matches() {
             url: &Url::parse(url).unwrap(),
             resource_type: ResourceType::Document,
             load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
         };
         println!("checking {:?}", url);
-        let reactions = process_rules_for_request_impl(&[rule.clone()], &request);
+        let reactions = process_rules_for_request_impl(slice::from_ref(&rule), &request);
         assert_eq!(reactions, expected);
     }
 }
 
+#[test]
+fn load_categories() {
+    let rules = parse_list_impl("[{ \"trigger\": { \"url-filter\": \".*\", \"site-relation\": [\"cross-site\"] }, \
+                                   \"action\": { \"type\": \"block\" } },\
+                                 { \"trigger\": { \"url-filter\": \".*\", \"load-type\": [\"third-party\"], \
+                                   \"site-relation\": [\"same-site\", \"top-frame\"] }, \
+                                   \"action\": { \"type\": \"block-cookies\" } },\
+                                 { \"trigger\": { \"url-filter\": \".*\", \"load-context\": [\"child-frame\"] }, \
+                                   \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } }]").unwrap();
+    assert_eq!(rules[1].trigger.load_type, LoadTypeList::List(vec![LoadType::ThirdParty, LoadType::SameSite]));
+    let bytes = compile_impl(&rules);
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    let reaction = |code| match code {
+        'b' => Reaction::Block,
+        'c' => Reaction::BlockCookies(CookieScope::Request),
+        _ => Reaction::HideMatchingElements(".ad".to_owned()),
+    };
+    for &(url, resource_type, load_type, document, expected) in
+        &[("https://cdn.example.com/a.js", ResourceType::Script, LoadType::ThirdParty,
+           Some("https://www.example.com/"), "c"),
+          ("https://tracker.net/a.js", ResourceType::Script, LoadType::ThirdParty,
+           Some("https://www.example.com/"), "b"),
+          ("https://www.example.com/", ResourceType::Script, LoadType::FirstParty,
+           Some("https://www.example.com/"), ""),
+          ("https://www.example.com/", ResourceType::Document, LoadType::FirstParty, None, ""),
+          ("https://ads.example.com/frame", ResourceType::Document, LoadType::ThirdParty,
+           Some("https://www.example.com/"), "ch"),
+          ("https://ads.net/frame", ResourceType::Document, LoadType::ThirdParty,
+           Some("https://www.example.com/"), "bh")] {
+        let expected: Vec<Reaction> = expected.chars().map(reaction).collect();
+        let request = Request {
+            url: &url,
+            resource_type,
+            load_type,
+            context: None,
+            opener: None,
+            document: document.as_ref().map(|document| document as &dyn RequestUrl),
+            redirect_chain: &[],
+            headers: None,
+        };
+        assert_eq!(process_rules_for_request_impl(&rules, &request), expected, "{}", url);
+        assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected, "{}", url);
+    }
+}
+
 #[test]
 fn resource_type_matches() {
     let rule = Rule {
         trigger: Trigger {
-            url_filter: Regex::new("http://domain.org").unwrap(),
+            url_filter: UrlFilter::new("http://domain.org").unwrap(),
             resource_type: ResourceTypeList::List(vec![ResourceType::Media, ResourceType::Raw]),
             .. Trigger::default()
         },
         action: Action::Block,
+        groups: Box::default(),
+        source: None,
     };
 
     for &(type_, expected) in &[(ResourceType::Document, &[][..]),
@@ -246,9 +382,14 @@ fn resource_type_matches() {
             url: &Url::parse("http://domain.org/test/page1.html").unwrap(),
             resource_type: type_,
             load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
         };
         println!("checking {:?}", type_);
-        let reactions = process_rules_for_request_impl(&[rule.clone()], &request);
+        let reactions = process_rules_for_request_impl(slice::from_ref(&rule), &request);
         assert_eq!(reactions, expected);
     }
 }
@@ -257,23 +398,44 @@ fn resource_type_matches() {
 fn load_type_matches() {
     let rule = Rule {
         trigger: Trigger {
-            url_filter: Regex::new("http://domain.org").unwrap(),
-            load_type: Some(LoadType::FirstParty),
+            url_filter: UrlFilter::new("http://domain.org").unwrap(),
+            load_type: LoadTypeList::List(vec![LoadType::FirstParty]),
             .. Trigger::default()
         },
         action: Action::Block,
+        groups: Box::default(),
+        source: None,
     };
+    let either = Rule {
+        trigger: Trigger {
+            url_filter: UrlFilter::new("http://domain.org").unwrap(),
+            load_type: LoadTypeList::List(vec![LoadType::FirstParty, LoadType::ThirdParty]),
+            .. Trigger::default()
+        },
+        action: Action::BlockCookies(CookieScope::Both),
+        groups: Box::default(),
+        source: None,
+    };
+    let rules = [rule, either];
+    let bytes = compile_impl(&rules);
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
 
-    for &(type_, expected) in &[(LoadType::FirstParty, &[Reaction::Block][..]),
-                                (LoadType::ThirdParty, &[][..])] {
+    for &(type_, expected) in &[(LoadType::FirstParty, &[Reaction::Block, Reaction::BlockCookies(CookieScope::Both)][..]),
+                                (LoadType::ThirdParty, &[Reaction::BlockCookies(CookieScope::Both)][..])] {
         let request = Request {
             url: &Url::parse("http://domain.org/test/page1.html").unwrap(),
             resource_type: ResourceType::Document,
             load_type: type_,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
         };
         println!("checking {:?}", type_);
-        let reactions = process_rules_for_request_impl(&[rule.clone()], &request);
+        let reactions = process_rules_for_request_impl(&rules, &request);
         assert_eq!(reactions, expected);
+        assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected);
     }
 }
 
@@ -281,13 +443,15 @@ fn load_type_matches() {
 fn if_domain_matches() {
     let rule = Rule {
         trigger: Trigger {
-            url_filter: Regex::new("ad.html").unwrap(),
+            url_filter: UrlFilter::new("ad.html").unwrap(),
             domain_constraint: Some(
                 DomainConstraint::If(
-                    DomainMatcher::new(&["bad.org", "*verybad.org"]))),
+                    DomainMatcher::new(["bad.org", "*verybad.org"]))),
             .. Trigger::default()
         },
         action: Action::Block,
+        groups: Box::default(),
+        source: None,
     };
 
     for &(url, expected) in &[("http://good.org/ad.html", &[][..]),
@@ -300,9 +464,14 @@ fn if_domain_matches() {
             url: &Url::parse(url).unwrap(),
             resource_type: ResourceType::Document,
             load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
         };
         println!("checking {:?}", url);
-        let reactions = process_rules_for_request_impl(&[rule.clone()], &request);
+        let reactions = process_rules_for_request_impl(slice::from_ref(&rule), &request);
         assert_eq!(reactions, expected);
     }
 }
@@ -311,13 +480,15 @@ fn if_domain_matches() {
 fn unless_domain_matches() {
     let rule = Rule {
         trigger: Trigger {
-            url_filter: Regex::new("ad.html").unwrap(),
+            url_filter: UrlFilter::new("ad.html").unwrap(),
             domain_constraint: Some(
                 DomainConstraint::Unless(
-                    DomainMatcher::new(&["bad.org", "*verybad.org"]))),
+                    DomainMatcher::new(["bad.org", "*verybad.org"]))),
             .. Trigger::default()
         },
         action: Action::Block,
+        groups: Box::default(),
+        source: None,
     };
 
     for &(url, expected) in &[("http://good.org/ad.html", &[Reaction::Block][..]),
@@ -330,9 +501,14 @@ fn unless_domain_matches() {
             url: &Url::parse(url).unwrap(),
             resource_type: ResourceType::Document,
             load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
         };
         println!("checking {:?}", url);
-        let reactions = process_rules_for_request_impl(&[rule.clone()], &request);
+        let reactions = process_rules_for_request_impl(slice::from_ref(&rule), &request);
         assert_eq!(reactions, expected);
     }
 }
@@ -342,61 +518,3459 @@ fn multiple_rules_match() {
     let rules = vec![
         Rule {
             trigger: Trigger {
-                url_filter: Regex::new("http://domain.org").unwrap(),
+                url_filter: UrlFilter::new("http://domain.org").unwrap(),
                 .. Trigger::default()
             },
             action: Action::Block,
+            groups: Box::default(),
+            source: None,
         },
         Rule {
             trigger: Trigger {
-                url_filter: Regex::new("http://domain.org/nocookies.sjs").unwrap(),
+                url_filter: UrlFilter::new("http://domain.org/nocookies.sjs").unwrap(),
                 .. Trigger::default()
             },
             action: Action::IgnorePreviousRules,
+            groups: Box::default(),
+            source: None,
         },
         Rule {
             trigger: Trigger {
-                url_filter: Regex::new("http://domain.org/nocookies.sjs").unwrap(),
+                url_filter: UrlFilter::new("http://domain.org/nocookies.sjs").unwrap(),
                 .. Trigger::default()
             },
-            action: Action::BlockCookies,
+            action: Action::BlockCookies(CookieScope::Request),
+            groups: Box::default(),
+            source: None,
         },
         Rule {
             trigger: Trigger {
-                url_filter: Regex::new("http://domain.org/hideme.jpg").unwrap(),
+                url_filter: UrlFilter::new("http://domain.org/hideme.jpg").unwrap(),
                 .. Trigger::default()
             },
-            action: Action::CssDisplayNone("#adblock".to_owned()),
+            action: Action::CssDisplayNone("#adblock".into()),
+            groups: Box::default(),
+            source: None,
         },
         Rule {
             trigger: Trigger {
-                url_filter: Regex::new("http://domain.org/ok.html").unwrap(),
+                url_filter: UrlFilter::new("http://domain.org/ok.html").unwrap(),
                 .. Trigger::default()
             },
             action: Action::IgnorePreviousRules,
+            groups: Box::default(),
+            source: None,
         },
         Rule {
             trigger: Trigger {
-                url_filter: Regex::new("http://domain.org/ok.html\\?except_this=1").unwrap(),
+                url_filter: UrlFilter::new("http://domain.org/ok.html\\?except_this=1").unwrap(),
                 .. Trigger::default()
             },
-            action: Action::BlockCookies,
+            action: Action::BlockCookies(CookieScope::Request),
+            groups: Box::default(),
+            source: None,
         },
     ];
 
     for &(url, expected) in &[("http://domain.org/test/page1.html", &[Reaction::Block][..]),
-                              ("http://domain.org/nocookies.sjs", &[Reaction::BlockCookies][..]),
+                              ("http://domain.org/nocookies.sjs", &[Reaction::BlockCookies(CookieScope::Request)][..]),
                               ("http://domain.org/hideme.jpg", &[Reaction::Block,
                                                                  Reaction::HideMatchingElements("#adblock".to_owned())][..]),
                               ("http://domain.org/ok.html", &[][..]),
-                              ("http://domain.org/ok.html?except_this=1", &[Reaction::BlockCookies][..])] {
+                              ("http://domain.org/ok.html?except_this=1", &[Reaction::BlockCookies(CookieScope::Request)][..])] {
         let request = Request {
             url: &Url::parse(url).unwrap(),
             resource_type: ResourceType::Document,
             load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
         };
         println!("checking {:?}", url);
         let reactions = process_rules_for_request_impl(&rules, &request);
         assert_eq!(reactions, expected);
     }
 }
+
+#[test]
+fn profiled_matching() {
+    let rules = vec![
+        Rule {
+            trigger: Trigger {
+                url_filter: UrlFilter::new("http://domain.org").unwrap(),
+                .. Trigger::default()
+            },
+            action: Action::Block,
+            groups: Box::default(),
+            source: None,
+        },
+        Rule {
+            trigger: Trigger {
+                url_filter: UrlFilter::new("script.js").unwrap(),
+                resource_type: ResourceTypeList::List(vec![ResourceType::Script]),
+                .. Trigger::default()
+            },
+            action: Action::Block,
+            groups: Box::default(),
+            source: None,
+        },
+    ];
+
+    let mut profile = Profile::new();
+    for &(url, expected) in &[("http://domain.org/test/page1.html", &[Reaction::Block][..]),
+                              ("http://other.org/test/page1.html", &[][..])] {
+        let request = Request {
+            url: &Url::parse(url).unwrap(),
+            resource_type: ResourceType::Document,
+            load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        println!("checking {:?}", url);
+        let reactions = process_rules_for_request_profiled_impl(&rules, None, &request, &mut profile);
+        assert_eq!(reactions, expected);
+    }
+
+    // The second rule never reaches its URL filter due to the resource type.
+    assert_eq!(profile.rules.len(), 2);
+    assert_eq!(profile.rules[0].evaluations, 2);
+    assert_eq!(profile.rules[1].evaluations, 0);
+
+    let slowest = profile.slowest(&RuleList::new(rules), 1);
+    assert_eq!(slowest.len(), 1);
+    assert_eq!(slowest[0].index, 0);
+    assert_eq!(slowest[0].url_filter, "http://domain.org");
+}
+
+#[test]
+fn optimize_rules() {
+    let rule = |filter: &str, action: Action| Rule {
+        trigger: Trigger {
+            url_filter: UrlFilter::new(filter).unwrap(),
+            .. Trigger::default()
+        },
+        action,
+        groups: Box::default(),
+        source: None,
+    };
+    let rules = vec![
+        rule("ads", Action::Block),
+        rule("ads", Action::CssDisplayNone(".banner".into())),
+        rule("ads", Action::Block),
+        rule("ads", Action::CssDisplayNone("#sidebar".into())),
+        rule("ok", Action::IgnorePreviousRules),
+        rule("ads", Action::CssDisplayNone(".footer".into())),
+    ];
+    let (optimized, report) = optimize_impl(rules);
+    assert_eq!(optimized, vec![
+        rule("ads", Action::CssDisplayNone(".banner, #sidebar".into())),
+        rule("ads", Action::Block),
+        rule("ok", Action::IgnorePreviousRules),
+        rule("ads", Action::CssDisplayNone(".footer".into())),
+    ]);
+    assert_eq!(report, OptimizationReport {
+        rules_before: 6,
+        rules_after: 4,
+        duplicates_removed: 1,
+        selectors_merged: 1,
+    });
+
+    for &(url, expected) in &[("http://ads.org/", &[Reaction::HideMatchingElements(".banner, #sidebar".to_owned()),
+                                                  Reaction::Block,
+                                                  Reaction::HideMatchingElements(".footer".to_owned())][..]),
+                              ("http://ads.org/ok", &[Reaction::HideMatchingElements(".footer".to_owned())][..])] {
+        let request = Request {
+            url: &Url::parse(url).unwrap(),
+            resource_type: ResourceType::Document,
+            load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        println!("checking {:?}", url);
+        assert_eq!(process_rules_for_request_impl(&optimized, &request), expected);
+    }
+}
+
+#[test]
+fn diff_rules() {
+    let rule = |filter: &str, action: Action| Rule {
+        trigger: Trigger {
+            url_filter: UrlFilter::new(filter).unwrap(),
+            .. Trigger::default()
+        },
+        action,
+        groups: Box::default(),
+        source: None,
+    };
+    let old = vec![
+        rule("ads", Action::Block),
+        rule("tracker", Action::BlockCookies(CookieScope::Request)),
+        rule("banner", Action::CssDisplayNone(".banner".into())),
+        rule("ads", Action::Block),
+    ];
+    let new = vec![
+        rule("banner", Action::CssDisplayNone("#banner".into())),
+        rule("ads", Action::Block),
+        rule("popup", Action::Block),
+    ];
+
+    assert!(diff_impl(&old, &old).is_empty());
+    assert_eq!(diff_impl(&old, &new), RuleListDelta {
+        added: vec![2],
+        removed: vec![1, 3],
+        changed: vec![(2, 0)],
+    });
+}
+
+#[test]
+fn delta_round_trip() {
+    let old_body = "[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                     { \"trigger\": { \"url-filter\": \"track\" }, \"action\": { \"type\": \"block-cookies\" } },\
+                     { \"trigger\": { \"url-filter\": \"ok\" }, \"action\": { \"type\": \"ignore-previous-rules\" } }]";
+    let new_body = "[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                     { \"trigger\": { \"url-filter\": \"track\" }, \"action\": { \"type\": \"block-cookies\" } },\
+                     { \"trigger\": { \"url-filter\": \"popup\" }, \"action\": { \"type\": \"block\" } },\
+                     { \"trigger\": { \"url-filter\": \"\" }, \"action\": { \"type\": \"invalid\" } },\
+                     { \"trigger\": { \"url-filter\": \"ok\" }, \"action\": { \"type\": \"ignore-previous-rules\" } }]";
+    let old = parse_list_impl(old_body).unwrap();
+    let new = parse_list_impl(new_body).unwrap();
+
+    let options = ParseOptions::default();
+    let delta = create_delta_impl(&old, new_body, &options).unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&delta).unwrap()["operations"].as_array().unwrap().len(), 3);
+    assert_eq!(apply_delta_impl(&old, &delta, &options), Ok(new));
+
+    assert_eq!(apply_delta_impl(&old[1..], &delta, &options), Err(Error::InvalidDelta));
+    let overflow = "{ \"base-length\": 3, \"operations\": [{ \"copy\": 2, \"count\": 2 }] }";
+    assert_eq!(apply_delta_impl(&old, overflow, &options), Err(Error::InvalidDelta));
+    assert_eq!(apply_delta_impl(&old, "[]", &options), Err(Error::InvalidDelta));
+
+    // Deltas keep the domain target of the list they apply to.
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\", \"if-domain\": [\"site.com\"] }, \
+                  \"action\": { \"type\": \"block\" } }]";
+    let mut list = parse_list(body).unwrap();
+    list.set_domain_target(DomainTarget::TopDocument);
+    let document = Url::parse("https://site.com/").unwrap();
+    let request = Request {
+        url: &"https://cdn.net/ads.js",
+        resource_type: ResourceType::Script,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: Some(&document),
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert_eq!(process_rules_for_request(&list, &request), vec![Reaction::Block]);
+    let inserted = "{ \"trigger\": { \"url-filter\": \"track\" }, \"action\": { \"type\": \"block\" } }";
+    let new_body = body.replace("}]", &format!("}}, {}]", inserted));
+    let delta = create_delta(&list, &new_body).unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&delta).unwrap()["operations"][0]["copy"], 0);
+    list.apply_delta(&delta).unwrap();
+    assert_eq!(list.len(), 2);
+    assert_eq!(process_rules_for_request(&list, &request), vec![Reaction::Block]);
+}
+
+#[test]
+fn list_metadata() {
+    let rule = Rule {
+        trigger: Trigger::default(),
+        action: Action::Block,
+        groups: Box::default(),
+        source: None,
+    };
+    let expected = ListMetadata {
+        title: Some("Example list".to_owned()),
+        version: Some("202401".to_owned()),
+        homepage: Some("https://example.org/".to_owned()),
+        expires: Some(Duration::from_secs(4 * 24 * 60 * 60)),
+        license: Some("MPL-2.0".to_owned()),
+    };
+    let options = ParseOptions::default();
+    let parse = |body: &str, options: &ParseOptions| {
+        parse_list_with_metadata_impl(body, options).map(|(parsed, metadata)| (parsed.rules, metadata))
+    };
+
+    assert_eq!(parse("[Adblock Plus 2.0]\n\
+                                              ! Title: Example list\n\
+                                              ! Version: 202401\n\
+                                              ! Homepage: https://example.org/\n\
+                                              ! Expires: 4 days (update frequency)\n\
+                                              ! License: MPL-2.0\n\
+                                              ! Some other comment\n\
+                                              [{ \"trigger\": { \"url-filter\": \"\" }, \
+                                              \"action\": { \"type\": \"block\" } }]", &options),
+               Ok((vec![rule.clone()], expected.clone())));
+
+    assert_eq!(parse("{ \"metadata\": { \"title\": \"Example list\", \
+                                              \"version\": 202401, \"homepage\": \"https://example.org/\", \
+                                              \"expires\": \"96 hours\", \"license\": \"MPL-2.0\" }, \
+                                              \"rules\": [{ \"trigger\": { \"url-filter\": \"\" }, \
+                                              \"action\": { \"type\": \"block\" } }] }", &options),
+               Ok((vec![rule.clone()], expected)));
+
+    assert_eq!(parse("[]", &options), Ok((vec![], ListMetadata::default())));
+    assert_eq!(parse("! Title: x\n{ \"action\": {}, \"trigger\": {} }", &options),
+               Err(Error::NotAList));
+    let strict = ParseOptions { strict: true, ..ParseOptions::default() };
+    assert_eq!(parse("! Title: x\n[{ \"action\": {}, \"trigger\": {} }]", &options),
+               Ok((vec![], ListMetadata { title: Some("x".to_owned()), ..ListMetadata::default() })));
+    assert!(parse("! Title: x\n[{ \"action\": {}, \"trigger\": {} }]", &strict).is_err());
+
+    // The rules are located in the body, and the rule limits apply to them.
+    let body = "{ \"metadata\": { \"title\": \"x\" },\n\
+                \"rules\": [{ \"trigger\": { \"url-filter\": \"a\" }, \"action\": { \"type\": \"block\" } },\
+                           { \"trigger\": { \"url-filter\": \"b\" }, \"action\": { \"type\": \"block\" } }] }";
+    let limited = ParseOptions { max_rules: Some(1), rule_limit_policy: RuleLimitPolicy::Truncate, ..options };
+    let (list, metadata) = parse_list_with_metadata_and_options(body, &limited).unwrap();
+    assert_eq!(metadata.title.as_deref(), Some("x"));
+    assert_eq!(list.len(), 1);
+    assert_eq!(list.compile_stats().dropped_rules, vec![1]);
+    let offset = body.find("[{").unwrap() + 1;
+    assert_eq!(list.get(0).unwrap().source(), Some(SourceLocation { index: 0, offset, line: 2 }));
+    let (list, _) = parse_list_with_metadata_and_options(&format!("! Title: x\n{}", &body[offset - 1..body.len() - 2]),
+                                                          &options).unwrap();
+    assert_eq!(list.get(1).unwrap().source().map(|source| (source.index, source.line)), Some((1, 2)));
+}
+
+#[cfg(feature = "subscriptions")]
+#[test]
+fn subscription_expiry() {
+    use std::collections::HashMap;
+    use std::time::UNIX_EPOCH;
+    use subscriptions::{Fetcher, RefreshError, SubscriptionManager};
+
+    struct MapFetcher(HashMap<String, String>);
+    impl Fetcher for MapFetcher {
+        type Error = ();
+        fn fetch(&mut self, url: &Url) -> Result<String, ()> {
+            self.0.get(url.as_str()).cloned().ok_or(())
+        }
+    }
+
+    let day = Duration::from_secs(24 * 60 * 60);
+    let short = Url::parse("https://lists.example/short.json").unwrap();
+    let long = Url::parse("https://lists.example/long.json").unwrap();
+    let missing = Url::parse("https://lists.example/missing.json").unwrap();
+    let mut fetcher = MapFetcher(HashMap::new());
+    fetcher.0.insert(short.to_string(), "! Expires: 1 day\n[]".to_owned());
+    fetcher.0.insert(long.to_string(), "[]".to_owned());
+
+    let mut manager = SubscriptionManager::new(day * 7);
+    assert!(manager.add(short.clone()));
+    assert!(manager.add(long.clone()));
+    assert!(manager.add(missing.clone()));
+    assert!(!manager.add(long.clone()));
+    assert_eq!(manager.due(UNIX_EPOCH), vec![&short, &long, &missing]);
+
+    let errors = manager.refresh_due(&mut fetcher, UNIX_EPOCH);
+    assert_eq!(errors, vec![(missing.clone(), RefreshError::Fetch(()))]);
+    assert!(manager.get(&short).unwrap().rules().is_some());
+    assert_eq!(manager.get(&short).unwrap().last_fetched(), Some(UNIX_EPOCH));
+
+    assert!(manager.remove(&missing).is_some());
+    assert_eq!(manager.next_refresh(), Some(UNIX_EPOCH + day));
+    assert!(manager.due(UNIX_EPOCH + day / 2).is_empty());
+    assert_eq!(manager.due(UNIX_EPOCH + day * 2), vec![&short]);
+    assert_eq!(manager.due(UNIX_EPOCH + day * 7), vec![&short, &long]);
+}
+
+#[cfg(feature = "servo")]
+#[test]
+fn servo_filter() {
+    use servo::{ContentBlockerFilter, FetchMetadata, FilterDecision, RequestDecision};
+
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\", \"load-type\": [\"third-party\"] }, \
+                                \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \"analytics\\\\.js\" }, \
+                             \"action\": { \"type\": \"redirect\", \"resource\": \"noop.js\" } }, \
+                           { \"trigger\": { \"url-filter\": \".*\" }, \
+                             \"action\": { \"type\": \"block-cookies\", \"scope\": \"response\" } }, \
+                           { \"trigger\": { \"url-filter\": \"news\" }, \
+                             \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } }]").unwrap();
+    let page = Url::parse("https://news.example/").unwrap();
+    let same_origin = Url::parse("https://news.example/ads/banner.png").unwrap();
+    let third_party = Url::parse("https://cdn.example/ads/analytics.js").unwrap();
+    let metadata = |url| FetchMetadata {
+        url,
+        resource_type: ResourceType::Script,
+        document_url: Some(&page),
+        context: None,
+        redirect_chain: &[],
+    };
+
+    assert_eq!(metadata(&same_origin).load_type(), LoadType::FirstParty);
+    let decision = list.filter(&metadata(&same_origin));
+    assert!(!decision.prevents_fetch());
+    assert!(decision.ignore_response_cookies && !decision.strip_request_cookies);
+
+    let decision = list.filter(&metadata(&third_party));
+    assert_eq!(decision.request, RequestDecision::Redirect(Resource::named("noop.js").unwrap()));
+
+    let navigation = FetchMetadata { document_url: None, resource_type: ResourceType::Document, ..metadata(&page) };
+    assert_eq!(navigation.load_type(), LoadType::FirstParty);
+    assert_eq!(list.filter(&navigation).document, vec![Reaction::HideMatchingElements(".ad".to_owned())]);
+
+    let mut engine = Engine::new();
+    engine.add_list(list);
+    engine.allowlist_mut().add_domain("news.example");
+    assert_eq!(engine.filter(&metadata(&third_party)), FilterDecision::default());
+}
+
+#[cfg(feature = "proxy")]
+#[test]
+fn proxy_service() {
+    use http::{Request as HttpRequest, Response, StatusCode, header};
+    use proxy::ContentBlockerLayer;
+    use std::future::{Future, Ready, ready};
+    use std::fmt::Debug;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+    use tower_layer::Layer;
+    use tower_service::Service;
+
+    // Echoes the cookies of the request, and sets two more.
+    struct Upstream;
+    impl Service<HttpRequest<()>> for Upstream {
+        type Response = Response<Vec<u8>>;
+        type Error = ();
+        type Future = Ready<Result<Response<Vec<u8>>, ()>>;
+        fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, request: HttpRequest<()>) -> Self::Future {
+            let cookies = request.headers().get(header::COOKIE).map_or(vec![], |value| value.as_bytes().to_vec());
+            ready(Ok(Response::builder().header(header::SET_COOKIE, "_ga=1; Path=/")
+                                        .header(header::SET_COOKIE, "session=2")
+                                        .body(cookies).unwrap()))
+        }
+    }
+    fn send<S: Service<HttpRequest<()>>>(service: &mut S, request: HttpRequest<()>) -> S::Response
+        where S::Error: Debug
+    {
+        let mut future = pin!(service.call(request));
+        match future.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(response) => response.unwrap(),
+            Poll::Pending => panic!("upstream responds immediately"),
+        }
+    }
+
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\", \"load-type\": [\"third-party\"] }, \
+                                \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \"^wss?://\", \"resource-type\": [\"websocket\"] }, \
+                             \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \".*\", \"resource-type\": [\"csp-report\"] }, \
+                             \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \"pixel\\\\.gif\" }, \
+                             \"action\": { \"type\": \"redirect\", \"resource\": \"1x1.gif\" } }, \
+                           { \"trigger\": { \"url-filter\": \"news\" }, \
+                             \"action\": { \"type\": \"block-cookies\", \"names\": [\"_ga*\"] } }]").unwrap();
+    let mut engine = Engine::new();
+    engine.add_list(list);
+    let mut service = ContentBlockerLayer::new(Arc::new(engine)).layer(Upstream);
+
+    let request = HttpRequest::get("http://cdn.example/ads/banner.png")
+                              .header(header::REFERER, "https://news.example/")
+                              .body(()).unwrap();
+    assert_eq!(send(&mut service, request).status(), StatusCode::FORBIDDEN);
+    let request = HttpRequest::get("http://cdn.example/ads/banner.png")
+                              .header("sec-fetch-site", "same-origin")
+                              .body(()).unwrap();
+    assert_eq!(send(&mut service, request).status(), StatusCode::OK);
+    let request = HttpRequest::get("/socket")
+                              .header(header::HOST, "chat.example")
+                              .header(header::UPGRADE, "websocket")
+                              .body(()).unwrap();
+    assert_eq!(send(&mut service, request).status(), StatusCode::FORBIDDEN);
+    let request = HttpRequest::post("http://news.example/csp")
+                              .header(header::CONTENT_TYPE, "application/csp-report")
+                              .body(()).unwrap();
+    assert_eq!(send(&mut service, request).status(), StatusCode::FORBIDDEN);
+
+    let response = send(&mut service, HttpRequest::get("http://cdn.example/pixel.gif").body(()).unwrap());
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/gif");
+    assert_eq!(response.body(), &Resource::named("1x1.gif").unwrap().content());
+
+    let request = HttpRequest::get("/index.html")
+                              .header(header::HOST, "news.example")
+                              .header(header::COOKIE, "_gat=1; session=2;_ga=3")
+                              .body(()).unwrap();
+    let response = send(&mut service, request);
+    assert_eq!(response.body(), b"session=2");
+    assert_eq!(response.headers().get_all(header::SET_COOKIE).iter().collect::<Vec<_>>(), vec!["session=2"]);
+}
+
+#[test]
+fn list_checksum() {
+    let body = "! Title: Example\r\n! Checksum: m81ipJCYwVCzMR9daMUL1Q\r\n\r\n\
+                [{\"trigger\": {\"url-filter\": \"ads\"}, \"action\": {\"type\": \"block\"}}]\r\n";
+    assert_eq!(checksum(body), "m81ipJCYwVCzMR9daMUL1Q");
+    assert_eq!(verify_checksum_impl(body), Ok(()));
+    assert_eq!(verify_checksum_impl(&body.replace("ads", "bds")), Err(Error::ChecksumMismatch));
+    assert_eq!(verify_checksum_impl("[]"), Err(Error::MissingChecksum));
+}
+
+#[test]
+fn list_signature() {
+    struct Reversed;
+    impl SignatureVerifier for Reversed {
+        fn verify(&self, body: &[u8], signature: &[u8]) -> bool {
+            body.iter().rev().eq(signature.iter())
+        }
+    }
+    assert_eq!(verify_signature_impl("[]", b"][", &Reversed), Ok(()));
+    assert_eq!(verify_signature_impl("[]", b"[]", &Reversed), Err(Error::InvalidSignature));
+}
+
+#[test]
+fn strict_parsing_errors() {
+    for &(rule, ref error) in &[("5", Error::MissingField { index: 1, field: "rule" }),
+                                ("{ \"action\": { \"type\": \"block\" } }",
+                                 Error::MissingField { index: 1, field: "trigger" }),
+                                ("{ \"trigger\": {}, \"action\": { \"type\": \"block\" } }",
+                                 Error::MissingField { index: 1, field: "trigger.url-filter" }),
+                                ("{ \"trigger\": { \"url-filter\": \"(\" }, \"action\": { \"type\": \"block\" } }",
+                                 Error::InvalidRegex { index: 1, pattern: "(".to_owned() }),
+                                ("{ \"trigger\": { \"url-filter\": \"\", \"if-domain\": [\"a\"], \
+                                 \"unless-domain\": [\"b\"] }, \"action\": { \"type\": \"block\" } }",
+                                 Error::ConflictingDomainConstraints { index: 1 }),
+                                ("{ \"trigger\": { \"url-filter\": \"\" }, \"action\": { \"type\": \"make-coffee\" } }",
+                                 Error::UnsupportedAction { index: 1, action: "make-coffee".to_owned() }),
+                                ("{ \"trigger\": { \"url-filter\": \"\" }, \"action\": { \"type\": \"css-display-none\" } }",
+                                 Error::MissingField { index: 1, field: "action.selector" })] {
+        println!("checking {:?}", rule);
+        let body = format!("[{{ \"trigger\": {{ \"url-filter\": \"\" }}, \"action\": {{ \"type\": \"block\" }} }}, {}]",
+                           rule);
+        assert_eq!(parse_list_strict_impl(&body).as_ref().err(), Some(error));
+        assert_eq!(parse_list_impl(&body).map(|rules| rules.len()), Ok(1));
+    }
+
+    assert_eq!(Error::InvalidRegex { index: 3, pattern: "(".to_owned() }.to_string(),
+               "rule 3: invalid url-filter `(`");
+}
+
+#[test]
+fn parse_from_reader() {
+    // Yields one byte per read, then fails if `fail` is set.
+    struct Trickle<'a>(&'a [u8], bool);
+    impl<'a> io::Read for Trickle<'a> {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((&byte, rest)) if !buffer.is_empty() => {
+                    buffer[0] = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ if self.1 => Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset")),
+                _ => Ok(0),
+            }
+        }
+    }
+
+    let body = " [{ \"trigger\": { \"url-filter\": \"\\\\[ads\\\\]\", \"if-domain\": [\"a.com\"] }, \
+                    \"action\": { \"type\": \"css-display-none\", \"selector\": \"a[title=\\\"],]\\\"]\" } },\
+                  { \"trigger\": {}, \"action\": { \"type\": \"block\" } }, 5,\n\
+                  { \"trigger\": { \"url-filter\": \"track\" }, \"action\": { \"type\": \"block\" } } ]\n";
+    let expected = parse_list_impl(body).unwrap();
+    assert_eq!(expected.len(), 2);
+    assert_eq!(parse_list_from_reader(Trickle(body.as_bytes(), false)).unwrap().rules, expected);
+    assert_eq!(parse_list_from_slice(body.as_bytes()).unwrap().rules, expected);
+    let strict = ParseOptions { strict: true, ..ParseOptions::default() };
+    assert!(parse_list_from_slice_with_options(body.as_bytes(), &strict).is_err());
+    assert!(parse_list_from_reader_with_options(Trickle(body.as_bytes(), false), &strict).is_err());
+
+    for &(body, ref error) in &[("", Error::JSON),
+                                ("  ", Error::JSON),
+                                ("{}", Error::NotAList),
+                                ("\"[\"", Error::NotAList),
+                                ("{", Error::JSON),
+                                ("[", Error::JSON),
+                                ("[{},]", Error::JSON),
+                                ("[,]", Error::JSON),
+                                ("[{}", Error::JSON),
+                                ("[{} {}]", Error::JSON),
+                                ("[\"]", Error::JSON),
+                                ("[] []", Error::JSON)] {
+        assert_eq!(parse_list_impl(body).err().as_ref(), Some(error), "{}", body);
+        assert_eq!(parse_list_from_slice(body.as_bytes()).err().as_ref(), Some(error), "{}", body);
+    }
+    assert_eq!(parse_list_from_slice(b" [ ] ").map(|list| list.len()), Ok(0));
+    assert_eq!(parse_list_from_reader(Trickle(b"[{}, {}", true)).err(), Some(Error::Io(io::ErrorKind::ConnectionReset)));
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn compressed_lists() {
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use std::io::Write;
+
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"track\" }, \"action\": { \"type\": \"block\" } }]";
+    let expected = parse_list_impl(body).unwrap();
+    let mut gzip = GzEncoder::new(vec![], Compression::default());
+    gzip.write_all(body.as_bytes()).unwrap();
+    let gzip = gzip.finish().unwrap();
+    let mut zlib = ZlibEncoder::new(vec![], Compression::best());
+    zlib.write_all(body.as_bytes()).unwrap();
+    let zlib = zlib.finish().unwrap();
+    assert_eq!(parse_list_from_slice(&gzip).unwrap().rules, expected);
+    assert_eq!(parse_list_from_reader(&zlib[..]).unwrap().rules, expected);
+
+    // Servers may split a body into several gzip members.
+    let (first, second) = body.split_at(40);
+    let mut members = vec![];
+    for part in &[first, second] {
+        let mut member = GzEncoder::new(vec![], Compression::fast());
+        member.write_all(part.as_bytes()).unwrap();
+        members.extend(member.finish().unwrap());
+    }
+    assert_eq!(parse_list_from_slice(&members).unwrap().rules, expected);
+    assert!(matches!(parse_list_from_slice(&gzip[..gzip.len() / 2]), Err(Error::Io(_))));
+}
+
+#[test]
+fn precompiled_lists() {
+    use std::env;
+    use std::fs;
+
+    let dir = env::temp_dir().join(format!("content-blocker-precompile-{}", ::std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("list.json");
+    let output = dir.join("list.json.rules");
+    fs::write(&input, "[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } }]").unwrap();
+    let list = precompile_list(&input, &output).unwrap();
+    let bytes = fs::read(&output).unwrap();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    let request = Request {
+        url: &Url::parse("https://example.com/ads.png").unwrap(),
+        resource_type: ResourceType::Image,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), vec![Reaction::Block]);
+    assert_eq!(process_rules_for_request(&list, &request), vec![Reaction::Block]);
+
+    // Rules that would be skipped at runtime fail the build instead.
+    fs::write(&input, "[{ \"trigger\": { \"url-filter\": \"(\" }, \"action\": { \"type\": \"block\" } }]").unwrap();
+    assert!(precompile_list(&input, &output).is_err());
+    assert_eq!(precompile_list(dir.join("missing.json"), &output).err(), Some(Error::Io(io::ErrorKind::NotFound)));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn parse_options() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"ads\", \"resource-type\": [\"image\", \"teapot\"] }, \
+                   \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"ads\", \"resource-type\": [] }, \
+                   \"action\": { \"type\": \"block\" } }]";
+    let parsed = parse_list_with_options_impl(body, &ParseOptions::default()).unwrap();
+    assert_eq!(parsed.skipped, 0);
+    let defaults = parsed.rules;
+    assert_eq!(defaults, parse_list_impl(body).unwrap());
+    assert_eq!(defaults[1].trigger.resource_type, ResourceTypeList::List(vec![ResourceType::Image]));
+
+    let options = ParseOptions {
+        case_sensitive: false,
+        reject_unknown_resource_types: true,
+        reject_empty_resource_types: true,
+        domain_target: DomainTarget::TopDocument,
+        ..ParseOptions::default()
+    };
+    let parsed = parse_list_with_options_impl(body, &options).unwrap();
+    assert_eq!(parsed.skipped, 2);
+    assert_eq!(parsed.rules, vec![Rule {
+        trigger: Trigger {
+            url_filter: UrlFilter::new("(?i)ads").unwrap(),
+            domain_target: DomainTarget::TopDocument,
+            .. Trigger::default()
+        },
+        action: Action::Block,
+        groups: Box::default(),
+        source: None,
+    }]);
+
+    let strict = ParseOptions { strict: true, ..options.clone() };
+    assert_eq!(parse_list_with_options_impl(body, &strict).err(),
+               Some(Error::UnknownResourceType { index: 1, name: "teapot".to_owned() }));
+    let body = body.replace("\"teapot\"", "\"script\"");
+    assert_eq!(parse_list_with_options_impl(&body, &strict).err(), Some(Error::EmptyResourceTypes { index: 2 }));
+
+    let limited = ParseOptions { max_rules: Some(2), ..ParseOptions::default() };
+    assert_eq!(parse_list_with_options_impl(&body, &limited).err(), Some(Error::TooManyRules { limit: 2 }));
+    assert_eq!(Error::TooManyRules { limit: 2 }.to_string(), "the list has more than 2 rules");
+}
+
+#[test]
+fn rule_introspection() {
+    let rules = RuleList::new(parse_list_impl("[{ \"trigger\": { \"url-filter\": \"ads\", \
+                                           \"resource-type\": [\"image\", \"script\"] }, \
+                                           \"action\": { \"type\": \"block\" } },\
+                                          { \"trigger\": { \"url-filter\": \"banner\", \
+                                           \"if-domain\": [\"news.org\", \"*example.com\"] }, \
+                                           \"action\": { \"type\": \"css-display-none\", \"selector\": \".banner\" } },\
+                                          { \"trigger\": { \"url-filter\": \"ok\", \"load-type\": [\"third-party\"], \
+                                           \"unless-domain\": [\"example.com\"] }, \
+                                           \"action\": { \"type\": \"ignore-previous-rules\" } }]").unwrap());
+
+    assert_eq!(rules.len(), 3);
+    assert_eq!(rules.iter().map(|r| r.url_filter()).collect::<Vec<_>>(), vec!["ads", "banner", "ok"]);
+
+    let first = rules.get(0).unwrap();
+    assert_eq!(first.resource_types(), Some(&[ResourceType::Image, ResourceType::Script][..]));
+    assert_eq!(first.load_types(), None);
+    assert_eq!(first.selector(), None);
+
+    let hiding: Vec<_> = rules.with_action(ActionType::CssDisplayNone).collect();
+    assert_eq!(hiding.len(), 1);
+    assert_eq!(hiding[0].index(), 1);
+    assert_eq!(hiding[0].selector(), Some(".banner"));
+    assert_eq!(hiding[0].if_domain(), Some(vec!["news.org".to_owned(), "*example.com".to_owned()]));
+    assert_eq!(rules.get(2).unwrap().unless_domain(), Some(vec!["example.com".to_owned()]));
+    assert_eq!(rules.get(2).unwrap().load_types(), Some(&[LoadType::ThirdParty][..]));
+
+    assert_eq!(rules.referencing_domain("example.com").map(|r| r.index()).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(rules.referencing_domain("www.example.com").map(|r| r.index()).collect::<Vec<_>>(), vec![1]);
+    assert_eq!(rules.referencing_domain("other.org").count(), 0);
+
+    let counts = rules.count_by_resource_type();
+    assert_eq!(counts[&ResourceType::Image], 3);
+    assert_eq!(counts[&ResourceType::Document], 2);
+}
+
+#[test]
+fn host_anchored_patterns() {
+    for &(pattern, expected) in &[("^https?://example\\.com/", Some(("example.com", false))),
+                                  ("^https?://(www\\.)?example\\.com[:/]", Some(("example.com", true))),
+                                  ("(?i)^[^:]+://+([^:/]+\\.)?Ads\\.Example\\.com(:|/)",
+                                   Some(("ads.example.com", true))),
+                                  ("^wss://tracker\\.org:", Some(("tracker.org", false))),
+                                  ("^https?://example\\.com", None),
+                                  ("^https?://example\\.com\\.", None),
+                                  ("^https?://example\\.com?/", None),
+                                  ("^.*://example\\.com/", None),
+                                  ("^https?://(.*\\.)?example\\.com/", None),
+                                  ("^https?://example\\.com/|ads", None),
+                                  ("https?://example\\.com/", None),
+                                  ("^https?://[a-z]+\\.com/", None)] {
+        println!("checking {:?}", pattern);
+        assert_eq!(host_anchor(pattern), expected.map(|(host, subdomains)| (host.to_owned(), subdomains)));
+    }
+}
+
+#[test]
+fn dns_blocklist_export() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"^https?://([^/]*\\\\.)?ads\\\\.example/\" }, \
+                                \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \"^[^:]+://tracker\\\\.net[:/]\" }, \
+                             \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \"^https?://cdn\\\\.example/ads/\" }, \
+                             \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \"^https?://pixel\\\\.example/\", \
+                                            \"load-type\": [\"third-party\"] }, \
+                             \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \".*\" }, \
+                             \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } }, \
+                           { \"trigger\": { \"url-filter\": \"^https://tracker\\\\.net/consent\" }, \
+                             \"action\": { \"type\": \"ignore-previous-rules\" } }]").unwrap();
+    let skipped = |rule, reason| DnsSkip { rule, reason };
+
+    let unbound = list.to_dns_blocklist(DnsFormat::Unbound);
+    assert_eq!(unbound.config, "server:\n    local-zone: \"ads.example.\" always_nxdomain\n    \
+                                local-data: \"tracker.net. A 0.0.0.0\"\n    local-data: \"tracker.net. AAAA ::\"\n");
+    assert_eq!(unbound.hosts, 2);
+    assert_eq!(unbound.skipped, vec![skipped(2, DnsSkipReason::UrlFilter),
+                                     skipped(3, DnsSkipReason::Conditions),
+                                     skipped(5, DnsSkipReason::Exception)]);
+
+    assert_eq!(list.to_dns_blocklist(DnsFormat::PiHole).config, "||ads.example^\ntracker.net\n");
+    let dnsmasq = list.to_dns_blocklist(DnsFormat::Dnsmasq);
+    assert_eq!(dnsmasq.config, "address=/ads.example/#\n");
+    assert_eq!(dnsmasq.skipped[0], skipped(1, DnsSkipReason::ExactHost));
+    assert_eq!(dnsmasq.skipped.len(), 3);
+}
+
+#[test]
+fn pac_file_generation() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"^https?://([^/]*\\\\.)?ads\\\\.example/\" }, \
+                                \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \"(?i)^https?://cdn\\\\.example/Ads/\" }, \
+                             \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \"^https?://cdn\\\\.example/track\\\\?\" }, \
+                             \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \"^https?://cdn\\\\.example/[0-9]+\\\\.js\" }, \
+                             \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \"^https?://ads\\\\.example/\", \
+                                            \"resource-type\": [\"image\"] }, \
+                             \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \"^https?://www\\\\.ads\\\\.example/ok\" }, \
+                             \"action\": { \"type\": \"ignore-previous-rules\" } }]").unwrap();
+    let pac = list.to_pac("127.0.0.1:9");
+    assert_eq!((pac.hosts, pac.prefixes), (1, 2));
+    assert!(pac.script.starts_with("var BLACKHOLE = \"PROXY 127.0.0.1:9\";\n\
+                                    var HOSTS = {\n    \"ads.example\": 1\n};\n\
+                                    var PREFIXES = [\n    [\"cdn.example\", 0, \"/ads/\", 1],\n    \
+                                    [\"cdn.example\", 0, \"/track?\", 0]\n];\n"));
+    assert!(pac.script.contains("function FindProxyForURL(url, host) {"));
+    assert_eq!(pac.skipped, vec![PacSkip { rule: 3, reason: PacSkipReason::UrlFilter },
+                                 PacSkip { rule: 4, reason: PacSkipReason::Conditions },
+                                 PacSkip { rule: 5, reason: PacSkipReason::Exception }]);
+
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"^https?://([^/]*\\\\.)?ads\\\\.example/\" }, \
+                                \"action\": { \"type\": \"block\" } }, \
+                           { \"trigger\": { \"url-filter\": \"^https?://ads\\\\.example/\" }, \
+                             \"action\": { \"type\": \"block\" } }]").unwrap();
+    let pac = list.to_pac("127.0.0.1:9");
+    assert_eq!(pac.hosts, 1);
+    assert!(pac.script.contains("var HOSTS = {\n    \"ads.example\": 1\n};\n"));
+}
+
+#[test]
+fn indexed_matching() {
+    let rules = parse_list_impl("[{ \"trigger\": { \"url-filter\": \".*\" }, \"action\": { \"type\": \"block-cookies\" } },\
+                                 { \"trigger\": { \"url-filter\": \"^https?://([^/]*\\\\.)?ads\\\\.org/\" }, \
+                                  \"action\": { \"type\": \"block\" } },\
+                                 { \"trigger\": { \"url-filter\": \"banner\", \"if-domain\": [\"*news.org\"] }, \
+                                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".banner\" } },\
+                                 { \"trigger\": { \"url-filter\": \"ok\", \"if-domain\": [\"ads.org\"] }, \
+                                  \"action\": { \"type\": \"ignore-previous-rules\" } },\
+                                 { \"trigger\": { \"url-filter\": \".*\", \"unless-domain\": [\"ads.org\"] }, \
+                                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".generic\" } }]").unwrap();
+    let index = DomainIndex::new(&rules);
+    let backend = MatchBackendKind::Indexed.build(&rules);
+    assert_eq!(index.candidates(Some("example.org")), vec![0, 4]);
+    assert_eq!(index.candidates(Some("cdn.ads.org")), vec![0, 1, 4]);
+    assert_eq!(index.candidates(Some("ads.org")), vec![0, 1, 3, 4]);
+    assert_eq!(index.candidates(Some("www.news.org")), vec![0, 2, 4]);
+    assert_eq!(index.candidates(None), vec![0, 4]);
+
+    for url in &["http://example.org/banner",
+                 "http://cdn.ads.org/ok",
+                 "http://ads.org/ok",
+                 "http://ads.org/ads",
+                 "http://www.news.org/banner",
+                 "http://news.org/banner",
+                 "http://127.0.0.1/banner",
+                 "http://cdn.ads.org:pw@example.org/",
+                 "data:text/plain,ads.org/"] {
+        let request = Request {
+            url: &Url::parse(url).unwrap(),
+            resource_type: ResourceType::Document,
+            load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        println!("checking {:?}", url);
+        let limits = MatchLimits::default();
+        assert_eq!(process_rules_for_request_indexed_impl(&rules, &*backend, &limits, &request, &mut vec![]).0.to_vec(),
+                   process_rules_for_request_impl(&rules, &request));
+    }
+}
+
+#[test]
+fn domain_matcher_lookup() {
+    let matcher = DomainMatcher::new(["b.org", "*c.org", "a.org", "*sub.a.org", "b.org"]);
+    assert_eq!(&*matcher.exact, &["a.org".into(), "b.org".into()][..]);
+    assert_eq!(&*matcher.subdomain, &["c.org".into(), "sub.a.org".into()][..]);
+
+    for &(domain, expected) in &[("a.org", true),
+                                 ("www.a.org", false),
+                                 ("sub.a.org", true),
+                                 ("deep.sub.a.org", true),
+                                 ("notsub.a.org", false),
+                                 ("c.org", true),
+                                 ("x.y.c.org", true),
+                                 ("abc.org", false),
+                                 ("org", false),
+                                 ("", false)] {
+        println!("checking {:?}", domain);
+        assert_eq!(matcher.matches_domain(domain), expected);
+    }
+}
+
+#[test]
+fn required_literals() {
+    for &(pattern, expected) in &[("ads", Some("ads")),
+                                  ("(?i)^https?://[^/]*\\.Tracker\\.com/", Some(".tracker.com/")),
+                                  ("ad[sv]ertising", Some("ertising")),
+                                  ("banners?/", Some("banner")),
+                                  ("a(bc)*defg+", Some("defg")),
+                                  ("pixel\\d{1,3}\\.gif", Some("pixel")),
+                                  ("x{2}abc", Some("abc")),
+                                  ("ads|tracking", None),
+                                  (".*", None),
+                                  ("\\x41bc", None),
+                                  ("unbalanced)", None)] {
+        println!("checking {:?}", pattern);
+        assert_eq!(required_literal(pattern), expected.map(|e| e.to_owned()));
+    }
+}
+
+#[test]
+fn literal_prefilter() {
+    let rules = parse_list_impl("[{ \"trigger\": { \"url-filter\": \"banner\" }, \"action\": { \"type\": \"block\" } },\
+                                 { \"trigger\": { \"url-filter\": \"(?i)tracker\" }, \"action\": { \"type\": \"block-cookies\" } },\
+                                 { \"trigger\": { \"url-filter\": \".*\" }, \"action\": { \"type\": \"ignore-previous-rules\" } }]").unwrap();
+    let filter = LiteralFilter::new(&rules);
+
+    let grams = GramSet::from_text("http://example.org/index.html");
+    assert!(filter.rejects_all(&grams));
+
+    let grams = GramSet::from_text("http://example.org/TRACKER.js");
+    assert!(!filter.rejects_all(&grams));
+    assert!(filter.may_match(1, &grams));
+    assert!(filter.may_match(2, &grams));
+
+    let rules = parse_list_impl("[{ \"trigger\": { \"url-filter\": \"banner\" }, \"action\": { \"type\": \"block\" } },\
+                                 { \"trigger\": { \"url-filter\": \"[0-9]+\" }, \"action\": { \"type\": \"block\" } }]").unwrap();
+    let filter = LiteralFilter::new(&rules);
+    assert!(!filter.rejects_all(&GramSet::from_text("http://example.org/index.html")));
+}
+
+#[test]
+fn lazy_regex_compilation() {
+    let rules = parse_list_impl("[{ \"trigger\": { \"url-filter\": \"banner[0-9]+\" }, \"action\": { \"type\": \"block\" } },\
+                                 { \"trigger\": { \"url-filter\": \"tracker\" }, \"action\": { \"type\": \"block\" } }]").unwrap();
+    assert!(rules.iter().all(|rule| !rule.trigger.url_filter.is_compiled()));
+
+    let backend = MatchBackendKind::Indexed.build(&rules);
+    let url = Url::parse("http://example.org/banner1.png").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Image,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    let limits = MatchLimits::default();
+    assert_eq!(process_rules_for_request_indexed_impl(&rules, &*backend, &limits, &request, &mut vec![]).0.to_vec(),
+               vec![Reaction::Block]);
+    assert!(rules[0].trigger.url_filter.is_compiled());
+    assert!(!rules[1].trigger.url_filter.is_compiled());
+
+    assert!(UrlFilter::new("a(b").is_none());
+    assert_eq!(parse_list_strict_impl("[{ \"trigger\": { \"url-filter\": \"a{1000}{1000}\" }, \
+                                        \"action\": { \"type\": \"block\" } }]").err(),
+               Some(Error::SizeLimitExceeded { index: 0, limit: 10 * (1 << 20) }));
+}
+
+#[test]
+fn compiled_list() {
+    let rules = parse_list_impl("[{ \"trigger\": { \"url-filter\": \".*\", \"resource-type\": [\"image\", \"script\"] }, \
+                                  \"action\": { \"type\": \"block\" } },\
+                                 { \"trigger\": { \"url-filter\": \"ads\", \"load-type\": [\"third-party\"] }, \
+                                  \"action\": { \"type\": \"block-cookies\" } },\
+                                 { \"trigger\": { \"url-filter\": \"banner\", \"if-domain\": [\"*news.org\", \"example.org\"] }, \
+                                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".banner\" } },\
+                                 { \"trigger\": { \"url-filter\": \"ok\", \"unless-domain\": [\"news.org\"] }, \
+                                  \"action\": { \"type\": \"ignore-previous-rules\" } }]").unwrap();
+    let bytes = compile_impl(&rules);
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    assert_eq!(compiled.len(), 4);
+
+    for url in &["http://example.org/banner.png",
+                 "http://www.news.org/ads/banner",
+                 "http://news.org/ok.js",
+                 "http://ads.org/ok.js",
+                 "http://example.com/banner"] {
+        let url = Url::parse(url).unwrap();
+        for &(resource_type, load_type) in &[(ResourceType::Image, LoadType::FirstParty),
+                                             (ResourceType::Document, LoadType::ThirdParty)] {
+            let request = Request {
+                url: &url,
+                resource_type,
+                load_type,
+                context: None,
+                opener: None,
+                document: None,
+                redirect_chain: &[],
+                headers: None,
+            };
+            assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request),
+                       process_rules_for_request_impl(&rules, &request));
+        }
+    }
+
+    assert_eq!(CompiledRuleList::from_bytes(&bytes[..bytes.len() - 1]).err(), Some(Error::InvalidCompiledList));
+    assert_eq!(CompiledRuleList::from_bytes(b"not a list").err(), Some(Error::InvalidCompiledList));
+    assert!(CompiledRuleList::from_bytes(&compile_impl(&[])).unwrap().is_empty());
+}
+
+#[test]
+fn memory_usage() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads[0-9]+\", \"if-domain\": [\"*example.org\"] }, \
+                            \"action\": { \"type\": \"block\" } },\
+                           { \"trigger\": { \"url-filter\": \"banner\" }, \
+                            \"action\": { \"type\": \"css-display-none\", \"selector\": \".banner\" } }]").unwrap();
+    let report = list.memory_usage();
+    assert_eq!(report.regex_programs, 0);
+    assert!(report.domain_tables >= "example.org".len());
+    assert!(report.selectors >= ".banner".len());
+    assert!(report.indexes > 0);
+    assert!(report.rules > 0);
+
+    let url = Url::parse("http://example.org/ads12").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Image,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert_eq!(process_rules_for_request(&list, &request), vec![Reaction::Block]);
+    let compiled = list.memory_usage();
+    assert!(compiled.regex_programs > 0);
+    assert_eq!(compiled.total(), report.total() + compiled.regex_programs);
+}
+
+#[test]
+fn interned_strings() {
+    let rules = parse_list_impl("[{ \"trigger\": { \"url-filter\": \"a\", \"if-domain\": [\"example.org\"] }, \
+                                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } },\
+                                 { \"trigger\": { \"url-filter\": \"b\", \"unless-domain\": [\"example.org\"] }, \
+                                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } }]").unwrap();
+    let domains: Vec<_> = rules.iter().map(|rule| match rule.trigger.domain_constraint {
+        Some(DomainConstraint::If(ref matcher)) | Some(DomainConstraint::Unless(ref matcher)) => matcher.exact[0].clone(),
+        None => panic!("missing domain constraint"),
+    }).collect();
+    assert!(Arc::ptr_eq(&domains[0], &domains[1]));
+    match (&rules[0].action, &rules[1].action) {
+        (Action::CssDisplayNone(a), Action::CssDisplayNone(b)) => assert!(Arc::ptr_eq(a, b)),
+        _ => panic!("unexpected actions"),
+    }
+}
+
+#[test]
+fn borrowed_reactions() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                           { \"trigger\": { \"url-filter\": \"ads\" }, \
+                            \"action\": { \"type\": \"css-display-none\", \"selector\": \".banner\" } }]").unwrap();
+    let url = Url::parse("http://example.org/ads").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Image,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    let reactions = process_rules_for_request_borrowed(&list, &request);
+    assert_eq!(&*reactions, &[ReactionRef::Block, ReactionRef::HideMatchingElements(".banner")]);
+    assert_eq!(reactions.to_vec(), process_rules_for_request(&list, &request));
+}
+
+#[test]
+fn first_block_policy() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                           { \"trigger\": { \"url-filter\": \"ads\", \"if-domain\": [\"ok.org\"] }, \
+                            \"action\": { \"type\": \"ignore-previous-rules\" } },\
+                           { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                           { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block-cookies\" } }]").unwrap();
+    let url = Url::parse("http://example.org/ads").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Image,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert_eq!(&*process_rules_for_request_with_policy(&list, &request, MatchPolicy::FirstBlock),
+               &[ReactionRef::Block, ReactionRef::Block]);
+    assert_eq!(&*process_rules_for_request_with_policy(&list, &request, MatchPolicy::All),
+               &[ReactionRef::Block, ReactionRef::Block, ReactionRef::BlockCookies(CookieScope::Request)]);
+
+    // A block before an exception is not final.
+    let url = Url::parse("http://ok.org/ads").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Image,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert_eq!(&*process_rules_for_request_with_policy(&list, &request, MatchPolicy::FirstBlock),
+               &[ReactionRef::Block]);
+}
+
+#[test]
+fn exception_reachability() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\", \"if-domain\": [\"*ads.org\"] }, \
+                            \"action\": { \"type\": \"block\" } },\
+                           { \"trigger\": { \"url-filter\": \"ads\", \"resource-type\": [\"script\"] }, \
+                            \"action\": { \"type\": \"block\" } },\
+                           { \"trigger\": { \"url-filter\": \"ok\", \"if-domain\": [\"ok.org\"] }, \
+                            \"action\": { \"type\": \"ignore-previous-rules\" } },\
+                           { \"trigger\": { \"url-filter\": \"ok\", \"unless-domain\": [\"*ads.org\"], \
+                                            \"resource-type\": [\"image\"] }, \
+                            \"action\": { \"type\": \"ignore-previous-rules\" } }]").unwrap();
+    let unreachable: Vec<usize> = list.unreachable_exceptions().map(|rule| rule.index()).collect();
+    assert_eq!(unreachable, vec![3]);
+
+    let reachability = Reachability::new(&list.rules);
+    assert_eq!(reachability.final_blocks, vec![true, false, false, false]);
+
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                           { \"trigger\": { \"url-filter\": \"ok\", \"if-domain\": [\"*sub.ads.org\"] }, \
+                            \"action\": { \"type\": \"ignore-previous-rules\" } }]").unwrap();
+    assert_eq!(list.unreachable_exceptions().count(), 0);
+    assert_eq!(Reachability::new(&list.rules).final_blocks, vec![false, false]);
+}
+
+#[test]
+fn deadline_matching() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } }]").unwrap();
+    let url = Url::parse("http://example.org/ads").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Image,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert_eq!(process_rules_for_request_with_deadline(&list, &request, Duration::from_secs(60)),
+               BoundedReactions { reactions: vec![Reaction::Block], complete: true });
+    assert_eq!(process_rules_for_request_with_deadline(&list, &request, Duration::new(0, 0)),
+               BoundedReactions { reactions: vec![], complete: false });
+
+    // Requests rejected by the prefilter need no time at all.
+    let url = Url::parse("http://example.org/").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Image,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert!(process_rules_for_request_with_deadline(&list, &request, Duration::new(0, 0)).complete);
+}
+
+#[test]
+fn string_request_urls() {
+    assert_eq!(RequestUrl::host("https://user:pw@www.example.org:8080/a@b"), Some("www.example.org"));
+    assert!(RequestUrl::has_credentials("https://user:pw@www.example.org:8080/a"));
+    assert!(!RequestUrl::has_credentials("https://www.example.org/a@b"));
+    assert_eq!(RequestUrl::host("http://[::1]:80/"), Some("::1"));
+    assert_eq!(RequestUrl::domain("http://[::1]:80/"), None);
+    assert_eq!(RequestUrl::domain("http://127.0.0.1/"), None);
+    assert_eq!(RequestUrl::host("data:text/plain,hello"), None);
+
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"^https?://([^/]*\\\\.)?ads\\\\.org/\" }, \
+                            \"action\": { \"type\": \"block\" } },\
+                           { \"trigger\": { \"url-filter\": \"banner\", \"unless-domain\": [\"*news.org\"] }, \
+                            \"action\": { \"type\": \"css-display-none\", \"selector\": \".banner\" } }]").unwrap();
+    for s in &["http://cdn.ads.org/banner", "http://news.org/banner", "http://cdn.ads.org:pw@example.org/banner"] {
+        let url = Url::parse(s).unwrap();
+        let parsed = Request {
+            url: &url,
+            resource_type: ResourceType::Image,
+            load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        let serialized = Request {
+            url: s,
+            resource_type: ResourceType::Image,
+            load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        assert_eq!(process_rules_for_request(&list, &serialized), process_rules_for_request(&list, &parsed));
+    }
+}
+
+#[test]
+fn owned_requests() {
+    let url = Url::parse("http://example.org/ads").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Script,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    let owned = OwnedRequest::from(&request);
+    assert_eq!(owned, OwnedRequest {
+        url: "http://example.org/ads".to_owned(),
+        resource_type: ResourceType::Script,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: vec![],
+        headers: None,
+    });
+
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\", \"load-type\": [\"third-party\"] }, \
+                            \"action\": { \"type\": \"block\" } }]").unwrap();
+    let handle = thread::spawn(move || owned);
+    let owned = handle.join().unwrap();
+    assert_eq!(process_rules_for_request(&list, &owned.as_request()), vec![Reaction::Block]);
+}
+
+#[test]
+fn infer_resource_type() {
+    assert_eq!(ResourceType::infer("https://example.org/a.js", None, Some("image")), ResourceType::Image);
+    assert_eq!(ResourceType::infer("https://example.org/a", None, Some("iframe")), ResourceType::Document);
+    assert_eq!(ResourceType::infer("https://example.org/a", Some("image/avif,image/webp,*/*;q=0.8"), Some("")),
+               ResourceType::Image);
+    assert_eq!(ResourceType::infer("https://example.org/a", Some("text/css,*/*;q=0.1"), None), ResourceType::StyleSheet);
+    assert_eq!(ResourceType::infer("https://example.org/lib.min.JS?v=2#x", Some("*/*"), None), ResourceType::Script);
+    assert_eq!(ResourceType::infer("https://example.org/fonts/a.woff2", None, None), ResourceType::Font);
+    assert_eq!(ResourceType::infer("https://example.org/clip.webm", None, None), ResourceType::Media);
+    assert_eq!(ResourceType::infer("https://example.org", None, None), ResourceType::Raw);
+    assert_eq!(ResourceType::infer("https://example.org/api/v1.0/items", None, None), ResourceType::Raw);
+    assert_eq!(ResourceType::infer("wss://example.org/feed.js", None, None), ResourceType::WebSocket);
+    assert_eq!(ResourceType::infer("https://example.org/feed", None, Some("websocket")), ResourceType::WebSocket);
+}
+
+#[test]
+fn fetch_destinations() {
+    for &(name, destination, resource_type) in &[("", Destination::Empty, ResourceType::Raw),
+                                                 ("audio", Destination::Audio, ResourceType::Media),
+                                                 ("iframe", Destination::IFrame, ResourceType::Document),
+                                                 ("xslt", Destination::Xslt, ResourceType::StyleSheet),
+                                                 ("manifest", Destination::Manifest, ResourceType::Raw),
+                                                 ("report", Destination::Report, ResourceType::CspReport),
+                                                 ("paintworklet", Destination::PaintWorklet, ResourceType::Script),
+                                                 ("serviceworker", Destination::ServiceWorker, ResourceType::ServiceWorker)] {
+        assert_eq!(name.parse(), Ok(destination));
+        assert_eq!(ResourceType::from(destination), resource_type, "{}", name);
+    }
+    assert_eq!("navigation".parse::<Destination>(), Err(()));
+
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"clip\", \"resource-type\": [\"media\"] }, \
+                             \"action\": { \"type\": \"block\" } }]").unwrap();
+    let request = Request {
+        url: &"https://cdn.example/clip.mp4",
+        resource_type: Destination::Video.into(),
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert_eq!(process_rules_for_request(&list, &request), vec![Reaction::Block]);
+}
+
+#[test]
+fn hiding_exceptions() {
+    let body = "[{ \"trigger\": { \"url-filter\": \".*\" }, \
+                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } },\
+                 { \"trigger\": { \"url-filter\": \".*\", \"if-domain\": [\"*shop.org\"] }, \
+                  \"action\": { \"type\": \"css-display-none-exception\", \"selector\": \".ad\" } },\
+                 { \"trigger\": { \"url-filter\": \".*\" }, \
+                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } },\
+                 { \"trigger\": { \"url-filter\": \".*\" }, \
+                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".banner\" } }]";
+    let mut list = parse_list(body).unwrap();
+    let compiled_bytes = list.to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&compiled_bytes).unwrap();
+    for &(url, expected) in &[("http://example.org/", &[".ad", ".ad", ".banner"][..]),
+                              ("http://www.shop.org/", &[".banner"][..])] {
+        let url = Url::parse(url).unwrap();
+        let request = Request {
+            url: &url,
+            resource_type: ResourceType::Document,
+            load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        let expected: Vec<_> = expected.iter().map(|s| Reaction::HideMatchingElements((*s).to_owned())).collect();
+        assert_eq!(process_rules_for_request(&list, &request), expected);
+        assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected);
+    }
+
+    // Selectors are not combined, since that would hide them from the exception.
+    let report = list.optimize();
+    assert_eq!(report.selectors_merged, 0);
+    assert_eq!(list.with_action(ActionType::CssDisplayNoneException).count(), 1);
+}
+
+#[test]
+fn procedural_selectors() {
+    let selector = ProceduralSelector::parse("div.ad:has(> a[href*=\"(x)\"]:has-text(/promo(ted)?/i)):upward(2) span").unwrap();
+    assert_eq!(selector.steps, vec![
+        ProceduralStep::Css("div.ad".to_owned()),
+        ProceduralStep::Has(ProceduralSelector {
+            source: "> a[href*=\"(x)\"]:has-text(/promo(ted)?/i)".to_owned(),
+            steps: vec![
+                ProceduralStep::Css("> a[href*=\"(x)\"]".to_owned()),
+                ProceduralStep::HasText(TextMatcher::Regex { pattern: "promo(ted)?".to_owned(), flags: "i".to_owned() }),
+            ],
+        }),
+        ProceduralStep::Upward(Upward::Levels(2)),
+        ProceduralStep::Css(" span".to_owned()),
+    ]);
+    assert_eq!(ProceduralSelector::parse(".x:has-text(\"Sponsored\")").unwrap().steps[1],
+               ProceduralStep::HasText(TextMatcher::Contains("Sponsored".to_owned())));
+    assert_eq!(ProceduralSelector::parse(".x:upward(.card)").unwrap().steps[1],
+               ProceduralStep::Upward(Upward::Closest(".card".to_owned())));
+    assert!(ProceduralSelector::parse(".x:upward(0)").is_none());
+    assert!(ProceduralSelector::parse(".x:has-text(oops").is_none());
+
+    let body = "[{ \"trigger\": { \"url-filter\": \".*\" }, \
+                  \"action\": { \"type\": \"css-display-none\", \"selector\": \"article:has-text(Sponsored)\" } },\
+                 { \"trigger\": { \"url-filter\": \".*\" }, \
+                  \"action\": { \"type\": \"css-display-none\", \"selector\": \"article:has(.ad)\" } }]";
+    let list = parse_list(body).unwrap();
+    let url = Url::parse("http://example.org/").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Document,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    let expected = vec![
+        Reaction::HideProceduralElements(ProceduralSelector::parse("article:has-text(Sponsored)").unwrap()),
+        Reaction::HideMatchingElements("article:has(.ad)".to_owned()),
+    ];
+    assert_eq!(process_rules_for_request(&list, &request), expected);
+    let bytes = list.to_bytes();
+    assert_eq!(process_rules_for_request_compiled_impl(&CompiledRuleList::from_bytes(&bytes).unwrap(), &request),
+               expected);
+
+    assert_eq!(parse_list_strict_impl("[{ \"trigger\": { \"url-filter\": \".*\" }, \
+                                        \"action\": { \"type\": \"css-display-none\", \"selector\": \"a:upward(x\" } }]").err(),
+               Some(Error::InvalidSelector { index: 0, selector: "a:upward(x".to_owned() }));
+}
+
+#[test]
+fn css_injection() {
+    let body = "[{ \"trigger\": { \"url-filter\": \".*\" }, \
+                  \"action\": { \"type\": \"css-inject\", \"selector\": \"body\", \
+                                \"style\": \"overflow: auto !important\" } },\
+                 { \"trigger\": { \"url-filter\": \".*\" }, \
+                  \"action\": { \"type\": \"css-display-none-exception\", \"selector\": \"body\" } }]";
+    let list = parse_list(body).unwrap();
+    let url = Url::parse("http://example.org/").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Document,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    // Hiding exceptions do not apply to injected styles.
+    let expected = vec![Reaction::InjectStyle {
+        selector: "body".to_owned(),
+        declarations: "overflow: auto !important".to_owned(),
+    }];
+    assert_eq!(process_rules_for_request(&list, &request), expected);
+    let bytes = list.to_bytes();
+    assert_eq!(process_rules_for_request_compiled_impl(&CompiledRuleList::from_bytes(&bytes).unwrap(), &request),
+               expected);
+    let rule = list.with_action(ActionType::CssInject).next().unwrap();
+    assert_eq!(rule.selector(), Some("body"));
+    assert_eq!(rule.style(), Some("overflow: auto !important"));
+
+    assert_eq!(parse_list_strict_impl("[{ \"trigger\": { \"url-filter\": \".*\" }, \
+                                        \"action\": { \"type\": \"css-inject\", \"selector\": \"a\", \
+                                                      \"style\": \"color: red } b { color: blue\" } }]").err(),
+               Some(Error::InvalidStyle { index: 0, style: "color: red } b { color: blue".to_owned() }));
+}
+
+#[test]
+fn selector_validation() {
+    use selector::is_valid_selector;
+
+    assert!(is_valid_selector("div.ad > a[href^=\"http://ads.\"], #banner"));
+    assert!(is_valid_selector("article:has(.sponsor):not(:first-child)"));
+    assert!(!is_valid_selector("  "));
+    assert!(!is_valid_selector("a } body { display: none"));
+    assert!(!is_valid_selector("</style><script>"));
+    #[cfg(feature = "selector-validation")]
+    {
+        assert!(is_valid_selector("a:hover::before"));
+        assert!(!is_valid_selector("div["));
+        assert!(!is_valid_selector("div:made-up"));
+        assert!(!is_valid_selector(".ad,"));
+    }
+
+    let body = "[{ \"trigger\": { \"url-filter\": \".*\" }, \
+                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad } *\" } },\
+                 { \"trigger\": { \"url-filter\": \".*\" }, \
+                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".banner\" } }]";
+    assert_eq!(parse_list(body).unwrap().len(), 1);
+    assert_eq!(parse_list_strict_impl(body).err(),
+               Some(Error::InvalidSelector { index: 0, selector: ".ad } *".to_owned() }));
+}
+
+#[test]
+fn redirect_resources() {
+    for resource in Resource::all() {
+        assert!(resource.data_uri.starts_with(&format!("data:{}", resource.content_type)));
+        assert_eq!(Resource::named(resource.name), Some(resource));
+    }
+    assert_eq!(Resource::named("noop.exe"), None);
+
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\\\\.js\" }, \
+                  \"action\": { \"type\": \"redirect\", \"resource\": \"noop.js\" } },\
+                 { \"trigger\": { \"url-filter\": \".*\" }, \
+                  \"action\": { \"type\": \"redirect\", \"resource\": \"noop.exe\" } }]";
+    let list = parse_list(body).unwrap();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list.with_action(ActionType::Redirect).next().unwrap().resource(), Resource::named("noop.js"));
+    let url = Url::parse("http://example.org/ads.js").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Script,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    let expected = vec![Reaction::Redirect(Resource::named("noop.js").unwrap())];
+    assert_eq!(process_rules_for_request(&list, &request), expected);
+    let bytes = list.to_bytes();
+    assert_eq!(process_rules_for_request_compiled_impl(&CompiledRuleList::from_bytes(&bytes).unwrap(), &request),
+               expected);
+    assert_eq!(parse_list_strict_impl(body).err(),
+               Some(Error::UnknownResource { index: 1, name: "noop.exe".to_owned() }));
+}
+
+#[test]
+fn cookie_scopes() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"a\" }, \"action\": { \"type\": \"block-cookies\" } },\
+                 { \"trigger\": { \"url-filter\": \"b\" }, \
+                   \"action\": { \"type\": \"block-cookies\", \"scope\": \"response\" } },\
+                 { \"trigger\": { \"url-filter\": \"c\" }, \
+                   \"action\": { \"type\": \"block-cookies\", \"scope\": \"both\" } }]";
+    let list = parse_list(body).unwrap();
+    let bytes = list.to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    for &(url, scope) in &[("http://a.org/", CookieScope::Request),
+                           ("http://b.org/", CookieScope::Response),
+                           ("http://c.org/", CookieScope::Both)] {
+        let request = Request {
+            url: &url,
+            resource_type: ResourceType::Document,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        let expected = vec![Reaction::BlockCookies(scope)];
+        assert_eq!(process_rules_for_request(&list, &request), expected);
+        assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected);
+    }
+    assert!(CookieScope::Both.applies_to_request() && CookieScope::Both.applies_to_response());
+    assert!(!CookieScope::Request.applies_to_response() && !CookieScope::Response.applies_to_request());
+
+    assert_eq!(parse_list_strict_impl("[{ \"trigger\": { \"url-filter\": \"\" }, \
+                                        \"action\": { \"type\": \"block-cookies\", \"scope\": \"all\" } }]").err(),
+               Some(Error::MissingField { index: 0, field: "action.scope" }));
+}
+
+#[test]
+fn named_cookies() {
+    let body = "[{ \"trigger\": { \"url-filter\": \".*\" }, \
+                   \"action\": { \"type\": \"block-cookies\", \"names\": [\"_ga\", \"_gid*\", \"*_track_*id\"] } }]";
+    let list = parse_list(body).unwrap();
+    let request = Request {
+        url: &"http://example.org/",
+        resource_type: ResourceType::Document,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    let names = vec!["_ga".to_owned(), "_gid*".to_owned(), "*_track_*id".to_owned()];
+    let expected = vec![Reaction::BlockCookiesMatching(names)];
+    assert_eq!(process_rules_for_request(&list, &request), expected);
+    let bytes = list.to_bytes();
+    assert_eq!(process_rules_for_request_compiled_impl(&CompiledRuleList::from_bytes(&bytes).unwrap(), &request),
+               expected);
+
+    for &(name, blocked) in &[("_ga", true), ("_gat", false), ("_gid", true), ("_gid_2", true),
+                              ("x_track_id", true), ("x_track_userid", true), ("_track_ids", false)] {
+        assert_eq!(expected[0].blocks_cookie(name), blocked, "{}", name);
+    }
+    assert!(Reaction::BlockCookies(CookieScope::Response).blocks_cookie("session"));
+    assert!(!Reaction::Block.blocks_cookie("session"));
+
+    for names in &["[]", "[\"a=b\"]", "\"_ga\""] {
+        let body = format!("[{{ \"trigger\": {{ \"url-filter\": \"\" }}, \
+                              \"action\": {{ \"type\": \"block-cookies\", \"names\": {} }} }}]", names);
+        assert_eq!(parse_list_strict_impl(&body).err(), Some(Error::MissingField { index: 0, field: "action.names" }));
+    }
+}
+
+#[test]
+fn source_locations() {
+    let body = "[\n  { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\n\
+                \x20 { \"trigger\": { \"url-filter\": \"(\" }, \"action\": { \"type\": \"block\" } },\n\
+                \x20 { \"trigger\": { \"url-filter\": \"track\" },\n    \"action\": { \"type\": \"block\" } }\n]";
+    let list = parse_list(body).unwrap();
+    let sources: Vec<_> = list.iter().map(|rule| rule.source().unwrap()).collect();
+    let second = body.find("{ \"trigger\": { \"url-filter\": \"track").unwrap();
+    assert_eq!(sources, vec![SourceLocation { index: 0, offset: 4, line: 2 },
+                             SourceLocation { index: 2, offset: second, line: 4 }]);
+    assert_eq!(&body[second..second + 12], "{ \"trigger\":");
+    assert_eq!(parse_list_from_slice(body.as_bytes()).unwrap().get(1).unwrap().source(), Some(sources[1]));
+
+    // Rules compare equal wherever they are found.
+    let moved = parse_list(&body.replace("\n", "\n\n")).unwrap();
+    assert_eq!(moved.get(1).unwrap().source().unwrap().line, 7);
+    assert_eq!(moved.rules, list.rules);
+    assert!(diff(&list, &moved).is_empty());
+}
+
+#[test]
+fn display_rules() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\\\\.js\", \"resource-type\": [\"script\", \"image\"], \
+                                  \"load-type\": [\"third-party\"], \"unless-domain\": [\"example.com\", \"*news.org\"] }, \
+                   \"action\": { \"type\": \"block\" }, \"tags\": [\"social\", \"annoyances\"] },\
+                 { \"trigger\": { \"url-filter\": \".*\", \"if-domain\": [\"*example.com\"], \"context\": [\"private\"] }, \
+                   \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } },\
+                 { \"trigger\": { \"url-filter\": \"track\", \"user-initiated\": false }, \
+                   \"action\": { \"type\": \"block-cookies\", \"names\": [\"_ga\", \"id*\"] } }]";
+    let list = parse_list(body).unwrap();
+    let lines: Vec<String> = list.iter().map(|rule| rule.to_string()).collect();
+    assert_eq!(lines, vec![
+        "ads\\.js resource-type=script,image load-type=third-party unless-domain=example.com,*news.org -> block \
+         group=annoyances,social",
+        ".* if-domain=*example.com context=private -> css-display-none .ad",
+        "track user-initiated=false -> block-cookies names=_ga,id*",
+    ]);
+    assert_eq!(format!("{:?}", UrlFilter::new("ads").unwrap()), "UrlFilter(\"ads\")");
+}
+
+#[test]
+fn json_round_trip() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\", \"url-filter-is-case-sensitive\": true, \
+                                  \"resource-type\": [\"script\", \"image\"], \"load-type\": [\"third-party\"], \
+                                  \"load-context\": [\"child-frame\"], \"unless-domain\": [\"example.com\", \"*news.org\"] }, \
+                   \"action\": { \"type\": \"block\" }, \"tags\": [\"social\"] },\
+                 { \"trigger\": { \"url-filter\": \".*\", \"if-domain\": [\"*example.*\"], \"context\": [\"private\"], \
+                                  \"user-initiated\": true, \"opener-domain\": [\"news.org\"] }, \
+                   \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad:has-text(Sponsored)\" } },\
+                 { \"trigger\": { \"url-filter\": \"track\" }, \
+                   \"action\": { \"type\": \"block-cookies\", \"names\": [\"_ga\", \"id*\"] } },\
+                 { \"trigger\": { \"url-filter\": \"track\", \"resource-type\": [] }, \
+                   \"action\": { \"type\": \"block-cookies\", \"scope\": \"both\" } },\
+                 { \"trigger\": { \"url-filter\": \"x\" }, \"action\": { \"type\": \"redirect\", \"resource\": \"noop.js\" } },\
+                 { \"trigger\": { \"url-filter\": \"x\" }, \
+                   \"action\": { \"type\": \"css-inject\", \"selector\": \"body\", \"style\": \"color: red\" } },\
+                 { \"trigger\": { \"url-filter\": \"x\" }, \
+                   \"action\": { \"type\": \"css-display-none-exception\", \"selector\": \".ad\" } },\
+                 { \"trigger\": { \"url-filter\": \"x\" }, \"action\": { \"type\": \"ignore-previous-rules\" } }]";
+    let list = parse_list_strict_impl(body).unwrap();
+    let reparsed = parse_list_strict_impl(&RuleList::new(list.clone()).to_json_string()).unwrap();
+    assert_eq!(reparsed, list);
+    assert_eq!(reparsed[0].trigger.url_filter.as_str(), "(?i)ads");
+
+    let list = RuleList::new(list);
+    let json: serde_json::Value = serde_json::from_str(&list.get(4).unwrap().to_json()).unwrap();
+    assert_eq!(json.pointer("/action/resource").and_then(|r| r.as_str()), Some("noop.js"));
+    assert_eq!(json.pointer("/trigger/url-filter-is-case-sensitive"), None);
+}
+
+#[test]
+fn rule_ids() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                           { \"trigger\": { \"url-filter\": \"track\", \"load-type\": [\"third-party\"] }, \
+                             \"action\": { \"type\": \"block\" } },\
+                           { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } }]").unwrap();
+    let ids = list.ids().to_vec();
+    assert_ne!(ids[0], ids[1]);
+    assert_eq!(ids[0], ids[2]);
+    assert_eq!(list.get(1).unwrap().id(), ids[1]);
+    assert_eq!(ids[1].to_string().len(), 16);
+
+    // Identifiers survive reordering and reformatting between updates.
+    let updated = parse_list("[{ \"trigger\": { \"url-filter\": \"banner\" }, \"action\": { \"type\": \"block\" } },\n\
+                              { \"action\": { \"type\": \"block\" }, \
+                                \"trigger\": { \"load-type\": [\"third-party\"], \"url-filter\": \"track\" } }]").unwrap();
+    assert_eq!(updated.position(ids[1]), Some(1));
+    assert_eq!(updated.position(ids[0]), None);
+
+    let mut profile = Profile::default();
+    let request = Request {
+        url: &Url::parse("https://example.com/track").unwrap(),
+        resource_type: ResourceType::Image,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    process_rules_for_request_profiled_impl(&updated.rules, None, &request, &mut profile);
+    assert!(profile.slowest(&updated, 2).iter().all(|timing| timing.id == updated.ids()[timing.index]));
+}
+
+#[test]
+fn hashable_rules() {
+    use std::collections::HashSet;
+
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\", \"if-domain\": [\"*example.com\"] }, \
+                   \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"ads\", \"if-domain\": [\"*example.com\"] }, \
+                   \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } }]";
+    let rules = parse_list_impl(body).unwrap();
+    // Compiling a filter does not change how it compares or hashes.
+    rules[0].trigger.url_filter.compile().unwrap();
+    let set: HashSet<&Rule> = rules.iter().collect();
+    assert_eq!(set.len(), 2);
+    assert!(set.contains(&rules[1]));
+    let triggers: HashSet<&Trigger> = rules.iter().map(|rule| &rule.trigger).collect();
+    assert_eq!(triggers.len(), 2);
+
+    let list = RuleList::new(rules);
+    let views: HashSet<_> = list.iter().collect();
+    assert_eq!(views.len(), 2);
+}
+
+#[test]
+fn merge_lists() {
+    let ads = "[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                { \"trigger\": { \"url-filter\": \"track\" }, \"action\": { \"type\": \"block\" } }]";
+    let allow = "[{ \"trigger\": { \"url-filter\": \"track\" }, \"action\": { \"type\": \"block\" } },\
+                  { \"trigger\": { \"url-filter\": \"ads\", \"if-domain\": [\"example.com\"] }, \
+                    \"action\": { \"type\": \"ignore-previous-rules\" } },\
+                  { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block-cookies\" } }]";
+    let request = |url| {
+        let url = Url::parse(url).unwrap();
+        move |list: &RuleList| process_rules_for_request(list, &Request {
+            url: &url,
+            resource_type: ResourceType::Image,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        })
+    };
+    let merge = |first, second, policy| {
+        let mut second = parse_list(second).unwrap();
+        second.set_group_enabled("unused", false);
+        RuleList::merge(vec![parse_list(first).unwrap(), second], policy)
+    };
+
+    // Identical rules are only kept once.
+    let merged = merge(ads, allow, MergePolicy::Concatenate);
+    assert_eq!(merged.len(), 4);
+    assert!(!merged.is_group_enabled("unused"));
+    // The exception of the second list cancels the blocking rule of the first.
+    assert_eq!(request("https://example.com/ads")(&merged), vec![Reaction::BlockCookies(CookieScope::Request)]);
+    // But not that of a later list.
+    let merged = merge(allow, ads, MergePolicy::Concatenate);
+    assert_eq!(request("https://example.com/ads")(&merged),
+               vec![Reaction::BlockCookies(CookieScope::Request), Reaction::Block]);
+
+    let merged = merge(allow, ads, MergePolicy::ExceptionsWin);
+    assert_eq!(request("https://example.com/ads")(&merged), vec![]);
+    assert_eq!(request("https://news.org/ads")(&merged),
+               vec![Reaction::BlockCookies(CookieScope::Request), Reaction::Block]);
+    assert_eq!(request("https://example.com/track")(&merged), vec![Reaction::Block]);
+}
+
+#[test]
+fn user_rules() {
+    let subscription = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                                    { \"trigger\": { \"url-filter\": \"ads\", \"if-domain\": [\"example.com\"] }, \
+                                      \"action\": { \"type\": \"ignore-previous-rules\" } },\
+                                    { \"trigger\": { \"url-filter\": \"track\" }, \"action\": { \"type\": \"block\" } },\
+                                    { \"trigger\": { \"url-filter\": \".*\" }, \
+                                      \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } }]").unwrap();
+    let mut engine = Engine::new();
+    engine.add_list(subscription);
+    let process = |engine: &Engine, url: &str| {
+        let url = Url::parse(url).unwrap();
+        engine.process(&Request {
+            url: &url,
+            resource_type: ResourceType::Script,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        })
+    };
+    let hide = || Reaction::HideMatchingElements(".ad".to_owned());
+    assert_eq!(process(&engine, "https://example.com/ads"), vec![hide()]);
+
+    // A user block applies despite the exception of the list.
+    let block = engine.add_user_rule("{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } }")
+                      .unwrap();
+    assert_eq!(process(&engine, "https://example.com/ads"), vec![hide(), Reaction::Block]);
+    // A user exception cancels the reactions of every list.
+    engine.add_user_rule("{ \"trigger\": { \"url-filter\": \"track\" }, \"action\": { \"type\": \"ignore-previous-rules\" } }")
+          .unwrap();
+    assert_eq!(process(&engine, "https://example.com/track"), vec![]);
+    engine.add_user_rule("{ \"trigger\": { \"url-filter\": \"news\" }, \
+                            \"action\": { \"type\": \"css-display-none-exception\", \"selector\": \".ad\" } }").unwrap();
+    assert_eq!(process(&engine, "https://news.org/"), vec![]);
+    assert_eq!(process(&engine, "https://example.org/"), vec![hide()]);
+    assert!(engine.add_user_rule("{ \"trigger\": { \"url-filter\": \"(\" }, \"action\": { \"type\": \"block\" } }").is_err());
+    assert_eq!(engine.user_rules().len(), 3);
+
+    let url = Url::parse("https://news.org/ads").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Script,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    let provenance: Vec<_> = engine.process_with_provenance(&request).into_iter().map(|(_, p)| p).collect();
+    assert_eq!(provenance, vec![Provenance { list: None, rule: 0, id: engine.lists()[0].ids()[0] },
+                                Provenance { list: Some("user rules"), rule: 0, id: block }]);
+
+    // The user rules survive being saved and restored.
+    let saved = engine.user_rules_to_json();
+    let mut restored = Engine::new();
+    restored.add_list(engine.lists()[0].clone());
+    restored.set_user_rules(&saved).unwrap();
+    for url in &["https://example.com/ads", "https://example.com/track", "https://news.org/", "https://example.org/"] {
+        assert_eq!(process(&restored, url), process(&engine, url));
+    }
+    assert!(restored.remove_user_rule(block));
+    assert!(!restored.remove_user_rule(block));
+    assert_eq!(process(&restored, "https://example.com/ads"), vec![hide()]);
+}
+
+#[test]
+fn redirect_chains() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"^https?://tracker\\\\.example/\", \"match-redirect-chain\": true }, \
+                   \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"^https?://ads\\\\.example/\" }, \
+                   \"action\": { \"type\": \"block\" } }]";
+    let list = parse_list(body).unwrap();
+    assert!(list.get(0).unwrap().match_redirect_chain());
+    assert!(!list.get(1).unwrap().match_redirect_chain());
+    assert_eq!(list.get(0).unwrap().to_string(),
+               "^https?://tracker\\.example/ match-redirect-chain -> block");
+    assert_eq!(parse_list(&list.to_json_string()).unwrap().get(0), list.get(0));
+    let bytes = list.to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    let bounce = |chain: &[&str]| chain.iter().map(|url| Url::parse(url).unwrap()).collect::<Vec<_>>();
+    for &(ref chain, expected) in &[(bounce(&[]), &[][..]),
+                                    (bounce(&["https://news.example/", "https://tracker.example/r?to=cdn"]),
+                                     &[Reaction::Block][..]),
+                                    (bounce(&["https://ads.example/click"]), &[][..])] {
+        let request = Request {
+            url: &"https://cdn.example/landing",
+            resource_type: ResourceType::Document,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: chain,
+            headers: None,
+        };
+        assert_eq!(process_rules_for_request(&list, &request), expected, "{:?}", chain);
+        assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected);
+        assert_eq!(process_rules_for_request(&list, &OwnedRequest::from(&request).as_request()), expected);
+    }
+
+    let broad = parse_list("[{ \"trigger\": { \"url-filter\": \".*\" }, \"action\": { \"type\": \"block\" } },\
+                             { \"trigger\": { \"url-filter\": \"x\", \"match-redirect-chain\": true }, \
+                               \"action\": { \"type\": \"block\" } }]").unwrap();
+    assert_eq!(broad.lint(), vec![LintFinding { rule: 0, kind: LintKind::MatchesEverything }]);
+}
+
+#[test]
+fn cname_uncloaking() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \".*\", \"if-domain\": [\"*tracker.example\"] }, \
+                              \"action\": { \"type\": \"block\" } },\
+                            { \"trigger\": { \"url-filter\": \"^https?://metrics\\\\.tracker\\\\.example/\" }, \
+                              \"action\": { \"type\": \"block-cookies\" } }]").unwrap();
+    let mut engine = Engine::new();
+    engine.add_list(list);
+    let process = |engine: &Engine, url: &str| {
+        let url = Url::parse(url).unwrap();
+        engine.process(&Request {
+            url: &url,
+            resource_type: ResourceType::Script,
+            load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        })
+    };
+    let cloaked = "https://stats.news.example/collect?id=1";
+    assert_eq!(process(&engine, cloaked), vec![]);
+
+    engine.set_cname_resolver(|host: &str| match host {
+        "stats.news.example" => Some("metrics.tracker.example.".to_owned()),
+        "www.news.example" => Some("WWW.news.example".to_owned()),
+        _ => None,
+    });
+    assert_eq!(process(&engine, cloaked), vec![Reaction::Block, Reaction::BlockCookies(CookieScope::Request)]);
+    assert_eq!(process(&engine, "https://www.news.example/"), vec![]);
+    assert_eq!(process(&engine, "https://metrics.tracker.example/"),
+               vec![Reaction::Block, Reaction::BlockCookies(CookieScope::Request)]);
+
+    engine.clear_cname_resolver();
+    assert_eq!(process(&engine, cloaked), vec![]);
+}
+
+#[test]
+fn websocket_requests() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"^wss?://([^/]*\\\\.)?tracker\\\\.example[:/]\" }, \
+                   \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"^wss?://\", \"resource-type\": [\"websocket\"], \
+                                  \"load-type\": [\"third-party\"] }, \
+                   \"action\": { \"type\": \"block-cookies\" } }]";
+    let list = parse_list(body).unwrap();
+    assert_eq!(list.get(1).unwrap().to_string(),
+               "^wss?:// resource-type=websocket load-type=third-party -> block-cookies scope=request");
+    let bytes = list.to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    let both = &[Reaction::Block, Reaction::BlockCookies(CookieScope::Request)][..];
+    for &(url, resource_type, expected) in &[("wss://live.tracker.example:8443/socket", ResourceType::WebSocket, both),
+                                             ("ws://tracker.example/socket", ResourceType::WebSocket, both),
+                                             ("wss://chat.example/socket", ResourceType::WebSocket, &both[1..]),
+                                             ("wss://chat.example/socket", ResourceType::Raw, &[][..]),
+                                             ("https://tracker.example/a.js", ResourceType::Script, &[][..])] {
+        let parsed = Url::parse(url).unwrap();
+        for request_url in &[&url as &dyn RequestUrl, &parsed] {
+            let request = Request {
+                url: *request_url,
+                resource_type,
+                load_type: LoadType::ThirdParty,
+                context: None,
+                opener: None,
+                document: None,
+                redirect_chain: &[],
+                headers: None,
+            };
+            assert_eq!(process_rules_for_request(&list, &request), expected, "{} {:?}", url, resource_type);
+            assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected);
+        }
+    }
+}
+
+#[test]
+fn worker_resource_types() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"miner\", \"resource-type\": [\"worker\", \"shared-worker\"] }, \
+                   \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"miner\", \"resource-type\": [\"script\"] }, \
+                   \"action\": { \"type\": \"block-cookies\" } },\
+                 { \"trigger\": { \"url-filter\": \"sw\\\\.js\", \"resource-type\": [\"service-worker\"] }, \
+                   \"action\": { \"type\": \"block\" } }]";
+    let list = parse_list(body).unwrap();
+    assert_eq!(list.get(0).unwrap().to_string(), "miner resource-type=worker,shared-worker -> block");
+    let bytes = list.to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    let cookies = &[Reaction::BlockCookies(CookieScope::Request)][..];
+    for &(url, resource_type, expected) in &[("https://cdn.example/miner.js", ResourceType::Worker, &[Reaction::Block][..]),
+                                             ("https://cdn.example/miner.js", ResourceType::SharedWorker,
+                                              &[Reaction::Block][..]),
+                                             ("https://cdn.example/miner.js", ResourceType::Script, cookies),
+                                             ("https://cdn.example/miner.js", ResourceType::ServiceWorker, &[][..]),
+                                             ("https://news.example/sw.js", ResourceType::ServiceWorker,
+                                              &[Reaction::Block][..]),
+                                             ("https://news.example/sw.js", ResourceType::Script, &[][..])] {
+        let request = Request {
+            url: &url,
+            resource_type,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        assert_eq!(process_rules_for_request(&list, &request), expected, "{} {:?}", url, resource_type);
+        assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected);
+    }
+    assert_eq!(ResourceType::infer("https://cdn.example/miner.js", None, Some("sharedworker")), ResourceType::SharedWorker);
+}
+
+#[test]
+fn telemetry_resource_types() {
+    let body = "[{ \"trigger\": { \"url-filter\": \".*\", \"resource-type\": [\"beacon\", \"csp-report\"], \
+                                  \"load-type\": [\"third-party\"] }, \
+                   \"action\": { \"type\": \"block\" } }]";
+    let list = parse_list(body).unwrap();
+    assert_eq!(list.get(0).unwrap().resource_types(), Some(&[ResourceType::Beacon, ResourceType::CspReport][..]));
+    assert_eq!(Destination::Empty.resource_type(true), ResourceType::Beacon);
+    assert_eq!(Destination::Empty.resource_type(false), ResourceType::Raw);
+    assert_eq!(Destination::Report.resource_type(true), ResourceType::CspReport);
+    assert_eq!(Destination::Object.resource_type(false), ResourceType::Raw);
+    let bytes = list.to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    for &(keepalive, expected) in &[(true, &[Reaction::Block][..]), (false, &[][..])] {
+        let request = Request {
+            url: &"https://stats.example/collect",
+            resource_type: Destination::Empty.resource_type(keepalive),
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        assert_eq!(process_rules_for_request(&list, &request), expected);
+        assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected);
+    }
+}
+
+#[test]
+fn header_conditions() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\", \
+                                  \"if-header\": [{ \"name\": \"Referer\", \"value\": \"^https://news\\\\.example/\" }] }, \
+                   \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"ads\", \"unless-header\": [{ \"name\": \"x-requested-with\" }] }, \
+                   \"action\": { \"type\": \"block-cookies\" } }]";
+    let list = parse_list(body).unwrap();
+    assert_eq!(list.get(0).unwrap().if_header(), vec![("referer", Some("^https://news\\.example/"))]);
+    assert_eq!(list.get(1).unwrap().unless_header(), vec![("x-requested-with", None)]);
+    assert_eq!(list.get(0).unwrap().to_string(), "ads if-header=referer~^https://news\\.example/ -> block");
+    assert_eq!(parse_list(&list.to_json_string()).unwrap().get(0), list.get(0));
+    assert_eq!(parse_list_strict_impl("[{ \"trigger\": { \"url-filter\": \"ads\", \"if-header\": [{ \"value\": \"x\" }] }, \
+                                          \"action\": { \"type\": \"block\" } }]"),
+               Err(Error::MissingField { index: 0, field: "trigger.if-header" }));
+
+    let bytes = list.to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    let cookies = || Reaction::BlockCookies(CookieScope::Request);
+    let from_news = vec![("referer", "https://news.example/story")];
+    let xhr = vec![("Referer", "https://news.example/"), ("X-Requested-With", "XMLHttpRequest")];
+    let elsewhere = vec![("referer", "https://blog.example/")];
+    for &(headers, ref expected) in &[(Some(&from_news), vec![Reaction::Block, cookies()]),
+                                      (Some(&xhr), vec![Reaction::Block]),
+                                      (Some(&elsewhere), vec![cookies()]),
+                                      (None, vec![])] {
+        let request = Request {
+            url: &"https://cdn.example/ads.js",
+            resource_type: ResourceType::Script,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: headers.map(|headers| headers as &dyn RequestHeaders),
+        };
+        assert_eq!(&process_rules_for_request(&list, &request), expected, "{:?}", headers);
+        assert_eq!(&process_rules_for_request_compiled_impl(&compiled, &request), expected);
+        assert_eq!(&process_rules_for_request(&list, &OwnedRequest::from(&request).as_request()), expected);
+    }
+}
+
+#[test]
+fn shadow_evaluation() {
+    let current = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } }]").unwrap();
+    let next = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                            { \"trigger\": { \"url-filter\": \"track\" }, \"action\": { \"type\": \"block\" } }]").unwrap();
+    let request = |url| Request {
+        url,
+        resource_type: ResourceType::Script,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    let budget = Duration::from_secs(60);
+    let same = process_rules_for_request_shadowed(&current, &next, &request(&"https://cdn.example/ads.js"), budget);
+    assert_eq!(same.reactions, vec![Reaction::Block]);
+    assert!(same.candidate.complete && !same.diverges());
+
+    let changed = process_rules_for_request_shadowed(&current, &next, &request(&"https://cdn.example/track.js"), budget);
+    assert_eq!(changed.reactions, vec![]);
+    assert_eq!(changed.candidate.reactions, vec![Reaction::Block]);
+    assert!(changed.diverges());
+
+    // A candidate that runs out of time is not reported as diverging.
+    let late = process_rules_for_request_shadowed(&current, &next, &request(&"https://cdn.example/track.js"),
+                                                  Duration::from_secs(0));
+    assert!(!late.candidate.complete && !late.diverges());
+}
+
+#[test]
+fn match_backends() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"^https?://ads\\\\.example/\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"banner\", \"if-domain\": [\"*news.example\"] }, \
+                   \"action\": { \"type\": \"css-display-none\", \"selector\": \".banner\" } },\
+                 { \"trigger\": { \"url-filter\": \"tracker\", \"match-redirect-chain\": true }, \
+                   \"action\": { \"type\": \"block-cookies\" } },\
+                 { \"trigger\": { \"url-filter\": \"ok\" }, \"action\": { \"type\": \"ignore-previous-rules\" } }]";
+    let mut list = parse_list(body).unwrap();
+    assert_eq!(list.match_backend(), MatchBackendKind::Indexed);
+    let chain = [Url::parse("https://tracker.example/bounce").unwrap()];
+    let requests = [("https://ads.example/x.js", &[][..]),
+                    ("https://ads.example/ok.js", &[][..]),
+                    ("https://www.news.example/banner.png", &[][..]),
+                    ("https://cdn.example/banner.png", &[][..]),
+                    ("https://cdn.example/landing", &chain[..])];
+    let evaluate = |list: &RuleList| requests.iter().map(|&(url, chain)| {
+        process_rules_for_request(list, &Request {
+            url: &url,
+            resource_type: ResourceType::Image,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: chain,
+            headers: None,
+        })
+    }).collect::<Vec<_>>();
+    let expected = evaluate(&list);
+    assert!(expected.iter().all(|reactions| reactions.len() <= 1));
+    assert_eq!(expected.iter().filter(|reactions| reactions.is_empty()).count(), 2);
+
+    for &kind in &[MatchBackendKind::Linear, MatchBackendKind::RegexSet, MatchBackendKind::Indexed] {
+        list.set_match_backend(kind);
+        assert_eq!(list.match_backend(), kind);
+        assert_eq!(evaluate(&list), expected, "{:?}", kind);
+    }
+
+    let options = ParseOptions { match_backend: MatchBackendKind::Linear, ..ParseOptions::default() };
+    let mut list = parse_list_with_options(body, &options).unwrap();
+    assert_eq!(list.match_backend(), MatchBackendKind::Linear);
+    assert_eq!(list.memory_usage().indexes, 0);
+    list.set_group_enabled("none", false);
+    list.set_domain_target(DomainTarget::TopDocument);
+    assert_eq!(list.match_backend(), MatchBackendKind::Linear);
+    let merged = RuleList::merge(vec![list, parse_list(body).unwrap()], MergePolicy::Concatenate);
+    assert_eq!(merged.match_backend(), MatchBackendKind::Linear);
+}
+
+#[cfg(feature = "automata")]
+#[test]
+fn automaton_backend() {
+    let rules: Vec<String> = (0..300).map(|i| {
+        format!("{{ \"trigger\": {{ \"url-filter\": \"^https?://ads{}\\\\.example/(banner|pixel)[0-9]*\" }}, \
+                    \"action\": {{ \"type\": \"block\" }} }}", i)
+    }).collect();
+    let body = format!("[{}, {{ \"trigger\": {{ \"url-filter\": \"(?i)track\", \"match-redirect-chain\": true }}, \
+                                \"action\": {{ \"type\": \"block-cookies\" }} }}]", rules.join(","));
+    let linear = parse_list_with_options(&body, &ParseOptions {
+        match_backend: MatchBackendKind::Linear,
+        ..ParseOptions::default()
+    }).unwrap();
+    let mut automaton = linear.clone();
+    automaton.set_match_backend(MatchBackendKind::Automaton);
+    assert!(automaton.memory_usage().indexes > 0);
+    let chain = [Url::parse("https://TRACKER.example/bounce").unwrap()];
+    for &(url, chain) in &[("https://ads7.example/banner12", &[][..]),
+                           ("https://ads299.example/pixel", &chain[..]),
+                           ("https://ads300.example/banner", &[][..]),
+                           ("https://cdn.example/landing", &chain[..]),
+                           ("https://cdn.example/track.js", &[][..])] {
+        let request = Request {
+            url: &url,
+            resource_type: ResourceType::Image,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: chain,
+            headers: None,
+        };
+        assert_eq!(process_rules_for_request(&automaton, &request), process_rules_for_request(&linear, &request), "{}", url);
+    }
+}
+
+#[cfg(feature = "simd")]
+#[test]
+fn literal_backend() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"/ads/\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"Banner\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"pixel\\\\.gif\", \"url-filter-is-case-sensitive\": false }, \
+                   \"action\": { \"type\": \"block-cookies\" } },\
+                 { \"trigger\": { \"url-filter\": \"track\", \"match-redirect-chain\": true }, \
+                   \"action\": { \"type\": \"block-cookies\" } },\
+                 { \"trigger\": { \"url-filter\": \"^https?://cdn\\\\.example/[0-9]+\\\\.js\" }, \
+                   \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"ok\" }, \"action\": { \"type\": \"ignore-previous-rules\" } }]";
+    let indexed = parse_list(body).unwrap();
+    assert_eq!(indexed.compile_stats().fast_path_rules, 0);
+    let mut literal = indexed.clone();
+    literal.set_match_backend(MatchBackendKind::Literal);
+    assert_eq!(literal.compile_stats().fast_path_rules, 5);
+
+    let chain = [Url::parse("https://tracker.example/bounce").unwrap()];
+    for &(url, chain) in &[("https://example.org/ads/1.png", &[][..]),
+                           ("https://example.org/ads/ok.png", &[][..]),
+                           ("https://example.org/Banner.png", &[][..]),
+                           ("https://example.org/banner.png", &[][..]),
+                           ("https://example.org/PIXEL.GIF", &[][..]),
+                           ("https://cdn.example/42.js", &[][..]),
+                           ("https://cdn.example/landing", &chain[..])] {
+        let request = Request {
+            url: &url,
+            resource_type: ResourceType::Image,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: chain,
+            headers: None,
+        };
+        assert_eq!(process_rules_for_request(&literal, &request), process_rules_for_request(&indexed, &request), "{}", url);
+    }
+}
+
+#[test]
+fn compile_stats() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"/ads/\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"banner[0-9]+\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"(\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"track\", \"if-domain\": [\"example.org\"] }, \
+                   \"action\": { \"type\": \"block-cookies\" } }]";
+    let mut list = parse_list(body).unwrap();
+    let stats = list.compile_stats().clone();
+    assert_eq!((stats.rules_accepted, stats.rules_skipped), (3, 2));
+    assert_eq!((stats.literal_rules, stats.regex_rules, stats.fast_path_rules), (2, 1, 0));
+    assert!(stats.index_size > 0);
+    assert_eq!(parse_list_from_slice(body.as_bytes()).unwrap().compile_stats().rules_skipped, 2);
+    let empty = parse_list_strict("[]").unwrap().compile_stats().clone();
+    assert_eq!(CompileStats { duration: Duration::default(), index_size: 0, ..empty }, CompileStats::default());
+
+    list.set_match_backend(MatchBackendKind::Linear);
+    assert_eq!(list.compile_stats().rules_skipped, 2);
+    assert_eq!(list.compile_stats().index_size, 0);
+    list.set_domain_target(DomainTarget::TopDocument);
+    assert_eq!(list.compile_stats().rules_skipped, 2);
+    let merged = RuleList::merge(vec![list, parse_list(body).unwrap()], MergePolicy::Concatenate);
+    assert_eq!((merged.compile_stats().rules_accepted, merged.compile_stats().rules_skipped), (6, 4));
+}
+
+#[test]
+fn rule_limit_policies() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"a\" }, \"action\": { \"type\": \"css-display-none\", \"selector\": \".a\" } },\
+                 { \"trigger\": { \"url-filter\": \"b\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"c\" }, \"action\": { \"type\": \"css-display-none\", \"selector\": \".c\" } },\
+                 { \"trigger\": { \"url-filter\": \"d\" }, \"action\": { \"type\": \"ignore-previous-rules\" } },\
+                 { \"trigger\": { \"url-filter\": \"e\" }, \"action\": { \"type\": \"block\" } }]";
+    let limited = |policy| ParseOptions { max_rules: Some(3), rule_limit_policy: policy, ..ParseOptions::default() };
+    let filters = |list: &RuleList| list.iter().map(|rule| rule.url_filter().to_owned()).collect::<Vec<_>>();
+
+    assert_eq!(parse_list_with_options(body, &limited(RuleLimitPolicy::Error)).err(),
+               Some(Error::TooManyRules { limit: 3 }));
+
+    let truncated = parse_list_with_options(body, &limited(RuleLimitPolicy::Truncate)).unwrap();
+    assert_eq!(filters(&truncated), vec!["a", "b", "c"]);
+    assert_eq!(truncated.compile_stats().rules_skipped, 1);
+    assert_eq!(truncated.compile_stats().dropped_rules, vec![4, 5]);
+
+    let prioritized = parse_list_with_options(body, &limited(RuleLimitPolicy::Prioritize)).unwrap();
+    assert_eq!(filters(&prioritized), vec!["b", "d", "e"]);
+    assert_eq!(prioritized.compile_stats().dropped_rules, vec![0, 3]);
+
+    let none = parse_list_with_options(body, &ParseOptions { max_rules: Some(0), ..limited(RuleLimitPolicy::Prioritize) });
+    assert!(none.unwrap().is_empty());
+    let unlimited = parse_list_with_options(body, &ParseOptions { max_rules: None, ..limited(RuleLimitPolicy::Truncate) });
+    assert_eq!(unlimited.unwrap().len(), 5);
+}
+
+#[test]
+fn match_arena() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                            { \"trigger\": { \"url-filter\": \".*\", \"if-domain\": [\"*news.example\"] }, \
+                              \"action\": { \"type\": \"css-display-none\", \"selector\": \".promo\" } },\
+                            { \"trigger\": { \"url-filter\": \"track\" }, \
+                              \"action\": { \"type\": \"block-cookies\" } },\
+                            { \"trigger\": { \"url-filter\": \"ok\", \"if-domain\": [\"news.example\"] }, \
+                              \"action\": { \"type\": \"ignore-previous-rules\" } }]").unwrap();
+    let mut arena = MatchArena::with_capacity(list.len());
+    let capacity = arena.candidates.capacity();
+    for url in &["https://news.example/ads/track.js",
+                 "https://www.news.example/ads.js",
+                 "https://news.example/ads/ok.js",
+                 "https://example.org/track",
+                 "https://example.org/"] {
+        let request = Request {
+            url,
+            resource_type: ResourceType::Script,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        assert_eq!(process_rules_for_request_in(&list, &request, &mut arena),
+                   process_rules_for_request_borrowed(&list, &request), "{}", url);
+        assert_eq!(arena.candidates.capacity(), capacity);
+    }
+}
+
+#[test]
+fn prepared_requests() {
+    let url = Url::parse("https://user@CDN.Example.com/Ads/Banner.png?Size=2#top").unwrap();
+    let prepared = PreparedUrl::new(&url);
+    assert_eq!(prepared.as_str(), url.as_str());
+    assert_eq!((prepared.host(), prepared.domain()), (Some("cdn.example.com"), Some("cdn.example.com")));
+    assert_eq!(prepared.registrable_domain(), Some("example.com"));
+    assert!(prepared.has_credentials());
+    assert_eq!((prepared.path(), prepared.query()), ("/Ads/Banner.png", Some("Size=2")));
+    let data = PreparedUrl::new(&"data:text/plain,ads");
+    assert_eq!((data.host(), data.registrable_domain(), data.path(), data.query()),
+               (None, None, "text/plain,ads", None));
+    assert_eq!(PreparedUrl::new(&"http://[::1]:8080").path(), "/");
+
+    let first = parse_list("[{ \"trigger\": { \"url-filter\": \"Banner\", \"load-type\": [\"third-party\"] }, \
+                               \"action\": { \"type\": \"block\" } }]").unwrap();
+    let second = parse_list("[{ \"trigger\": { \"url-filter\": \".*\", \"if-domain\": [\"*example.com\"] }, \
+                                \"action\": { \"type\": \"block-cookies\" } }]").unwrap();
+    let document = Url::parse("https://news.example/").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Image,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: Some(&document),
+        redirect_chain: &[],
+        headers: None,
+    };
+    let prepared = PreparedRequest::new(&request);
+    assert_eq!(prepared.document().and_then(|document| document.registrable_domain()), Some("news.example"));
+    for list in &[first, second] {
+        assert_eq!(process_rules_for_request(list, &prepared.as_request()), process_rules_for_request(list, &request));
+        assert_eq!(process_rules_for_request(list, &request).len(), 1);
+    }
+}
+
+#[test]
+fn shared_domain_table() {
+    fn first_domain(list: &RuleList) -> &Arc<str> {
+        match list.rules[0].trigger.domain_constraint {
+            Some(DomainConstraint::If(ref matcher)) => &matcher.exact[0],
+            _ => panic!("expected an if-domain constraint"),
+        }
+    }
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\", \"if-domain\": [\"example.com\"] }, \
+                 \"action\": { \"type\": \"block\" } }]";
+    let first = parse_list(body).unwrap();
+    let second = parse_list(body).unwrap();
+    assert!(!Arc::ptr_eq(first_domain(&first), first_domain(&second)));
+    let separate = first.memory_usage().domain_tables + second.memory_usage().domain_tables;
+
+    let mut engine = Engine::new();
+    engine.add_list(first);
+    engine.add_list(second);
+    assert!(Arc::ptr_eq(first_domain(&engine.lists()[0]), first_domain(&engine.lists()[1])));
+    assert!(engine.memory_usage().domain_tables < separate);
+
+    let url = Url::parse("http://example.com/ads").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Image,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert_eq!(engine.process(&request), vec![Reaction::Block, Reaction::Block]);
+}
+
+#[test]
+fn warm_up() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"banner[0-9]+\" }, \"action\": { \"type\": \"block\" } },\
+                { \"trigger\": { \"url-filter\": \"tracker\", \
+                                 \"if-header\": [{ \"name\": \"Referer\", \"value\": \"^https://\" }] }, \
+                  \"action\": { \"type\": \"block\" } },\
+                { \"trigger\": { \"url-filter\": \".*\", \"path-filter\": \"^/ads/\", \"query-filter\": \"id=\" }, \
+                  \"action\": { \"type\": \"block\" } }]";
+    let mut engine = Engine::new();
+    engine.add_list(parse_list(body).unwrap());
+    let engine = Arc::new(engine);
+    let rules = &engine.lists()[0].rules;
+    assert!(rules.iter().all(|rule| !rule.trigger.url_filter.is_compiled()));
+
+    Engine::warm_up_in_background(&engine).join().unwrap();
+    assert!(rules.iter().all(|rule| rule.trigger.url_filter.is_compiled()));
+    assert!(rules[1].trigger.if_header[0].value.as_ref().unwrap().is_compiled());
+    assert!(rules[2].trigger.path_filter.as_ref().unwrap().is_compiled());
+    assert!(rules[2].trigger.query_filter.as_ref().unwrap().is_compiled());
+
+    let bytes = engine.lists()[0].to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    compiled.warm_up();
+    let url = Url::parse("http://example.org/banner1.png").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Image,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), vec![Reaction::Block]);
+    assert_eq!(engine.process(&request), vec![Reaction::Block]);
+}
+
+#[test]
+fn list_cache() {
+    use std::env;
+    use std::fs;
+
+    let dir = env::temp_dir().join(format!("content-blocker-cache-{}", ::std::process::id()));
+    let cache = ListCache::new(&dir);
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } }]";
+    let options = ParseOptions::default();
+    let request = Request {
+        url: &Url::parse("https://example.com/Ads.png").unwrap(),
+        resource_type: ResourceType::Image,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+
+    let first = cache.load(body, &options).unwrap();
+    assert!(!first.from_cache());
+    assert_eq!(process_rules_for_request_compiled_impl(&first.compiled(), &request), vec![]);
+    let second = cache.load(body, &options).unwrap();
+    assert!(second.from_cache());
+    assert_eq!(second.bytes(), first.bytes());
+
+    // Changing the options or the body invalidates the entry.
+    let changed = ParseOptions { case_sensitive: false, ..ParseOptions::default() };
+    let list = cache.load(body, &changed).unwrap();
+    assert!(!list.from_cache());
+    assert_eq!(process_rules_for_request_compiled_impl(&list.compiled(), &request), vec![Reaction::Block]);
+    assert!(!cache.load(&body.replace("ads", "banner"), &options).unwrap().from_cache());
+
+    // Damaged entries are compiled again.
+    for entry in fs::read_dir(&dir).unwrap() {
+        fs::write(entry.unwrap().path(), b"CBRL").unwrap();
+    }
+    assert!(!cache.load(body, &options).unwrap().from_cache());
+    assert!(cache.load(body, &options).unwrap().from_cache());
+    assert!(cache.load("[", &options).is_err());
+
+    cache.clear().unwrap();
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    assert!(!cache.load(body, &options).unwrap().from_cache());
+
+    // Concurrent writers of the same entry leave a single, complete entry.
+    cache.clear().unwrap();
+    let writers: Vec<_> = (0..4).map(|_| {
+        let cache = ListCache::new(&dir);
+        thread::spawn(move || cache.load(body, &ParseOptions::default()).unwrap().bytes().to_vec())
+    }).collect();
+    for writer in writers {
+        assert_eq!(writer.join().unwrap(), first.bytes());
+    }
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    assert!(cache.load(body, &options).unwrap().from_cache());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn safari_export() {
+    let mut list = parse_list("[{ \"trigger\": { \"url-filter\": \"a\" }, \"action\": { \"type\": \"block\" }, \
+                                  \"tags\": [\"ads\"] },\
+                                { \"trigger\": { \"url-filter\": \".*\" }, \
+                                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".x\" } },\
+                                { \"trigger\": { \"url-filter\": \".*\" }, \
+                                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".y\" } },\
+                                { \"trigger\": { \"url-filter\": \".*\" }, \
+                                  \"action\": { \"type\": \"css-display-none-exception\", \"selector\": \".z\" } },\
+                                { \"trigger\": { \"url-filter\": \"d\", \"if-domain\": [\"example.com\"] }, \
+                                  \"action\": { \"type\": \"block\" } },\
+                                { \"trigger\": { \"url-filter\": \"b\" }, \
+                                  \"action\": { \"type\": \"ignore-previous-rules\" } },\
+                                { \"trigger\": { \"url-filter\": \"c\" }, \
+                                  \"action\": { \"type\": \"block-cookies\" } }]").unwrap();
+    let export = list.to_safari(SAFARI_RULE_LIMIT).unwrap();
+    assert_eq!(export.skipped, vec![SafariSkip { rule: 3, reason: SafariSkipReason::Action },
+                                    SafariSkip { rule: 4, reason: SafariSkipReason::Conditions }]);
+    assert_eq!(export.rules_per_file, vec![4]);
+    assert!(!export.files[0].contains("tags") && !export.files[0].contains("scope"));
+    let file: Vec<String> = parse_list(&export.files[0]).unwrap().iter().map(|rule| rule.to_string()).collect();
+    assert_eq!(file, vec!["a -> block", ".* -> css-display-none .x, .y", "b -> ignore-previous-rules",
+                          "c -> block-cookies scope=request"]);
+
+    // Only the element hiding exception is left out once domain constraints
+    // are compared with the top-level document, as in Safari.
+    list.set_domain_target(DomainTarget::TopDocument);
+    let export = list.to_safari(2).unwrap();
+    assert_eq!(export.skipped, vec![SafariSkip { rule: 3, reason: SafariSkipReason::Action }]);
+    assert_eq!(export.selectors_merged, 1);
+    // Files before the exception end with a copy of it.
+    assert_eq!(export.rules_per_file, vec![2, 2, 2, 1]);
+    let files: Vec<RuleList> = export.files.iter().map(|file| parse_list(file).unwrap()).collect();
+    for file in &files[..3] {
+        assert_eq!(file.get(1).unwrap().to_string(), "b -> ignore-previous-rules");
+    }
+    assert_eq!(files[3].get(0).unwrap().to_string(), "c -> block-cookies scope=request");
+    assert_eq!(list.to_safari(1).err(), Some(Error::TooManyRules { limit: 1 }));
+
+    // Safari has no domains with any top-level domain.
+    let mut list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\", \"if-domain\": [\"*example.*\"] }, \
+                                  \"action\": { \"type\": \"block\" } }]").unwrap();
+    list.set_domain_target(DomainTarget::TopDocument);
+    assert_eq!(list.to_safari(SAFARI_RULE_LIMIT).unwrap().skipped,
+               vec![SafariSkip { rule: 0, reason: SafariSkipReason::Conditions }]);
+
+    // The rules that an exception left out may cancel are left out with it.
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"^https?://([^/]*\\\\.)?ads\\\\.example/\" }, \
+                              \"action\": { \"type\": \"block\" } },\
+                            { \"trigger\": { \"url-filter\": \"^https?://([^/]*\\\\.)?other\\\\.example/\" }, \
+                              \"action\": { \"type\": \"block\" } },\
+                            { \"trigger\": { \"url-filter\": \"tracker\", \"resource-type\": [\"script\"] }, \
+                              \"action\": { \"type\": \"block\" } },\
+                            { \"trigger\": { \"url-filter\": \"^https?://([^/]*\\\\.)?ads\\\\.example/\", \
+                                             \"resource-type\": [\"image\"], \
+                                             \"if-header\": [{ \"name\": \"Referer\" }] }, \
+                              \"action\": { \"type\": \"ignore-previous-rules\" } }]").unwrap();
+    let export = list.to_safari(SAFARI_RULE_LIMIT).unwrap();
+    assert_eq!(export.skipped, vec![SafariSkip { rule: 0, reason: SafariSkipReason::Exception },
+                                    SafariSkip { rule: 3, reason: SafariSkipReason::Conditions }]);
+    assert_eq!(export.rules_per_file, vec![2]);
+
+    // Safari rejects lists naming resource types it does not know.
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"a\", \
+                                             \"resource-type\": [\"worker\", \"csp-report\", \"beacon\"] }, \
+                              \"action\": { \"type\": \"block\" } },\
+                            { \"trigger\": { \"url-filter\": \"b\", \"resource-type\": [\"script\", \"service-worker\"] }, \
+                              \"action\": { \"type\": \"block\" } }]").unwrap();
+    let export = list.to_safari(SAFARI_RULE_LIMIT).unwrap();
+    assert_eq!(export.skipped, vec![SafariSkip { rule: 0, reason: SafariSkipReason::Conditions }]);
+    assert!(export.files[0].contains("\"resource-type\":[\"script\"]"));
+}
+
+#[test]
+fn html_filters() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"example\\\\.com\" }, \
+                              \"action\": { \"type\": \"html-filter\", \
+                                            \"selector\": \"script:has-text(/adblock/i)\" } },\
+                            { \"trigger\": { \"url-filter\": \"example\\\\.com\" }, \
+                              \"action\": { \"type\": \"html-filter\", \
+                                            \"selector\": \"script[type][tag-content=\\\"say \\\"\\\"hi\\\"\\\"\\\"]\
+                                                           [max-length=\\\"500\\\"]\" } }]")
+                   .unwrap();
+    let url = Url::parse("https://example.com/").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Document,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    let expected = vec![
+        Reaction::FilterHtml(HtmlFilter {
+            source: "script:has-text(/adblock/i)".to_owned(),
+            selector: "script".to_owned(),
+            text: Some(TextMatcher::Regex { pattern: "adblock".to_owned(), flags: "i".to_owned() }),
+            max_length: None,
+        }),
+        Reaction::FilterHtml(HtmlFilter {
+            source: "script[type][tag-content=\"say \"\"hi\"\"\"][max-length=\"500\"]".to_owned(),
+            selector: "script[type]".to_owned(),
+            text: Some(TextMatcher::Contains("say \"hi\"".to_owned())),
+            max_length: Some(500),
+        }),
+    ];
+    assert_eq!(process_rules_for_request(&list, &request), expected);
+    let bytes = compile_impl(&list.rules);
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected);
+    assert_eq!(list.get(0).unwrap().action_type(), ActionType::HtmlFilter);
+    assert_eq!(list.get(0).unwrap().to_string(), "example\\.com -> html-filter script:has-text(/adblock/i)");
+    assert_eq!(parse_list(&list.to_json_string()).unwrap().rules, list.rules);
+
+    for selector in &["script:has(a)", "script:has-text(a)[tag-content=\\\"b\\\"]", "[max-length=\\\"x\\\"]",
+                      "script[tag-content=\\\"a]"] {
+        let body = format!("[{{ \"trigger\": {{ \"url-filter\": \".*\" }}, \
+                                \"action\": {{ \"type\": \"html-filter\", \"selector\": \"{}\" }} }}]", selector);
+        assert!(matches!(parse_list_strict(&body), Err(Error::InvalidSelector { index: 0, .. })), "{}", selector);
+    }
+}
+
+#[test]
+fn inline_script_blocking() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"^https://news\\\\.example/\", \
+                                              \"resource-type\": [\"document\"] }, \
+                              \"action\": { \"type\": \"block-inline-scripts\" } }]").unwrap();
+    let url = Url::parse("https://news.example/article").unwrap();
+    let request = Request {
+        url: &url,
+        resource_type: ResourceType::Document,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert_eq!(process_rules_for_request(&list, &request), vec![Reaction::BlockInlineScripts]);
+    let bytes = compile_impl(&list.rules);
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), vec![Reaction::BlockInlineScripts]);
+    assert_eq!(list.get(0).unwrap().action_type(), ActionType::BlockInlineScripts);
+    assert_eq!(parse_list(&list.to_json_string()).unwrap().rules, list.rules);
+
+    let script = Request { resource_type: ResourceType::Script, ..request };
+    assert_eq!(process_rules_for_request(&list, &script), vec![]);
+    #[cfg(feature = "servo")]
+    assert!(::servo::FilterDecision::from_reactions(vec![Reaction::BlockInlineScripts]).block_inline_scripts);
+}
+
+#[test]
+fn cosmetic_split() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \".*\" }, \
+                              \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } },\
+                            { \"trigger\": { \"url-filter\": \".*\", \"if-domain\": [\"*example.com\"] }, \
+                              \"action\": { \"type\": \"css-display-none\", \"selector\": \".banner\" } },\
+                            { \"trigger\": { \"url-filter\": \".*\" }, \
+                              \"action\": { \"type\": \"css-display-none\", \"selector\": \".promo\" } },\
+                            { \"trigger\": { \"url-filter\": \".*\", \"if-domain\": [\"example.com\"] }, \
+                              \"action\": { \"type\": \"css-display-none-exception\", \"selector\": \".promo\" } },\
+                            { \"trigger\": { \"url-filter\": \"ads\" }, \
+                              \"action\": { \"type\": \"css-display-none\", \"selector\": \".x\" } }]")
+                   .unwrap();
+    assert_eq!(list.generic_stylesheet(), ".ad { display: none !important; }\n");
+    assert_eq!(list.specific_selectors_for("example.com"), vec![".banner"]);
+    assert_eq!(list.specific_selectors_for("www.example.com"), vec![".promo", ".banner"]);
+    assert_eq!(list.specific_selectors_for("example.org"), vec![".promo"]);
+
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"\" }, \
+                              \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } },\
+                            { \"trigger\": { \"url-filter\": \"\", \"if-domain\": [\"example.com\"] }, \
+                              \"action\": { \"type\": \"ignore-previous-rules\" } }]")
+                   .unwrap();
+    assert_eq!(list.generic_stylesheet(), "");
+    assert_eq!(list.specific_selectors_for("example.com"), Vec::<&str>::new());
+    assert_eq!(list.specific_selectors_for("example.org"), vec![".ad"]);
+}
+
+#[test]
+fn cosmetic_buckets() {
+    let hide = |selector: &str, domains: &str, group: &str| {
+        format!("{{ \"trigger\": {{ \"url-filter\": \".*\", \"if-domain\": [{}] }}, \
+                    \"action\": {{ \"type\": \"css-display-none\", \"selector\": \"{}\" }}, \"group\": \"{}\" }}",
+                domains, selector, group)
+    };
+    let body = format!("[{}, {}, {}, {}, {}]",
+                       hide(".ad-banner", "\"a.com\"", "base"),
+                       hide(".ad-banner", "\"b.com\", \"*c.com\"", "base"),
+                       hide(".ad-banner", "\"d.com\"", "extra"),
+                       hide(".sidebar", "\"a.com\"", "base"),
+                       hide(".ad-banner", "\"a.com\"", "extra"));
+    let mut list = parse_list(&body).unwrap();
+    assert_eq!(list.specific_selectors_for("a.com"), vec![".ad-banner", ".sidebar"]);
+    assert_eq!(list.specific_selectors_for("www.c.com"), vec![".ad-banner"]);
+    assert_eq!(list.specific_selectors_for("www.b.com"), Vec::<&str>::new());
+    assert!(list.memory_usage().indexes > 0);
+
+    list.set_group_enabled("extra", false);
+    assert_eq!(list.specific_selectors_for("a.com"), vec![".ad-banner", ".sidebar"]);
+    assert_eq!(list.specific_selectors_for("d.com"), Vec::<&str>::new());
+}
+
+#[test]
+fn frame_cosmetics() {
+    let mut list = parse_list("[{ \"trigger\": { \"url-filter\": \".*\", \"if-domain\": [\"ads.net\"] }, \
+                                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".frame-ad\" } },\
+                                { \"trigger\": { \"url-filter\": \".*\", \"if-domain\": [\"example.com\"] }, \
+                                  \"action\": { \"type\": \"css-display-none\", \"selector\": \".page-ad\" } },\
+                                { \"trigger\": { \"url-filter\": \".*\" }, \"action\": { \"type\": \"block\" } }]")
+                       .unwrap();
+    let hidden = |list: &RuleList, frame: &str| {
+        cosmetic_rules_for_frame(list, &frame, &"https://example.com/")
+    };
+    let frame_ad = vec![Reaction::HideMatchingElements(".frame-ad".to_owned())];
+    let page_ad = vec![Reaction::HideMatchingElements(".page-ad".to_owned())];
+    assert_eq!(hidden(&list, "https://ads.net/frame"), frame_ad);
+    assert_eq!(hidden(&list, "https://example.com/"), page_ad);
+    assert_eq!(hidden(&list, "about:blank"), page_ad);
+
+    list.set_domain_target(DomainTarget::TopDocument);
+    assert_eq!(hidden(&list, "https://ads.net/frame"), page_ad);
+}
+
+#[test]
+fn hiding_hints() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \".*\" }, \
+                              \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } },\
+                            { \"trigger\": { \"url-filter\": \".*\", \"if-domain\": [\"example.com\"] }, \
+                              \"action\": { \"type\": \"css-display-none\", \"selector\": \".banner\" } },\
+                            { \"trigger\": { \"url-filter\": \".*\" }, \"action\": { \"type\": \"block\" } }]")
+                   .unwrap();
+    let request = Request {
+        url: &"https://example.com/",
+        resource_type: ResourceType::Document,
+        load_type: LoadType::FirstParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    let ids = list.ids();
+    assert_eq!(process_rules_for_request_with_hiding_hints(&list, &request),
+               vec![(Reaction::HideMatchingElements(".ad".to_owned()),
+                     Some(HidingHints { generic: true, rule: ids[0] })),
+                    (Reaction::HideMatchingElements(".banner".to_owned()),
+                     Some(HidingHints { generic: false, rule: ids[1] })),
+                    (Reaction::Block, None)]);
+}
+
+#[cfg(feature = "fixtures")]
+#[test]
+fn fixture_corpus() {
+    use fixtures::{self, Fixture};
+
+    let requests = fixtures::requests();
+    assert!(requests.len() >= 50);
+    for &fixture in &Fixture::ALL {
+        let list = fixture.load();
+        assert_eq!(list.name(), Some(fixture.name()));
+        assert_eq!(list.compile_stats().rules_skipped, 0);
+        assert!(list.len() >= 50);
+        let blocked = requests.iter().filter(|request| {
+            process_rules_for_request(&list, &request.as_request()).contains(&Reaction::Block)
+        }).count();
+        assert!(blocked > 0 && blocked < requests.len(), "{}: {}", fixture.name(), blocked);
+    }
+
+    let easylist = Fixture::EasyList.load();
+    let gpt = requests.iter().find(|request| request.url.ends_with("/gpt.js")).unwrap();
+    assert!(process_rules_for_request(&easylist, &gpt.as_request()).contains(&Reaction::Block));
+    assert!(!easylist.generic_stylesheet().is_empty());
+    assert!(easylist.specific_selectors_for("www.reddit.com").contains(&".promotedlink"));
+}
+
+#[test]
+fn single_rule_validation() {
+    let rule = Rule::parse_json("{ \"trigger\": { \"url-filter\": \"ads\", \"resource-type\": [] }, \
+                                   \"action\": { \"type\": \"block\" } }").unwrap();
+    assert_eq!(rule.validate(&ValidationOptions::default()), Ok(()));
+    let options = ValidationOptions { reject_empty_resource_types: true, ..ValidationOptions::default() };
+    let error = rule.validate(&options).unwrap_err();
+    assert_eq!(error, Error::EmptyResourceTypes { index: 0 });
+    assert_eq!(error.field(), Some("trigger.resource-type"));
+    let options = ValidationOptions { max_url_filter_length: Some(2), ..ValidationOptions::default() };
+    assert_eq!(rule.validate(&options), Err(Error::SizeLimitExceeded { index: 0, limit: 2 }));
+
+    let rule = Rule::parse_json("{ \"trigger\": { \"url-filter\": \"a{1000}{1000}\" }, \
+                                   \"action\": { \"type\": \"block\" } }").unwrap();
+    let options = ValidationOptions { compile_url_filter: true, ..ValidationOptions::default() };
+    assert_eq!(rule.validate(&options).unwrap_err().field(), Some("trigger.url-filter"));
+
+    let error = Rule::parse_json("{ \"trigger\": { \"url-filter\": \".*\" }, \
+                                    \"action\": { \"type\": \"css-inject\", \"selector\": \".ad\" } }").unwrap_err();
+    assert_eq!(error, Error::MissingField { index: 0, field: "action.style" });
+    assert_eq!(error.field(), Some("action.style"));
+    let error = Rule::parse_json("{ \"trigger\": { \"url-filter\": \"(\" }, \"action\": { \"type\": \"block\" } }");
+    assert_eq!(error.unwrap_err().field(), Some("trigger.url-filter"));
+    assert_eq!(Rule::parse_json("{ \"trigger\": "), Err(Error::JSON));
+    assert_eq!(Error::JSON.field(), None);
+}
+
+#[test]
+fn costly_regexes() {
+    assert_eq!(regex_cost("(a+)*b"), Some(RegexCost::NestedRepetition));
+    assert_eq!(regex_cost("^https?://([^/]*\\.)+tracker"), Some(RegexCost::NestedRepetition));
+    assert_eq!(regex_cost("(?i).*(ads|banner|track)"), Some(RegexCost::WildcardAlternation));
+    assert_eq!(regex_cost("^[^:]+:(//)?([^/]+\\.)?example\\.com"), None);
+    assert_eq!(regex_cost("(ads|banner)"), None);
+    assert_eq!(regex_cost("([a-z]{2}\\.)+com"), None);
+
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \".*(ads|track)\" }, \
+                              \"action\": { \"type\": \"block\" } },\
+                            { \"trigger\": { \"url-filter\": \"/(ad-?)+/\" }, \"action\": { \"type\": \"block\" } },\
+                            { \"trigger\": { \"url-filter\": \"(x+y*)+z\" }, \"action\": { \"type\": \"block\" } }]")
+                   .unwrap();
+    let findings = list.lint();
+    assert_eq!(findings, vec![
+        LintFinding { rule: 0, kind: LintKind::NoLiteral },
+        LintFinding { rule: 0, kind: LintKind::CostlyRegex(RegexCost::WildcardAlternation) },
+        LintFinding { rule: 2, kind: LintKind::CostlyRegex(RegexCost::NestedRepetition) },
+    ]);
+    assert_eq!(findings[2].severity(), Severity::Warning);
+    assert_eq!(findings[2].to_string(), "rule 2: url-filter nests unbounded repetitions");
+}
+
+#[test]
+fn untrusted_lists() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"^https?://ads\\\\.\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"^https://cdn\\\\.example\\\\.com/ads/.*\\\\.js$\" }, \
+                   \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"track\", \
+                                  \"if-header\": [{ \"name\": \"Referer\", \"value\": \"(news|blog)\" }] }, \
+                   \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"pixel\" }, \"action\": { \"type\": \"block\" } }]";
+    let untrusted = ParseOptions { trust: TrustLevel::Untrusted, ..ParseOptions::default() };
+
+    assert_eq!(parse_list_with_options(body, &ParseOptions::default()).unwrap().len(), 4);
+    let lenient = parse_list_with_options(body, &untrusted).unwrap();
+    let filters: Vec<_> = lenient.iter().map(|rule| rule.url_filter().to_owned()).collect();
+    assert_eq!(filters, vec!["^https://cdn\\.example\\.com/ads/.*\\.js$", "pixel"]);
+    assert_eq!(lenient.compile_stats().rules_skipped, 2);
+
+    let strict = ParseOptions { strict: true, ..untrusted.clone() };
+    let error = parse_list_with_options(body, &strict).unwrap_err();
+    assert_eq!(error, Error::UntrustedPattern {
+        index: 0,
+        field: "trigger.url-filter",
+        pattern: "^https?://ads\\.".to_owned(),
+    });
+    assert_eq!(error.field(), Some("trigger.url-filter"));
+    let header = "[{ \"trigger\": { \"url-filter\": \"track\", \
+                                   \"if-header\": [{ \"name\": \"Referer\", \"value\": \"a+\" }] }, \
+                    \"action\": { \"type\": \"block\" } }]";
+    assert_eq!(parse_list_with_options(header, &strict).unwrap_err().field(), Some("trigger.if-header"));
+    let long = format!("[{{ \"trigger\": {{ \"url-filter\": \"{}\" }}, \"action\": {{ \"type\": \"block\" }} }}]",
+                       "a".repeat(UNTRUSTED_PATTERN_LIMIT + 1));
+    assert_eq!(parse_list_with_options(&long, &strict).err(),
+               Some(Error::SizeLimitExceeded { index: 0, limit: UNTRUSTED_PATTERN_LIMIT }));
+}
+
+#[test]
+fn host_anchored_filters() {
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"Tracker.net\", \"url-filter-anchor\": \"host\" }, \
+                              \"action\": { \"type\": \"block\" } },\
+                            { \"trigger\": { \"url-filter\": \"ads.\", \"url-filter-anchor\": \"host\" }, \
+                              \"action\": { \"type\": \"block\" } }]").unwrap();
+    assert_eq!(list.get(0).unwrap().to_string(), "tracker.net url-filter-anchor=host -> block");
+    assert_eq!(list.get(0).unwrap().url_filter_anchor(), UrlFilterAnchor::Host);
+    let bytes = list.to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    for &(url, blocked) in &[("https://tracker.net/a.js", true),
+                             ("https://cdn.tracker.net:8080/a.js", true),
+                             ("https://ads.example.com/", true),
+                             ("https://www.ads.example.com/", true),
+                             ("https://mytracker.net/", false),
+                             ("https://tracker.net.example.com/", false),
+                             ("https://example.com/tracker.net/a.js", false),
+                             ("https://example.com/?ref=ads.example.com", false),
+                             ("https://ads/", false)] {
+        let url = Url::parse(url).unwrap();
+        let request = Request {
+            url: &url,
+            resource_type: ResourceType::Script,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        let expected = if blocked { vec![Reaction::Block] } else { vec![] };
+        assert_eq!(process_rules_for_request(&list, &request), expected, "{}", url);
+        assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected, "{}", url);
+    }
+
+    assert_eq!(list.to_dns_blocklist(DnsFormat::PiHole).config, "||tracker.net^\n");
+    let safari = list.to_safari(SAFARI_RULE_LIMIT).unwrap();
+    assert!(safari.files[0].contains("\"url-filter\":\"^[^:]+://+([^:/]+\\\\.)?tracker\\\\.net[:/]\""));
+    assert!(!safari.files[0].contains("url-filter-anchor"));
+    assert_eq!(parse_list(&list.to_json_string()).unwrap().to_json_string(), list.to_json_string());
+
+    for pattern in &["", ".example.com", "example..com", "^example\\\\.com", "*.example.com"] {
+        let body = format!("[{{ \"trigger\": {{ \"url-filter\": \"{}\", \"url-filter-anchor\": \"host\" }}, \
+                              \"action\": {{ \"type\": \"block\" }} }}]", pattern);
+        assert_eq!(parse_list_strict(&body).err().and_then(|error| error.field()), Some("trigger.url-filter"));
+    }
+    let unknown = "[{ \"trigger\": { \"url-filter\": \"ads\", \"url-filter-anchor\": \"path\" }, \
+                     \"action\": { \"type\": \"block\" } }]";
+    assert_eq!(parse_list_strict(unknown).err(),
+               Some(Error::MissingField { index: 0, field: "trigger.url-filter-anchor" }));
+}
+
+#[test]
+fn path_and_query_filters() {
+    for &(url, expected) in &[("https://example.com/a/b?c=d#e?f", ("/a/b", Some("c=d"))),
+                              ("https://example.com?q", ("/", Some("q"))),
+                              ("https://example.com#/ads/", ("/", None)),
+                              ("data:text/plain,ads?", ("text/plain,ads", Some("")))] {
+        assert_eq!(url.path_and_query(), expected, "{}", url);
+        assert_eq!(Url::parse(url).unwrap().path_and_query(), expected, "{}", url);
+    }
+
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"example\\\\.com\", \"path-filter\": \"^/ads/\" }, \
+                              \"action\": { \"type\": \"block\" } },\
+                            { \"trigger\": { \"url-filter\": \".*\", \"query-filter\": \"(^|&)utm_source=\" }, \
+                              \"action\": { \"type\": \"block\" } }]").unwrap();
+    assert_eq!(list.get(0).unwrap().to_string(), "example\\.com path-filter=^/ads/ -> block");
+    assert_eq!(list.get(1).unwrap().query_filter(), Some("(^|&)utm_source="));
+    let bytes = list.to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    for &(url, blocked) in &[("https://example.com/ads/banner.png", true),
+                             ("https://example.com/static/ads/banner.png", false),
+                             ("https://example.com/?next=/ads/", false),
+                             ("https://ads.net/?campaign=1&utm_source=mail", true),
+                             ("https://ads.net/utm_source=mail", false),
+                             ("https://ads.net/#utm_source=mail", false)] {
+        let request = Request {
+            url: &url,
+            resource_type: ResourceType::Image,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        let expected = if blocked { vec![Reaction::Block] } else { vec![] };
+        assert_eq!(process_rules_for_request(&list, &request), expected, "{}", url);
+        assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected, "{}", url);
+    }
+    assert_eq!(parse_list(&list.to_json_string()).unwrap().to_json_string(), list.to_json_string());
+    let safari = list.to_safari(SAFARI_RULE_LIMIT).unwrap();
+    assert_eq!(safari.skipped.iter().map(|skip| skip.reason).collect::<Vec<_>>(),
+               vec![SafariSkipReason::Conditions; 2]);
+
+    let untrusted = ParseOptions { strict: true, trust: TrustLevel::Untrusted, ..ParseOptions::default() };
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\", \"path-filter\": \"^/(ads|banners)/\" }, \
+                  \"action\": { \"type\": \"block\" } }]";
+    assert_eq!(parse_list_with_options(body, &untrusted).unwrap_err().field(), Some("trigger.path-filter"));
+
+    // The path and query filters share the case sensitivity flag of the URL filter.
+    let json = "{\"action\":{\"type\":\"block\"},\"trigger\":{\"path-filter\":\"^/ads/\",\"query-filter\":\"id=\",\
+                \"url-filter\":\"example\",\"url-filter-is-case-sensitive\":true}}";
+    let list = parse_list(&format!("[{}]", json)).unwrap();
+    assert_eq!(list.get(0).unwrap().path_filter(), Some("(?i)^/ads/"));
+    assert_eq!(list.to_json_string(), format!("[{}]", json));
+    let caseless = ParseOptions { case_sensitive: false, ..ParseOptions::default() };
+    let body = "[{ \"trigger\": { \"url-filter\": \".*\", \"query-filter\": \"id=\" }, \
+                  \"action\": { \"type\": \"block\" } }]";
+    assert_eq!(parse_list_with_options(body, &caseless).unwrap().get(0).unwrap().query_filter(), Some("(?i)id="));
+    let request = Request {
+        url: &"https://EXAMPLE.com/Ads/banner.png?ID=2",
+        resource_type: ResourceType::Image,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert_eq!(process_rules_for_request(&list, &request), vec![Reaction::Block]);
+}
+
+#[test]
+fn rule_groups() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"ignore-previous-rules\" }, \
+                   \"group\": \"acceptable-ads\" },\
+                 { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"css-display-none\", \"selector\": \".share\" }, \
+                   \"tags\": [\"social\", \"annoyances\"] },\
+                 { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"css-display-none\", \"selector\": \".popup\" }, \
+                   \"group\": \"annoyances\" }]";
+    let request = Request {
+        url: &"https://ads.example.com/",
+        resource_type: ResourceType::Script,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    let hide = |selector: &str| Reaction::HideMatchingElements(selector.to_owned());
+    let mut list = parse_list(body).unwrap();
+    assert_eq!(list.groups(), vec!["acceptable-ads", "annoyances", "social"]);
+    assert_eq!(list.get(2).unwrap().groups(), vec!["annoyances", "social"]);
+    assert_eq!(process_rules_for_request(&list, &request), vec![hide(".share"), hide(".popup")]);
+
+    list.set_group_enabled("acceptable-ads", false);
+    assert!(!list.is_group_enabled("acceptable-ads"));
+    assert_eq!(process_rules_for_request(&list, &request), vec![Reaction::Block, hide(".share"), hide(".popup")]);
+    // Optimizing keeps the selectors of rules from different groups apart.
+    list.optimize();
+    list.set_group_enabled("social", false);
+    assert_eq!(process_rules_for_request(&list, &request), vec![Reaction::Block, hide(".popup")]);
+    list.set_group_enabled("acceptable-ads", true);
+    assert_eq!(process_rules_for_request(&list, &request), vec![hide(".popup")]);
+    // Profiling and coverage measurements skip disabled groups too.
+    list.set_group_enabled("annoyances", false);
+    assert_eq!(process_rules_for_request_profiled(&list, &request, &mut Profile::new()), vec![]);
+    let coverage = list.coverage(&[OwnedRequest::from(&request)]);
+    assert_eq!(coverage.requests_with_reactions, 0);
+    for (rule, counts) in list.iter().zip(&coverage.rules) {
+        if rule.groups().contains(&"annoyances") {
+            assert_eq!(*counts, RuleCoverage { matches: 0, reactions: 0 });
+        }
+    }
+
+    // Changing only the groups of a rule is a change, which deltas carry along.
+    let retagged = body.replace("\"group\": \"annoyances\"", "\"group\": \"popups\"");
+    let old = parse_list(body).unwrap();
+    assert_eq!(diff(&old, &parse_list(&retagged).unwrap()).changed, vec![(3, 3)]);
+    let mut updated = parse_list(body).unwrap();
+    updated.apply_delta(&create_delta(&old, &retagged).unwrap()).unwrap();
+    assert_eq!(updated.groups(), vec!["acceptable-ads", "annoyances", "popups", "social"]);
+
+    // Exports leave out the rules of disabled groups.
+    let mut exported = parse_list("[{ \"trigger\": { \"url-filter\": \"^https?://([^/]*\\\\.)?ads\\\\.example/\" }, \
+                                      \"action\": { \"type\": \"block\" }, \"group\": \"ads\" },\
+                                    { \"trigger\": { \"url-filter\": \"tracker\" }, \
+                                      \"action\": { \"type\": \"block\" } }]").unwrap();
+    exported.set_group_enabled("ads", false);
+    let blocklist = exported.to_dns_blocklist(DnsFormat::Unbound);
+    assert_eq!(blocklist.hosts, 0);
+    assert_eq!(blocklist.skipped, vec![DnsSkip { rule: 1, reason: DnsSkipReason::UrlFilter }]);
+    assert_eq!(exported.to_pac("127.0.0.1:9").hosts, 0);
+    assert_eq!(exported.to_safari(SAFARI_RULE_LIMIT).unwrap().rules_per_file, vec![1]);
+    assert_eq!(CompiledRuleList::from_bytes(&exported.to_bytes()).unwrap().len(), 1);
+
+    let mut engine = Engine::new();
+    engine.set_group_enabled("annoyances", false);
+    engine.add_list(parse_list(body).unwrap());
+    engine.add_list(parse_list("[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" }, \
+                                  \"group\": \"trackers\" }]").unwrap());
+    assert_eq!(engine.groups(), vec!["acceptable-ads", "annoyances", "social", "trackers"]);
+    assert_eq!(engine.process(&request), vec![Reaction::Block]);
+    engine.set_group_enabled("trackers", false);
+    engine.set_group_enabled("annoyances", true);
+    assert!(engine.is_group_enabled("annoyances") && !engine.is_group_enabled("trackers"));
+    assert_eq!(engine.process(&request), vec![hide(".share"), hide(".popup")]);
+}
+
+#[test]
+fn allowlist() {
+    let mut engine = Engine::new();
+    engine.add_list(parse_list("[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } }]").unwrap());
+    engine.add_list(parse_list("[{ \"trigger\": { \"url-filter\": \"track\" }, \"action\": { \"type\": \"block\" } }]").unwrap());
+    let changes = Arc::new(std::sync::Mutex::new(vec![]));
+    let saved = changes.clone();
+    engine.allowlist_mut().on_change(move |allowlist| saved.lock().unwrap().push(allowlist.to_json()));
+
+    let page: &dyn RequestUrl = &"https://www.news.org/article";
+    let request = Request {
+        url: &"http://ads.example.com/track.js",
+        resource_type: ResourceType::Script,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: Some(page),
+        redirect_chain: &[],
+        headers: None,
+    };
+    let navigation = Request { document: None, ..request };
+    assert_eq!(engine.process(&request), vec![Reaction::Block, Reaction::Block]);
+
+    assert!(engine.allowlist_mut().add_domain("News.org."));
+    assert!(!engine.allowlist_mut().add_domain("news.org"));
+    assert_eq!(engine.process(&request), vec![]);
+    // The page, not the destination of the request, decides.
+    assert!(!engine.allowlist().matches(&"https://fakenews.org/"));
+    assert_eq!(engine.process(&navigation).len(), 2);
+
+    assert!(engine.allowlist_mut().remove_domain("news.org"));
+    assert_eq!(engine.allowlist_mut().add_url_filter("^https?://[^/]*\\.news\\.org/article"), Ok(true));
+    assert_eq!(engine.process(&request), vec![]);
+    assert_eq!(engine.allowlist_mut().add_url_filter("("),
+               Err(Error::InvalidRegex { index: 1, pattern: "(".to_owned() }));
+
+    let saved = changes.lock().unwrap().last().unwrap().clone();
+    assert_eq!(changes.lock().unwrap().len(), 3);
+    let restored = Allowlist::from_json(&saved).unwrap();
+    assert_eq!(restored.domains().count(), 0);
+    assert_eq!(restored.url_filters().collect::<Vec<_>>(), vec!["^https?://[^/]*\\.news\\.org/article"]);
+    assert!(restored.matches(page));
+    // Non-ASCII hosts are compared without splitting their characters.
+    let mut allowlist = Allowlist::new();
+    allowlist.add_domain("xb.com");
+    assert!(!allowlist.matches(&"https://äb.com/"));
+    allowlist.add_domain("b.com");
+    assert!(allowlist.matches(&"https://ä.b.com/"));
+    assert_eq!(Allowlist::from_json("{ \"domains\": [1] }").err(), Some(Error::MissingField { index: 0, field: "domains" }));
+}
+
+#[test]
+fn contexts() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"track\", \"context\": [\"private\", \"work\"] }, \
+                   \"action\": { \"type\": \"block-cookies\" } }]";
+    let mut list = parse_list(body).unwrap();
+    assert_eq!(list.get(1).unwrap().contexts(), Some(vec!["private", "work"]));
+    let check = |list: &RuleList, context, expected: &[Reaction]| {
+        let request = Request {
+            url: &"http://ads.example.com/track",
+            resource_type: ResourceType::Script,
+            load_type: LoadType::ThirdParty,
+            context,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        assert_eq!(process_rules_for_request(list, &request), expected, "{:?}", context);
+        let bytes = list.to_bytes();
+        assert_eq!(process_rules_for_request_compiled_impl(&CompiledRuleList::from_bytes(&bytes).unwrap(), &request),
+                   expected, "{:?}", context);
+    };
+    check(&list, None, &[Reaction::Block]);
+    check(&list, Some("private"), &[Reaction::Block, Reaction::BlockCookies(CookieScope::Request)]);
+
+    list.limit_to_contexts(&["private", "shopping"]);
+    assert_eq!(list.get(0).unwrap().contexts(), Some(vec!["private", "shopping"]));
+    assert_eq!(list.get(1).unwrap().contexts(), Some(vec!["private"]));
+    check(&list, None, &[]);
+    check(&list, Some("work"), &[]);
+    check(&list, Some("shopping"), &[Reaction::Block]);
+    check(&list, Some("private"), &[Reaction::Block, Reaction::BlockCookies(CookieScope::Request)]);
+}
+
+#[test]
+fn popup_openers() {
+    let body = "[{ \"trigger\": { \"url-filter\": \".*\", \"resource-type\": [\"popup\"], \"user-initiated\": false }, \
+                   \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"casino\", \"opener-domain\": [\"*streams.example\"] }, \
+                   \"action\": { \"type\": \"block\" } }]";
+    let list = parse_list(body).unwrap();
+    assert_eq!(list.get(0).unwrap().user_initiated(), Some(false));
+    assert_eq!(list.get(1).unwrap().opener_domain(), Some(vec!["*streams.example".to_owned()]));
+    let bytes = list.to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    for &(opener, user_initiated, expected) in &[(None, false, &[][..]),
+                                                 (Some("https://news.org/"), true, &[][..]),
+                                                 (Some("https://news.org/"), false, &[Reaction::Block][..]),
+                                                 (Some("https://tv.streams.example/"), true, &[Reaction::Block][..])] {
+        let request = Request {
+            url: &"https://casino.example/",
+            resource_type: ResourceType::Popup,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: opener.as_ref().map(|url| Opener { url, user_initiated }),
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        assert_eq!(process_rules_for_request(&list, &request), expected, "{:?} {}", opener, user_initiated);
+        assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected);
+        let owned = OwnedRequest::from(&request);
+        assert_eq!(process_rules_for_request(&list, &owned.as_request()), expected);
+    }
+}
+
+#[test]
+fn top_document_domains() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"track\", \"if-domain\": [\"*news.example\"] }, \
+                   \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \".*\", \"unless-domain\": [\"shop.example\"] }, \
+                   \"action\": { \"type\": \"block-cookies\" } }]";
+    let mut list = parse_list(body).unwrap();
+    let request = |url, document| Request {
+        url,
+        resource_type: ResourceType::Script,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document,
+        redirect_chain: &[],
+        headers: None,
+    };
+    let block = &[Reaction::Block, Reaction::BlockCookies(CookieScope::Request)][..];
+    let on_news = request(&"https://cdn.example/track.js", Some(&"https://www.news.example/"));
+    assert_eq!(process_rules_for_request(&list, &on_news), &block[1..]);
+
+    list.set_domain_target(DomainTarget::TopDocument);
+    assert_eq!(list.get(0).unwrap().domain_target(), DomainTarget::TopDocument);
+    let bytes = list.to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    for &(ref request, expected) in &[(on_news, block),
+                                      (request(&"https://news.example/track", None), block),
+                                      (request(&"https://news.example/track", Some(&"https://blog.example/")),
+                                       &block[1..]),
+                                      (request(&"https://cdn.example/a.js", Some(&"https://shop.example/")), &[][..])] {
+        assert_eq!(process_rules_for_request(&list, request), expected, "{}", request.url.as_str());
+        assert_eq!(process_rules_for_request_compiled_impl(&compiled, request), expected);
+        assert_eq!(process_rules_for_request(&list, &OwnedRequest::from(request).as_request()), expected);
+    }
+}
+
+#[test]
+fn public_suffixes() {
+    let psl = PublicSuffixList::parse("// ===BEGIN ICANN DOMAINS===\nuk\nco.uk\n\n*.ck\n!www.ck\n\u{5546}\u{5E97}\n");
+    assert_eq!(psl.len(), 5);
+    for &(domain, suffix, registrable) in &[("www.example.co.uk", "co.uk", Some("example.co.uk")),
+                                             ("example.uk.", "uk", Some("example.uk")),
+                                             ("co.uk", "co.uk", None),
+                                             ("a.b.ck", "b.ck", Some("a.b.ck")),
+                                             ("www.ck", "ck", Some("www.ck")),
+                                             ("shop.xn--hxt814e", "xn--hxt814e", Some("shop.xn--hxt814e")),
+                                             ("localhost", "localhost", None)] {
+        assert_eq!(psl.public_suffix(domain), suffix, "{}", domain);
+        assert_eq!(psl.registrable_domain(domain), registrable, "{}", domain);
+    }
+
+    assert_eq!(registrable_domain(&"https://cdn.example.com/a.js"), Some("example.com"));
+    assert_eq!(registrable_domain(&"http://127.0.0.1/"), None);
+    assert_eq!(LoadType::by_site(&"https://cdn.example.com/", &"https://www.example.com/"), LoadType::FirstParty);
+    assert_eq!(LoadType::by_site(&"https://example.net/", &"https://example.com/"), LoadType::ThirdParty);
+
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\", \"if-domain\": [\"*example.*\"] }, \
+                             \"action\": { \"type\": \"block\" } }]").unwrap();
+    let bytes = list.to_bytes();
+    let compiled = CompiledRuleList::from_bytes(&bytes).unwrap();
+    for &(url, blocked) in &[("https://www.example.com/ads.js", true),
+                             ("https://example.org/ads.js", true),
+                             ("https://example.com.evil.net/ads.js", false),
+                             ("https://notexample.com/ads.js", false)] {
+        let request = Request {
+            url: &url,
+            resource_type: ResourceType::Script,
+            load_type: LoadType::FirstParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        let expected = if blocked { &[Reaction::Block][..] } else { &[][..] };
+        assert_eq!(process_rules_for_request(&list, &request), expected, "{}", url);
+        assert_eq!(process_rules_for_request_compiled_impl(&compiled, &request), expected, "{}", url);
+    }
+}
+
+#[test]
+fn entity_list() {
+    let entities = parse_entity_list("{ \"license\": \"CC BY-NC-SA 4.0\", \"categories\": {\
+        \"Advertising\": [{ \"Google\": { \"http://www.google.com/\": [\"doubleclick.net\", \"google.com\"] } }],\
+        \"Analytics\": [{ \"Google\": { \"http://www.google.com/\": [\"google-analytics.com\", \"google.com\"],\
+                                        \"performance\": \"true\" } },\
+                        { \"Chartbeat\": { \"https://chartbeat.com/\": [\"chartbeat.net\"] } }] } }").unwrap();
+    assert_eq!(entities.len(), 4);
+    assert_eq!(entities.lookup("stats.g.DoubleClick.net."), Some(&Tracker {
+        entity: "Google".to_owned(),
+        categories: vec!["Advertising".to_owned()],
+    }));
+    assert_eq!(entities.lookup("www.google.com").unwrap().categories, vec!["Advertising", "Analytics"]);
+    assert_eq!(entities.lookup("notchartbeat.net"), None);
+    assert_eq!(parse_entity_list("[]").err(), Some(Error::MissingField { index: 0, field: "categories" }));
+
+    let list = parse_list("[{ \"trigger\": { \"url-filter\": \"^https?://[^/]*chartbeat\\\\.net/\" }, \
+                             \"action\": { \"type\": \"block\" } }]").unwrap();
+    for &(url, blocked) in &[("https://static.chartbeat.net/js/chartbeat.js", true),
+                             ("https://www.google-analytics.com/analytics.js", false)] {
+        let request = Request {
+            url: &url,
+            resource_type: ResourceType::Script,
+            load_type: LoadType::ThirdParty,
+            context: None,
+            opener: None,
+            document: None,
+            redirect_chain: &[],
+            headers: None,
+        };
+        let categorized = process_rules_for_request_categorized(&list, &entities, &request);
+        assert_eq!(categorized.reactions.is_empty(), !blocked);
+        assert_eq!(categorized.tracker.map(|tracker| &*tracker.entity), if blocked { Some("Chartbeat") } else { None });
+    }
+}
+
+#[test]
+fn provenance() {
+    let mut easylist = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } },\
+                                    { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                                    { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"css-display-none\", \"selector\": \"#banner\" } },\
+                                    { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"ignore-previous-rules\" } },\
+                                    { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } },\
+                                    { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block-cookies\" } },\
+                                    { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"css-display-none-exception\", \"selector\": \".ad\" } }]").unwrap();
+    let request = Request {
+        url: &"http://ads.example.com/",
+        resource_type: ResourceType::Script,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: &[],
+        headers: None,
+    };
+    assert_eq!(process_rules_for_request_borrowed(&easylist, &request).rules(), &[5]);
+    assert_eq!(process_rules_for_request_with_provenance(&easylist, &request),
+               vec![(Reaction::BlockCookies(CookieScope::Request),
+                     Provenance { list: None, rule: 5, id: easylist.get(5).unwrap().id() })]);
+
+    easylist.set_name("EasyList");
+    easylist.limit_to_contexts(&["private"]);
+    assert_eq!(easylist.name(), Some("EasyList"));
+    let mut privacy = parse_list("[{ \"trigger\": { \"url-filter\": \"example\" }, \"action\": { \"type\": \"block\" } }]").unwrap();
+    privacy.set_name("EasyPrivacy");
+    let ids = (easylist.ids()[5], privacy.ids()[0]);
+    let mut engine = Engine::new();
+    engine.add_list(easylist);
+    engine.add_list(privacy);
+    let private = Request { context: Some("private"), ..request };
+    assert_eq!(engine.process_with_provenance(&private),
+               vec![(Reaction::BlockCookies(CookieScope::Request), Provenance { list: Some("EasyList"), rule: 5, id: ids.0 }),
+                    (Reaction::Block, Provenance { list: Some("EasyPrivacy"), rule: 0, id: ids.1 })]);
+}
+
+#[test]
+fn conformance_vectors() {
+    let vectors = include_str!("../conformance/webkit.json");
+    for report in &[run_conformance_vectors(&RuleListBackend, vectors).unwrap(),
+                    run_conformance_vectors(&CompiledBackend, vectors).unwrap()] {
+        assert_eq!(report.failures, vec![]);
+        assert_eq!(report.passed, 38);
+    }
+
+    let broken = "[{ \"name\": \"broken\", \"list\": [{ \"trigger\": {} }], \"requests\": [\
+                    { \"url\": \"http://webkit.org/\", \"resource-type\": \"document\", \
+                      \"load-type\": \"first-party\", \"reactions\": [{ \"type\": \"block\" }] }] }]";
+    let report = run_conformance_vectors(&RuleListBackend, broken).unwrap();
+    assert_eq!(report.passed, 0);
+    assert_eq!(report.failures[0].actual, Ok(vec![]));
+    assert_eq!(report.failures[0].expected, vec![Reaction::Block]);
+    assert_eq!(run_conformance_vectors(&RuleListBackend, "[{ \"name\": \"no requests\", \"list\": [] }]").err(),
+               Some(Error::MissingField { index: 0, field: "requests" }));
+}
+
+#[test]
+fn golden_vectors() {
+    struct Unfiltered;
+    impl Backend for Unfiltered {
+        type List = ();
+        fn load(&self, _: &str) -> Result<(), Error> {
+            Ok(())
+        }
+        fn process(&self, _: &(), _: &Request) -> Vec<Reaction> {
+            vec![]
+        }
+    }
+
+    let list = "[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"pixel\" }, \"action\": { \"type\": \"redirect\", \"resource\": \"1x1.gif\" } },\
+                 { \"trigger\": { \"url-filter\": \"news\" }, \"action\": { \"type\": \"block-cookies\", \"names\": [\"_ga*\"] } },\
+                 { \"trigger\": { \"url-filter\": \"news\" }, \"action\": { \"type\": \"block-cookies\", \"scope\": \"both\" } },\
+                 { \"trigger\": { \"url-filter\": \"news\", \"if-domain\": [\"*news.example\"] }, \
+                   \"action\": { \"type\": \"css-display-none\", \"selector\": \"div:has-text(Sponsored)\" } },\
+                 { \"trigger\": { \"url-filter\": \"news\" }, \
+                   \"action\": { \"type\": \"css-inject\", \"selector\": \"body\", \"style\": \"overflow: auto\" } }]";
+    let request = |url: &str, resource_type, document: Option<&str>| OwnedRequest {
+        url: url.to_owned(),
+        resource_type,
+        load_type: LoadType::ThirdParty,
+        context: Some("private".to_owned()),
+        opener: None,
+        document: document.map(str::to_owned),
+        redirect_chain: vec![],
+        headers: None,
+    };
+    let corpus = vec![request("https://cdn.example/ads.js", ResourceType::Script, Some("https://news.example/")),
+                      request("https://cdn.example/pixel.gif", ResourceType::Image, None),
+                      request("https://www.news.example/", ResourceType::Document, None),
+                      request("https://example.org/", ResourceType::Raw, None)];
+    let golden = record_golden_vectors(&RuleListBackend, "golden", list, &corpus).unwrap();
+    for report in &[run_conformance_vectors(&RuleListBackend, &golden).unwrap(),
+                    run_conformance_vectors(&CompiledBackend, &golden).unwrap()] {
+        assert_eq!(report.failures, vec![]);
+        assert_eq!(report.passed, 4);
+    }
+
+    let report = run_conformance_vectors(&Unfiltered, &golden).unwrap();
+    assert_eq!(report.passed, 1);
+    assert_eq!(report.failures.iter().map(|failure| &*failure.url).collect::<Vec<_>>(),
+               vec!["https://cdn.example/ads.js", "https://cdn.example/pixel.gif", "https://www.news.example/"]);
+    assert_eq!(report.failures[2].expected.len(), 4);
+    assert_eq!(record_golden_vectors(&RuleListBackend, "golden", "[", &corpus).err(), Some(Error::JSON));
+}
+
+#[cfg(feature = "quickcheck")]
+#[test]
+fn quickcheck_properties() {
+    use properties::{json_preserves_rules, optimize_preserves_reactions, serialization_preserves_reactions};
+    use quickcheck::QuickCheck;
+
+    let mut quickcheck = QuickCheck::new().tests(200);
+    quickcheck.quickcheck(serialization_preserves_reactions as fn(RuleList, OwnedRequest) -> bool);
+    quickcheck.quickcheck(optimize_preserves_reactions as fn(RuleList, OwnedRequest) -> bool);
+    quickcheck.quickcheck(json_preserves_rules as fn(RuleList) -> bool);
+}
+
+#[test]
+fn untrusted_input_limits() {
+    let nested = |depth| format!("{}a:has-text(x){}", "div:has(".repeat(depth), ")".repeat(depth));
+    assert!(ProceduralSelector::parse(&nested(8)).is_some());
+    assert!(ProceduralSelector::parse(&nested(9)).is_none());
+    let body = format!("[{{ \"trigger\": {{ \"url-filter\": \".*\" }}, \
+                          \"action\": {{ \"type\": \"css-display-none\", \"selector\": \"{}\" }} }}]", nested(100_000));
+    assert_eq!(parse_list(&body).map(|list| list.len()), Ok(0));
+
+    let bytes = parse_list("[{ \"trigger\": { \"url-filter\": \"ads\", \"if-domain\": [\"*example.com\"] }, \
+                             \"action\": { \"type\": \"block-cookies\", \"names\": [\"id\"] } }]").unwrap().to_bytes();
+    for len in 0..bytes.len() {
+        assert_eq!(CompiledRuleList::from_bytes(&bytes[..len]).err(), Some(Error::InvalidCompiledList));
+    }
+}
+
+#[test]
+fn lint() {
+    let rule = |trigger: &str, action: &str| format!("{{ \"trigger\": {{ {} }}, \"action\": {{ \"type\": \"{}\" }} }}", trigger, action);
+    let body = format!("[{}]", [
+        rule("\"url-filter\": \"(?i)tracker\"", "block"),
+        rule("\"url-filter\": \"ads\\\\.example\", \"resource-type\": [\"image\"]", "block"),
+        rule("\"url-filter\": \"cdn.Tracker.net\"", "block"),
+        rule("\"url-filter\": \"ads\\\\.example\\\\.com/banner\", \"resource-type\": [\"image\"], \"load-type\": [\"third-party\"]", "block"),
+        rule("\"url-filter\": \"ads\\\\.example\\\\.com/script\"", "block"),
+        rule("\"url-filter\": \"ads\", \"resource-type\": []", "block-cookies"),
+        rule("\"url-filter\": \"/track\", \"resource-type\": [\"font\"]", "block-cookies"),
+        rule("\"url-filter\": \"/tracker\\\\.js\"", "block"),
+        rule("\"url-filter\": \"/Tracker/\"", "block"),
+        rule("\"url-filter\": \"ads\\\\.example\", \"resource-type\": [\"image\"]", "block"),
+    ].join(","));
+    let list = parse_list(&body).unwrap();
+    let findings = list.lint();
+    assert_eq!(findings, vec![
+        LintFinding { rule: 3, kind: LintKind::ShadowedBlock { by: 1 } },
+        LintFinding { rule: 5, kind: LintKind::EmptyResourceTypes },
+        LintFinding { rule: 7, kind: LintKind::ShadowedBlock { by: 0 } },
+        LintFinding { rule: 8, kind: LintKind::ShadowedBlock { by: 0 } },
+        LintFinding { rule: 9, kind: LintKind::ShadowedBlock { by: 1 } },
+    ]);
+    assert_eq!(findings[0].to_string(), "rule 3: always blocked already by rule 1");
+
+    // An exception between the rules may cancel the earlier block.
+    let body = format!("[{}]", [
+        rule("\"url-filter\": \"ok\"", "ignore-previous-rules"),
+        rule("\"url-filter\": \".*\"", "block"),
+        rule("\"url-filter\": \"ok\"", "ignore-previous-rules"),
+        rule("\"url-filter\": \"ads\"", "block"),
+        rule("\"url-filter\": \"ads\", \"context\": [\"private\"]", "block"),
+    ].join(","));
+    assert_eq!(parse_list(&body).unwrap().lint(), vec![
+        LintFinding { rule: 0, kind: LintKind::UnreachableException },
+        LintFinding { rule: 1, kind: LintKind::MatchesEverything },
+        LintFinding { rule: 4, kind: LintKind::ShadowedBlock { by: 3 } },
+    ]);
+}
+
+#[test]
+fn lint_severity() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"make-coffee\" } },\
+                 { \"trigger\": { \"url-filter\": \"[0-9]{8}\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \".*\" }, \
+                   \"action\": { \"type\": \"css-display-none\", \"selector\": \".ad\" } },\
+                 { \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } }]";
+    let findings = lint_list(body).unwrap();
+    assert_eq!(findings, vec![
+        LintFinding { rule: 0, kind: LintKind::MatchesEverything },
+        LintFinding { rule: 1, kind: LintKind::Invalid(Error::UnsupportedAction { index: 1,
+                                                                                action: "make-coffee".to_owned() }) },
+        LintFinding { rule: 2, kind: LintKind::NoLiteral },
+        LintFinding { rule: 2, kind: LintKind::ShadowedBlock { by: 0 } },
+        LintFinding { rule: 4, kind: LintKind::ShadowedBlock { by: 0 } },
+    ]);
+    let severities: Vec<Severity> = findings.iter().map(LintFinding::severity).collect();
+    assert_eq!(severities, vec![Severity::Warning, Severity::Error, Severity::Warning, Severity::Info, Severity::Info]);
+    assert_eq!(findings.iter().filter(|finding| finding.severity() >= Severity::Warning).count(), 3);
+    assert_eq!(findings[1].to_string(), "rule 1: unsupported action type `make-coffee`");
+    assert_eq!(lint_list("{}"), Err(Error::NotAList));
+    assert_eq!(parse_list(body).unwrap().lint().len(), 4);
+}
+
+#[test]
+fn coverage() {
+    let body = "[{ \"trigger\": { \"url-filter\": \"ads\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"^https?://([^/]*\\\\.)?tracker\\\\.net/\" }, \
+                   \"action\": { \"type\": \"block-cookies\" } },\
+                 { \"trigger\": { \"url-filter\": \"unused\" }, \"action\": { \"type\": \"block\" } },\
+                 { \"trigger\": { \"url-filter\": \"ads\\\\.example\\\\.com/ok\" }, \
+                   \"action\": { \"type\": \"ignore-previous-rules\" } }]";
+    let list = parse_list(body).unwrap();
+    let request = |url: &str| OwnedRequest {
+        url: url.to_owned(),
+        resource_type: ResourceType::Script,
+        load_type: LoadType::ThirdParty,
+        context: None,
+        opener: None,
+        document: None,
+        redirect_chain: vec![],
+        headers: None,
+    };
+    let corpus = vec![
+        request("http://ads.example.com/banner.js"),
+        request("http://ads.example.com/ok.js"),
+        request("https://cdn.tracker.net/ads.js"),
+        request("https://news.org/"),
+    ];
+    let coverage = list.coverage(&corpus);
+    assert_eq!(coverage.requests, 4);
+    assert_eq!(coverage.requests_with_reactions, 2);
+    assert_eq!(coverage.rules, vec![
+        RuleCoverage { matches: 3, reactions: 2 },
+        RuleCoverage { matches: 1, reactions: 1 },
+        RuleCoverage { matches: 0, reactions: 0 },
+        RuleCoverage { matches: 1, reactions: 0 },
+    ]);
+    assert_eq!(coverage.never_matched().collect::<Vec<_>>(), vec![2]);
+    assert_eq!(coverage.most_matched(2), vec![(0, &coverage.rules[0]), (1, &coverage.rules[1])]);
+}
@@ -0,0 +1,55 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use base64;
+use md5;
+use parse::Error;
+use regex::Regex;
+
+/// A verifier for detached signatures over list bodies, typically backed by the
+/// embedder's cryptography library and a set of trusted keys.
+pub trait SignatureVerifier {
+    /// Whether `signature` is a valid signature of `body`.
+    fn verify(&self, body: &[u8], signature: &[u8]) -> bool;
+}
+
+fn checksum_regex() -> Regex {
+    Regex::new(r"(?im)^\s*!\s*checksum[\s\-:]+([\w+/=]+).*\n").unwrap()
+}
+
+/// Compute the ABP-style checksum of a list body: the base64-encoded MD5 digest
+/// of the body with carriage returns, blank lines and any existing checksum line
+/// removed, without trailing padding.
+pub fn checksum(body: &str) -> String {
+    let normalized = body.replace('\r', "");
+    let normalized = Regex::new(r"\n+").unwrap().replace_all(&normalized, "\n");
+    let normalized = checksum_regex().replace_all(&normalized, "");
+    let digest = md5::compute(normalized.as_bytes());
+    base64::encode(&digest[..]).trim_end_matches('=').to_owned()
+}
+
+/// Check the `! Checksum:` comment of a list body against its contents.
+pub fn verify_checksum_impl(body: &str) -> Result<(), Error> {
+    let normalized = body.replace('\r', "");
+    let expected = match checksum_regex().captures(&normalized) {
+        Some(captures) => captures[1].trim_end_matches('=').to_owned(),
+        None => return Err(Error::MissingChecksum),
+    };
+    if checksum(body) == expected {
+        Ok(())
+    } else {
+        Err(Error::ChecksumMismatch)
+    }
+}
+
+/// Check a detached signature over a list body.
+pub fn verify_signature_impl<V: SignatureVerifier + ?Sized>(body: &str,
+                                                            signature: &[u8],
+                                                            verifier: &V) -> Result<(), Error> {
+    if verifier.verify(body.as_bytes(), signature) {
+        Ok(())
+    } else {
+        Err(Error::InvalidSignature)
+    }
+}
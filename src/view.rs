@@ -0,0 +1,300 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use html::HtmlFilter;
+use repr::{Action, DomainConstraint, DomainMatcher, DomainTarget, LoadType, ResourceType, UrlFilter, UrlFilterAnchor};
+use repr::{HeaderCondition, LoadTypeList, ResourceTypeList, Rule, RuleId, SourceLocation};
+use resources::Resource;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::slice;
+
+/// The kind of action performed by a rule.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ActionType {
+    /// The request is blocked.
+    Block,
+    /// Cookies are stripped from the request or its response.
+    BlockCookies,
+    /// The response is replaced with a surrogate resource.
+    Redirect,
+    /// Elements of the requesting page are hidden.
+    CssDisplayNone,
+    /// Elements of the requesting page are exempted from being hidden.
+    CssDisplayNoneException,
+    /// Styles are applied to elements of the requesting page.
+    CssInject,
+    /// Elements are removed from the HTML of the response.
+    HtmlFilter,
+    /// The inline scripts of the requested document are prevented from running.
+    BlockInlineScripts,
+    /// The actions of previously triggered rules are discarded.
+    IgnorePreviousRules,
+}
+
+/// A read-only view of a single parsed rule.
+#[derive(Copy, Clone, Debug)]
+pub struct RuleView<'a> {
+    index: usize,
+    rule: &'a Rule,
+}
+
+impl<'a> RuleView<'a> {
+    /// The position of this rule in its list.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Where this rule was found in the body of its list, or `None` if it was
+    /// not parsed from one, eg. when it was added by a delta.
+    pub fn source(&self) -> Option<SourceLocation> {
+        self.rule.source
+    }
+
+    /// The identifier of this rule, derived from its content.
+    pub fn id(&self) -> RuleId {
+        self.rule.id()
+    }
+
+    /// This rule as a JSON rule object, in the format read by `parse_list`.
+    pub fn to_json(&self) -> String {
+        self.rule.to_json().to_string()
+    }
+
+    /// The source of the regular expression matched against request URLs.
+    pub fn url_filter(&self) -> &'a str {
+        self.rule.trigger.url_filter.as_str()
+    }
+
+    /// The resource types this rule is restricted to, or `None` if it applies
+    /// to every resource type.
+    pub fn resource_types(&self) -> Option<&'a [ResourceType]> {
+        match self.rule.trigger.resource_type {
+            ResourceTypeList::All => None,
+            ResourceTypeList::List(ref types) => Some(types),
+        }
+    }
+
+    /// The load types this rule is restricted to, or `None` if it applies to
+    /// every load type.
+    pub fn load_types(&self) -> Option<&'a [LoadType]> {
+        match self.rule.trigger.load_type {
+            LoadTypeList::All => None,
+            LoadTypeList::List(ref types) => Some(types),
+        }
+    }
+
+    /// The domains this rule is restricted to, in list syntax (subdomain patterns
+    /// are prefixed with `*`).
+    pub fn if_domain(&self) -> Option<Vec<String>> {
+        match self.rule.trigger.domain_constraint {
+            Some(DomainConstraint::If(ref matcher)) => Some(domain_strings(matcher)),
+            _ => None,
+        }
+    }
+
+    /// The domains excluded from this rule, in list syntax.
+    pub fn unless_domain(&self) -> Option<Vec<String>> {
+        match self.rule.trigger.domain_constraint {
+            Some(DomainConstraint::Unless(ref matcher)) => Some(domain_strings(matcher)),
+            _ => None,
+        }
+    }
+
+    /// What this rule's domain constraint is evaluated against.
+    pub fn domain_target(&self) -> DomainTarget {
+        self.rule.trigger.domain_target
+    }
+
+    /// Whether this rule only applies to popups opened by a user action, or only
+    /// to those opened without one.
+    pub fn user_initiated(&self) -> Option<bool> {
+        self.rule.trigger.user_initiated
+    }
+
+    /// The domains of the pages whose popups this rule applies to, in list syntax.
+    pub fn opener_domain(&self) -> Option<Vec<String>> {
+        self.rule.trigger.opener_domain.as_ref().map(domain_strings)
+    }
+
+    /// The names of the headers a request must have for this rule to apply,
+    /// along with the patterns their values must match, if any.
+    pub fn if_header(&self) -> Vec<(&'a str, Option<&'a str>)> {
+        header_conditions(&self.rule.trigger.if_header)
+    }
+
+    /// The names of the headers a request must not have for this rule to apply,
+    /// along with the patterns their values must match to exclude it, if any.
+    pub fn unless_header(&self) -> Vec<(&'a str, Option<&'a str>)> {
+        header_conditions(&self.rule.trigger.unless_header)
+    }
+
+    /// What this rule's URL filter is matched against.
+    pub fn url_filter_anchor(&self) -> UrlFilterAnchor {
+        self.rule.trigger.url_filter_anchor
+    }
+
+    /// The pattern the path of a URL must also match for this rule to apply.
+    pub fn path_filter(&self) -> Option<&'a str> {
+        self.rule.trigger.path_filter.as_ref().map(UrlFilter::as_str)
+    }
+
+    /// The pattern the query of a URL must also match for this rule to apply.
+    pub fn query_filter(&self) -> Option<&'a str> {
+        self.rule.trigger.query_filter.as_ref().map(UrlFilter::as_str)
+    }
+
+    /// Whether this rule's URL filter also matches the URLs a request was
+    /// redirected from.
+    pub fn match_redirect_chain(&self) -> bool {
+        self.rule.trigger.match_redirect_chain
+    }
+
+    /// The browsing contexts this rule is restricted to, if any.
+    pub fn contexts(&self) -> Option<Vec<&'a str>> {
+        self.rule.trigger.contexts.as_ref().map(|contexts| contexts.iter().map(|c| &**c).collect())
+    }
+
+    /// The groups this rule belongs to, in sorted order.
+    pub fn groups(&self) -> Vec<&'a str> {
+        self.rule.groups.iter().map(|g| &**g).collect()
+    }
+
+    /// Whether any of this rule's domain constraints cover `domain`.
+    pub fn references_domain(&self, domain: &str) -> bool {
+        match self.rule.trigger.domain_constraint {
+            Some(DomainConstraint::If(ref matcher)) |
+            Some(DomainConstraint::Unless(ref matcher)) => matcher.matches_domain(domain),
+            None => false,
+        }
+    }
+
+    /// The kind of action this rule performs.
+    pub fn action_type(&self) -> ActionType {
+        match self.rule.action {
+            Action::Block => ActionType::Block,
+            Action::BlockCookies(_) | Action::BlockCookiesMatching(_) => ActionType::BlockCookies,
+            Action::Redirect(_) => ActionType::Redirect,
+            Action::CssDisplayNone(_) | Action::CssDisplayNoneProcedural(_) => ActionType::CssDisplayNone,
+            Action::CssDisplayNoneException(_) => ActionType::CssDisplayNoneException,
+            Action::CssInject { .. } => ActionType::CssInject,
+            Action::HtmlFilter(_) => ActionType::HtmlFilter,
+            Action::BlockInlineScripts => ActionType::BlockInlineScripts,
+            Action::IgnorePreviousRules => ActionType::IgnorePreviousRules,
+        }
+    }
+
+    /// The CSS selector of an element hiding rule.
+    pub fn selector(&self) -> Option<&'a str> {
+        match self.rule.action {
+            Action::CssDisplayNone(ref selector) |
+            Action::CssDisplayNoneException(ref selector) |
+            Action::CssInject { ref selector, .. } => Some(selector),
+            Action::CssDisplayNoneProcedural(ref selector) => Some(&selector.source),
+            _ => None,
+        }
+    }
+
+    /// The surrogate resource of a redirect rule.
+    pub fn resource(&self) -> Option<&'static Resource> {
+        match self.rule.action {
+            Action::Redirect(resource) => Some(resource),
+            _ => None,
+        }
+    }
+
+    /// The CSS declarations applied by a CSS injection rule.
+    pub fn style(&self) -> Option<&'a str> {
+        match self.rule.action {
+            Action::CssInject { ref style, .. } => Some(style),
+            _ => None,
+        }
+    }
+
+    /// The elements removed by an HTML filtering rule.
+    pub fn html_filter(&self) -> Option<&'a HtmlFilter> {
+        match self.rule.action {
+            Action::HtmlFilter(ref filter) => Some(filter),
+            _ => None,
+        }
+    }
+}
+
+fn domain_strings(matcher: &DomainMatcher) -> Vec<String> {
+    matcher.exact.iter().map(|d| d.to_string())
+           .chain(matcher.subdomain.iter().map(|d| format!("*{}", d)))
+           .collect()
+}
+
+fn header_conditions(conditions: &[HeaderCondition]) -> Vec<(&str, Option<&str>)> {
+    conditions.iter().map(|condition| (&*condition.name, condition.value.as_ref().map(|v| v.as_str()))).collect()
+}
+
+/// Views compare their rules, not their positions, so that a set of views holds
+/// each distinct rule once.
+impl<'a> PartialEq for RuleView<'a> {
+    fn eq(&self, other: &RuleView<'a>) -> bool {
+        self.rule == other.rule
+    }
+}
+
+impl<'a> Eq for RuleView<'a> {}
+
+impl<'a> Hash for RuleView<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rule.hash(state)
+    }
+}
+
+/// A one-line summary of the rule, eg. `ads resource-type=image -> block`.
+impl<'a> fmt::Display for RuleView<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.rule.fmt(f)
+    }
+}
+
+/// An iterator over the rules of a list.
+#[derive(Clone, Debug)]
+pub struct Rules<'a> {
+    inner: ::std::iter::Enumerate<slice::Iter<'a, Rule>>,
+}
+
+/// Create an iterator over views of `rules`.
+pub fn rules_iter(rules: &[Rule]) -> Rules<'_> {
+    Rules {
+        inner: rules.iter().enumerate(),
+    }
+}
+
+impl<'a> Iterator for Rules<'a> {
+    type Item = RuleView<'a>;
+
+    fn next(&mut self) -> Option<RuleView<'a>> {
+        self.inner.next().map(|(index, rule)| RuleView { index, rule })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Rules<'a> {}
+
+/// Count the rules that can apply to each resource type. Rules without a
+/// resource type restriction are counted for every type.
+pub fn count_by_resource_type_impl(rules: &[Rule]) -> HashMap<ResourceType, usize> {
+    let mut counts = HashMap::new();
+    for rule in rules {
+        let types = match rule.trigger.resource_type {
+            ResourceTypeList::All => ResourceType::ALL,
+            ResourceTypeList::List(ref types) => types,
+        };
+        for type_ in types {
+            *counts.entry(*type_).or_insert(0) += 1;
+        }
+    }
+    counts
+}